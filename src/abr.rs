@@ -0,0 +1,492 @@
+use crate::manifest::RepresentationId;
+use crate::manifest::Track;
+
+use core::time::Duration;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+/// Share of the estimated bandwidth a rendition's bitrate may use when selecting automatically.
+pub(crate) const BANDWIDTH_SAFETY_FACTOR: f64 = 0.8;
+
+/// Prefix of the `localStorage` keys estimates are kept under, followed by the origin.
+const STORED_ESTIMATE_PREFIX: &str = "ashina.bandwidth.";
+
+/// How the player picks the video rendition.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum QualitySelection {
+    /// Pick the best rendition the estimated bandwidth can sustain. The player refines this with
+    /// the rules of [`crate::config::AbrConfig::rules`].
+    #[default]
+    Auto,
+    /// Always play the representation with this id.
+    Pinned(RepresentationId),
+    /// Best rendition no taller than this many pixels.
+    MaxHeight(u32),
+    /// Best rendition whose bitrate doesn't exceed this many bits per second.
+    MaxBitrate(u64),
+}
+
+impl QualitySelection {
+    /// Picks the rendition to play from `candidates` (representations of one adaptation set).
+    /// Returns `None` if the selection has no opinion, e.g. `Auto` without a bandwidth estimate,
+    /// in which case the current rendition should be kept.
+    pub fn select<'a>(&self, candidates: &'a [Track], estimate: Option<f64>) -> Option<&'a Track> {
+        let best_within = |fits: &dyn Fn(&Track) -> bool| {
+            candidates
+                .iter()
+                .filter(|track| fits(track))
+                .max_by_key(|track| track.bitrate().unwrap_or_default())
+                .or_else(|| lowest_rendition(candidates))
+        };
+
+        match self {
+            Self::Auto => {
+                let budget = estimate? * BANDWIDTH_SAFETY_FACTOR;
+                best_within(&|track| (track.bitrate().unwrap_or_default() as f64) <= budget)
+            }
+            Self::Pinned(id) => candidates.iter().find(|track| &track.id() == id),
+            Self::MaxHeight(height) => {
+                best_within(&|track| track.height().unwrap_or_default() <= *height as u64)
+            }
+            Self::MaxBitrate(bitrate) => {
+                best_within(&|track| track.bitrate().unwrap_or_default() <= *bitrate)
+            }
+        }
+    }
+}
+
+/// The rendition with the smallest bitrate.
+pub fn lowest_rendition(candidates: &[Track]) -> Option<&Track> {
+    candidates
+        .iter()
+        .min_by_key(|track| track.bitrate().unwrap_or_default())
+}
+
+/// Estimator shared between the loader feeding it and the player reading it.
+pub type SharedEstimator = Rc<RefCell<Box<dyn BandwidthEstimator>>>;
+
+/// Estimates available network throughput from completed transfers.
+pub trait BandwidthEstimator {
+    /// Records a transfer of `bytes` that took `duration`.
+    fn sample(&mut self, bytes: usize, duration: Duration);
+
+    /// Current estimate in bits per second, `None` until a sample has been recorded.
+    fn estimate(&self) -> Option<f64>;
+}
+
+/// The estimate, in bits per second, an earlier session stored for the origin `origin` with
+/// [`store_estimate`]. `None` where `localStorage` isn't available.
+pub fn stored_estimate(origin: &str) -> Option<f64> {
+    local_storage()?
+        .get_item(&format!("{STORED_ESTIMATE_PREFIX}{origin}"))
+        .ok()??
+        .parse()
+        .ok()
+        .filter(|estimate: &f64| estimate.is_finite() && *estimate > 0.)
+}
+
+/// Keeps `estimate` for the next session fetching from the origin `origin`, see
+/// [`crate::config::AbrConfig::persist_bandwidth`].
+pub fn store_estimate(origin: &str, estimate: f64) {
+    let Some(storage) = local_storage() else {
+        return;
+    };
+
+    // Quota errors and the like only cost the next session its head start.
+    if let Err(error) = storage.set_item(
+        &format!("{STORED_ESTIMATE_PREFIX}{origin}"),
+        &estimate.round().to_string(),
+    ) {
+        tracing::debug!(?error, "Failed to store the bandwidth estimate.");
+    }
+}
+
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+/// Exponentially weighted moving average of throughput, where each sample's weight grows with
+/// the time its transfer took, so one short burst can't swing the estimate.
+#[derive(Clone, Debug)]
+pub struct EwmaEstimator {
+    /// Seconds of transfer time after which a sample's weight has halved.
+    half_life: f64,
+    average: f64,
+    total_weight: f64,
+}
+
+impl EwmaEstimator {
+    pub fn new(half_life: Duration) -> Self {
+        Self {
+            half_life: half_life.as_secs_f64(),
+            average: 0.,
+            total_weight: 0.,
+        }
+    }
+}
+
+impl Default for EwmaEstimator {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(4))
+    }
+}
+
+impl BandwidthEstimator for EwmaEstimator {
+    fn sample(&mut self, bytes: usize, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if seconds <= 0. {
+            return;
+        }
+
+        let bps = bytes as f64 * 8. / seconds;
+        let alpha = 0.5f64.powf(seconds / self.half_life);
+
+        self.average = bps * (1. - alpha) + self.average * alpha;
+        self.total_weight += seconds;
+    }
+
+    fn estimate(&self) -> Option<f64> {
+        if self.total_weight == 0. {
+            return None;
+        }
+
+        // The average starts at zero, correct for that bias while few samples are in.
+        let zero_factor = 1. - 0.5f64.powf(self.total_weight / self.half_life);
+        Some(self.average / zero_factor)
+    }
+}
+
+/// Harmonic mean of the throughput of the last `window` transfers. The harmonic mean is
+/// dominated by the slow samples, which makes it a conservative estimate.
+#[derive(Clone, Debug)]
+pub struct HarmonicMeanEstimator {
+    window: usize,
+    samples: VecDeque<f64>,
+}
+
+impl HarmonicMeanEstimator {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            samples: VecDeque::new(),
+        }
+    }
+}
+
+impl Default for HarmonicMeanEstimator {
+    fn default() -> Self {
+        Self::new(5)
+    }
+}
+
+impl BandwidthEstimator for HarmonicMeanEstimator {
+    fn sample(&mut self, bytes: usize, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if seconds <= 0. || bytes == 0 {
+            return;
+        }
+
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+
+        self.samples.push_back(bytes as f64 * 8. / seconds);
+    }
+
+    fn estimate(&self) -> Option<f64> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        let reciprocal_sum: f64 = self.samples.iter().map(|bps| 1. / bps).sum();
+        Some(self.samples.len() as f64 / reciprocal_sum)
+    }
+}
+
+/// What an [`AbrRule`] gets to look at when the video rendition is up for selection.
+#[derive(Clone, Copy, Debug)]
+pub struct AbrContext<'a> {
+    /// Renditions of the adaptation set, in manifest order.
+    pub candidates: &'a [Track],
+    /// Rendition currently buffering.
+    pub current: &'a Track,
+    /// Estimated throughput in bits per second, see [`BandwidthEstimator`].
+    pub bandwidth_estimate: Option<f64>,
+    /// Seconds buffered contiguously ahead of the playhead.
+    pub buffer_level: f64,
+    /// Duration of a segment, in seconds.
+    pub segment_duration: f64,
+    /// Frames dropped by the video element since it loaded the media.
+    pub dropped_frames: u32,
+    /// Frames decoded by the video element since it loaded the media.
+    pub total_frames: u32,
+}
+
+/// One opinion on how much bitrate playback can afford, for [`QualitySelection::Auto`]. Rules
+/// are combined by an [`AbrController`], which plays the best rendition within the lowest limit
+/// any rule sets. Register custom rules through [`crate::config::AbrConfig::rules`].
+pub trait AbrRule {
+    fn name(&self) -> &str;
+
+    /// Highest bitrate, in bits per second, this rule allows right now. `None` if the rule has no
+    /// opinion, e.g. while it lacks the data it needs.
+    fn max_bitrate(&mut self, context: &AbrContext) -> Option<u64>;
+}
+
+/// Allows a share of the estimated bandwidth, the classic throughput-based selection.
+#[derive(Clone, Debug)]
+pub struct ThroughputRule {
+    safety_factor: f64,
+}
+
+impl ThroughputRule {
+    /// Renditions may use `safety_factor` of the estimated bandwidth.
+    pub fn new(safety_factor: f64) -> Self {
+        Self { safety_factor }
+    }
+}
+
+impl Default for ThroughputRule {
+    fn default() -> Self {
+        Self::new(BANDWIDTH_SAFETY_FACTOR)
+    }
+}
+
+impl AbrRule for ThroughputRule {
+    fn name(&self) -> &str {
+        "throughput"
+    }
+
+    fn max_bitrate(&mut self, context: &AbrContext) -> Option<u64> {
+        Some((context.bandwidth_estimate? * self.safety_factor) as u64)
+    }
+}
+
+/// BOLA: picks the rendition from the buffer level alone, trading the utility of a higher
+/// bitrate against the risk of draining the buffer. Steadier than throughput-based selection
+/// once the buffer has filled, but has nothing to go on before that.
+#[derive(Clone, Debug)]
+pub struct BufferOccupancyRule {
+    /// Buffer level, in seconds, at which the highest rendition is picked.
+    target: f64,
+    /// How strongly buffer level is favoured over bitrate, BOLA's `gamma * p`.
+    gamma: f64,
+}
+
+impl BufferOccupancyRule {
+    pub fn new(target: Duration) -> Self {
+        Self {
+            target: target.as_secs_f64(),
+            gamma: 5.,
+        }
+    }
+}
+
+impl Default for BufferOccupancyRule {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(12))
+    }
+}
+
+impl AbrRule for BufferOccupancyRule {
+    fn name(&self) -> &str {
+        "buffer-occupancy"
+    }
+
+    fn max_bitrate(&mut self, context: &AbrContext) -> Option<u64> {
+        if context.buffer_level < context.segment_duration {
+            return None;
+        }
+
+        let lowest = lowest_rendition(context.candidates)?.bitrate()?.max(1) as f64;
+        let utility = |bitrate: f64| (bitrate / lowest).ln();
+        let bitrates: Vec<_> = context
+            .candidates
+            .iter()
+            .filter_map(|track| track.bitrate())
+            .map(|bitrate| bitrate.max(1) as f64)
+            .collect();
+        let max_utility = bitrates.iter().copied().map(utility).fold(0., f64::max);
+
+        let target = self.target.max(2. * context.segment_duration);
+        let v = (target - context.segment_duration) / (max_utility + self.gamma);
+        let score =
+            |bitrate: f64| (v * (utility(bitrate) + self.gamma) - context.buffer_level) / bitrate;
+
+        bitrates
+            .into_iter()
+            .max_by(|a, b| score(*a).total_cmp(&score(*b)))
+            .map(|bitrate| bitrate as u64)
+    }
+}
+
+/// Caps the bitrate below renditions the device dropped too many frames decoding, even if the
+/// bandwidth would allow them. Once the renditions below have played `recovery_frames` frames
+/// without dropping too many, the lightest rendition found too heavy gets another chance, so a
+/// passing load on the device doesn't cap the rest of the presentation.
+#[derive(Clone, Debug)]
+pub struct DroppedFramesRule {
+    /// Share of dropped frames above which a rendition is considered too heavy.
+    max_drop_ratio: f64,
+    /// Frames a rendition has to have decoded before its drop ratio counts.
+    min_frames: u32,
+    /// Frames decoded within the drop ratio after which a rendition found too heavy is tried
+    /// again.
+    recovery_frames: u32,
+    /// `(dropped, total)` frames decoded per rendition bitrate.
+    history: HashMap<u64, (u32, u32)>,
+    /// `(dropped, total)` frames decoded since a rendition was last found too heavy.
+    clean: (u32, u32),
+    /// Counters of the video element at the previous evaluation.
+    last: (u32, u32),
+}
+
+impl DroppedFramesRule {
+    /// Name the rule reports, see [`AbrController::limited_by`].
+    pub const NAME: &'static str = "dropped-frames";
+
+    pub fn new(max_drop_ratio: f64, min_frames: u32) -> Self {
+        Self {
+            max_drop_ratio,
+            min_frames,
+            recovery_frames: min_frames.saturating_mul(4),
+            history: HashMap::new(),
+            clean: (0, 0),
+            last: (0, 0),
+        }
+    }
+
+    /// Frames to play within the drop ratio before a rendition found too heavy is tried again.
+    /// Four times `min_frames` unless set.
+    pub fn with_recovery_frames(mut self, frames: u32) -> Self {
+        self.recovery_frames = frames;
+        self
+    }
+
+    fn exceeds_ratio(&self, (dropped, total): (u32, u32)) -> bool {
+        total > 0 && dropped as f64 / total as f64 > self.max_drop_ratio
+    }
+
+    /// Whether the rendition with `bitrate` decoded enough frames to tell, and dropped too many.
+    fn too_heavy(&self, bitrate: u64) -> bool {
+        self.history
+            .get(&bitrate)
+            .is_some_and(|frames| frames.1 >= self.min_frames && self.exceeds_ratio(*frames))
+    }
+}
+
+impl Default for DroppedFramesRule {
+    fn default() -> Self {
+        Self::new(0.15, 375)
+    }
+}
+
+impl AbrRule for DroppedFramesRule {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn max_bitrate(&mut self, context: &AbrContext) -> Option<u64> {
+        // The counters start over when the element loads new media.
+        if context.total_frames < self.last.1 {
+            self.last = (0, 0);
+        }
+
+        let dropped = context.dropped_frames.saturating_sub(self.last.0);
+        let total = context.total_frames.saturating_sub(self.last.1);
+        self.last = (context.dropped_frames, context.total_frames);
+
+        let current = context.current.bitrate().unwrap_or_default();
+        let entry = self.history.entry(current).or_default();
+        entry.0 += dropped;
+        entry.1 += total;
+
+        let mut bitrates: Vec<_> = context
+            .candidates
+            .iter()
+            .filter_map(|track| track.bitrate())
+            .collect();
+        bitrates.sort_unstable();
+
+        if self.too_heavy(current) {
+            self.clean = (0, 0);
+        } else {
+            self.clean.0 += dropped;
+            self.clean.1 += total;
+        }
+
+        let mut heavy = bitrates
+            .iter()
+            .position(|bitrate| self.too_heavy(*bitrate))?;
+        if self.clean.1 >= self.recovery_frames && !self.exceeds_ratio(self.clean) {
+            tracing::debug!(
+                bitrate = bitrates[heavy],
+                "Trying a rendition that dropped too many frames again."
+            );
+            self.history.remove(&bitrates[heavy]);
+            self.clean = (0, 0);
+            heavy = bitrates
+                .iter()
+                .position(|bitrate| self.too_heavy(*bitrate))?;
+        }
+
+        Some(bitrates[heavy.saturating_sub(1)])
+    }
+}
+
+/// The rules [`crate::config::AbrConfig::rules`] starts with.
+pub fn default_abr_rules() -> Vec<Box<dyn AbrRule>> {
+    vec![
+        Box::new(ThroughputRule::default()),
+        Box::new(DroppedFramesRule::default()),
+    ]
+}
+
+/// Combines [`AbrRule`]s: every rule sets a bitrate limit, and the best rendition within the
+/// lowest one is played.
+pub struct AbrController {
+    rules: Vec<Box<dyn AbrRule>>,
+    /// Name of the rule that set the limit at the last selection.
+    limited_by: Option<String>,
+}
+
+impl AbrController {
+    pub fn new(rules: Vec<Box<dyn AbrRule>>) -> Self {
+        Self {
+            rules,
+            limited_by: None,
+        }
+    }
+
+    /// Name of the rule whose limit the last [`Self::select`] applied, `None` if no rule had an
+    /// opinion.
+    pub fn limited_by(&self) -> Option<&str> {
+        self.limited_by.as_deref()
+    }
+
+    /// The rendition to play, `None` if no rule has an opinion and the current rendition should
+    /// be kept. Falls back to the lowest rendition if even that exceeds the limit.
+    pub fn select<'a>(&mut self, context: &AbrContext<'a>) -> Option<&'a Track> {
+        let (limit, rule) = self
+            .rules
+            .iter_mut()
+            .filter_map(|rule| {
+                let limit = rule.max_bitrate(context)?;
+                tracing::trace!(rule = rule.name(), limit, "ABR rule evaluated.");
+                Some((limit, rule.name()))
+            })
+            .min_by_key(|(limit, _)| *limit)
+            .unzip();
+        self.limited_by = rule.map(str::to_owned);
+        let limit = limit?;
+
+        context
+            .candidates
+            .iter()
+            .filter(|track| track.bitrate().unwrap_or_default() <= limit)
+            .max_by_key(|track| track.bitrate().unwrap_or_default())
+            .or_else(|| lowest_rendition(context.candidates))
+    }
+}
@@ -0,0 +1,173 @@
+//! Client-side ad insertion. Breaks come from [`AdConfig::breaks`], from
+//! [`crate::MediaPlayer::schedule_ad_break`], or from `EventStream` and `emsg` cues that
+//! [`AdConfig::cue_handler`] turns into breaks. When a break is due, the player pauses the main
+//! content and stops buffering it, plays the break's manifest in a second video element through
+//! a player of its own, and resumes the main content once the ad ended or failed.
+//! [`crate::event::PlayerEvent::AdBreakStarted`] and [`crate::event::PlayerEvent::AdBreakEnded`]
+//! frame every break, e.g. to swap which of the two elements is shown.
+//!
+//! ```ignore
+//! let ads = AdConfig::new("ad-video")
+//!     .with_break(AdBreak::new("pre", AdPosition::PreRoll, pre_roll_url))
+//!     .with_break(AdBreak::new("mid", AdPosition::MidRoll(600.), mid_roll_url))
+//!     .with_cue_handler(|cue| (cue.scheme == SCTE35_SCHEME).then(|| ad_url(cue)));
+//! ```
+
+use core::fmt;
+use std::rc::Rc;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// When a break plays.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AdPosition {
+    /// Before the main content starts.
+    PreRoll,
+    /// Once the playhead reaches this position, in seconds of media element time.
+    MidRoll(f64),
+    /// After the main content ended.
+    PostRoll,
+}
+
+/// An ad break: one ad presentation played from its start. Several breaks at the same position
+/// play back to back.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdBreak {
+    /// Reported in the break's events.
+    pub id: String,
+    pub position: AdPosition,
+    /// Manifest of the ad.
+    pub manifest: String,
+}
+
+impl AdBreak {
+    pub fn new(id: impl Into<String>, position: AdPosition, manifest: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            position,
+            manifest: manifest.into(),
+        }
+    }
+}
+
+/// A timed metadata cue offered to [`AdConfig::cue_handler`], as published through
+/// [`crate::event::PlayerEvent::TimedMetadata`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdCue {
+    pub scheme: String,
+    pub value: Option<String>,
+    pub id: Option<String>,
+    /// In seconds of media element time.
+    pub start: f64,
+    pub duration: Option<f64>,
+    pub payload: Vec<u8>,
+}
+
+/// Returns the manifest of the ad to play at a cue, or `None` for cues that aren't ad markers.
+pub type CueHandler = Rc<dyn Fn(&AdCue) -> Option<String>>;
+
+/// Ad insertion for a player, see the [module docs](self).
+#[derive(Clone)]
+pub struct AdConfig {
+    /// Id of the video element ads play in. The app keeps it hidden outside of breaks.
+    pub element_id: String,
+    /// Breaks of every presentation the player loads.
+    pub breaks: Vec<AdBreak>,
+    pub cue_handler: Option<CueHandler>,
+}
+
+impl AdConfig {
+    pub fn new(element_id: impl Into<String>) -> Self {
+        Self {
+            element_id: element_id.into(),
+            breaks: vec![],
+            cue_handler: None,
+        }
+    }
+
+    pub fn with_break(mut self, ad_break: AdBreak) -> Self {
+        self.breaks.push(ad_break);
+        self
+    }
+
+    /// Schedules a mid-roll at every cue `handler` returns a manifest for. Cues for positions
+    /// already played are ignored.
+    pub fn with_cue_handler(
+        mut self,
+        handler: impl Fn(&AdCue) -> Option<String> + 'static,
+    ) -> Self {
+        self.cue_handler = Some(Rc::new(handler));
+        self
+    }
+}
+
+impl fmt::Debug for AdConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdConfig")
+            .field("element_id", &self.element_id)
+            .field("breaks", &self.breaks)
+            .field("cue_handler", &self.cue_handler.is_some())
+            .finish()
+    }
+}
+
+/// Breaks still to play in the current presentation.
+#[derive(Debug, Default)]
+pub struct AdScheduler {
+    pending: Vec<AdBreak>,
+}
+
+impl AdScheduler {
+    pub fn new(breaks: Vec<AdBreak>) -> Self {
+        Self { pending: breaks }
+    }
+
+    pub fn schedule(&mut self, ad_break: AdBreak) {
+        self.pending.push(ad_break);
+    }
+
+    /// Takes the next pre-roll.
+    pub fn pre_roll(&mut self) -> Option<AdBreak> {
+        self.take_first(|position| position == AdPosition::PreRoll)
+    }
+
+    /// Takes the next post-roll.
+    pub fn post_roll(&mut self) -> Option<AdBreak> {
+        self.take_first(|position| position == AdPosition::PostRoll)
+    }
+
+    /// Takes the latest mid-roll at or before `position`. Earlier ones are dropped, so seeking
+    /// over several breaks only plays the last of them.
+    pub fn due(&mut self, position: f64) -> Option<AdBreak> {
+        let latest = self
+            .pending
+            .iter()
+            .filter_map(|ad_break| match ad_break.position {
+                AdPosition::MidRoll(start) if start <= position => Some(start),
+                _ => None,
+            })
+            .reduce(f64::max)?;
+
+        let due = self.at(latest);
+        self.pending.retain(
+            |ad_break| !matches!(ad_break.position, AdPosition::MidRoll(start) if start < latest),
+        );
+
+        due
+    }
+
+    /// Takes the next mid-roll at exactly `position`, for breaks playing back to back.
+    pub fn at(&mut self, position: f64) -> Option<AdBreak> {
+        self.take_first(|candidate| candidate == AdPosition::MidRoll(position))
+    }
+
+    fn take_first(&mut self, matches: impl Fn(AdPosition) -> bool) -> Option<AdBreak> {
+        let index = self
+            .pending
+            .iter()
+            .position(|ad_break| matches(ad_break.position))?;
+
+        Some(self.pending.remove(index))
+    }
+}
@@ -1,46 +1,353 @@
+use crate::error::AshinaError;
+use crate::loader::HttpLoader;
+use crate::loader::LoadError;
+use crate::loader::Loader;
+use crate::loader::RequestKind;
+use crate::loader::RequestPriority;
+use crate::loader::SegmentRequest;
+use crate::log::log;
+use crate::log::Logger;
+use crate::manifest::RepresentationId;
+use crate::manifest::SegmentIndex;
+use crate::manifest::SegmentUrlTemplate;
 use crate::manifest::Track;
+use crate::manifest::TrackId;
+use crate::operations::Operation;
+use crate::operations::OperationQueue;
+use crate::parse::cea::CaptionFormat;
+use crate::parse::yield_to_browser;
+use crate::parse::ChunkParser;
+use crate::parse::InitMetadata;
 use crate::parse::SegmentMetadata;
-use crate::player::BoxError;
 use crate::player::Error;
+use crate::player::InternalEvent;
 use crate::range::NRangeInclusive;
 
+use wasm_bindgen::closure::Closure;
 use wasm_bindgen::JsCast;
+use wasm_bindgen::JsValue;
 use web_sys::MediaSource;
 use web_sys::SourceBuffer;
 
 use core::future::Future;
 use core::ops::RangeInclusive;
+use core::time::Duration;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
 
+use bytes::Bytes;
+use futures::StreamExt;
 use url::Url;
 
-// default segment duration in case the dash template has no segment duration defined.
+/// Segment duration, in seconds, used when neither the segment template nor
+/// `MPD@maxSegmentDuration` define one.
 const SEGMENT_DURATION: f64 = 10.;
 
+/// Segments a [`SegmentMap`] remembers, the oldest being forgotten first. Plenty for hours of
+/// live streaming.
+const MAX_MAPPED_SEGMENTS: usize = 10_000;
+
+/// How far, in seconds, before the end of the presentation a segment may start and still count
+/// as starting at the end, so that rounding doesn't make for one more segment.
+const SEGMENT_END_TOLERANCE: f64 = 0.001;
+
+/// Media segments are requested with [`RequestPriority::Media`] while less than this many
+/// segments are buffered ahead of the playhead, and as prefetches beyond.
+const NEAR_PLAYHEAD_SEGMENTS: f64 = 2.;
+
+/// The start of a media segment whose download broke off, to be resumed with a range request
+/// instead of downloaded again.
+#[derive(Clone, Debug)]
+struct PartialSegment {
+    url: String,
+    data: Vec<u8>,
+    /// Length of the whole segment.
+    total: usize,
+}
+
+impl PartialSegment {
+    /// The whole segment, given the response to the range request for the rest of it. Servers
+    /// ignoring the range send the whole segment again. `None` if the lengths don't add up.
+    fn complete(mut self, rest: Bytes) -> Option<Vec<u8>> {
+        if rest.len() == self.total {
+            return Some(rest.to_vec());
+        }
+        if self.data.len() + rest.len() != self.total {
+            return None;
+        }
+
+        self.data.extend_from_slice(&rest);
+        Some(self.data)
+    }
+
+    /// Adds what arrived of a resumed download that broke off again, `total` being the length
+    /// of the response.
+    fn extend(mut self, received: Bytes, total: usize) -> Option<Self> {
+        if total == self.total {
+            self.data = received.to_vec();
+        } else if self.data.len() + total == self.total {
+            self.data.extend_from_slice(&received);
+        } else {
+            return None;
+        }
+
+        Some(self)
+    }
+}
+
+/// A media segment handed to the source buffer, awaiting its `updateend`.
+#[derive(Clone, Debug)]
+pub struct PendingAppend {
+    pub number: usize,
+    /// Presentation time of the first sample, in seconds.
+    pub pts: f64,
+    /// In seconds.
+    pub duration: f64,
+    pub bytes: usize,
+    /// When `appendBuffer` was called, as a JS timestamp in milliseconds.
+    pub started: f64,
+}
+
+/// The rendition each part of the buffered media was appended from, in element time, so that
+/// playback can be credited to the rendition playing rather than the one being fetched, which
+/// after a switch is a whole buffer ahead.
+#[derive(Clone, Debug, Default)]
+pub struct RenditionTimeline {
+    /// Disjoint spans, ordered by start.
+    spans: Vec<(RangeInclusive<f64>, Track)>,
+}
+
+impl RenditionTimeline {
+    /// Records that `track` was appended over `range`, replacing whatever was appended there
+    /// before.
+    pub fn insert(&mut self, range: RangeInclusive<f64>, track: &Track) {
+        let mut replaced = NRangeInclusive::new();
+        replaced.push(range.clone());
+
+        let mut spans: Vec<_> = std::mem::take(&mut self.spans)
+            .into_iter()
+            .flat_map(|(span, rendition)| {
+                let mut kept = NRangeInclusive::new();
+                kept.push(span);
+                kept.difference(&replaced)
+                    .into_iter()
+                    .filter(|part| part.end() > part.start())
+                    .map(move |part| (part, rendition.clone()))
+            })
+            .collect();
+        spans.push((range, track.clone()));
+        spans.sort_by(|(a, _), (b, _)| a.start().total_cmp(b.start()));
+
+        // Consecutive segments of one rendition make one span.
+        for (span, rendition) in spans {
+            match self.spans.last_mut() {
+                Some((last, previous))
+                    if previous.id() == rendition.id()
+                        && *span.start() - *last.end() <= SEGMENT_END_TOLERANCE =>
+                {
+                    *last = *last.start()..=last.end().max(*span.end());
+                }
+                _ => self.spans.push((span, rendition)),
+            }
+        }
+    }
+
+    /// The rendition appended at element time `time`, if any.
+    pub fn rendition_at(&self, time: f64) -> Option<&Track> {
+        self.spans
+            .iter()
+            .find(|(span, _)| span.contains(&time))
+            .map(|(_, rendition)| rendition)
+    }
+
+    /// Forgets the spans no longer buffered at all.
+    pub fn retain_buffered(&mut self, buffered: &NRangeInclusive<f64>) {
+        self.spans.retain(|(span, _)| {
+            buffered
+                .ranges()
+                .iter()
+                .any(|range| range.start() <= span.end() && span.start() <= range.end())
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.spans.clear();
+    }
+}
+
+/// Start and duration of the media segments seen so far, in seconds of media time, as read from
+/// their `sidx` or fragments. Lets seeks find the segment holding a time when segments vary in
+/// length, where the template's constant duration is only a guess.
+#[derive(Clone, Debug, Default)]
+pub struct SegmentMap {
+    segments: BTreeMap<usize, (f64, f64)>,
+}
+
+impl SegmentMap {
+    pub fn insert(&mut self, number: usize, pts: f64, duration: f64) {
+        self.segments.insert(number, (pts, duration));
+
+        if self.segments.len() > MAX_MAPPED_SEGMENTS {
+            self.segments.pop_first();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.segments.clear();
+    }
+
+    /// The segment holding media time `time` if it was seen, else an estimate from the seen
+    /// segments around it and their average duration. `None` while no segment was seen.
+    pub fn segment_at(&self, time: f64) -> Option<usize> {
+        let count = self.segments.len();
+        let average = self
+            .segments
+            .values()
+            .map(|(_, duration)| duration)
+            .sum::<f64>()
+            / count as f64;
+        if count == 0 || average <= 0. {
+            return None;
+        }
+
+        let before = self
+            .segments
+            .iter()
+            .rev()
+            .find(|(_, (pts, _))| *pts <= time);
+        let after = self.segments.iter().find(|(_, (pts, _))| *pts > time);
+
+        match (before, after) {
+            (Some((number, (pts, duration))), after) => {
+                let end = pts + duration;
+                if time < end {
+                    return Some(*number);
+                }
+
+                let estimate = number + 1 + ((time - end) / average).floor() as usize;
+                // Between two seen segments the estimate can't pass either of them.
+                Some(match after {
+                    Some((next, _)) => estimate.min(next.saturating_sub(1)).max(number + 1),
+                    None => estimate,
+                })
+            }
+            (None, Some((number, (pts, _)))) => {
+                Some(number.saturating_sub(((pts - time) / average).ceil() as usize))
+            }
+            (None, None) => None,
+        }
+    }
+}
+
 pub struct TrackBufferManager {
-    /// The base URL for this track
+    /// The MPD-level base URL for this track. Period, adaptation set and representation
+    /// `BaseURL`s are resolved against it.
     base_url: Url,
     /// Copy of the video track from the manifest
     track: Track,
+    /// The track's media template with representation-level identifiers already resolved
+    media_template: SegmentUrlTemplate,
     /// The source buffer for which we are responsible
     source_buffer: SourceBuffer,
+    /// Serializes appends on `source_buffer`
+    operations: OperationQueue,
+    /// Content type `source_buffer` currently accepts.
+    content_type: String,
     /// The last fetched segment
     current_segment: usize,
+    /// The segment number of the most recent fetch request
+    requested_segment: usize,
+    /// Whether an init or media segment fetch is currently in flight
+    fetching: bool,
+    /// Media segment fetches that failed in a row
+    failed_fetches: u32,
+    /// Media segment fetches that failed in a row, CDN switches notwithstanding
+    retries: u32,
+    /// Whether the init segment of the current representation still has to be appended
+    needs_init: bool,
     /// Reference to the media source
     media_source: MediaSource,
     /// The target render timestamp for the current video.
     current_time: f64,
+    /// End of the last appended segment, in presentation time after `timestampOffset`.
+    appended_end: Option<f64>,
+    /// Largest audio discontinuity between consecutive segments we conceal, in seconds.
+    gap_tolerance: f64,
+    /// Segment duration assumed when the template doesn't define one, in seconds.
+    fallback_segment_duration: f64,
+    /// Timing information from the init segment of the current representation.
+    init_metadata: InitMetadata,
+    /// Timing of the segments seen so far, see [`SegmentMap`].
+    segment_map: SegmentMap,
+    /// Renditions of the buffered media, see [`RenditionTimeline`].
+    renditions: RenditionTimeline,
+    /// How the current representation carries in-band captions, if it's H.264 or H.265 video.
+    caption_format: Option<CaptionFormat>,
+    /// Whether chunked segments are appended as they download.
+    low_latency: bool,
+    /// Segment to start a live stream with, consumed by the first fetch.
+    live_start: Option<usize>,
+    /// User requested shift of this track's presentation, in seconds, on top of whatever
+    /// `timestampOffset` gap concealment applies.
+    presentation_offset: f64,
+    /// `@presentationTimeOffset` currently taken out of the media timestamps by
+    /// `timestampOffset`. Representations of an adaptation set may differ in it.
+    applied_time_offset: f64,
+    /// Performs the init and media segment requests.
+    loader: Rc<dyn Loader>,
+    /// The media segment whose download last broke off, filled in by the fetch.
+    partial_segment: Rc<RefCell<Option<PartialSegment>>>,
+    /// Buffered ranges as of the last `updateend`, so that seeks and timeupdates don't have to
+    /// ask the source buffer every time.
+    buffered: NRangeInclusive<f64>,
+    /// The media segment currently being appended.
+    pending_append: Option<PendingAppend>,
+    /// `updateend` listener installed by [`Self::with_update_events`].
+    on_updateend: Option<Closure<dyn FnMut()>>,
+    logger: Logger,
 }
 
 impl TrackBufferManager {
     pub fn new(media_source: MediaSource, track: Track) -> Self {
-        let codec = format!("{}; codecs=\"{}\"", track.mime(), track.codecs());
-        let source_buffer = media_source.add_source_buffer(&codec).unwrap();
+        // The player only sets up tracks it checked with `isTypeSupported`.
+        let content_type = track.mime_type_with_codecs().unwrap_or_default();
+        let source_buffer = media_source.add_source_buffer(&content_type).unwrap();
+
+        // Media timestamps start at `@presentationTimeOffset`, the element's timeline at zero.
+        let applied_time_offset = track.presentation_time_offset();
+        source_buffer.set_timestamp_offset(-applied_time_offset);
 
         Self {
+            media_template: track.media_url_template(),
+            content_type,
             current_segment: 0,
+            requested_segment: 0,
+            fetching: false,
+            failed_fetches: 0,
+            retries: 0,
+            needs_init: true,
             base_url: Url::parse("http://127.0.0.1/").unwrap(),
             current_time: 0.,
+            appended_end: None,
+            gap_tolerance: 0.,
+            fallback_segment_duration: SEGMENT_DURATION,
+            init_metadata: InitMetadata::default(),
+            segment_map: SegmentMap::default(),
+            renditions: RenditionTimeline::default(),
+            caption_format: None,
+            low_latency: false,
+            live_start: None,
+            presentation_offset: 0.,
+            applied_time_offset,
+            loader: Rc::new(HttpLoader::default()),
+            partial_segment: Rc::default(),
+            buffered: NRangeInclusive::new(),
+            pending_append: None,
+            on_updateend: None,
+            logger: Logger::default(),
             track,
+            operations: OperationQueue::new(source_buffer.clone()),
             source_buffer,
             media_source,
         }
@@ -51,32 +358,344 @@ impl TrackBufferManager {
         self
     }
 
-    pub fn id(&self) -> String {
-        self.track.id()
+    pub fn with_loader(mut self, loader: Rc<dyn Loader>) -> Self {
+        self.loader = loader;
+        self
     }
 
-    pub fn cleanup(self) {
-        self.media_source
-            .remove_source_buffer(&self.source_buffer)
-            .unwrap();
+    pub fn with_logger(mut self, logger: Logger) -> Self {
+        self.logger = logger;
+        self
+    }
+
+    /// Appends segments of representations with `availabilityTimeComplete=false` while they
+    /// download instead of once complete.
+    pub fn with_low_latency(mut self, enabled: bool) -> Self {
+        self.low_latency = enabled;
+        self
     }
 
-    pub fn fetch_init_segment(&self) -> impl Future<Output = Result<Vec<u8>, BoxError>> {
-        let mut init_segment = self.track.initialization();
-        init_segment.set_id(self.id());
+    /// Starts at the live edge: the first fetch without a segment id asks for the newest
+    /// segment available `elapsed` seconds into the period.
+    pub fn start_at_live_edge(&mut self, elapsed: f64) {
+        let index = self.segment_index();
+        let segment = index.live_edge(elapsed, self.track.availability_time_offset());
 
-        let path = self.segment_path(&init_segment);
+        log!(
+            self.logger,
+            Live,
+            Info,
+            "Track {} starting at live edge, segment {segment}.",
+            self.track_id()
+        );
+        self.live_start = Some(segment);
+    }
 
-        async move { Ok(reqwest::get(path).await?.bytes().await?.to_vec()) }
+    /// Uses `MPD@maxSegmentDuration` for segment math when the template has no `@duration`.
+    pub fn with_max_segment_duration(mut self, duration: Option<Duration>) -> Self {
+        if let Some(duration) = duration.filter(|duration| !duration.is_zero()) {
+            self.fallback_segment_duration = duration.as_secs_f64();
+        }
+        self
     }
 
-    pub fn append_init_segment(&mut self, mut data: Vec<u8>) -> Result<(), BoxError> {
+    /// Sends [`InternalEvent::BufferUpdated`] whenever the source buffer finishes an append or
+    /// removal.
+    pub fn with_update_events(mut self, events: flume::Sender<InternalEvent>) -> Self {
+        let track = self.track_id().clone();
+        let on_updateend = Closure::<dyn FnMut()>::new(move || {
+            let _ = events.send(InternalEvent::BufferUpdated {
+                track: track.clone(),
+            });
+        });
+
         self.source_buffer
-            .append_buffer_with_u8_array(&mut data)
+            .add_event_listener_with_callback("updateend", on_updateend.as_ref().unchecked_ref())
             .unwrap();
+
+        self.on_updateend = Some(on_updateend);
+        self
+    }
+
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
+
+    /// Points future segment requests at another CDN.
+    pub fn set_base_url(&mut self, base_url: Url) {
+        self.base_url = base_url;
+    }
+
+    /// Shifts the track's presentation by `offset` seconds from the start. Meant for new
+    /// managers, before anything is appended.
+    pub fn with_presentation_offset(mut self, offset: f64) -> Self {
+        self.presentation_offset = offset;
+        self.source_buffer
+            .set_timestamp_offset(self.timeline_offset());
+        self
+    }
+
+    /// Changes the presentation shift of the track, e.g. to fix lip-sync. Media that was already
+    /// buffered carries the old timestamps, so it is dropped; the next fetch starts over at the
+    /// playhead.
+    pub async fn set_presentation_offset(&mut self, offset: f64) -> Result<(), Error> {
+        let delta = offset - self.presentation_offset;
+        if delta == 0. {
+            return Ok(());
+        }
+
+        self.presentation_offset = offset;
+
+        self.flush().await?;
+
+        let timestamp_offset = self.source_buffer.timestamp_offset() + delta;
+        self.operations
+            .push(Operation::SetTimestampOffset(timestamp_offset))
+            .await?;
+
+        self.appended_end = None;
+
+        Ok(())
+    }
+
+    /// Drops everything buffered, so the next fetch starts over at the playhead.
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        if !self.buffered.ranges().is_empty() {
+            self.operations
+                .push(Operation::Remove(0.0..=f64::INFINITY))
+                .await?;
+
+            // The next fetch has to see the flush to start over at the playhead.
+            self.refresh_buffered();
+        }
+
+        self.appended_end = None;
+
+        Ok(())
+    }
+
+    /// Starts the track over after a failed append: aborts the current append, which also
+    /// resets the parser, drops everything buffered and asks for the init segment again.
+    pub async fn reset(&mut self) -> Result<(), Error> {
+        self.source_buffer
+            .abort()
+            .map_err(|_| Error::InvalidState)?;
+        self.pending_append = None;
+        self.flush().await?;
+        self.needs_init = true;
+
+        Ok(())
+    }
+
+    pub fn with_gap_tolerance(mut self, tolerance: f64) -> Self {
+        self.gap_tolerance = tolerance;
+        self
+    }
+
+    pub fn id(&self) -> RepresentationId {
+        self.track.id()
+    }
+
+    pub fn track_id(&self) -> &TrackId {
+        self.track.track_id()
+    }
+
+    pub fn track(&self) -> &Track {
+        &self.track
+    }
+
+    /// Switches to another representation of the same adaptation set. Its init segment has to be
+    /// appended before the next media segment, see [`Self::needs_init`].
+    pub fn switch_track(&mut self, track: Track) {
+        // Segments line up across an adaptation set, unless their media times are offset.
+        if track.presentation_time_offset() != self.track.presentation_time_offset() {
+            self.segment_map.clear();
+        }
+        self.update_track(track);
+        self.needs_init = true;
+    }
+
+    pub fn needs_init(&self) -> bool {
+        self.needs_init
+    }
+
+    /// Swaps in the same representation from a refreshed manifest.
+    pub fn update_track(&mut self, track: Track) {
+        self.media_template = track.media_url_template();
+        self.track = track;
+    }
+
+    /// Makes the source buffer accept the codecs of the current representation, after a switch
+    /// to one with different codecs. Uses `changeType` where the browser has it, and otherwise
+    /// replaces the source buffer, which drops everything buffered for this track.
+    async fn ensure_content_type(&mut self) -> Result<(), Error> {
+        let content_type = self.track.mime_type_with_codecs().map_err(|error| {
+            log!(
+                self.logger,
+                Buffer,
+                Error,
+                "Can't switch to representation: {error}."
+            );
+            Error::UnsupportedCodec
+        })?;
+        if content_type == self.content_type {
+            return Ok(());
+        }
+
+        log!(
+            self.logger,
+            Buffer,
+            Info,
+            "Switching codecs from {} to {content_type}.",
+            self.content_type
+        );
+
+        if supports_change_type(&self.source_buffer) {
+            self.operations
+                .push(Operation::ChangeType(content_type.clone()))
+                .await?;
+        } else {
+            self.recreate_source_buffer(&content_type)?;
+        }
+
+        self.content_type = content_type;
+        Ok(())
+    }
+
+    fn recreate_source_buffer(&mut self, content_type: &str) -> Result<(), Error> {
+        log!(
+            self.logger,
+            Buffer,
+            Warn,
+            "changeType unsupported, recreating source buffer."
+        );
+
+        if let Some(listener) = &self.on_updateend {
+            let _ = self.source_buffer.remove_event_listener_with_callback(
+                "updateend",
+                listener.as_ref().unchecked_ref(),
+            );
+        }
+
+        let _ = self.media_source.remove_source_buffer(&self.source_buffer);
+        let source_buffer = self
+            .media_source
+            .add_source_buffer(content_type)
+            .map_err(|error| {
+                log!(
+                    self.logger,
+                    Buffer,
+                    Error,
+                    "Failed to recreate source buffer for {content_type}: {error:?}."
+                );
+                Error::UnsupportedCodec
+            })?;
+
+        if let Some(listener) = &self.on_updateend {
+            let _ = source_buffer
+                .add_event_listener_with_callback("updateend", listener.as_ref().unchecked_ref());
+        }
+
+        self.applied_time_offset = self.track.presentation_time_offset();
+        source_buffer.set_timestamp_offset(self.timeline_offset());
+
+        let previous = std::mem::replace(
+            &mut self.operations,
+            OperationQueue::new(source_buffer.clone()),
+        );
+        previous.close();
+
+        self.source_buffer = source_buffer;
+        self.buffered = NRangeInclusive::new();
+        self.renditions.clear();
+        self.appended_end = None;
+        self.pending_append = None;
+
+        Ok(())
+    }
+
+    pub fn cleanup(self) {
+        self.operations.close();
+
+        if let Some(listener) = self.on_updateend {
+            let _ = self.source_buffer.remove_event_listener_with_callback(
+                "updateend",
+                listener.as_ref().unchecked_ref(),
+            );
+        }
+
+        let _ = self.media_source.remove_source_buffer(&self.source_buffer);
+    }
+
+    pub fn fetch_init_segment(
+        &mut self,
+    ) -> impl Future<Output = Result<Vec<u8>, AshinaError>> + use<> {
+        self.fetching = true;
+
+        let path = self.init_segment_url(&self.base_url);
+        let request = self.loader.fetch(
+            SegmentRequest::new(path, RequestKind::Init).with_track(self.track_id().clone()),
+        );
+
+        async move { Ok(request.await?.to_vec()) }
+    }
+
+    pub async fn append_init_segment(&mut self, data: Vec<u8>) -> Result<(), AshinaError> {
+        self.init_metadata = InitMetadata::parse(&data);
+        self.caption_format = match self.track.is_video() {
+            true => CaptionFormat::parse(&data),
+            false => None,
+        };
+        self.ensure_content_type().await?;
+        self.apply_time_offset().await?;
+        self.operations.push(Operation::Append(data)).await?;
+        self.needs_init = false;
+        Ok(())
+    }
+
+    /// Moves `timestampOffset` along when the current representation has a different
+    /// `@presentationTimeOffset` than the media appended so far.
+    async fn apply_time_offset(&mut self) -> Result<(), Error> {
+        let time_offset = self.track.presentation_time_offset();
+        let delta = self.applied_time_offset - time_offset;
+        if delta == 0. {
+            return Ok(());
+        }
+
+        log!(
+            self.logger,
+            Buffer,
+            Info,
+            "Presentation time offset of {} changed to {time_offset}.",
+            self.track_id()
+        );
+        self.applied_time_offset = time_offset;
+
+        let timestamp_offset = self.source_buffer.timestamp_offset() + delta;
+        self.operations
+            .push(Operation::SetTimestampOffset(timestamp_offset))
+            .await?;
+        self.appended_end = None;
+
         Ok(())
     }
 
+    /// Difference between media element time and the media timestamps of the current
+    /// representation: the presentation shift minus `@presentationTimeOffset`, so the period
+    /// starts at zero on the element's timeline.
+    fn timeline_offset(&self) -> f64 {
+        self.presentation_offset - self.track.presentation_time_offset()
+    }
+
+    pub fn caption_format(&self) -> Option<CaptionFormat> {
+        self.caption_format
+    }
+
+    /// Media element time of media time `time`.
+    pub fn element_time(&self, time: f64) -> f64 {
+        time + self.timeline_offset()
+    }
+
     /// Method sets the current time of seek to `time` and returns a boolean indicating whether the
     /// timestamp is within the buffered range of time or not. This is meant to be used as an
     /// indication of whether we need to ask the player to fetch the next segment or not for the
@@ -91,48 +710,242 @@ impl TrackBufferManager {
     pub fn fetch_segment(
         &mut self,
         segment_id: Option<usize>,
-    ) -> impl Future<Output = Result<Vec<u8>, Error>> {
+    ) -> impl Future<Output = Result<Vec<u8>, Error>> + use<> {
+        let segment = self.next_segment(segment_id);
+        let path = self.segment_path(&self.media_template.number(segment));
+
+        // A download of a different segment, rendition or CDN that broke off is dropped.
+        let partial = self
+            .partial_segment
+            .borrow_mut()
+            .take()
+            .filter(|partial| partial.url == path);
+        let mut request = self.media_request(path.clone(), segment);
+        match &partial {
+            Some(partial) => {
+                let offset = partial.data.len();
+                log!(
+                    self.logger,
+                    Network,
+                    Debug,
+                    "Resuming segment {path} from byte {offset}."
+                );
+                request.add_header("Range", format!("bytes={offset}-"));
+            }
+            None => log!(self.logger, Network, Debug, "Fetching segment {path}."),
+        }
+
+        let request = self.loader.fetch(request);
+        let partial_segment = self.partial_segment.clone();
+
+        async move {
+            match (request.await, partial) {
+                (Ok(data), None) => Ok(data.to_vec()),
+                (Ok(rest), Some(partial)) => partial.complete(rest).ok_or(Error::DataError),
+                (
+                    Err(LoadError::Interrupted {
+                        received, total, ..
+                    }),
+                    partial,
+                ) => {
+                    *partial_segment.borrow_mut() = match partial {
+                        Some(partial) => partial.extend(received, total),
+                        None => Some(PartialSegment {
+                            url: path,
+                            data: received.to_vec(),
+                            total,
+                        }),
+                    };
+                    Err(Error::FetchError)
+                }
+                // A server refusing the range won't accept it on the next attempt either.
+                (Err(error @ LoadError::HttpStatus(_)), _) => Err(fetch_error(error)),
+                (Err(error), partial) => {
+                    *partial_segment.borrow_mut() = partial;
+                    Err(fetch_error(error))
+                }
+            }
+        }
+    }
+
+    /// Like [`Self::fetch_segment`], but appends the segment chunk by chunk while it downloads,
+    /// for low-latency streams where the segment is still being produced. Resolves with the
+    /// number of bytes appended once the segment is complete; finish up with
+    /// [`Self::segment_streamed`].
+    pub fn stream_segment(
+        &mut self,
+        segment_id: Option<usize>,
+    ) -> impl Future<Output = Result<usize, Error>> + use<> {
+        let segment = self.next_segment(segment_id);
+        let path = self.segment_path(&self.media_template.number(segment));
+
+        log!(self.logger, Network, Debug, "Streaming segment {path}.");
+        let mut chunks = self.loader.fetch_stream(self.media_request(path, segment));
+        let operations = self.operations.handle();
+
+        async move {
+            let mut bytes = 0;
+            let mut parser = ChunkParser::default();
+
+            while let Some(chunk) = chunks.next().await {
+                let chunk = chunk.map_err(fetch_error)?;
+                bytes += chunk.len();
+
+                // Only complete CMAF chunks go in, so that a transfer breaking off doesn't leave
+                // half a box in the source buffer's parser.
+                for complete in parser.push(&chunk) {
+                    operations.push(Operation::Append(complete)).await?;
+                }
+            }
+
+            let rest = parser.finish();
+            if !rest.is_empty() {
+                operations.push(Operation::Append(rest)).await?;
+            }
+
+            Ok(bytes)
+        }
+    }
+
+    /// Bookkeeping after [`Self::stream_segment`] appended the whole requested segment. Returns
+    /// what was appended.
+    pub fn segment_streamed(&mut self, bytes: usize, started: f64) -> PendingAppend {
+        let index = self.segment_index();
+
+        self.current_segment = self.requested_segment;
+        // The segment wasn't parsed, so there's nothing to conceal gaps against.
+        self.appended_end = None;
+
+        let pts = index.segment_start(self.requested_segment);
+        let start = self.element_time(pts);
+        self.renditions
+            .insert(start..=start + index.duration(), &self.track);
+
+        PendingAppend {
+            number: self.requested_segment,
+            pts,
+            duration: index.duration(),
+            bytes,
+            started,
+        }
+    }
+
+    /// Whether segments are appended while they download, see [`Self::with_low_latency`].
+    pub fn streams_segments(&self) -> bool {
+        self.low_latency && !self.track.availability_time_complete()
+    }
+
+    /// Picks the segment to fetch next and marks it as requested.
+    fn next_segment(&mut self, segment_id: Option<usize>) -> usize {
         let segment = if !self.buffered().contains(&self.current_time) {
             // We are buffering, so we fetch the current_time segment or the segment id passed in.
-            let target = segment_id.unwrap_or_else(|| self.segment_for_ts(self.current_time));
-            tracing::info!(
-                target,
-                current = self.current_time,
-                "Guessing segment because of hard seek."
+            let target = segment_id
+                .or(self.live_start.take())
+                .unwrap_or_else(|| self.segment_for_ts(self.current_time - self.timeline_offset()));
+            log!(
+                self.logger,
+                Buffer,
+                Debug,
+                "Guessing segment {target} because of hard seek to {}.",
+                self.current_time
             );
             target
         } else {
             // We are not buffering so we can continue fetching the next segment
             let target = self.current_segment + 1;
-            tracing::info!(target, "Asking for segment.");
+            log!(self.logger, Buffer, Debug, "Asking for segment {target}.");
             target
         };
 
-        let mut path = self.track.media();
-        path.set_id(self.id());
-        path.set_number(segment);
+        self.requested_segment = segment;
+        self.fetching = true;
 
-        let path = self.segment_path(&path);
+        segment
+    }
 
-        async move {
-            tracing::info!(?path, "Fetching segment.");
-            let request = reqwest::get(path).await.map_err(|_| Error::FetchError)?;
+    pub fn is_fetching(&self) -> bool {
+        self.fetching
+    }
 
-            if request.status() != reqwest::StatusCode::OK {
-                return Err(Error::HttpCode);
-            }
+    /// URLs of the `count` media segments after the one last requested.
+    pub fn upcoming_segment_urls(&self, count: usize) -> Vec<String> {
+        (self.requested_segment + 1..=self.requested_segment + count)
+            .map(|segment| self.segment_path(&self.media_template.number(segment)))
+            .collect()
+    }
+
+    /// Marks the in-flight fetch started by [`Self::fetch_segment`] or
+    /// [`Self::fetch_init_segment`] as done.
+    pub fn fetch_finished(&mut self) {
+        self.fetching = false;
+    }
 
-            let data = request
-                .bytes()
-                .await
-                .map_err(|_| Error::DataError)?
-                .to_vec();
+    /// Counts a failed media segment fetch. Returns the number of failures in a row.
+    pub fn fetch_failed(&mut self) -> u32 {
+        self.failed_fetches += 1;
+        self.retries += 1;
+        self.failed_fetches
+    }
 
-            Ok(data)
-        }
+    pub fn fetch_succeeded(&mut self) {
+        self.failed_fetches = 0;
+        self.retries = 0;
+    }
+
+    /// Starts counting failures towards the next failover over, after moving to another CDN.
+    pub fn switched_cdn(&mut self) {
+        self.failed_fetches = 0;
+    }
+
+    /// Media segment fetches that failed since the last successful one, on any CDN.
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    /// Whether the segment [`Self::fetch_segment`] would fetch next, given the same
+    /// `segment_id`, starts at or after media element time `end`.
+    pub fn next_segment_starts_after(&self, segment_id: Option<usize>, end: f64) -> bool {
+        let segment = if !self.buffered().contains(&self.current_time) {
+            segment_id
+                .or(self.live_start)
+                .unwrap_or_else(|| self.segment_for_ts(self.current_time - self.timeline_offset()))
+        } else {
+            self.current_segment + 1
+        };
+
+        let start = self.element_time(self.segment_index().segment_start(segment));
+        start + SEGMENT_END_TOLERANCE >= end
+    }
+
+    /// Media time at which the most recently requested segment starts, as far as the template
+    /// tells.
+    pub fn requested_segment_start(&self) -> f64 {
+        self.segment_index().segment_start(self.requested_segment)
     }
 
-    pub fn buffered(&self) -> NRangeInclusive<f64> {
+    /// Whether the segment requested last is the one holding media element time `time`.
+    pub fn requested_covers(&self, time: f64) -> bool {
+        self.segment_for_ts(time - self.timeline_offset()) == self.requested_segment
+    }
+
+    /// The segment number requested by the most recent [`Self::fetch_segment`] call.
+    pub fn requested_segment(&self) -> usize {
+        self.requested_segment
+    }
+
+    /// Buffered ranges as of the last [`Self::refresh_buffered`].
+    pub fn buffered(&self) -> &NRangeInclusive<f64> {
+        &self.buffered
+    }
+
+    /// The rendition the media buffered at element time `time` was appended from.
+    pub fn rendition_at(&self, time: f64) -> Option<&Track> {
+        self.renditions.rendition_at(time)
+    }
+
+    /// Re-reads the buffered ranges from the source buffer. Returns the previous ranges if they
+    /// changed.
+    pub fn refresh_buffered(&mut self) -> Option<NRangeInclusive<f64>> {
         let mut range = NRangeInclusive::new();
 
         let ranges = self.source_buffer.buffered().unwrap();
@@ -144,74 +957,404 @@ impl TrackBufferManager {
             range.push(start..=end);
         }
 
-        range
+        range.normalize();
+        self.renditions.retain_buffered(&range);
+
+        if range == self.buffered {
+            return None;
+        }
+
+        Some(core::mem::replace(&mut self.buffered, range))
+    }
+
+    /// The media segment whose append just finished, to be called on `updateend`.
+    pub fn take_completed_append(&mut self) -> Option<PendingAppend> {
+        self.pending_append.take()
     }
 
     pub fn is_buffering(&self) -> bool {
         !self.buffered().contains(&self.current_time)
     }
 
-    pub async fn append_segment(&mut self, mut segment: Vec<u8>) -> Result<(), Error> {
-        let metadata = SegmentMetadata::parse(&segment).expect("Failed to parse segment.");
+    pub async fn append_segment(&mut self, segment: Vec<u8>) -> Result<(), Error> {
+        yield_to_browser(segment.len()).await;
+        let metadata = SegmentMetadata::parse(&segment, &self.init_metadata).map_err(|error| {
+            log!(
+                self.logger,
+                Buffer,
+                Error,
+                "Failed to parse segment: {error}."
+            );
+            Error::DataError
+        })?;
 
-        tracing::info!(?metadata, "New segment...");
+        log!(self.logger, Buffer, Debug, "New segment {metadata:?}.");
+        self.segment_map.insert(
+            metadata.segment_number,
+            metadata.pts(),
+            metadata.duration().as_secs_f64(),
+        );
 
+        let mut seek_target = None;
         if self.is_buffering() {
-            let segment_range = RangeInclusive::new(
-                metadata.pts(),
-                metadata.pts() + metadata.duration().as_secs_f64(),
-            );
+            let start = self.element_time(metadata.pts());
+            let segment_range =
+                RangeInclusive::new(start, start + metadata.duration().as_secs_f64());
 
-            tracing::info!(
-                start = segment_range.start(),
-                end = segment_range.end(),
-                "Segment range."
+            log!(
+                self.logger,
+                Buffer,
+                Debug,
+                "Segment range {segment_range:?}."
             );
             if !segment_range.contains(&self.current_time) {
-                // The segment we are attempting to append does not contain our requested timestamp
-                let next_segment = if self.current_time < metadata.pts() {
-                    metadata.segment_number - 1
+                // The segment we are attempting to append does not contain our requested
+                // timestamp. Knowing its timing, the map may tell the right one straight away.
+                let target = self.segment_for_ts(self.current_time - self.timeline_offset());
+                let next_segment = if target != metadata.segment_number {
+                    target
+                } else if self.current_time < start {
+                    // Never step back past the first segment.
+                    metadata
+                        .segment_number
+                        .saturating_sub(1)
+                        .max(self.segment_index().start_number())
                 } else {
                     metadata.segment_number + 1
                 };
 
                 return Err(Error::OutOfRange { next_segment });
             }
+
+            seek_target = Some(self.current_time);
         }
 
-        // NOTE: Don't be tempted to use append_buffer_async_* as no browsers support this.
-        if let Err(error) = self.source_buffer.append_buffer_with_u8_array(&mut segment) {
-            let Ok(error) = error.dyn_into::<js_sys::Error>() else {
-                panic!("Weird error mhmmm.");
-            };
+        let contiguous = metadata.segment_number == self.current_segment + 1;
+        if self.track.is_audio() && contiguous {
+            self.conceal_audio_gap(&metadata);
+        }
 
-            let name = error.name().as_string().unwrap();
+        let bytes = segment.len();
+        let pending = PendingAppend {
+            number: metadata.segment_number,
+            pts: metadata.pts(),
+            duration: metadata.duration().as_secs_f64(),
+            bytes,
+            started: js_sys::Date::now(),
+        };
 
-            match name.as_str() {
-                "QuotaExceededError" => return Err(Error::QuotaExceededError),
-                error => {
-                    tracing::error!(?error, "Weird error");
-                    // TODO: Handle InvalidStateError
-                    return Err(Error::QuotaExceededError);
-                }
-            }
+        // Every audio frame is a random access point, so the append window can cut the segment
+        // exactly at the seek target. Video is appended whole: frames before the window are
+        // dropped up to the next keyframe, which usually means the entire segment.
+        let trim_start = seek_target.filter(|_| self.track.is_audio());
+        if let Some(start) = trim_start {
+            log!(
+                self.logger,
+                Buffer,
+                Debug,
+                "Trimming segment to seek target {start}."
+            );
+            self.operations
+                .push(Operation::SetAppendWindowStart(start.max(0.)))
+                .await?;
+        }
+
+        self.pending_append = Some(pending);
+        let appended = self.operations.push(Operation::Append(segment)).await;
+
+        if trim_start.is_some() {
+            self.operations
+                .push(Operation::SetAppendWindowStart(0.))
+                .await?;
+        }
+
+        if let Err(error) = appended {
+            self.pending_append = None;
+            return Err(error);
         }
 
         self.current_segment = metadata.segment_number;
+        let start = self.element_time(metadata.pts());
+        self.renditions.insert(
+            start..=start + metadata.duration().as_secs_f64(),
+            &self.track,
+        );
+        self.appended_end = Some(
+            metadata.pts()
+                + metadata.duration().as_secs_f64()
+                + self.source_buffer.timestamp_offset(),
+        );
 
         Ok(())
     }
 
+    /// Shifts the timestamps of the incoming audio segment so that it starts exactly where the
+    /// previous one ended, when the two are off by less than the gap tolerance. Period boundaries
+    /// in stitched streams routinely leave a few milliseconds of silence or overlap between
+    /// segments, which is heard as a click if appended as-is.
+    fn conceal_audio_gap(&mut self, metadata: &SegmentMetadata) {
+        let Some(previous_end) = self.appended_end else {
+            return;
+        };
+
+        let offset = self.source_buffer.timestamp_offset();
+        let delta = metadata.pts() + offset - previous_end;
+
+        if delta == 0. || delta.abs() > self.gap_tolerance {
+            return;
+        }
+
+        // timestampOffset can't be changed while an operation is still being processed.
+        if self.source_buffer.updating() {
+            log!(
+                self.logger,
+                Buffer,
+                Warn,
+                "Source buffer busy, not concealing audio gap of {delta}."
+            );
+            return;
+        }
+
+        log!(
+            self.logger,
+            Buffer,
+            Info,
+            "Concealing audio discontinuity of {delta}."
+        );
+        self.source_buffer.set_timestamp_offset(offset - delta);
+    }
+
     /// Method attempts to guess the segment index for the segment to fetch during a seek. This
     /// needs to be somewhat accurate, but it doesnt have to be as we can bruteforce search
-    /// forwards or backwards depending on the real ts that the returned segment has.
+    /// forwards or backwards depending on the real ts that the returned segment has. Segments
+    /// seen before are found through the [`SegmentMap`], the template only guesses.
     fn segment_for_ts(&self, ts: f64) -> usize {
-        let segment_length = self.track.segment_duration().unwrap();
-        ((ts / segment_length) + 1.0) as _
+        let index = self.segment_index();
+
+        self.segment_map
+            .segment_at(ts)
+            .map(|segment| segment.max(index.start_number()))
+            .unwrap_or_else(|| index.segment_at(ts))
+    }
+
+    fn segment_index(&self) -> SegmentIndex {
+        self.track.segment_index().unwrap_or_else(|| {
+            SegmentIndex::new(
+                self.track.start_number(),
+                self.fallback_segment_duration,
+                self.track.presentation_time_offset(),
+            )
+        })
+    }
+
+    /// Duration of a segment of the current representation, in seconds.
+    pub fn segment_duration(&self) -> f64 {
+        self.track
+            .segment_duration()
+            .unwrap_or(self.fallback_segment_duration)
+    }
+
+    /// Seconds of media buffered contiguously ahead of the current time.
+    pub fn buffered_ahead(&self) -> f64 {
+        self.buffered
+            .ranges()
+            .iter()
+            .find(|range| range.contains(&self.current_time))
+            .map_or(0., |range| range.end() - self.current_time)
+    }
+
+    /// Where the init segment of the current representation lives on the CDN at `base_url`.
+    pub fn init_segment_url(&self, base_url: &Url) -> String {
+        init_segment_url(&self.track, base_url)
+    }
+
+    fn media_request(&self, path: String, segment: usize) -> SegmentRequest {
+        let priority = if self.is_buffering() {
+            RequestPriority::Urgent
+        } else if self.buffered_ahead() < NEAR_PLAYHEAD_SEGMENTS * self.segment_duration() {
+            RequestPriority::Media
+        } else {
+            RequestPriority::Prefetch
+        };
+
+        SegmentRequest::new(path, RequestKind::Media)
+            .with_track(self.track_id().clone())
+            .with_segment(segment)
+            .with_priority(priority)
     }
 
     fn segment_path(&self, path: &impl AsRef<str>) -> String {
-        let base = self.base_url.as_str().to_string();
-        format!("{base}/{}", path.as_ref())
+        resolve_segment_url(&self.track.base_url(&self.base_url), path.as_ref())
+    }
+}
+
+/// Where the init segment of `track` lives on the CDN at `base_url`.
+pub fn init_segment_url(track: &Track, base_url: &Url) -> String {
+    let mut init_segment = track.initialization();
+    track.resolve_identifiers(&mut init_segment);
+
+    resolve_segment_url(&track.base_url(base_url), init_segment.as_ref())
+}
+
+/// Whether the browser implements `SourceBuffer.changeType`.
+fn supports_change_type(source_buffer: &SourceBuffer) -> bool {
+    js_sys::Reflect::has(source_buffer, &JsValue::from_str("changeType")).unwrap_or(false)
+}
+
+fn fetch_error(error: LoadError) -> Error {
+    match error {
+        LoadError::Network(_) | LoadError::Signing(_) | LoadError::Interrupted { .. } => {
+            Error::FetchError
+        }
+        LoadError::HttpStatus(_) => Error::HttpCode,
+        LoadError::Body(_) => Error::DataError,
+        LoadError::Timeout(_) => Error::Timeout,
+    }
+}
+
+fn resolve_segment_url(base: &Url, path: &str) -> String {
+    match base.join(path) {
+        Ok(url) => url.into(),
+        Err(error) => {
+            tracing::warn!(%base, path, ?error, "Failed to resolve segment URL.");
+            format!("{base}{path}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::manifest::Manifest;
+
+    const MPD: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT1M"
+    minBufferTime="PT2S" profiles="urn:mpeg:dash:profile:isoff-live:2011">
+  <Period id="0" start="PT0S">
+    <AdaptationSet mimeType="video/mp4" segmentAlignment="true">
+      <SegmentTemplate timescale="1000" duration="2000" startNumber="1"
+          initialization="$RepresentationID$/init.mp4" media="$RepresentationID$/$Number$.m4s"/>
+      <Representation id="low" codecs="avc1.64001f" bandwidth="500000" width="640" height="360"/>
+      <Representation id="high" codecs="avc1.64001f" bandwidth="3000000" width="1920" height="1080"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+
+    fn renditions() -> (Track, Track) {
+        let manifest: Manifest = MPD.parse().unwrap();
+        let tracks = manifest.tracks();
+        let find = |id: &str| {
+            tracks
+                .iter()
+                .find(|track| track.id().as_str() == id)
+                .unwrap()
+                .clone()
+        };
+
+        (find("low"), find("high"))
+    }
+
+    #[test]
+    fn rendition_timeline_credits_the_rendition_appended_at_a_time() {
+        let (low, high) = renditions();
+        let mut timeline = RenditionTimeline::default();
+
+        timeline.insert(0. ..=2., &low);
+        timeline.insert(2. ..=4., &low);
+        // A switch only shows once playback reaches the new rendition's segments.
+        timeline.insert(4. ..=6., &high);
+
+        assert_eq!(timeline.rendition_at(3.).map(Track::id), Some(low.id()));
+        assert_eq!(timeline.rendition_at(5.).map(Track::id), Some(high.id()));
+        assert!(timeline.rendition_at(7.).is_none());
+        assert_eq!(timeline.spans.len(), 2);
+    }
+
+    #[test]
+    fn rendition_timeline_replaces_media_appended_again() {
+        let (low, high) = renditions();
+        let mut timeline = RenditionTimeline::default();
+
+        timeline.insert(0. ..=6., &low);
+        timeline.insert(2. ..=4., &high);
+
+        assert_eq!(timeline.rendition_at(1.).map(Track::id), Some(low.id()));
+        assert_eq!(timeline.rendition_at(3.).map(Track::id), Some(high.id()));
+        assert_eq!(timeline.rendition_at(5.).map(Track::id), Some(low.id()));
+    }
+
+    #[test]
+    fn rendition_timeline_forgets_evicted_media() {
+        let (low, high) = renditions();
+        let mut timeline = RenditionTimeline::default();
+        timeline.insert(0. ..=2., &low);
+        timeline.insert(2. ..=4., &high);
+
+        let mut buffered = NRangeInclusive::new();
+        buffered.push(2.5..=4.);
+        timeline.retain_buffered(&buffered);
+
+        assert!(timeline.rendition_at(1.).is_none());
+        assert_eq!(timeline.rendition_at(3.).map(Track::id), Some(high.id()));
+    }
+
+    fn partial_segment(data: &[u8], total: usize) -> PartialSegment {
+        PartialSegment {
+            url: "low/1.m4s".into(),
+            data: data.to_vec(),
+            total,
+        }
+    }
+
+    #[test]
+    fn partial_segment_completes_with_the_rest() {
+        let partial = partial_segment(b"abc", 6);
+
+        assert_eq!(
+            partial.complete(Bytes::from_static(b"def")).as_deref(),
+            Some(&b"abcdef"[..])
+        );
+    }
+
+    #[test]
+    fn partial_segment_takes_a_whole_body_instead_of_the_range() {
+        let partial = partial_segment(b"abc", 6);
+
+        assert_eq!(
+            partial.complete(Bytes::from_static(b"ABCDEF")).as_deref(),
+            Some(&b"ABCDEF"[..])
+        );
+    }
+
+    #[test]
+    fn partial_segment_rejects_a_rest_of_the_wrong_length() {
+        assert!(partial_segment(b"abc", 6)
+            .complete(Bytes::from_static(b"de"))
+            .is_none());
+        assert!(partial_segment(b"abc", 6)
+            .complete(Bytes::from_static(b"defg"))
+            .is_none());
+    }
+
+    #[test]
+    fn partial_segment_extends_with_an_interrupted_resume() {
+        // The range response for the remaining 7 bytes broke off after 2.
+        let partial = partial_segment(b"abc", 10)
+            .extend(Bytes::from_static(b"de"), 7)
+            .unwrap();
+        assert_eq!(partial.data, b"abcde");
+        assert_eq!(partial.total, 10);
+
+        // A whole body that broke off replaces what arrived before.
+        let partial = partial_segment(b"abc", 10)
+            .extend(Bytes::from_static(b"ABCD"), 10)
+            .unwrap();
+        assert_eq!(partial.data, b"ABCD");
+
+        // A response for neither the rest nor the whole segment.
+        assert!(partial_segment(b"abc", 10)
+            .extend(Bytes::from_static(b"de"), 8)
+            .is_none());
     }
 }
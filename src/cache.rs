@@ -0,0 +1,185 @@
+use crate::loader::ChunkStream;
+use crate::loader::LoadFuture;
+use crate::loader::Loader;
+use crate::loader::RequestKind;
+use crate::loader::SegmentRequest;
+
+use core::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use bytes::Bytes;
+
+/// Identifies a cached response: the URL plus the byte range, for indexed segments that share a
+/// file.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct CacheKey {
+    url: String,
+    range: Option<String>,
+}
+
+impl CacheKey {
+    fn new(request: &SegmentRequest) -> Self {
+        let range = request
+            .headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("range"))
+            .map(|(_, value)| value.clone());
+
+        Self {
+            url: request.url.clone(),
+            range,
+        }
+    }
+}
+
+struct Entry {
+    data: Bytes,
+    /// Value of [`Inner::clock`] when the entry was last read or written.
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    capacity: usize,
+    size: usize,
+    clock: u64,
+    entries: HashMap<CacheKey, Entry>,
+    hits: u64,
+    misses: u64,
+}
+
+impl Inner {
+    fn evict_until_fits(&mut self, bytes: usize) {
+        while self.size + bytes > self.capacity {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                return;
+            };
+
+            if let Some(entry) = self.entries.remove(&oldest) {
+                self.size -= entry.data.len();
+            }
+        }
+    }
+}
+
+/// Segment bodies kept in memory, least recently used ones evicted first once the total size
+/// exceeds the capacity in bytes. Clones share the same entries and counters.
+#[derive(Clone, Default)]
+pub struct SegmentCache {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl SegmentCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Rc::new(RefCell::new(Inner {
+                capacity,
+                ..Inner::default()
+            })),
+        }
+    }
+
+    /// Cached body for `request`, counted as a hit or a miss.
+    pub fn get(&self, request: &SegmentRequest) -> Option<Bytes> {
+        let mut inner = self.inner.borrow_mut();
+        inner.clock += 1;
+        let clock = inner.clock;
+
+        match inner.entries.get_mut(&CacheKey::new(request)) {
+            Some(entry) => {
+                entry.last_used = clock;
+                let data = entry.data.clone();
+                inner.hits += 1;
+                Some(data)
+            }
+            None => {
+                inner.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Stores the body of `request`. Bodies larger than the whole cache are not kept.
+    pub fn insert(&self, request: &SegmentRequest, data: Bytes) {
+        let mut inner = self.inner.borrow_mut();
+        if data.len() > inner.capacity {
+            return;
+        }
+
+        let key = CacheKey::new(request);
+        if let Some(previous) = inner.entries.remove(&key) {
+            inner.size -= previous.data.len();
+        }
+
+        inner.evict_until_fits(data.len());
+        inner.clock += 1;
+        inner.size += data.len();
+
+        let last_used = inner.clock;
+        inner.entries.insert(key, Entry { data, last_used });
+    }
+
+    /// Bytes currently held.
+    pub fn size(&self) -> usize {
+        self.inner.borrow().size
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.inner.borrow().hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.inner.borrow().misses
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.inner.borrow_mut();
+        inner.entries.clear();
+        inner.size = 0;
+    }
+}
+
+/// Answers init and media segment requests from a [`SegmentCache`] when possible, so seeking
+/// back a few seconds or switching back to a rendition doesn't download the same segments again.
+/// Streamed low-latency segments are passed through.
+pub struct CachingLoader {
+    inner: Rc<dyn Loader>,
+    cache: SegmentCache,
+}
+
+impl CachingLoader {
+    pub fn new(inner: Rc<dyn Loader>, cache: SegmentCache) -> Self {
+        Self { inner, cache }
+    }
+}
+
+impl Loader for CachingLoader {
+    fn fetch(&self, request: SegmentRequest) -> LoadFuture {
+        if !matches!(request.kind, RequestKind::Init | RequestKind::Media) {
+            return self.inner.fetch(request);
+        }
+
+        if let Some(data) = self.cache.get(&request) {
+            return Box::pin(async move { Ok(data) });
+        }
+
+        let cache = self.cache.clone();
+        let fetch = self.inner.fetch(request.clone());
+
+        Box::pin(async move {
+            let data = fetch.await?;
+            cache.insert(&request, data.clone());
+            Ok(data)
+        })
+    }
+
+    fn fetch_stream(&self, request: SegmentRequest) -> ChunkStream {
+        self.inner.fetch_stream(request)
+    }
+}
@@ -0,0 +1,108 @@
+//! The Media Capabilities API, `navigator.mediaCapabilities`, where the browser has it. Unlike
+//! `MediaSource.isTypeSupported`, it knows about color, resolution and frame rate: HDR
+//! representations the device can't decode are left out of playback, as are the ones the display
+//! can't show if there is an SDR rendition to play instead, and automatic quality selection avoids the ones it can't decode smoothly, e.g. 4K at 60 fps
+//! on a laptop without a hardware decoder for the codec, see
+//! [`crate::config::AbrConfig::media_capabilities`].
+
+use crate::manifest::DynamicRange;
+use crate::manifest::Track;
+
+use wasm_bindgen::JsCast;
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::MediaCapabilities;
+use web_sys::MediaCapabilitiesInfo;
+use web_sys::MediaDecodingConfiguration;
+use web_sys::MediaDecodingType;
+use web_sys::VideoConfiguration;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// What `mediaCapabilities.decodingInfo` reports about a representation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecodingInfo {
+    pub supported: bool,
+    /// Playback at the representation's frame rate drops no frames.
+    pub smooth: bool,
+    /// Decoded in hardware, or otherwise light on the battery.
+    pub power_efficient: bool,
+}
+
+impl DecodingInfo {
+    /// Asks `mediaCapabilities.decodingInfo` about the video representation `track`, as played
+    /// through media source extensions. `None` where the browser can't tell, e.g. without the
+    /// API or for representations missing their codecs.
+    pub async fn query(track: &Track) -> Option<Self> {
+        let navigator = web_sys::window()?.navigator();
+        let capabilities: MediaCapabilities =
+            js_sys::Reflect::get(&navigator, &JsValue::from_str("mediaCapabilities"))
+                .ok()?
+                .dyn_into()
+                .ok()?;
+
+        let video = VideoConfiguration::new();
+        video.set_content_type(&track.mime_type_with_codecs().ok()?);
+        video.set_width(track.width()?.try_into().ok()?);
+        video.set_height(track.height()?.try_into().ok()?);
+        video.set_bitrate(track.bitrate()? as f64);
+        video.set_framerate(&track.frame_rate().unwrap_or(DEFAULT_FRAME_RATE).to_string());
+        set_color(&video, track);
+
+        let configuration = MediaDecodingConfiguration::new(MediaDecodingType::MediaSource);
+        configuration.set_video(&video);
+
+        let info: MediaCapabilitiesInfo =
+            JsFuture::from(capabilities.decoding_info(&configuration))
+                .await
+                .ok()?
+                .unchecked_into();
+
+        Some(Self {
+            supported: info.supported(),
+            smooth: info.smooth(),
+            power_efficient: info.power_efficient(),
+        })
+    }
+}
+
+/// Frame rate asked about for representations that don't declare theirs.
+const DEFAULT_FRAME_RATE: f64 = 30.;
+
+/// Whether the display can show high dynamic range, by the `dynamic-range` media query. `None`
+/// where the browser can't tell.
+pub fn hdr_display() -> Option<bool> {
+    web_sys::window()?
+        .match_media("(dynamic-range: high)")
+        .ok()
+        .flatten()
+        .map(|query| query.matches())
+}
+
+/// Sets the color members `VideoConfiguration` has no bindings for: `transferFunction`,
+/// `colorGamut` and `hdrMetadataType`.
+fn set_color(video: &VideoConfiguration, track: &Track) {
+    let dynamic_range = track.dynamic_range();
+    let transfer_function = match dynamic_range {
+        DynamicRange::Sdr => return,
+        DynamicRange::Hdr10 | DynamicRange::DolbyVision => "pq",
+        DynamicRange::Hlg => "hlg",
+    };
+    let color_gamut = match track.color_info().colour_primaries {
+        Some(1) => "srgb",
+        Some(11 | 12) => "p3",
+        _ => "rec2020",
+    };
+
+    let set = |name: &str, value: &str| {
+        let _ = js_sys::Reflect::set(video, &JsValue::from_str(name), &JsValue::from_str(value));
+    };
+    set("transferFunction", transfer_function);
+    set("colorGamut", color_gamut);
+    match dynamic_range {
+        DynamicRange::Hdr10 => set("hdrMetadataType", "smpteSt2086"),
+        DynamicRange::DolbyVision => set("hdrMetadataType", "smpteSt2094-10"),
+        _ => {}
+    }
+}
@@ -0,0 +1,121 @@
+use serde::Deserialize;
+use serde::Serialize;
+use url::Url;
+
+/// Weight of a new probe in the smoothed latency of a CDN.
+const LATENCY_SMOOTHING: f64 = 0.3;
+/// A CDN has to be this much faster than the current one, relative to its latency, before we
+/// move over, so that two CDNs with similar latency don't make us flap between them.
+const SWITCH_THRESHOLD: f64 = 0.2;
+/// Differences below this many milliseconds are noise and never cause a switch.
+const MIN_SWITCH_GAIN_MS: f64 = 20.;
+
+/// Why segment requests moved to another CDN.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CdnSwitchReason {
+    /// Another CDN answered probes consistently faster.
+    Latency,
+    /// Segment requests to the previous CDN kept failing.
+    Failover,
+}
+
+struct Candidate {
+    base_url: Url,
+    /// Smoothed time to first byte of the probe requests, in milliseconds.
+    latency: Option<f64>,
+}
+
+/// Picks which of the manifest's `BaseURL`s segments are fetched from, based on the latency of
+/// small probe requests. The selection is sticky: it only moves when another CDN is clearly
+/// faster.
+pub struct CdnSelector {
+    candidates: Vec<Candidate>,
+    current: usize,
+}
+
+impl CdnSelector {
+    /// `base_urls` in manifest order. The first one is used until probes say otherwise.
+    pub fn new(base_urls: Vec<Url>) -> Self {
+        Self {
+            candidates: base_urls
+                .into_iter()
+                .map(|base_url| Candidate {
+                    base_url,
+                    latency: None,
+                })
+                .collect(),
+            current: 0,
+        }
+    }
+
+    /// Whether there is more than one CDN to choose from.
+    pub fn has_alternatives(&self) -> bool {
+        self.candidates.len() > 1
+    }
+
+    pub fn current(&self) -> Option<&Url> {
+        self.candidates
+            .get(self.current)
+            .map(|candidate| &candidate.base_url)
+    }
+
+    pub fn base_urls(&self) -> impl Iterator<Item = &Url> {
+        self.candidates.iter().map(|candidate| &candidate.base_url)
+    }
+
+    /// The CDN after `base_url` in manifest order, wrapping around. `None` without alternatives.
+    pub fn next_after(&self, base_url: &Url) -> Option<&Url> {
+        if !self.has_alternatives() {
+            return None;
+        }
+
+        let index = self
+            .candidates
+            .iter()
+            .position(|candidate| &candidate.base_url == base_url)
+            .map_or(0, |index| (index + 1) % self.candidates.len());
+
+        Some(&self.candidates[index].base_url)
+    }
+
+    /// Records the outcome of a probe. Failed probes (`None`) are ignored here, failover is a
+    /// separate concern.
+    pub fn record(&mut self, index: usize, latency_ms: Option<f64>) {
+        let (Some(candidate), Some(sample)) = (self.candidates.get_mut(index), latency_ms) else {
+            return;
+        };
+
+        candidate.latency = Some(match candidate.latency {
+            Some(latency) => latency + LATENCY_SMOOTHING * (sample - latency),
+            None => sample,
+        });
+    }
+
+    /// Moves to the fastest CDN if it beats the current one by the hysteresis margin. Returns
+    /// the new base URL if the selection changed.
+    pub fn reselect(&mut self) -> Option<&Url> {
+        let current = self.candidates.get(self.current)?.latency;
+
+        let (fastest, latency) = self
+            .candidates
+            .iter()
+            .enumerate()
+            .filter_map(|(index, candidate)| Some((index, candidate.latency?)))
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))?;
+
+        if fastest == self.current {
+            return None;
+        }
+
+        // A current CDN that was never measured is replaced by any measured one.
+        if let Some(current) = current {
+            let gain = current - latency;
+            if gain < MIN_SWITCH_GAIN_MS || gain < current * SWITCH_THRESHOLD {
+                return None;
+            }
+        }
+
+        self.current = fastest;
+        self.current()
+    }
+}
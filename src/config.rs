@@ -0,0 +1,561 @@
+use crate::abr::default_abr_rules;
+use crate::abr::AbrRule;
+use crate::abr::BandwidthEstimator;
+use crate::abr::EwmaEstimator;
+use crate::ads::AdConfig;
+use crate::drm::decode_hex;
+use crate::drm::normalize_key_id;
+use crate::drm::DrmConfig;
+use crate::hotkeys::HotkeyConfig;
+use crate::loader::DecoratedLoader;
+use crate::loader::HttpLoader;
+use crate::loader::InterceptingLoader;
+use crate::loader::Interceptor;
+use crate::loader::Loader;
+use crate::loader::ManifestDecryptor;
+use crate::loader::RequestDecorator;
+use crate::loader::RequestLimits;
+use crate::loader::RequestTagging;
+use crate::loader::RequestTimeouts;
+use crate::loader::TaggingLoader;
+use crate::loader::TimeoutLoader;
+use crate::loader::UrlSigner;
+use crate::log::LogConfig;
+use crate::manifest::TrackRole;
+
+use core::fmt;
+use core::time::Duration;
+use std::rc::Rc;
+
+use displaydoc::Display;
+use thiserror::Error;
+
+/// How much a player downloads before playback is requested, like the `preload` attribute of
+/// media elements. Whatever is held back is fetched once the element starts playing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PreloadPolicy {
+    /// Only the manifest.
+    None,
+    /// The manifest and the init segments.
+    Metadata,
+    /// Buffer ahead right away.
+    #[default]
+    Auto,
+}
+
+/// What a player downloads while the page is hidden, e.g. in a background tab. Audio keeps
+/// buffering regardless, and full quality selection resumes once the page is visible again.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BackgroundPolicy {
+    /// The same as in the foreground.
+    #[default]
+    Continue,
+    /// Video at its lowest rendition.
+    LowestQuality,
+    /// No video segments at all. Playback waits once the video already buffered runs out, so
+    /// this suits content that is paused or muted in the background.
+    PauseVideo,
+}
+
+/// Knobs controlling how a [`crate::MediaPlayer`] behaves, grouped into sections. Build players
+/// with [`crate::MediaPlayer::builder`] to have the config validated first.
+#[derive(Clone)]
+pub struct PlayerConfig {
+    pub buffer: BufferConfig,
+    pub abr: AbrConfig,
+    /// License servers and keys for encrypted content.
+    pub drm: DrmConfig,
+    pub network: NetworkConfig,
+    pub live: LiveConfig,
+    pub text: TextConfig,
+    /// How often a [`crate::event::PlayerEvent::Heartbeat`] with playback statistics is
+    /// published while media is attached.
+    pub heartbeat_interval: Duration,
+    /// Start with an audio description track, where the manifest has one, instead of the main
+    /// audio. Other tracks can still be picked with [`crate::MediaPlayer::set_audio_track`].
+    pub prefer_audio_description: bool,
+    /// BCP 47 language, e.g. `de` or `pt-BR`, of the audio track to start with. A tag without a
+    /// region also matches tracks with one. Outranks [`Self::preferred_audio_role`].
+    pub preferred_audio_language: Option<String>,
+    /// Role of the audio track to start with, e.g. [`TrackRole::Commentary`].
+    pub preferred_audio_role: Option<TrackRole>,
+    /// Most audio channels the device plays, e.g. `2` where multichannel audio can't be
+    /// decoded or would only be downmixed. Audio tracks with more channels are only picked if
+    /// no other matches [`Self::preferred_audio_language`]. `None` doesn't limit.
+    pub max_audio_channels: Option<u32>,
+    /// Only buffer audio, e.g. for podcasts or background playback. Can be changed later with
+    /// [`crate::MediaPlayer::set_video_enabled`].
+    pub audio_only: bool,
+    /// Seconds into the presentation to start at instead of the beginning, for every
+    /// [`crate::MediaPlayer::create`] and [`crate::MediaPlayer::load`]. The first segments
+    /// fetched are the ones at this position. Live streams ignore it.
+    pub start_position: Option<f64>,
+    /// How long [`crate::MediaPlayer::create`] waits for the video element to be mounted, for
+    /// UI frameworks that render it after the player was created. `0` doesn't wait.
+    pub element_timeout: Duration,
+    /// Recent events, errors and rendition switches kept of each for
+    /// [`crate::MediaPlayer::diagnostics`]. `0` keeps none.
+    pub diagnostics_capacity: usize,
+    /// Keyboard control of the player. Off unless set.
+    pub hotkeys: Option<HotkeyConfig>,
+    /// Ad breaks and the element they play in. Off unless set.
+    pub ads: Option<AdConfig>,
+    /// Log levels per subsystem. Can be changed later with
+    /// [`crate::MediaPlayer::set_log_config`].
+    pub log: LogConfig,
+}
+
+impl PlayerConfig {
+    /// Checks for values the player can't work with, like a zero heartbeat interval or a
+    /// negative gap tolerance. [`crate::MediaPlayer::builder`] runs this before starting a player.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        positive("heartbeat_interval", self.heartbeat_interval)?;
+        if let Some(start) = self.start_position {
+            seconds("start_position", start)?;
+        }
+        if self.max_audio_channels == Some(0) {
+            return Err(ConfigError::Zero("max_audio_channels"));
+        }
+
+        self.buffer.validate()?;
+        self.abr.validate()?;
+        self.network.validate()?;
+        self.live.validate()?;
+        validate_drm(&self.drm)?;
+
+        if let Some(hotkeys) = &self.hotkeys {
+            seconds("hotkeys.seek_step", hotkeys.seek_step)?;
+            if !(hotkeys.volume_step > 0. && hotkeys.volume_step <= 1.) {
+                return Err(ConfigError::OutOfRange(
+                    "hotkeys.volume_step",
+                    hotkeys.volume_step,
+                ));
+            }
+        }
+
+        if self
+            .ads
+            .as_ref()
+            .is_some_and(|ads| ads.element_id.is_empty())
+        {
+            return Err(ConfigError::Empty("ads.element_id"));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for PlayerConfig {
+    fn default() -> Self {
+        Self {
+            buffer: BufferConfig::default(),
+            abr: AbrConfig::default(),
+            drm: DrmConfig::default(),
+            network: NetworkConfig::default(),
+            live: LiveConfig::default(),
+            text: TextConfig::default(),
+            heartbeat_interval: Duration::from_secs(10),
+            prefer_audio_description: false,
+            preferred_audio_language: None,
+            preferred_audio_role: None,
+            max_audio_channels: None,
+            audio_only: false,
+            start_position: None,
+            element_timeout: Duration::from_secs(3),
+            diagnostics_capacity: 100,
+            hotkeys: None,
+            ads: None,
+            log: LogConfig::default(),
+        }
+    }
+}
+
+impl fmt::Debug for PlayerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PlayerConfig")
+            .field("buffer", &self.buffer)
+            .field("abr", &self.abr)
+            .field("drm", &self.drm)
+            .field("network", &self.network)
+            .field("live", &self.live)
+            .field("text", &self.text)
+            .field("heartbeat_interval", &self.heartbeat_interval)
+            .field("prefer_audio_description", &self.prefer_audio_description)
+            .field("preferred_audio_language", &self.preferred_audio_language)
+            .field("preferred_audio_role", &self.preferred_audio_role)
+            .field("max_audio_channels", &self.max_audio_channels)
+            .field("audio_only", &self.audio_only)
+            .field("start_position", &self.start_position)
+            .field("element_timeout", &self.element_timeout)
+            .field("diagnostics_capacity", &self.diagnostics_capacity)
+            .field("hotkeys", &self.hotkeys)
+            .field("ads", &self.ads)
+            .field("log", &self.log)
+            .finish()
+    }
+}
+
+/// What is buffered, when, and how buffering problems are dealt with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BufferConfig {
+    /// Validate the box structure of every media segment before appending it, reporting
+    /// malformed segments through [`crate::event::PlayerEvent::MalformedSegment`] instead of
+    /// letting MSE fail with an opaque error. Playback fails on a malformed segment. Meant for
+    /// debugging, as it walks every segment.
+    pub verify_segments: bool,
+    /// Largest gap or overlap, in seconds, between consecutive audio segments that gets
+    /// concealed by shifting the incoming segment's timestamps. Stitched periods (ad insertion)
+    /// rarely line up exactly and the resulting discontinuity is audible as a click. Set to `0.`
+    /// to disable.
+    pub audio_gap_tolerance: f64,
+    /// How long the playhead may sit still while the element lacks data before the player
+    /// declares a stall and tries to recover.
+    pub stall_timeout: Duration,
+    /// Largest hole in the buffered ranges, in seconds, the player skips by moving the playhead
+    /// to the next buffered range. Encoder discontinuities often leave sub-second gaps that
+    /// would otherwise freeze playback. Set to `0.` to disable.
+    pub max_gap_jump: f64,
+    /// Time every active track must have buffered at the start position before
+    /// [`crate::event::PlayerEvent::PlaybackReady`] is published and `autoplay` starts playback,
+    /// so playback doesn't begin with one track, e.g. audio, still missing. Capped at the end of
+    /// the presentation.
+    pub ready_buffer: Duration,
+    /// How much is downloaded before playback starts. Lower it for grids of previews, where
+    /// most players are never played. Elements with `autoplay` always buffer ahead.
+    pub preload: PreloadPolicy,
+    /// What is downloaded while the page is hidden.
+    pub background_policy: BackgroundPolicy,
+}
+
+impl BufferConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        seconds("buffer.audio_gap_tolerance", self.audio_gap_tolerance)?;
+        positive("buffer.stall_timeout", self.stall_timeout)?;
+        seconds("buffer.max_gap_jump", self.max_gap_jump)
+    }
+}
+
+impl Default for BufferConfig {
+    fn default() -> Self {
+        Self {
+            verify_segments: false,
+            audio_gap_tolerance: 0.1,
+            stall_timeout: Duration::from_millis(1500),
+            max_gap_jump: 0.5,
+            ready_buffer: Duration::from_secs(2),
+            preload: PreloadPolicy::Auto,
+            background_policy: BackgroundPolicy::Continue,
+        }
+    }
+}
+
+/// Automatic quality selection.
+#[derive(Clone)]
+pub struct AbrConfig {
+    /// Creates the estimator fed with the throughput of every segment download.
+    pub bandwidth_estimator: Rc<dyn Fn() -> Box<dyn BandwidthEstimator>>,
+    /// Creates the rules automatic quality selection combines. The lowest bitrate limit any rule
+    /// sets wins. Defaults to throughput and dropped frames, see [`crate::abr`] for more.
+    pub rules: Rc<dyn Fn() -> Vec<Box<dyn AbrRule>>>,
+    /// Number of video segments fetched from the lowest rendition before automatic quality
+    /// selection kicks in, to get frames on screen as quickly as possible and to measure the
+    /// bandwidth with. Only applies with [`crate::abr::QualitySelection::Auto`]. `0` starts with
+    /// the rendition [`Self::max_startup_bitrate`] allows.
+    pub fast_start_segments: usize,
+    /// Bitrate, in bits per second, of the best rendition automatic quality selection may start
+    /// with before it has measured the bandwidth. `None` starts with the first rendition listed.
+    pub max_startup_bitrate: Option<u64>,
+    /// Keep the bandwidth estimate in `localStorage`, per origin of the manifest, and start the
+    /// next session from it rather than from [`Self::max_startup_bitrate`]. Off by default.
+    pub persist_bandwidth: bool,
+    /// Seed and bound the bandwidth estimate with what `navigator.connection` reports, and
+    /// reselect the rendition when it changes, see [`crate::connection`]. On by default.
+    pub network_information: bool,
+    /// Highest bitrate, in bits per second, automatic quality selection picks while the
+    /// connection is metered, i.e. cellular or in data saver mode. Needs
+    /// [`Self::network_information`].
+    pub metered_max_bitrate: Option<u64>,
+    /// Ask `navigator.mediaCapabilities` about video representations after the manifest
+    /// loaded, leave out the ones the device can't decode or the display can't show, and keep
+    /// automatic selection to the ones it plays smoothly, see [`crate::capabilities`]. On by
+    /// default.
+    pub media_capabilities: bool,
+    /// Keep automatic selection to renditions `mediaCapabilities` reports as power efficient,
+    /// i.e. decoded in hardware, as long as any is. Needs [`Self::media_capabilities`]. Off by
+    /// default, as it can keep devices without hardware decoding for the top renditions from
+    /// playing them.
+    pub prefer_power_efficient: bool,
+}
+
+impl AbrConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_startup_bitrate == Some(0) {
+            return Err(ConfigError::Zero("abr.max_startup_bitrate"));
+        }
+        if self.metered_max_bitrate == Some(0) {
+            return Err(ConfigError::Zero("abr.metered_max_bitrate"));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for AbrConfig {
+    fn default() -> Self {
+        Self {
+            bandwidth_estimator: Rc::new(|| Box::new(EwmaEstimator::default())),
+            rules: Rc::new(default_abr_rules),
+            fast_start_segments: 0,
+            max_startup_bitrate: Some(2_000_000),
+            persist_bandwidth: false,
+            network_information: true,
+            metered_max_bitrate: None,
+            media_capabilities: true,
+            prefer_power_efficient: false,
+        }
+    }
+}
+
+impl fmt::Debug for AbrConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AbrConfig")
+            .field("fast_start_segments", &self.fast_start_segments)
+            .field("max_startup_bitrate", &self.max_startup_bitrate)
+            .field("persist_bandwidth", &self.persist_bandwidth)
+            .field("network_information", &self.network_information)
+            .field("metered_max_bitrate", &self.metered_max_bitrate)
+            .field("media_capabilities", &self.media_capabilities)
+            .field("prefer_power_efficient", &self.prefer_power_efficient)
+            .finish_non_exhaustive()
+    }
+}
+
+/// How requests are made, and where they go.
+#[derive(Clone)]
+pub struct NetworkConfig {
+    /// Performs every manifest and segment request.
+    pub loader: Rc<dyn Loader>,
+    /// Called on every manifest, init and media request before it is dispatched, to add
+    /// authorization headers or signed query strings.
+    pub request_decorator: Option<RequestDecorator>,
+    /// Signs init and media segment URLs for CDNs with expiring URL signatures. Applied after the
+    /// request decorator, so the signature covers the final URL.
+    pub url_signer: Option<UrlSigner>,
+    /// Called with every fetched manifest body before it is parsed, e.g. to decrypt it with a key
+    /// obtained from the provider's API. Applies to live refreshes too.
+    pub manifest_decryptor: Option<ManifestDecryptor>,
+    /// How often the latency of each CDN is probed when the manifest lists several `BaseURL`s.
+    /// Segments are fetched from the fastest one. `None` sticks to the first `BaseURL`.
+    pub cdn_probe_interval: Option<Duration>,
+    /// Consecutive failed segment fetches after which a track moves to the next `BaseURL`, when
+    /// the manifest lists several.
+    pub failover_after: u32,
+    /// Times a failed media segment fetch is retried, waiting longer after every failure, before
+    /// playback fails with [`crate::player::Error::FetchError`]. CDN failovers don't start the
+    /// count over.
+    pub max_segment_retries: u32,
+    /// Size in bytes of the in-memory cache of recently fetched segments, which saves
+    /// downloading them again when seeking back or switching back to a rendition. `0` disables
+    /// the cache.
+    pub segment_cache_bytes: usize,
+    /// Name of a Cache Storage cache filled by a [`crate::download::Downloader`]. Manifests and
+    /// segments found there are played without going to the network.
+    pub offline_cache: Option<String>,
+    /// Labels every request with what it is for, so service workers can tell player traffic
+    /// apart. See [`crate::loader::RequestTag`].
+    pub request_tagging: RequestTagging,
+    /// How long manifest, init and media requests may take. Timed out segment requests are
+    /// retried like other failed ones and lower the bandwidth estimate.
+    pub request_timeouts: RequestTimeouts,
+    /// How many segment, manifest and license requests may be in flight at once. Requests over
+    /// the limit wait, the most urgent going out first, see
+    /// [`crate::loader::RequestPriority`].
+    pub request_limits: RequestLimits,
+    /// Called around every request that goes out to the network, in order. Requests answered
+    /// from the segment or offline cache don't reach them.
+    pub interceptors: Vec<Interceptor>,
+}
+
+impl NetworkConfig {
+    /// The configured loader with the request decorator, if any, applied on top.
+    pub fn effective_loader(&self) -> Rc<dyn Loader> {
+        self.decorated(self.network_loader())
+    }
+
+    /// The configured loader with request tagging applied, right before requests go out, the
+    /// request timeouts and the interceptors.
+    pub fn network_loader(&self) -> Rc<dyn Loader> {
+        let loader = match self.request_tagging {
+            RequestTagging::Off => self.loader.clone(),
+            tagging => Rc::new(TaggingLoader::new(self.loader.clone(), tagging)),
+        };
+
+        let loader: Rc<dyn Loader> = match self.request_timeouts {
+            RequestTimeouts::NONE => loader,
+            timeouts => Rc::new(TimeoutLoader::new(loader, timeouts)),
+        };
+
+        match self.interceptors.is_empty() {
+            true => loader,
+            false => Rc::new(InterceptingLoader::new(loader, self.interceptors.clone())),
+        }
+    }
+
+    /// `loader` with the request decorator, if any, applied on top.
+    pub fn decorated(&self, loader: Rc<dyn Loader>) -> Rc<dyn Loader> {
+        match &self.request_decorator {
+            Some(decorator) => Rc::new(DecoratedLoader::new(loader, decorator.clone())),
+            None => loader,
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if let Some(interval) = self.cdn_probe_interval {
+            positive("network.cdn_probe_interval", interval)?;
+        }
+
+        if self.failover_after == 0 {
+            return Err(ConfigError::Zero("network.failover_after"));
+        }
+
+        if self.request_limits.max_concurrent == Some(0) {
+            return Err(ConfigError::Zero("network.request_limits.max_concurrent"));
+        }
+
+        if self.request_limits.max_per_host == Some(0) {
+            return Err(ConfigError::Zero("network.request_limits.max_per_host"));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            loader: Rc::new(HttpLoader::default()),
+            request_decorator: None,
+            url_signer: None,
+            manifest_decryptor: None,
+            cdn_probe_interval: Some(Duration::from_secs(30)),
+            failover_after: 3,
+            max_segment_retries: 6,
+            segment_cache_bytes: 16 * 1024 * 1024,
+            offline_cache: None,
+            request_tagging: RequestTagging::Off,
+            request_timeouts: RequestTimeouts::default(),
+            request_limits: RequestLimits::default(),
+            interceptors: vec![],
+        }
+    }
+}
+
+impl fmt::Debug for NetworkConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NetworkConfig")
+            .field("cdn_probe_interval", &self.cdn_probe_interval)
+            .field("failover_after", &self.failover_after)
+            .field("max_segment_retries", &self.max_segment_retries)
+            .field("segment_cache_bytes", &self.segment_cache_bytes)
+            .field("offline_cache", &self.offline_cache)
+            .field("request_tagging", &self.request_tagging)
+            .field("request_timeouts", &self.request_timeouts)
+            .field("request_limits", &self.request_limits)
+            .field("interceptors", &self.interceptors)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Live streams.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LiveConfig {
+    /// Low-latency DASH: start live streams at the live edge and append segments signalled with
+    /// `availabilityTimeComplete="false"` chunk by chunk while they download.
+    pub low_latency: bool,
+    /// Distance from the live edge to keep live playback at, by adjusting the playback rate
+    /// slightly or seeking. `None` leaves live playback alone.
+    pub target_latency: Option<Duration>,
+}
+
+impl LiveConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        match self.target_latency {
+            Some(latency) => positive("live.target_latency", latency),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Text tracks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextConfig {
+    /// Language of the text track to show once it is added to the element. Text tracks stay as
+    /// the element has them unless this or [`Self::preferred_role`] is set.
+    pub preferred_language: Option<String>,
+    /// Role of the text track to show, e.g. [`TrackRole::Caption`] for captions rather than
+    /// subtitles.
+    pub preferred_role: Option<TrackRole>,
+    /// Add the CEA-608 and CEA-708 captions carried in H.264 and H.265 video as `captions` text
+    /// tracks labelled after their channel, e.g. `CC1`. On by default.
+    pub in_band_captions: bool,
+}
+
+impl Default for TextConfig {
+    fn default() -> Self {
+        Self {
+            preferred_language: None,
+            preferred_role: None,
+            in_band_captions: true,
+        }
+    }
+}
+
+/// A [`PlayerConfig`] value the player can't work with. Names the offending setting.
+#[derive(Clone, Debug, Display, Error, PartialEq)]
+pub enum ConfigError {
+    /// {0} must not be zero
+    Zero(&'static str),
+    /// {0} must be a finite, non-negative number, not {1}
+    OutOfRange(&'static str, f64),
+    /// {0} must not be empty
+    Empty(&'static str),
+    /// license server {0} is not a valid URL
+    LicenseServer(String),
+    /// ClearKey key for {0} is not hex
+    ClearKey(String),
+}
+
+fn positive(name: &'static str, duration: Duration) -> Result<(), ConfigError> {
+    if duration.is_zero() {
+        return Err(ConfigError::Zero(name));
+    }
+
+    Ok(())
+}
+
+fn seconds(name: &'static str, value: f64) -> Result<(), ConfigError> {
+    if !(value.is_finite() && value >= 0.) {
+        return Err(ConfigError::OutOfRange(name, value));
+    }
+
+    Ok(())
+}
+
+fn validate_drm(drm: &DrmConfig) -> Result<(), ConfigError> {
+    if let Some(server) = drm
+        .license_servers
+        .values()
+        .find(|server| url::Url::parse(server).is_err())
+    {
+        return Err(ConfigError::LicenseServer(server.clone()));
+    }
+
+    if let Some(key_id) = drm.clearkey_keys.iter().find_map(|(key_id, key)| {
+        let valid = decode_hex(&normalize_key_id(key_id)).is_some() && decode_hex(key).is_some();
+        (!valid).then(|| key_id.clone())
+    }) {
+        return Err(ConfigError::ClearKey(key_id));
+    }
+
+    Ok(())
+}
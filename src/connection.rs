@@ -0,0 +1,81 @@
+//! The Network Information API, `navigator.connection`, where the browser has it. What the
+//! browser knows about the connection seeds the bandwidth estimate until the first segment was
+//! measured and bounds it by the connection's effective type, and metered connections can cap
+//! the bitrate, see [`crate::config::AbrConfig::metered_max_bitrate`].
+
+use wasm_bindgen::JsCast;
+use wasm_bindgen::JsValue;
+use web_sys::EventTarget;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// What `navigator.connection` reports.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionInfo {
+    /// Bandwidth estimate of the browser, in bits per second. Browsers round it and cap it, at
+    /// 10 Mbps in Chrome, so it only ever seeds the player's estimate.
+    pub downlink: Option<f64>,
+    /// `effectiveType`: `slow-2g`, `2g`, `3g` or `4g`.
+    pub effective_type: Option<String>,
+    /// `type`, e.g. `wifi` or `cellular`, where the browser tells.
+    pub connection_type: Option<String>,
+    /// The user asked for reduced data usage.
+    pub save_data: bool,
+}
+
+impl ConnectionInfo {
+    /// Reads `navigator.connection`. `None` where the browser doesn't have it.
+    pub fn current() -> Option<Self> {
+        let connection = connection()?;
+        let get = |name: &str| js_sys::Reflect::get(&connection, &JsValue::from_str(name)).ok();
+
+        Some(Self {
+            downlink: get("downlink")
+                .and_then(|downlink| downlink.as_f64())
+                .filter(|downlink| *downlink > 0.)
+                // Reported in megabits per second.
+                .map(|downlink| downlink * 1_000_000.),
+            effective_type: get("effectiveType").and_then(|value| value.as_string()),
+            connection_type: get("type").and_then(|value| value.as_string()),
+            save_data: get("saveData").is_some_and(|value| value.is_truthy()),
+        })
+    }
+
+    /// Whether data is likely to cost the user: a cellular connection, or one they asked to
+    /// save data on.
+    pub fn is_metered(&self) -> bool {
+        self.save_data || self.connection_type.as_deref() == Some("cellular")
+    }
+
+    /// Highest throughput the effective type stands for, in bits per second. `None` for `4g`,
+    /// which has no upper bound.
+    pub fn bandwidth_ceiling(&self) -> Option<f64> {
+        match self.effective_type.as_deref()? {
+            "slow-2g" => Some(50_000.),
+            "2g" => Some(70_000.),
+            "3g" => Some(700_000.),
+            _ => None,
+        }
+    }
+
+    /// `estimate`, else the browser's `downlink`, bounded by [`Self::bandwidth_ceiling`].
+    pub fn bound(&self, estimate: Option<f64>) -> Option<f64> {
+        let estimate = estimate.or(self.downlink)?;
+
+        Some(match self.bandwidth_ceiling() {
+            Some(ceiling) => estimate.min(ceiling),
+            None => estimate,
+        })
+    }
+}
+
+/// `navigator.connection`, to listen for its `change` events.
+pub fn connection() -> Option<EventTarget> {
+    let navigator = web_sys::window()?.navigator();
+
+    js_sys::Reflect::get(&navigator, &JsValue::from_str("connection"))
+        .ok()?
+        .dyn_into()
+        .ok()
+}
@@ -0,0 +1,156 @@
+//! Diagnostics for bug reports. Every player keeps its most recent events, errors and rendition
+//! switches; [`crate::MediaPlayer::diagnostics`] combines them with a snapshot of the manifest and
+//! the track buffers into a [`Diagnostics`] dump that serializes to JSON.
+
+use crate::event::PlaybackState;
+use crate::event::PlayerEvent;
+use crate::manifest::Manifest;
+
+use std::collections::VecDeque;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// A dump of a player's state and recent history.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Diagnostics {
+    pub player: String,
+    pub manifest_url: Option<String>,
+    pub state: PlaybackState,
+    /// Position of the video element, in seconds.
+    pub position: Option<f64>,
+    pub manifest: Option<ManifestSummary>,
+    pub tracks: Vec<TrackDiagnostics>,
+    /// Bandwidth estimate in bits per second.
+    pub bandwidth_estimate: Option<f64>,
+    /// Published events, oldest first, without heartbeats and log records.
+    pub events: Vec<LoggedEvent>,
+    /// Published errors, oldest first.
+    pub errors: Vec<LoggedEvent>,
+    /// Rendition switches, oldest first.
+    pub abr_decisions: Vec<AbrDecision>,
+}
+
+impl Diagnostics {
+    /// The dump as pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+/// The parts of the manifest that matter when reading a report.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ManifestSummary {
+    pub live: bool,
+    /// Seconds, for VOD.
+    pub duration: Option<f64>,
+    pub min_buffer_time: Option<f64>,
+    pub periods: usize,
+    pub representations: usize,
+}
+
+impl ManifestSummary {
+    pub fn new(manifest: &Manifest) -> Self {
+        Self {
+            live: manifest.is_live(),
+            duration: manifest.duration().map(|duration| duration.as_secs_f64()),
+            min_buffer_time: manifest.min_buffer_time().map(|time| time.as_secs_f64()),
+            periods: manifest.info().periods().len(),
+            representations: manifest.tracks().len(),
+        }
+    }
+}
+
+/// State of one active track buffer.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TrackDiagnostics {
+    pub track: String,
+    pub representation: String,
+    pub bitrate: Option<u64>,
+    /// Buffered ranges, in seconds.
+    pub buffered: Vec<(f64, f64)>,
+    /// Seconds buffered contiguously ahead of the playhead.
+    pub buffered_ahead: f64,
+    pub segment_duration: f64,
+    pub fetching: bool,
+}
+
+/// An event as recorded for diagnostics.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LoggedEvent {
+    /// When it happened, as a JS timestamp in milliseconds.
+    pub time: f64,
+    pub event: String,
+}
+
+/// A rendition switch and what it was based on.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AbrDecision {
+    pub time: f64,
+    pub track: String,
+    pub from: String,
+    pub to: String,
+    pub reason: String,
+    pub bandwidth_estimate: Option<f64>,
+    /// Seconds buffered ahead when switching.
+    pub buffer_level: f64,
+}
+
+/// Ring buffers of the recent history, each holding at most `capacity` entries.
+#[derive(Debug, Default)]
+pub struct DiagnosticsLog {
+    capacity: usize,
+    events: VecDeque<LoggedEvent>,
+    errors: VecDeque<LoggedEvent>,
+    decisions: VecDeque<AbrDecision>,
+}
+
+impl DiagnosticsLog {
+    /// Keeps the last `capacity` entries of each kind. `0` records nothing.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ..Self::default()
+        }
+    }
+
+    /// Records a published event, errors separately.
+    pub fn record_event(&mut self, event: &PlayerEvent) {
+        let entries = match event {
+            PlayerEvent::Heartbeat(_) | PlayerEvent::Log(_) => return,
+            PlayerEvent::Error(_) => &mut self.errors,
+            _ => &mut self.events,
+        };
+
+        push_bounded(
+            entries,
+            self.capacity,
+            LoggedEvent {
+                time: js_sys::Date::now(),
+                event: format!("{event:?}"),
+            },
+        );
+    }
+
+    pub fn record_decision(&mut self, decision: AbrDecision) {
+        push_bounded(&mut self.decisions, self.capacity, decision);
+    }
+
+    /// Copies the history into `diagnostics`.
+    pub fn fill(&self, diagnostics: &mut Diagnostics) {
+        diagnostics.events = self.events.iter().cloned().collect();
+        diagnostics.errors = self.errors.iter().cloned().collect();
+        diagnostics.abr_decisions = self.decisions.iter().cloned().collect();
+    }
+}
+
+fn push_bounded<T>(entries: &mut VecDeque<T>, capacity: usize, entry: T) {
+    if capacity == 0 {
+        return;
+    }
+
+    while entries.len() >= capacity {
+        entries.pop_front();
+    }
+    entries.push_back(entry);
+}
@@ -0,0 +1,350 @@
+//! Offline viewing. A [`Downloader`] stores the manifest and every init and media segment of the
+//! chosen renditions in the browser's Cache Storage, and [`CacheStorageLoader`] serves them from
+//! there before going to the network. Point [`crate::config::NetworkConfig::offline_cache`] at the
+//! same cache to play downloads.
+//!
+//! ```ignore
+//! let mut downloader = Downloader::new(&PlayerConfig::default());
+//! let progress = downloader.subscribe();
+//! downloader.download(manifest_url, QualitySelection::MaxHeight(720)).await?;
+//! ```
+
+use crate::abr::QualitySelection;
+use crate::config::PlayerConfig;
+use crate::error::AshinaError;
+use crate::loader::ChunkStream;
+use crate::loader::LoadFuture;
+use crate::loader::Loader;
+use crate::loader::ManifestDecryptor;
+use crate::loader::RequestKind;
+use crate::loader::SegmentRequest;
+use crate::manifest::Track;
+use crate::manifest::XlinkActuate;
+use crate::player::is_type_supported;
+use crate::player::parse_manifest;
+
+use wasm_bindgen::JsCast;
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::Cache;
+use web_sys::Response;
+
+use std::collections::HashSet;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use displaydoc::Display;
+use thiserror::Error;
+use url::Url;
+
+/// Cache Storage cache used unless another name is configured.
+pub const DEFAULT_CACHE_NAME: &str = "ashina-offline";
+
+#[derive(Clone, Debug, Display, Error)]
+pub enum DownloadError {
+    /// live streams can't be downloaded
+    Live,
+    /// representation {0} has no fixed segment duration to enumerate segments with
+    NoSegmentIndex(String),
+    /// manifest has no duration
+    NoDuration,
+    /// cache storage failed: {0}
+    Storage(String),
+}
+
+impl From<JsValue> for DownloadError {
+    fn from(error: JsValue) -> Self {
+        Self::Storage(format!("{error:?}"))
+    }
+}
+
+/// How far a [`Downloader::download`] got. Sent after the manifest and after every segment.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DownloadProgress {
+    /// Requests completed, the manifest included.
+    pub completed: usize,
+    pub total: usize,
+    /// Bytes stored so far.
+    pub bytes: usize,
+}
+
+impl DownloadProgress {
+    /// Share of requests completed, from `0.` to `1.`.
+    pub fn fraction(&self) -> f64 {
+        match self.total {
+            0 => 0.,
+            total => self.completed as f64 / total as f64,
+        }
+    }
+}
+
+/// Downloads whole presentations into Cache Storage for offline playback.
+pub struct Downloader {
+    loader: Rc<dyn Loader>,
+    decryptor: Option<ManifestDecryptor>,
+    cache_name: String,
+    subscribers: Vec<flume::Sender<DownloadProgress>>,
+}
+
+impl Downloader {
+    /// Fetches through the loader and request decorator of `config`.
+    pub fn new(config: &PlayerConfig) -> Self {
+        Self {
+            loader: config.network.effective_loader(),
+            decryptor: config.network.manifest_decryptor.clone(),
+            cache_name: config
+                .network
+                .offline_cache
+                .clone()
+                .unwrap_or(DEFAULT_CACHE_NAME.into()),
+            subscribers: vec![],
+        }
+    }
+
+    pub fn with_cache_name(mut self, name: impl Into<String>) -> Self {
+        self.cache_name = name.into();
+        self
+    }
+
+    /// Returns a receiver for the progress of every download from now on.
+    pub fn subscribe(&mut self) -> flume::Receiver<DownloadProgress> {
+        let (tx, rx) = flume::unbounded();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    /// Stores the manifest at `manifest_url` together with the video rendition `quality` picks
+    /// from every video adaptation set, the highest one for [`QualitySelection::Auto`], and the
+    /// first rendition of every audio adaptation set, the one the player starts with. Segments
+    /// already stored are downloaded again. Only VOD streams with a fixed segment duration can
+    /// be downloaded.
+    pub async fn download(
+        &mut self,
+        manifest_url: &str,
+        quality: QualitySelection,
+    ) -> Result<DownloadProgress, AshinaError> {
+        let cache = open_cache(&self.cache_name).await?;
+
+        let request = SegmentRequest::new(manifest_url, RequestKind::Manifest);
+        let body = self
+            .loader
+            .fetch(request.clone())
+            .await
+            .map_err(|error| AshinaError::ManifestFetch(Arc::new(error)))?;
+        // The raw body is stored, the player decrypts it again when loading it from the cache.
+        let mut manifest = parse_manifest(body.clone(), self.decryptor.clone()).await?;
+        manifest
+            .resolve_xlinks(self.loader.as_ref(), manifest_url, XlinkActuate::OnRequest)
+            .await;
+
+        if manifest.is_live() {
+            return Err(DownloadError::Live.into());
+        }
+        let duration = manifest
+            .duration()
+            .ok_or(DownloadError::NoDuration)?
+            .as_secs_f64();
+
+        let manifest_url = Url::parse(manifest_url)
+            .map_err(|error| AshinaError::ManifestParse(Arc::new(error)))?;
+        let root = manifest
+            .base_urls()
+            .first()
+            .and_then(|base_url| manifest_url.join(base_url.trim()).ok())
+            .unwrap_or(manifest_url.clone());
+
+        let mut requests = vec![];
+        for track in selected_tracks(manifest.tracks(), &quality) {
+            requests.extend(track_requests(&track, &root, duration)?);
+        }
+
+        let mut progress = DownloadProgress {
+            completed: 0,
+            total: requests.len() + 1,
+            bytes: 0,
+        };
+
+        store(&cache, &request.url, body.clone()).await?;
+        progress.completed += 1;
+        progress.bytes += body.len();
+        self.report(progress);
+
+        for request in requests {
+            let url = request.url.clone();
+            let data = self.loader.fetch(request).await?;
+            store(&cache, &url, data.clone()).await?;
+
+            progress.completed += 1;
+            progress.bytes += data.len();
+            self.report(progress);
+        }
+
+        Ok(progress)
+    }
+
+    /// Deletes every download in the cache.
+    pub async fn clear(&self) -> Result<(), AshinaError> {
+        let caches = web_sys::window()
+            .ok_or(DownloadError::Storage("no window".into()))?
+            .caches()
+            .map_err(DownloadError::from)?;
+
+        JsFuture::from(caches.delete(&self.cache_name))
+            .await
+            .map_err(DownloadError::from)?;
+
+        Ok(())
+    }
+
+    fn report(&mut self, progress: DownloadProgress) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(progress).is_ok());
+    }
+}
+
+/// Answers manifest, init and media requests from a Cache Storage cache filled by a
+/// [`Downloader`], and passes everything else, including cache misses, to the inner loader.
+/// Requests are matched by URL, before decoration and signing.
+pub struct CacheStorageLoader {
+    inner: Rc<dyn Loader>,
+    cache_name: String,
+}
+
+impl CacheStorageLoader {
+    pub fn new(inner: Rc<dyn Loader>, cache_name: impl Into<String>) -> Self {
+        Self {
+            inner,
+            cache_name: cache_name.into(),
+        }
+    }
+}
+
+impl Loader for CacheStorageLoader {
+    fn fetch(&self, request: SegmentRequest) -> LoadFuture {
+        if !matches!(
+            request.kind,
+            RequestKind::Manifest | RequestKind::Init | RequestKind::Media
+        ) {
+            return self.inner.fetch(request);
+        }
+
+        let inner = self.inner.clone();
+        let cache_name = self.cache_name.clone();
+
+        Box::pin(async move {
+            match lookup(&cache_name, &request.url).await {
+                Ok(Some(data)) => Ok(data),
+                Ok(None) => inner.fetch(request).await,
+                Err(error) => {
+                    tracing::warn!(%error, "Offline cache lookup failed.");
+                    inner.fetch(request).await
+                }
+            }
+        })
+    }
+
+    fn fetch_stream(&self, request: SegmentRequest) -> ChunkStream {
+        self.inner.fetch_stream(request)
+    }
+}
+
+/// The renditions a download covers, see [`Downloader::download`].
+fn selected_tracks(tracks: Vec<Track>, quality: &QualitySelection) -> Vec<Track> {
+    let tracks: Vec<_> = tracks
+        .into_iter()
+        .filter(|track| is_type_supported(track) && !track.is_trick_mode())
+        .collect();
+
+    let mut selected = vec![];
+    let mut adaptations = HashSet::new();
+
+    for track in &tracks {
+        if !adaptations.insert(track.track_id().clone()) {
+            continue;
+        }
+
+        if track.is_audio() {
+            selected.push(track.clone());
+        } else if track.is_video() {
+            let renditions: Vec<_> = tracks
+                .iter()
+                .filter(|candidate| candidate.same_adaptation(track))
+                .cloned()
+                .collect();
+
+            let rendition = quality.select(&renditions, None).or_else(|| {
+                renditions
+                    .iter()
+                    .max_by_key(|rendition| rendition.bitrate().unwrap_or_default())
+            });
+            selected.extend(rendition.cloned());
+        }
+    }
+
+    selected
+}
+
+/// The init segment and every media segment of `track` covering `duration` seconds.
+fn track_requests(
+    track: &Track,
+    root: &Url,
+    duration: f64,
+) -> Result<Vec<SegmentRequest>, DownloadError> {
+    let index = track
+        .segment_index()
+        .ok_or(DownloadError::NoSegmentIndex(track.id().to_string()))?;
+    let base_url = track.base_url(root);
+    let resolve = |path: &str| {
+        base_url
+            .join(path)
+            .map_or(format!("{base_url}{path}"), String::from)
+    };
+
+    let mut init = track.initialization();
+    track.resolve_identifiers(&mut init);
+    let mut requests = vec![SegmentRequest::new(
+        resolve(init.as_ref()),
+        RequestKind::Init,
+    )];
+
+    let template = track.media_url_template();
+    let first = index.start_number();
+    let segments = (duration / index.duration()).ceil() as usize;
+    for number in first..first + segments {
+        requests.push(SegmentRequest::new(
+            resolve(&template.number(number)),
+            RequestKind::Media,
+        ));
+    }
+
+    Ok(requests)
+}
+
+async fn open_cache(name: &str) -> Result<Cache, DownloadError> {
+    let caches = web_sys::window()
+        .ok_or(DownloadError::Storage("no window".into()))?
+        .caches()?;
+
+    Ok(JsFuture::from(caches.open(name)).await?.unchecked_into())
+}
+
+async fn store(cache: &Cache, url: &str, data: Bytes) -> Result<(), DownloadError> {
+    let response = Response::new_with_opt_u8_array(Some(&mut data.to_vec()))?;
+    JsFuture::from(cache.put_with_str(url, &response)).await?;
+
+    Ok(())
+}
+
+async fn lookup(cache_name: &str, url: &str) -> Result<Option<Bytes>, DownloadError> {
+    let cache = open_cache(cache_name).await?;
+    let response = JsFuture::from(cache.match_with_str(url)).await?;
+    if response.is_undefined() {
+        return Ok(None);
+    }
+
+    let response: Response = response.unchecked_into();
+    let body = JsFuture::from(response.array_buffer()?).await?;
+
+    Ok(Some(js_sys::Uint8Array::new(&body).to_vec().into()))
+}
@@ -0,0 +1,716 @@
+use crate::loader::LoadError;
+use crate::loader::LoadFuture;
+use crate::loader::Loader;
+use crate::loader::RequestKind;
+use crate::loader::SegmentRequest;
+use crate::manifest::Track;
+use crate::player::InternalEvent;
+
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsCast;
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::JsFuture;
+
+use web_sys::HtmlVideoElement;
+use web_sys::MediaEncryptedEvent;
+use web_sys::MediaKeyMessageEvent;
+use web_sys::MediaKeyMessageType;
+use web_sys::MediaKeySession;
+use web_sys::MediaKeySystemAccess;
+use web_sys::MediaKeySystemConfiguration;
+use web_sys::MediaKeySystemMediaCapability;
+use web_sys::MediaKeys;
+
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use bytes::Bytes;
+use displaydoc::Display;
+use serde::Deserialize;
+use serde::Serialize;
+use thiserror::Error;
+
+/// The EME key systems the player knows how to talk to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum KeySystem {
+    Widevine,
+    PlayReady,
+    ClearKey,
+}
+
+impl KeySystem {
+    /// Key system string passed to `requestMediaKeySystemAccess`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Widevine => "com.widevine.alpha",
+            Self::PlayReady => "com.microsoft.playready",
+            Self::ClearKey => "org.w3.clearkey",
+        }
+    }
+
+    /// Maps a `ContentProtection@schemeIdUri` to the key system it signals.
+    pub fn from_scheme_id_uri(uri: &str) -> Option<Self> {
+        match uri.to_ascii_lowercase().as_str() {
+            "urn:uuid:edef8ba9-79d6-4ace-a3c8-27dcd51d21ed" => Some(Self::Widevine),
+            "urn:uuid:9a04f079-9840-4286-ab92-e65be0885f95" => Some(Self::PlayReady),
+            "urn:uuid:e2719d58-a985-b3c9-781a-b030af78d30e"
+            | "urn:uuid:1077efec-c0b2-4d02-ace3-3c1e52e2fb4b" => Some(Self::ClearKey),
+            _ => None,
+        }
+    }
+}
+
+/// Why a key session sent a message, from `MediaKeyMessageEvent.messageType`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LicenseMessageType {
+    /// First license for the session.
+    Request,
+    /// The license is about to expire and needs renewing.
+    Renewal,
+    /// Keys were released, e.g. for a persistent license.
+    Release,
+    /// The CDM wants to be individualized.
+    Individualization,
+}
+
+impl From<MediaKeyMessageType> for LicenseMessageType {
+    fn from(message_type: MediaKeyMessageType) -> Self {
+        match message_type {
+            MediaKeyMessageType::LicenseRenewal => Self::Renewal,
+            MediaKeyMessageType::LicenseRelease => Self::Release,
+            MediaKeyMessageType::IndividualizationRequest => Self::Individualization,
+            _ => Self::Request,
+        }
+    }
+}
+
+/// State of a key in a key session, from the session's `keyStatuses`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeyStatus {
+    Usable,
+    /// The license expired; renewal failed or wasn't attempted.
+    Expired,
+    Released,
+    /// The output, e.g. an external display without HDCP, isn't allowed for this key.
+    OutputRestricted,
+    OutputDownscaled,
+    Pending,
+    InternalError,
+}
+
+impl KeyStatus {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "usable" => Some(Self::Usable),
+            "expired" => Some(Self::Expired),
+            "released" => Some(Self::Released),
+            "output-restricted" => Some(Self::OutputRestricted),
+            "output-downscaled" => Some(Self::OutputDownscaled),
+            "status-pending" => Some(Self::Pending),
+            "internal-error" => Some(Self::InternalError),
+            _ => None,
+        }
+    }
+}
+
+/// What a license hook is called for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LicenseContext {
+    pub key_system: KeySystem,
+    pub message_type: LicenseMessageType,
+    /// Index of the key session, in the order sessions were opened.
+    pub session: usize,
+}
+
+/// Changes a license request before it is sent, e.g. to add auth headers, point renewals at
+/// another URL or wrap the CDM message in the JSON envelope the license server expects.
+pub type LicenseRequestFilter = Rc<dyn Fn(&LicenseContext, &mut SegmentRequest)>;
+/// Turns the license server response into the license the CDM expects, e.g. by unwrapping it
+/// from a JSON envelope.
+pub type LicenseResponseFilter = Rc<dyn Fn(&LicenseContext, Bytes) -> Result<Bytes, DrmError>>;
+
+#[derive(Clone, Default)]
+pub struct DrmConfig {
+    /// License server URL for each key system. Key systems without an entry here fall back to
+    /// the `dashif:laurl` advertised in the manifest, if any.
+    pub license_servers: HashMap<KeySystem, String>,
+    /// Applied to every license request, renewals included.
+    pub license_request_filter: Option<LicenseRequestFilter>,
+    /// Applied to every license server response before it is handed to the key session.
+    pub license_response_filter: Option<LicenseResponseFilter>,
+    /// Content keys by key ID, both in hex, for ClearKey playback without a license server, e.g.
+    /// of test streams. Key IDs may be written with dashes, like `cenc:default_KID`. When set,
+    /// ClearKey is tried before any other key system.
+    pub clearkey_keys: HashMap<String, String>,
+}
+
+impl fmt::Debug for DrmConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DrmConfig")
+            .field("license_servers", &self.license_servers)
+            .field("clearkey_keys", &self.clearkey_keys.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Clone, Debug, Display, Error)]
+pub enum DrmError {
+    /// None of the key systems signalled by the manifest have a license server
+    NoLicenseServer,
+    /// The browser supports none of the key systems signalled by the manifest
+    Unsupported,
+    /// Media keys could not be set up: {0}
+    MediaKeys(String),
+    /// License request failed: {0}
+    License(#[from] LoadError),
+    /// License response could not be used: {0}
+    LicenseResponse(String),
+}
+
+/// `cenc:default_KID`s of every `ContentProtection` descriptor on any of the tracks, as lowercase
+/// hex without dashes.
+pub fn signalled_key_ids(tracks: &[Track]) -> Vec<String> {
+    let mut key_ids = vec![];
+
+    for protection in tracks.iter().flat_map(|track| track.content_protection()) {
+        let Some(key_id) = protection.default_KID.as_deref().map(normalize_key_id) else {
+            continue;
+        };
+
+        if !key_ids.contains(&key_id) {
+            key_ids.push(key_id);
+        }
+    }
+
+    key_ids
+}
+
+/// Key systems signalled by `ContentProtection` descriptors on any of the tracks, in manifest
+/// order, together with the license URL the manifest advertises for them.
+pub fn signalled_key_systems(tracks: &[Track]) -> Vec<(KeySystem, Option<String>)> {
+    let mut systems: Vec<(KeySystem, Option<String>)> = vec![];
+
+    for protection in tracks.iter().flat_map(|track| track.content_protection()) {
+        let Some(system) = KeySystem::from_scheme_id_uri(&protection.schemeIdUri) else {
+            continue;
+        };
+
+        let laurl = protection
+            .laurl
+            .as_ref()
+            .or(protection.clearkey_laurl.as_ref())
+            .and_then(|laurl| laurl.content.clone());
+
+        match systems.iter_mut().find(|(known, _)| *known == system) {
+            Some((_, url)) => *url = url.take().or(laurl),
+            None => systems.push((system, laurl)),
+        }
+    }
+
+    systems
+}
+
+/// Work handed to the browser's CDM, to be driven by the player's task group.
+pub type DrmTask = Pin<Box<dyn Future<Output = ()>>>;
+
+struct Session {
+    session: MediaKeySession,
+    init_data: Vec<u8>,
+    /// Type of the message the license request in flight answers.
+    message_type: LicenseMessageType,
+    _on_message: Closure<dyn FnMut(MediaKeyMessageEvent)>,
+    _on_key_statuses: Closure<dyn FnMut()>,
+}
+
+/// Owns the EME state of a player: the negotiated media keys, the `encrypted` listener on the
+/// video element and one key session per distinct init data. Everything is released again in
+/// [`DrmManager::detach`].
+pub struct DrmManager {
+    config: DrmConfig,
+    /// ClearKey content keys by key ID, both base64url encoded as in license messages.
+    clearkey_keys: HashMap<String, String>,
+    key_system: Option<KeySystem>,
+    license_server: Option<String>,
+    media_keys: Option<MediaKeys>,
+    video: Option<HtmlVideoElement>,
+    sessions: Vec<Session>,
+    on_encrypted: Option<Closure<dyn FnMut(MediaEncryptedEvent)>>,
+}
+
+impl DrmManager {
+    pub fn new(config: DrmConfig) -> Self {
+        Self {
+            config,
+            clearkey_keys: HashMap::new(),
+            key_system: None,
+            license_server: None,
+            media_keys: None,
+            video: None,
+            sessions: vec![],
+            on_encrypted: None,
+        }
+    }
+
+    /// Answers ClearKey license requests locally with `keys`, content keys by key ID, both in
+    /// hex. Keys that aren't valid hex are skipped.
+    pub fn with_clearkey_keys(mut self, keys: &HashMap<String, String>) -> Self {
+        for (key_id, key) in keys {
+            let (Some(key_id), Some(key)) =
+                (decode_hex(&normalize_key_id(key_id)), decode_hex(key))
+            else {
+                tracing::warn!(key_id, "Skipping ClearKey key that isn't hex.");
+                continue;
+            };
+
+            self.clearkey_keys
+                .insert(encode_base64url(&key_id), encode_base64url(&key));
+        }
+
+        self
+    }
+
+    pub fn key_system(&self) -> Option<KeySystem> {
+        self.key_system
+    }
+
+    /// Negotiates media keys for the first signalled key system the browser supports and starts
+    /// listening for `encrypted` events. Does nothing for clear content.
+    pub async fn attach(
+        &mut self,
+        video: &HtmlVideoElement,
+        tracks: &[Track],
+        events: flume::Sender<InternalEvent>,
+    ) -> Result<(), DrmError> {
+        let protected = tracks
+            .iter()
+            .any(|track| !track.content_protection().is_empty());
+
+        let mut candidates: Vec<_> = signalled_key_systems(tracks)
+            .into_iter()
+            .filter_map(|(system, laurl)| {
+                let server = self.config.license_servers.get(&system).cloned();
+                server.or(laurl).map(|server| (system, Some(server)))
+            })
+            .collect();
+
+        // Configured keys need no license server, and work for any `cenc` content.
+        if protected && !self.clearkey_keys.is_empty() {
+            candidates.retain(|(system, _)| *system != KeySystem::ClearKey);
+            candidates.insert(0, (KeySystem::ClearKey, None));
+
+            for key_id in signalled_key_ids(tracks) {
+                let configured = decode_hex(&key_id).is_some_and(|key_id| {
+                    self.clearkey_keys.contains_key(&encode_base64url(&key_id))
+                });
+                if !configured {
+                    tracing::warn!(key_id, "No ClearKey key configured for key ID.");
+                }
+            }
+        }
+
+        if candidates.is_empty() {
+            if protected {
+                return Err(DrmError::NoLicenseServer);
+            }
+
+            return Ok(());
+        }
+
+        let configurations = key_system_configurations(tracks);
+        let navigator = web_sys::window().unwrap().navigator();
+
+        let mut access = None;
+        for (system, server) in candidates {
+            let request = navigator.request_media_key_system_access(system.name(), &configurations);
+
+            match JsFuture::from(request).await {
+                Ok(granted) => {
+                    access = Some((
+                        system,
+                        server,
+                        granted.unchecked_into::<MediaKeySystemAccess>(),
+                    ));
+                    break;
+                }
+                Err(error) => tracing::info!(?system, ?error, "Key system not supported."),
+            }
+        }
+
+        let Some((system, server, access)) = access else {
+            return Err(DrmError::Unsupported);
+        };
+
+        tracing::info!(?system, server, "Using key system.");
+
+        let media_keys: MediaKeys = JsFuture::from(access.create_media_keys())
+            .await
+            .map_err(js_error)?
+            .unchecked_into();
+
+        JsFuture::from(video.set_media_keys(Some(&media_keys)))
+            .await
+            .map_err(js_error)?;
+
+        let on_encrypted =
+            Closure::<dyn FnMut(MediaEncryptedEvent)>::new(move |event: MediaEncryptedEvent| {
+                let Ok(Some(init_data)) = event.init_data() else {
+                    return;
+                };
+
+                let _ = events.send(InternalEvent::Encrypted {
+                    init_data_type: event.init_data_type(),
+                    init_data: js_sys::Uint8Array::new(&init_data).to_vec(),
+                });
+            });
+
+        video
+            .add_event_listener_with_callback("encrypted", on_encrypted.as_ref().unchecked_ref())
+            .map_err(js_error)?;
+
+        self.key_system = Some(system);
+        self.license_server = server;
+        self.media_keys = Some(media_keys);
+        self.video = Some(video.clone());
+        self.on_encrypted = Some(on_encrypted);
+
+        Ok(())
+    }
+
+    /// Opens a key session for new init data. Duplicate init data (e.g. audio and video sharing a
+    /// key) reuses the existing session. Returns the license request generation to drive.
+    pub fn on_encrypted(
+        &mut self,
+        init_data_type: String,
+        mut init_data: Vec<u8>,
+        events: flume::Sender<InternalEvent>,
+    ) -> Result<Option<DrmTask>, DrmError> {
+        let Some(media_keys) = self.media_keys.as_ref() else {
+            tracing::warn!("Got encrypted event without media keys.");
+            return Ok(None);
+        };
+
+        if self
+            .sessions
+            .iter()
+            .any(|session| session.init_data == init_data)
+        {
+            return Ok(None);
+        }
+
+        let session = media_keys.create_session().map_err(js_error)?;
+        let index = self.sessions.len();
+        let key_events = events.clone();
+
+        let on_message =
+            Closure::<dyn FnMut(MediaKeyMessageEvent)>::new(move |event: MediaKeyMessageEvent| {
+                let Ok(message) = event.message() else {
+                    return;
+                };
+
+                let _ = events.send(InternalEvent::LicenseMessage {
+                    session: index,
+                    message: js_sys::Uint8Array::new(&message).to_vec(),
+                    message_type: event.message_type().into(),
+                });
+            });
+
+        let on_key_statuses = Closure::<dyn FnMut()>::new(move || {
+            let _ = key_events.send(InternalEvent::KeyStatusesChanged { session: index });
+        });
+
+        session.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        session.set_onkeystatuseschange(Some(on_key_statuses.as_ref().unchecked_ref()));
+
+        let request = session.generate_request_with_u8_array(&init_data_type, &mut init_data);
+        let task = Box::pin(async move {
+            if let Err(error) = JsFuture::from(request).await {
+                tracing::error!(?error, "Failed to generate license request.");
+            }
+        });
+
+        self.sessions.push(Session {
+            session,
+            init_data,
+            message_type: LicenseMessageType::Request,
+            _on_message: on_message,
+            _on_key_statuses: on_key_statuses,
+        });
+
+        Ok(Some(task))
+    }
+
+    /// Builds the license request for a key session message, passed through
+    /// [`DrmConfig::license_request_filter`].
+    pub fn license_request(
+        &mut self,
+        loader: &dyn Loader,
+        session: usize,
+        message: Vec<u8>,
+        message_type: LicenseMessageType,
+    ) -> Option<LoadFuture> {
+        let server = self.license_server.as_ref()?;
+        let context = self.license_context(session, message_type)?;
+
+        let mut request = SegmentRequest::new(server, RequestKind::License);
+        request.body = Some(Bytes::from(message));
+
+        if let Some(filter) = &self.config.license_request_filter {
+            filter(&context, &mut request);
+        }
+
+        self.sessions[session].message_type = message_type;
+        Some(loader.fetch(request))
+    }
+
+    /// Hands a license server response, passed through [`DrmConfig::license_response_filter`],
+    /// to the key session that asked for it. Returns the session update to drive.
+    pub fn on_license_response(
+        &mut self,
+        session: usize,
+        response: Bytes,
+    ) -> Result<Option<DrmTask>, DrmError> {
+        let Some(filter) = self.config.license_response_filter.clone() else {
+            return Ok(self.on_license(session, response.to_vec()));
+        };
+
+        let message_type = self
+            .sessions
+            .get(session)
+            .map_or(LicenseMessageType::Request, |session| session.message_type);
+        let Some(context) = self.license_context(session, message_type) else {
+            return Ok(None);
+        };
+
+        let license = filter(&context, response)?;
+        Ok(self.on_license(session, license.to_vec()))
+    }
+
+    fn license_context(
+        &self,
+        session: usize,
+        message_type: LicenseMessageType,
+    ) -> Option<LicenseContext> {
+        self.sessions.get(session)?;
+
+        Some(LicenseContext {
+            key_system: self.key_system?,
+            message_type,
+            session,
+        })
+    }
+
+    /// Current status of every key of a key session, by key ID in lowercase hex.
+    pub fn key_statuses(&self, session: usize) -> Vec<(String, KeyStatus)> {
+        let Some(session) = self.sessions.get(session) else {
+            return vec![];
+        };
+
+        let statuses = Rc::new(core::cell::RefCell::new(vec![]));
+        let callback = {
+            let statuses = statuses.clone();
+            Closure::<dyn FnMut(JsValue, JsValue)>::new(move |status: JsValue, key_id: JsValue| {
+                let key_id: String = js_sys::Uint8Array::new(&key_id)
+                    .to_vec()
+                    .iter()
+                    .map(|byte| format!("{byte:02x}"))
+                    .collect();
+
+                if let Some(status) = status.as_string().as_deref().and_then(KeyStatus::from_name) {
+                    statuses.borrow_mut().push((key_id, status));
+                }
+            })
+        };
+
+        // `forEach` calls back synchronously.
+        let _ = session
+            .session
+            .key_statuses()
+            .for_each(callback.as_ref().unchecked_ref());
+
+        statuses.take()
+    }
+
+    /// Answers a ClearKey license request with the configured keys, without a license server.
+    /// `None` unless ClearKey with configured keys is in use. Returns the session update to
+    /// drive.
+    pub fn clearkey_license(&mut self, session: usize, message: &[u8]) -> Option<DrmTask> {
+        if self.key_system != Some(KeySystem::ClearKey) || self.clearkey_keys.is_empty() {
+            return None;
+        }
+
+        // `{"kids":["<base64url>", ...],"type":"temporary"}`
+        let request = js_sys::JSON::parse(core::str::from_utf8(message).ok()?).ok()?;
+        let kids: js_sys::Array = js_sys::Reflect::get(&request, &"kids".into())
+            .ok()?
+            .dyn_into()
+            .ok()?;
+
+        let keys: Vec<_> = kids
+            .iter()
+            .filter_map(|kid| kid.as_string())
+            .filter_map(|kid| match self.clearkey_keys.get(&kid) {
+                Some(key) => Some(format!(r#"{{"kty":"oct","kid":"{kid}","k":"{key}"}}"#)),
+                None => {
+                    tracing::warn!(kid, "No ClearKey key configured for requested key ID.");
+                    None
+                }
+            })
+            .collect();
+
+        let license = format!(r#"{{"keys":[{}],"type":"temporary"}}"#, keys.join(","));
+        self.on_license(session, license.into_bytes())
+    }
+
+    /// Hands a license to the key session that asked for it. Returns the session update to
+    /// drive.
+    pub fn on_license(&mut self, session: usize, mut license: Vec<u8>) -> Option<DrmTask> {
+        let session = self.sessions.get(session)?;
+
+        let update = session.session.update_with_u8_array(&mut license);
+        Some(Box::pin(async move {
+            if let Err(error) = JsFuture::from(update).await {
+                tracing::error!(?error, "Key session rejected the license.");
+            }
+        }))
+    }
+
+    /// Closes all key sessions, removes the `encrypted` listener and detaches the media keys.
+    pub fn detach(&mut self) {
+        for session in self.sessions.drain(..) {
+            session.session.set_onmessage(None);
+            session.session.set_onkeystatuseschange(None);
+            let _ = session.session.close();
+        }
+
+        if let Some(video) = self.video.take() {
+            if let Some(listener) = self.on_encrypted.take() {
+                let _ = video.remove_event_listener_with_callback(
+                    "encrypted",
+                    listener.as_ref().unchecked_ref(),
+                );
+            }
+
+            let _ = video.set_media_keys(None);
+        }
+
+        self.key_system = None;
+        self.license_server = None;
+        self.media_keys = None;
+    }
+}
+
+/// A single `MediaKeySystemConfiguration` listing the content types of every track.
+fn key_system_configurations(tracks: &[Track]) -> JsValue {
+    let video = js_sys::Array::new();
+    let audio = js_sys::Array::new();
+
+    for track in tracks {
+        let Ok(content_type) = track.mime_type_with_codecs() else {
+            continue;
+        };
+
+        let capability = MediaKeySystemMediaCapability::new();
+        capability.set_content_type(&content_type);
+
+        if track.is_video() {
+            video.push(&capability);
+        } else if track.is_audio() {
+            audio.push(&capability);
+        }
+    }
+
+    let configuration = MediaKeySystemConfiguration::new();
+    configuration.set_init_data_types(&js_sys::Array::of1(&JsValue::from_str("cenc")));
+    configuration.set_video_capabilities(&video);
+    configuration.set_audio_capabilities(&audio);
+
+    js_sys::Array::of1(&configuration).into()
+}
+
+/// Lowercase hex without the dashes of the UUID form.
+pub(crate) fn normalize_key_id(key_id: &str) -> String {
+    key_id
+        .chars()
+        .filter(|c| *c != '-')
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+pub(crate) fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    // `from_str_radix` would take a sign, e.g. `+f`.
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+/// Unpadded base64url, as used by the JSON Web Keys of ClearKey licenses.
+fn encode_base64url(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (index, byte)| {
+            bits | (*byte as u32) << (16 - 8 * index)
+        });
+
+        for index in 0..=chunk.len() {
+            encoded.push(ALPHABET[(bits >> (18 - 6 * index) & 0x3f) as usize] as char);
+        }
+    }
+
+    encoded
+}
+
+fn js_error(error: JsValue) -> DrmError {
+    DrmError::MediaKeys(format!("{error:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_hex() {
+        assert_eq!(decode_hex(""), Some(vec![]));
+        assert_eq!(decode_hex("00ff7fA0"), Some(vec![0x00, 0xFF, 0x7F, 0xA0]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("0g"), None);
+        assert_eq!(decode_hex("+f"), None);
+        assert_eq!(decode_hex("é0"), None);
+    }
+
+    #[test]
+    fn encodes_unpadded_base64url() {
+        // The RFC 4648 test vectors, without padding.
+        for (data, encoded) in [
+            ("", ""),
+            ("f", "Zg"),
+            ("fo", "Zm8"),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg"),
+            ("fooba", "Zm9vYmE"),
+            ("foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(encode_base64url(data.as_bytes()), encoded);
+        }
+
+        // The characters that differ from standard base64.
+        assert_eq!(encode_base64url(&[0xFB, 0xFF, 0xBF]), "-_-_");
+    }
+
+    #[test]
+    fn normalizes_key_ids() {
+        assert_eq!(
+            normalize_key_id("9EB4050D-E44B-4802-932E-27D75083E266"),
+            "9eb4050de44b4802932e27d75083e266"
+        );
+    }
+}
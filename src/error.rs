@@ -0,0 +1,162 @@
+use crate::download::DownloadError;
+use crate::drm::DrmError;
+use crate::loader::LoadError;
+use crate::messages::ErrorCode;
+use crate::player::Error;
+
+use std::sync::Arc;
+
+use displaydoc::Display;
+use serde::Deserialize;
+use serde::Serialize;
+use thiserror::Error;
+
+/// The underlying error of an [`AshinaError`]. Shared so the error can be cloned into events.
+pub type ErrorSource = Arc<dyn std::error::Error + Send + Sync>;
+
+/// Everything that can go wrong in the player, by what failed. The technical cause is kept as
+/// the error's [`std::error::Error::source`]. Serializes as its [`ErrorCode`] and the message of
+/// the cause, which is all that survives a round trip.
+#[derive(Clone, Debug, Display, Error, Serialize, Deserialize)]
+#[serde(into = "SerializedError", from = "SerializedError")]
+pub enum AshinaError {
+    /// manifest could not be downloaded
+    ManifestFetch(#[source] ErrorSource),
+    /// manifest could not be parsed
+    ManifestParse(#[source] ErrorSource),
+    /// stream uses features or codecs this browser cannot play
+    Unsupported(#[source] ErrorSource),
+    /// media could not be decoded or buffered
+    Media(#[source] ErrorSource),
+    /// network failure while loading media
+    Network(#[source] ErrorSource),
+    /// protected content could not be decrypted
+    Drm(#[source] ErrorSource),
+    /// internal player error
+    Internal(#[source] ErrorSource),
+}
+
+impl AshinaError {
+    /// Wraps a plain message, for causes that aren't an error type of their own, e.g. a
+    /// `JsValue`.
+    pub fn message(message: impl Into<String>) -> ErrorSource {
+        Arc::from(Box::<dyn std::error::Error + Send + Sync>::from(
+            message.into(),
+        ))
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::ManifestFetch(_) => ErrorCode::ManifestFetch,
+            Self::ManifestParse(_) => ErrorCode::ManifestParse,
+            Self::Unsupported(_) => ErrorCode::Unsupported,
+            Self::Media(_) => ErrorCode::Media,
+            Self::Network(_) => ErrorCode::Network,
+            Self::Drm(_) => ErrorCode::Drm,
+            Self::Internal(_) => ErrorCode::Internal,
+        }
+    }
+
+    /// The underlying player [`Error`], if that is what failed.
+    pub fn player_error(&self) -> Option<Error> {
+        let (Self::ManifestFetch(source)
+        | Self::ManifestParse(source)
+        | Self::Unsupported(source)
+        | Self::Media(source)
+        | Self::Network(source)
+        | Self::Drm(source)
+        | Self::Internal(source)) = self;
+
+        source.downcast_ref::<Error>().copied()
+    }
+
+    /// Whether the operation was cancelled rather than failed.
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self.player_error(), Some(Error::Cancelled))
+    }
+}
+
+/// Serialized form of an [`AshinaError`].
+#[derive(Serialize, Deserialize)]
+struct SerializedError {
+    code: ErrorCode,
+    message: String,
+}
+
+impl From<AshinaError> for SerializedError {
+    fn from(error: AshinaError) -> Self {
+        let (AshinaError::ManifestFetch(source)
+        | AshinaError::ManifestParse(source)
+        | AshinaError::Unsupported(source)
+        | AshinaError::Media(source)
+        | AshinaError::Network(source)
+        | AshinaError::Drm(source)
+        | AshinaError::Internal(source)) = &error;
+
+        Self {
+            code: error.code(),
+            message: source.to_string(),
+        }
+    }
+}
+
+impl From<SerializedError> for AshinaError {
+    fn from(error: SerializedError) -> Self {
+        let source = Self::message(error.message);
+
+        match error.code {
+            ErrorCode::ManifestFetch => Self::ManifestFetch(source),
+            ErrorCode::ManifestParse => Self::ManifestParse(source),
+            ErrorCode::Unsupported => Self::Unsupported(source),
+            ErrorCode::Media => Self::Media(source),
+            ErrorCode::Network => Self::Network(source),
+            ErrorCode::Drm => Self::Drm(source),
+            ErrorCode::Internal => Self::Internal(source),
+        }
+    }
+}
+
+impl From<Error> for AshinaError {
+    fn from(error: Error) -> Self {
+        let source = Arc::new(error);
+
+        match error.code() {
+            ErrorCode::Media => Self::Media(source),
+            ErrorCode::Network => Self::Network(source),
+            _ => Self::Internal(source),
+        }
+    }
+}
+
+impl From<LoadError> for AshinaError {
+    fn from(error: LoadError) -> Self {
+        Self::Network(Arc::new(error))
+    }
+}
+
+impl From<DrmError> for AshinaError {
+    fn from(error: DrmError) -> Self {
+        Self::Drm(Arc::new(error))
+    }
+}
+
+impl From<DownloadError> for AshinaError {
+    fn from(error: DownloadError) -> Self {
+        match error {
+            DownloadError::Storage(_) => Self::Internal(Arc::new(error)),
+            _ => Self::Unsupported(Arc::new(error)),
+        }
+    }
+}
+
+impl From<dash_mpd::DashMpdError> for AshinaError {
+    fn from(error: dash_mpd::DashMpdError) -> Self {
+        Self::ManifestParse(Arc::new(error))
+    }
+}
+
+impl<T> From<flume::SendError<T>> for AshinaError {
+    fn from(_: flume::SendError<T>) -> Self {
+        Self::Internal(Self::message("player event channel closed"))
+    }
+}
@@ -0,0 +1,178 @@
+use crate::cdn::CdnSwitchReason;
+use crate::drm::KeyStatus;
+use crate::error::AshinaError;
+use crate::log::LogRecord;
+use crate::manifest::MissingInfo;
+use crate::manifest::RepresentationId;
+use crate::manifest::TrackId;
+use crate::parse::id3::Id3Frame;
+use crate::parse::SegmentIssue;
+use crate::stats::PlaybackStats;
+
+use core::ops::RangeInclusive;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Events published by the player to every subscriber registered through
+/// [`crate::MediaPlayer::subscribe`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum PlayerEvent {
+    /// A media segment failed structural verification and was not appended.
+    MalformedSegment {
+        track: TrackId,
+        segment: usize,
+        issues: Vec<SegmentIssue>,
+    },
+    /// A media segment finished appending. `append_ms` is the time from `appendBuffer` to
+    /// `updateend`.
+    SegmentAppended {
+        track: TrackId,
+        number: usize,
+        pts: f64,
+        duration: f64,
+        bytes: usize,
+        append_ms: f64,
+    },
+    /// The browser evicted media from a track's buffer to make room for new appends.
+    RangeEvicted {
+        track: TrackId,
+        range: RangeInclusive<f64>,
+    },
+    /// The buffered ranges of a track changed after an append or removal.
+    BufferedChanged {
+        track: TrackId,
+        ranges: Vec<RangeInclusive<f64>>,
+    },
+    /// Every active track has [`crate::config::BufferConfig::ready_buffer`] buffered at
+    /// `position`, the start position. Sent once per load, before `autoplay` starts playback.
+    PlaybackReady { position: f64 },
+    /// The playhead stopped advancing because there is no media at `position`. Recovery is
+    /// attempted automatically.
+    BufferStalled { position: f64 },
+    /// The video element entered or left picture-in-picture.
+    PictureInPictureChanged { active: bool },
+    /// The video element entered or left fullscreen.
+    FullscreenChanged { active: bool },
+    /// Playback reached the end of a VOD presentation.
+    Ended,
+    /// The player moved from one [`PlaybackState`] to another.
+    StateChanged {
+        from: PlaybackState,
+        to: PlaybackState,
+    },
+    /// The current presentation ended or was skipped and the next queued manifest took over.
+    QueueAdvanced { manifest: String },
+    /// The main content paused for an ad break, which plays in the ad element.
+    AdBreakStarted { id: String, manifest: String },
+    /// An ad break is over and the main content resumes. `error` is set if the ad failed to
+    /// play, in which case the rest of it was skipped.
+    AdBreakEnded {
+        id: String,
+        error: Option<AshinaError>,
+    },
+    /// Segment requests moved to another CDN.
+    CdnSwitched {
+        base_url: String,
+        reason: CdnSwitchReason,
+    },
+    /// A representation is left out because the manifest doesn't say enough about it, e.g. it
+    /// has no `@codecs`.
+    TrackSkipped {
+        track: TrackId,
+        representation: RepresentationId,
+        missing: MissingInfo,
+    },
+    /// A track switched to a different rendition.
+    QualityChanged {
+        track: TrackId,
+        representation: RepresentationId,
+        bitrate: Option<u64>,
+        reason: SwitchReason,
+    },
+    /// An event from an MPD `EventStream` or an in-band `emsg` box, e.g. an SCTE-35 ad marker.
+    /// Published once per event as soon as it is known, ahead of `start`, which is in media
+    /// element time.
+    TimedMetadata {
+        scheme: String,
+        value: Option<String>,
+        id: Option<String>,
+        start: f64,
+        duration: Option<f64>,
+        payload: Vec<u8>,
+        /// The frames of `payload` if it is an ID3 tag, e.g. song titles of an audio stream.
+        id3: Vec<Id3Frame>,
+    },
+    /// The keys of a DRM key session changed status, e.g. became usable after a license was
+    /// loaded, or expired. Key IDs are in lowercase hex.
+    KeyStatusesChanged {
+        session: usize,
+        statuses: Vec<(String, KeyStatus)>,
+    },
+    /// A media error is being recovered from with `action`. `track` is `None` for errors of the
+    /// media element as a whole. Once recovery keeps failing, [`Self::Error`] follows instead.
+    MediaRecovery {
+        track: Option<TrackId>,
+        action: RecoveryAction,
+        error: AshinaError,
+    },
+    /// Something went wrong. Errors failing [`crate::MediaPlayer::create`] are published as well
+    /// as returned.
+    Error(AshinaError),
+    /// Periodic statistics report, sent every [`crate::config::PlayerConfig::heartbeat_interval`].
+    Heartbeat(PlaybackStats),
+    /// A log record, if [`crate::log::LogConfig::forward_events`] is set.
+    Log(LogRecord),
+}
+
+/// How the player recovers from a media error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecoveryAction {
+    /// Append the failed segment again, for a source buffer that was busy.
+    Retry,
+    /// Drop the track's buffer and append again from the init segment and the segment at the
+    /// playhead, which starts with a keyframe.
+    ResetSourceBuffer,
+    /// Load the manifest again into a new media source, resuming at the same position.
+    Reload,
+}
+
+/// Where playback stands, see [`crate::MediaPlayer::state`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PlaybackState {
+    /// Nothing is loaded.
+    #[default]
+    Idle,
+    /// The manifest is loading, or the start position buffering.
+    Loading,
+    /// The start position is buffered and playback hasn't started yet.
+    Ready,
+    /// Playback waits for media at the playhead.
+    Buffering,
+    Playing,
+    Paused,
+    /// The playhead moved and the media at the new position is loading.
+    Seeking,
+    /// Playback reached the end of a VOD presentation.
+    Ended,
+    /// Loading or playback failed and won't recover on its own.
+    Errored,
+}
+
+/// Why the player switched renditions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SwitchReason {
+    /// The application changed the quality selection.
+    Selection,
+    /// Automatic selection reacted to the bandwidth estimate.
+    Bandwidth,
+    /// Rapid seeking moved to the trick mode rendition, or seeking stopped and playback moved
+    /// back.
+    Scrubbing,
+    /// The page was hidden or shown again, see [`crate::config::BackgroundPolicy`].
+    Visibility,
+    /// The browser reported a change of the network connection, see [`crate::connection`].
+    Connection,
+    /// The video element dropped too many frames, see [`crate::abr::DroppedFramesRule`].
+    DroppedFrames,
+}
@@ -0,0 +1,133 @@
+//! Opt-in keyboard control of a player, enabled through
+//! [`crate::config::PlayerConfig::hotkeys`]. Keys are matched against `KeyboardEvent.key`, so
+//! bindings follow the user's keyboard layout.
+//!
+//! ```ignore
+//! let hotkeys = HotkeyConfig::default()
+//!     .with_target(HotkeyTarget::Document)
+//!     .with_binding("k", HotkeyAction::TogglePlay);
+//! ```
+
+use wasm_bindgen::JsCast;
+use web_sys::HtmlElement;
+use web_sys::KeyboardEvent;
+
+use std::collections::HashMap;
+
+/// What a key does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HotkeyAction {
+    TogglePlay,
+    /// Seeks back by [`HotkeyConfig::seek_step`].
+    SeekBackward,
+    SeekForward,
+    /// Raises the volume by [`HotkeyConfig::volume_step`].
+    VolumeUp,
+    VolumeDown,
+    ToggleMute,
+    ToggleFullscreen,
+}
+
+/// Where key presses are listened for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HotkeyTarget {
+    /// The video element, which only gets key presses while it has focus. Elements without
+    /// `controls` need a `tabindex` to be focusable.
+    #[default]
+    Video,
+    /// The whole page, except while typing into a form field or editable element.
+    Document,
+}
+
+/// Key bindings of a player. The defaults are space to play and pause, left and right arrows to
+/// seek, up and down arrows for the volume, `m` to mute and `f` for fullscreen.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HotkeyConfig {
+    pub target: HotkeyTarget,
+    /// Actions by `KeyboardEvent.key`, e.g. `" "` or `"ArrowLeft"`. Letters also match with
+    /// caps lock on.
+    pub bindings: HashMap<String, HotkeyAction>,
+    /// Seconds a seek moves the playhead.
+    pub seek_step: f64,
+    /// Volume change per key press, between `0.` and `1.`.
+    pub volume_step: f64,
+}
+
+impl Default for HotkeyConfig {
+    fn default() -> Self {
+        let bindings = [
+            (" ", HotkeyAction::TogglePlay),
+            ("ArrowLeft", HotkeyAction::SeekBackward),
+            ("ArrowRight", HotkeyAction::SeekForward),
+            ("ArrowUp", HotkeyAction::VolumeUp),
+            ("ArrowDown", HotkeyAction::VolumeDown),
+            ("m", HotkeyAction::ToggleMute),
+            ("f", HotkeyAction::ToggleFullscreen),
+        ]
+        .into_iter()
+        .map(|(key, action)| (key.to_string(), action))
+        .collect();
+
+        Self {
+            target: HotkeyTarget::Video,
+            bindings,
+            seek_step: 5.,
+            volume_step: 0.1,
+        }
+    }
+}
+
+impl HotkeyConfig {
+    pub fn with_target(mut self, target: HotkeyTarget) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Binds `key` to `action`, replacing any previous binding of `key`.
+    pub fn with_binding(mut self, key: impl Into<String>, action: HotkeyAction) -> Self {
+        self.bindings.insert(key.into(), action);
+        self
+    }
+
+    pub fn without_binding(mut self, key: &str) -> Self {
+        self.bindings.remove(key);
+        self
+    }
+
+    pub fn with_seek_step(mut self, seconds: f64) -> Self {
+        self.seek_step = seconds;
+        self
+    }
+
+    pub fn with_volume_step(mut self, step: f64) -> Self {
+        self.volume_step = step;
+        self
+    }
+
+    /// The action bound to the key pressed in `event`. Presses with Ctrl, Alt or Meta held are
+    /// left to the browser, as are presses while typing.
+    pub fn action_for(&self, event: &KeyboardEvent) -> Option<HotkeyAction> {
+        if event.ctrl_key() || event.alt_key() || event.meta_key() || is_typing(event) {
+            return None;
+        }
+
+        let key = event.key();
+        self.bindings
+            .get(&key)
+            .or_else(|| self.bindings.get(&key.to_lowercase()))
+            .copied()
+    }
+}
+
+/// Whether `event` is aimed at a form field or an editable element.
+fn is_typing(event: &KeyboardEvent) -> bool {
+    let Some(element) = event
+        .target()
+        .and_then(|target| target.dyn_into::<HtmlElement>().ok())
+    else {
+        return false;
+    };
+
+    element.is_content_editable()
+        || matches!(element.tag_name().as_str(), "INPUT" | "TEXTAREA" | "SELECT")
+}
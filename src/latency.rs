@@ -0,0 +1,75 @@
+/// Rate used to catch up when playback fell behind the target latency.
+const CATCH_UP_RATE: f64 = 1.05;
+/// Rate used to fall back when playback got closer to the live edge than targeted.
+const SLOW_DOWN_RATE: f64 = 0.95;
+/// Deviation from the target latency, in seconds, at which the rate starts being adjusted.
+const ENGAGE_THRESHOLD: f64 = 0.5;
+/// Deviation, in seconds, below which playback returns to normal speed. Lower than
+/// [`ENGAGE_THRESHOLD`] so the rate doesn't flip back and forth around a single threshold,
+/// which is heard as pitch wobble.
+const RELEASE_THRESHOLD: f64 = 0.1;
+/// Falling this many seconds behind the target is too much to catch up on by speeding up.
+const SEEK_THRESHOLD: f64 = 5.;
+
+/// What to do about the distance to the live edge.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LatencyAction {
+    /// Change `playbackRate`.
+    SetRate(f64),
+    /// Jump forward by this many seconds.
+    Seek(f64),
+}
+
+/// Keeps live playback at a target distance from the live edge by gently speeding up or
+/// slowing down playback, and by seeking when it fell too far behind.
+#[derive(Clone, Debug)]
+pub struct LatencyController {
+    /// Target latency, in seconds.
+    target: f64,
+    /// Rate the controller last asked for.
+    rate: f64,
+}
+
+impl LatencyController {
+    pub fn new(target: f64) -> Self {
+        Self { target, rate: 1. }
+    }
+
+    pub fn target(&self) -> f64 {
+        self.target
+    }
+
+    /// Forgets the rate set before, e.g. after the player was detached.
+    pub fn reset(&mut self) {
+        self.rate = 1.;
+    }
+
+    /// Decides on an action for the measured `latency` in seconds. `None` when nothing has to
+    /// change.
+    pub fn update(&mut self, latency: f64) -> Option<LatencyAction> {
+        let drift = latency - self.target;
+
+        if drift > SEEK_THRESHOLD {
+            self.rate = 1.;
+            return Some(LatencyAction::Seek(drift));
+        }
+
+        let rate = if drift.abs() <= RELEASE_THRESHOLD {
+            1.
+        } else if drift > ENGAGE_THRESHOLD {
+            CATCH_UP_RATE
+        } else if drift < -ENGAGE_THRESHOLD {
+            SLOW_DOWN_RATE
+        } else {
+            // Between the thresholds: keep doing whatever we did.
+            self.rate
+        };
+
+        if rate == self.rate {
+            return None;
+        }
+
+        self.rate = rate;
+        Some(LatencyAction::SetRate(rate))
+    }
+}
@@ -1,76 +1,622 @@
+#[cfg(not(any(feature = "fetch-backend", feature = "reqwest")))]
+compile_error!("ashina needs a loader backend, enable `fetch-backend` or `reqwest`.");
+
+pub mod abr;
+pub mod ads;
 pub mod buffer;
+pub mod cache;
+pub mod capabilities;
+pub mod cdn;
+pub mod config;
+pub mod connection;
+pub mod diagnostics;
+pub mod download;
+pub mod drm;
+pub mod error;
+pub mod event;
+pub mod hotkeys;
+pub mod latency;
+pub mod loader;
+pub mod log;
 pub mod manifest;
+pub mod messages;
+pub mod operations;
 pub mod parse;
 pub mod player;
 pub mod range;
+pub mod stats;
+pub mod task;
+pub mod text;
+pub mod thumbnail;
+#[cfg(feature = "ui")]
+pub mod ui;
 
 use dioxus::prelude::*;
 use futures::channel::{mpsc, oneshot};
+use tracing::Instrument;
 use wasm_bindgen_futures::spawn_local;
 
+use crate::abr::QualitySelection;
+use crate::ads::AdBreak;
+use crate::config::AbrConfig;
+use crate::config::BufferConfig;
+use crate::config::ConfigError;
+use crate::config::LiveConfig;
+use crate::config::NetworkConfig;
+use crate::config::PlayerConfig;
+use crate::config::TextConfig;
+use crate::diagnostics::Diagnostics;
+use crate::drm::DrmConfig;
+use crate::error::AshinaError;
+use crate::event::PlaybackState;
+use crate::event::PlayerEvent;
+use crate::log::LogConfig;
+use crate::manifest::ManifestInfo;
+use crate::manifest::TrackId;
+use crate::manifest::TrackInfo;
+use crate::messages::DefaultMessages;
+use crate::messages::ErrorMessages;
+use crate::player::PlayerId;
+use crate::stats::PlaybackStats;
+use crate::thumbnail::Thumbnail;
+
+use core::cell::RefCell;
+use core::future::Future;
+use std::rc::Rc;
 
 #[derive(Debug)]
 pub enum PlayerState {
     Created {
         id: String,
         manifest: String,
-        tx: Option<oneshot::Sender<Result<(), Box<dyn std::error::Error>>>>,
+        /// Position to start at, in seconds, instead of [`config::PlayerConfig::start_position`].
+        start: Option<f64>,
+        tx: Option<oneshot::Sender<Result<ManifestInfo, AshinaError>>>,
+    },
+    /// Load another manifest into the video element of the last [`PlayerState::Created`].
+    Load {
+        manifest: String,
+        tx: Option<oneshot::Sender<Result<ManifestInfo, AshinaError>>>,
+    },
+    SetQuality(QualitySelection),
+    /// Subtitle delay in milliseconds.
+    SetTextOffset(i64),
+    /// Audio delay in milliseconds.
+    SetAudioOffset(i64),
+    /// Volume between `0.` and `1.`, kept across loads.
+    SetVolume(f64),
+    SetMuted(bool),
+    /// Play audio from this adaptation set.
+    SetAudioTrack(TrackId),
+    /// Replaces [`config::PlayerConfig::log`].
+    SetLogConfig(LogConfig),
+    /// Whether video is buffered at all, see [`config::PlayerConfig::audio_only`].
+    SetVideoEnabled(bool),
+    /// Enter or leave picture-in-picture with the video element.
+    SetPictureInPicture(bool),
+    /// Show the video element fullscreen, or leave fullscreen.
+    SetFullscreen(bool),
+    /// Play this manifest once the current presentation ends.
+    Enqueue(String),
+    /// Move on to the next queued manifest right away.
+    Next,
+    ClearQueue,
+    /// Play this break in the current presentation, see [`config::PlayerConfig::ads`].
+    ScheduleAdBreak(AdBreak),
+    /// Abort a [`PlayerState::Created`] that hasn't finished loading yet.
+    Cancel,
+    Subscribe {
+        tx: flume::Sender<PlayerEvent>,
+    },
+    /// Report what `kind` asks for through `reply`.
+    Query {
+        kind: QueryKind,
+        reply: oneshot::Sender<QueryResult>,
     },
-    Cleanup,
+    /// Tear everything down and stop. `tx` is completed once done.
+    Cleanup {
+        tx: Option<oneshot::Sender<()>>,
+    },
+}
+
+/// What a [`PlayerState::Query`] asks the player about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueryKind {
+    /// Every representation of the current manifest, see [`MediaPlayer::tracks`].
+    Tracks,
+    /// A [`PlaybackStats`] snapshot.
+    Stats,
+    /// Buffered ranges of the video element.
+    Buffered,
+    /// The [`PlaybackState`].
+    State,
+    /// A [`Diagnostics`] dump.
+    Diagnostics,
+}
+
+/// The player's answer to a [`PlayerState::Query`], the variant named like the [`QueryKind`].
+#[derive(Clone, Debug)]
+pub enum QueryResult {
+    Tracks(Vec<TrackInfo>),
+    Stats(PlaybackStats),
+    Buffered(Vec<(f64, f64)>),
+    State(PlaybackState),
+    Diagnostics(Box<Diagnostics>),
 }
 
+/// A player driving one video element. Every instance has its own event loop, channels and
+/// `MediaSource`, so several can play side by side, e.g. in a grid of previews, as long as each
+/// gets its own element.
 pub struct MediaPlayer {
+    id: PlayerId,
     tx: mpsc::Sender<PlayerState>,
 
-    cached_track_list: Option<Vec<()>>,
+    /// Set once a `create` or `load` finished, by the future it returned.
+    manifest_info: Rc<RefCell<Option<Rc<ManifestInfo>>>>,
+    messages: Box<dyn ErrorMessages>,
 }
 
 impl MediaPlayer {
+    /// A player with the default config, see [`Self::builder`] for anything else.
     pub fn new() -> Self {
-        let mut player = player::Player::new();
+        Self::with_config(PlayerConfig::default())
+    }
+
+    /// Configures a player section by section and validates the config before starting it.
+    ///
+    /// ```ignore
+    /// let player = MediaPlayer::builder()
+    ///     .with_buffer(BufferConfig {
+    ///         ready_buffer: Duration::from_secs(4),
+    ///         ..BufferConfig::default()
+    ///     })
+    ///     .with_live(LiveConfig {
+    ///         low_latency: true,
+    ///         target_latency: Some(Duration::from_secs(3)),
+    ///     })
+    ///     .build()?;
+    /// ```
+    pub fn builder() -> MediaPlayerBuilder {
+        MediaPlayerBuilder::default()
+    }
+
+    /// Starts a player with `config` as is. Unlike [`Self::builder`], the config isn't
+    /// validated.
+    pub fn with_config(config: PlayerConfig) -> Self {
+        let mut player = player::Player::new(config);
+        let id = player.id();
         let (tx, rx) = mpsc::channel(2048);
 
-        spawn_local(async move {
-            if let Err(e) = player.listen(rx).await {
-                tracing::error!("Player listen failed: {:?}", e);
+        spawn_local(
+            async move {
+                if let Err(e) = player.listen(rx).await {
+                    tracing::error!("Player listen failed: {:?}", e);
+                }
             }
-        });
+            .instrument(tracing::info_span!("player", %id)),
+        );
+
+        Self {
+            id,
+            tx,
+            manifest_info: Rc::default(),
+            messages: Box::new(DefaultMessages),
+        }
+    }
+
+    pub fn id(&self) -> PlayerId {
+        self.id
+    }
+
+    /// Loads `manifest` into the video element with id `id`. Resolves with an error wrapping
+    /// [`player::Error::Cancelled`], see [`AshinaError::is_cancelled`], if [`Self::cancel`] is
+    /// called or another `create` or `load` is issued before loading finished.
+    /// Fails if another player is attached to the element, and with
+    /// [`player::Error::ElementNotFound`] if no video element with id `id` is mounted within
+    /// [`config::PlayerConfig::element_timeout`].
+    ///
+    /// The command is sent right away, so the player can be used, and the load cancelled, while
+    /// the returned future is pending.
+    pub fn create(
+        &mut self,
+        id: String,
+        manifest: String,
+    ) -> impl Future<Output = Result<(), AshinaError>> + use<> {
+        self.create_with_start(id, manifest, None)
+    }
 
-        Self { tx, cached_track_list: None }
+    /// Like [`Self::create`], starting `position` seconds into the presentation, e.g. to resume
+    /// where the viewer left off. Buffering starts at `position` right away.
+    pub fn create_at(
+        &mut self,
+        id: String,
+        manifest: String,
+        position: f64,
+    ) -> impl Future<Output = Result<(), AshinaError>> + use<> {
+        self.create_with_start(id, manifest, Some(position))
     }
 
-    pub async fn create(&mut self, id: String, manifest: String) -> Result<(), Box<dyn std::error::Error>> {
+    fn create_with_start(
+        &mut self,
+        id: String,
+        manifest: String,
+        start: Option<f64>,
+    ) -> impl Future<Output = Result<(), AshinaError>> + use<> {
         let (tx, rx) = oneshot::channel();
+        self.manifest_info.replace(None);
+
+        self.tx
+            .try_send(PlayerState::Created {
+                id,
+                manifest,
+                start,
+                tx: Some(tx),
+            })
+            .expect("Channel full");
+
+        finish_load(rx, self.manifest_info.clone())
+    }
+
+    /// Replaces what is playing with `manifest`, on the video element passed to the last
+    /// [`Self::create`]. Everything of the previous media is torn down first, down to the
+    /// `MediaSource`, so playlist-style apps can switch videos on one element.
+    pub fn load(
+        &mut self,
+        manifest: String,
+    ) -> impl Future<Output = Result<(), AshinaError>> + use<> {
+        let (tx, rx) = oneshot::channel();
+        self.manifest_info.replace(None);
+
+        self.tx
+            .try_send(PlayerState::Load {
+                manifest,
+                tx: Some(tx),
+            })
+            .expect("Channel full");
+
+        finish_load(rx, self.manifest_info.clone())
+    }
+
+    /// Returns a receiver for every event the player publishes from now on.
+    pub fn subscribe(&mut self) -> flume::Receiver<PlayerEvent> {
+        let (tx, rx) = flume::unbounded();
+
+        self.tx
+            .try_send(PlayerState::Subscribe { tx })
+            .expect("Channel full");
+
+        rx
+    }
+
+    /// Asks the player about `kind`. Resolves with `None` if the player has stopped. The query
+    /// is sent right away, so the future doesn't hold on to `self`.
+    pub fn query(&self, kind: QueryKind) -> impl Future<Output = Option<QueryResult>> + use<> {
+        let (reply, rx) = oneshot::channel();
+        let sent = self
+            .tx
+            .clone()
+            .try_send(PlayerState::Query { kind, reply })
+            .is_ok();
+
+        async move {
+            match sent {
+                true => rx.await.ok(),
+                false => None,
+            }
+        }
+    }
+
+    /// Time ranges, in seconds, the video element has media for, e.g. to draw loaded regions on
+    /// a seek bar. Empty while nothing is attached.
+    pub async fn buffered(&self) -> Vec<(f64, f64)> {
+        match self.query(QueryKind::Buffered).await {
+            Some(QueryResult::Buffered(ranges)) => ranges,
+            _ => vec![],
+        }
+    }
+
+    /// Where playback stands. Every change is published as [`PlayerEvent::StateChanged`].
+    /// [`PlaybackState::Idle`] if the player has stopped.
+    pub async fn state(&self) -> PlaybackState {
+        match self.query(QueryKind::State).await {
+            Some(QueryResult::State(state)) => state,
+            _ => PlaybackState::Idle,
+        }
+    }
+
+    /// Playback statistics as of now, like the ones [`PlayerEvent::Heartbeat`] publishes.
+    /// Empty if the player has stopped.
+    pub async fn stats(&self) -> PlaybackStats {
+        match self.query(QueryKind::Stats).await {
+            Some(QueryResult::Stats(stats)) => stats,
+            _ => PlaybackStats::default(),
+        }
+    }
+
+    /// A dump of the player's state and its recent events, errors and rendition switches, see
+    /// [`config::PlayerConfig::diagnostics_capacity`]. Attach [`Diagnostics::to_json`] to bug
+    /// reports. Empty if the player has stopped.
+    pub async fn diagnostics(&self) -> Diagnostics {
+        match self.query(QueryKind::Diagnostics).await {
+            Some(QueryResult::Diagnostics(diagnostics)) => *diagnostics,
+            _ => Diagnostics::default(),
+        }
+    }
+
+    /// Replaces the catalog used by [`Self::error_message`], e.g. with localized messages.
+    pub fn set_error_messages(&mut self, messages: impl ErrorMessages + 'static) {
+        self.messages = Box::new(messages);
+    }
+
+    /// User-presentable message for an error returned by the player.
+    pub fn error_message(&self, error: &AshinaError) -> String {
+        self.messages.message(error.code())
+    }
+
+    /// Parsed structure of the most recently loaded manifest, if any.
+    pub fn manifest_info(&self) -> Option<Rc<ManifestInfo>> {
+        self.manifest_info.borrow().clone()
+    }
+
+    /// Representations of the current manifest, as the player sees them now, e.g. with the
+    /// ones a live refresh added. Ones the browser can't decode are listed with
+    /// `supported: false` and never played. Falls back to [`Self::manifest_info`] if the player
+    /// has stopped.
+    pub fn tracks(&self) -> impl Future<Output = Vec<TrackInfo>> + use<> {
+        let query = self.query(QueryKind::Tracks);
+        let loaded = self
+            .manifest_info
+            .borrow()
+            .as_ref()
+            .map(|info| info.tracks().to_vec())
+            .unwrap_or_default();
+
+        async move {
+            match query.await {
+                Some(QueryResult::Tracks(tracks)) => tracks,
+                _ => loaded,
+            }
+        }
+    }
+
+    /// Seek-bar preview for `time`, in seconds, from the manifest's thumbnail track. `None` if
+    /// there is none.
+    pub fn thumbnail_for(&self, time: f64) -> Option<Thumbnail> {
+        self.manifest_info
+            .borrow()
+            .as_ref()?
+            .thumbnails()?
+            .thumbnail_for(time)
+    }
+
+    /// Changes how the video rendition is picked. The switch happens at the next segment
+    /// boundary.
+    pub fn set_quality(&mut self, selection: QualitySelection) {
+        self.tx
+            .try_send(PlayerState::SetQuality(selection))
+            .expect("Channel full");
+    }
+
+    /// Delays subtitle cues by `ms` milliseconds, or shows them earlier if negative. Corrects
+    /// captions that are out of sync in badly muxed sources.
+    pub fn set_text_offset(&mut self, ms: i64) {
+        self.tx
+            .try_send(PlayerState::SetTextOffset(ms))
+            .expect("Channel full");
+    }
+
+    /// Delays audio by `ms` milliseconds, or plays it earlier if negative, to correct lip-sync
+    /// e.g. on Bluetooth outputs. Buffered audio is refetched with the new timing.
+    pub fn set_audio_offset(&mut self, ms: i64) {
+        self.tx
+            .try_send(PlayerState::SetAudioOffset(ms))
+            .expect("Channel full");
+    }
+
+    /// Sets the volume, from `0.` to `1.`.
+    pub fn set_volume(&mut self, volume: f64) {
+        self.tx
+            .try_send(PlayerState::SetVolume(volume))
+            .expect("Channel full");
+    }
+
+    pub fn mute(&mut self) {
+        self.tx
+            .try_send(PlayerState::SetMuted(true))
+            .expect("Channel full");
+    }
+
+    pub fn unmute(&mut self) {
+        self.tx
+            .try_send(PlayerState::SetMuted(false))
+            .expect("Channel full");
+    }
+
+    /// Changes which log records are emitted, see [`PlayerConfig::log`].
+    pub fn set_log_config(&mut self, config: LogConfig) {
+        self.tx
+            .try_send(PlayerState::SetLogConfig(config))
+            .expect("Channel full");
+    }
+
+    /// Switches audio to another adaptation set from [`Self::tracks`], e.g. another language.
+    /// Reloads the media and resumes at the same position if playing.
+    pub fn set_audio_track(&mut self, track: TrackId) {
+        self.tx
+            .try_send(PlayerState::SetAudioTrack(track))
+            .expect("Channel full");
+    }
+
+    /// Turns video off for audio-only playback, or back on. Turning it off drops the video
+    /// buffer right away. Turning it on reloads the media and resumes at the same position, as
+    /// a video buffer can't be added to media that is already playing.
+    pub fn set_video_enabled(&mut self, enabled: bool) {
+        self.tx
+            .try_send(PlayerState::SetVideoEnabled(enabled))
+            .expect("Channel full");
+    }
+
+    /// Moves the video element into a picture-in-picture window. Browsers only allow this in
+    /// response to a user gesture, so call it from an input handler.
+    /// [`PlayerEvent::PictureInPictureChanged`] reports the outcome.
+    pub fn request_pip(&mut self) {
+        self.tx
+            .try_send(PlayerState::SetPictureInPicture(true))
+            .expect("Channel full");
+    }
+
+    /// Leaves picture-in-picture if the video element is in it.
+    pub fn exit_pip(&mut self) {
+        self.tx
+            .try_send(PlayerState::SetPictureInPicture(false))
+            .expect("Channel full");
+    }
+
+    /// Shows the video element fullscreen. Like [`Self::request_pip`], this needs a user
+    /// gesture. [`PlayerEvent::FullscreenChanged`] reports the outcome.
+    pub fn request_fullscreen(&mut self) {
+        self.tx
+            .try_send(PlayerState::SetFullscreen(true))
+            .expect("Channel full");
+    }
+
+    /// Leaves fullscreen if the video element is shown fullscreen.
+    pub fn exit_fullscreen(&mut self) {
+        self.tx
+            .try_send(PlayerState::SetFullscreen(false))
+            .expect("Channel full");
+    }
 
+    /// Queues `manifest` to play once the current presentation and everything queued before it
+    /// ended. The manifest and init segments of the next item are fetched while the current one
+    /// plays, so the switch is close to gapless. [`PlayerEvent::QueueAdvanced`] reports each
+    /// switch; [`Self::manifest_info`] keeps describing the presentation loaded by
+    /// [`Self::create`] or [`Self::load`].
+    pub fn enqueue(&mut self, manifest: String) {
         self.tx
-            .try_send(PlayerState::Created { id, manifest, tx: Some(tx) })
+            .try_send(PlayerState::Enqueue(manifest))
             .expect("Channel full");
+    }
+
+    /// Skips to the next queued manifest. Does nothing if the queue is empty.
+    pub fn next(&mut self) {
+        self.tx.try_send(PlayerState::Next).expect("Channel full");
+    }
+
+    pub fn clear_queue(&mut self) {
+        self.tx
+            .try_send(PlayerState::ClearQueue)
+            .expect("Channel full");
+    }
+
+    /// Adds a break to the current presentation, e.g. at a cue point known to the app. Breaks
+    /// play in the element of [`PlayerConfig::ads`], without it they are dropped when due.
+    pub fn schedule_ad_break(&mut self, ad_break: AdBreak) {
+        self.tx
+            .try_send(PlayerState::ScheduleAdBreak(ad_break))
+            .expect("Channel full");
+    }
+
+    /// Cancels a [`Self::create`] that is still loading. The manifest fetch is dropped and the
+    /// player stays detached.
+    pub fn cancel(&self) {
+        let _ = self.tx.clone().try_send(PlayerState::Cancel);
+    }
+
+    /// Stops the player: in-flight requests are aborted, listeners and source buffers removed
+    /// and the video element emptied. The teardown starts right away; the returned future
+    /// resolves once it is complete.
+    pub fn destroy(mut self) -> impl Future<Output = ()> {
+        let (tx, rx) = oneshot::channel();
+        let _ = self.tx.try_send(PlayerState::Cleanup { tx: Some(tx) });
+
+        async move {
+            let _ = rx.await;
+        }
+    }
+}
 
-        let result = rx.await;
-        match result {
-            Ok(Ok(())) => {
-                tracing::info!("Manifest loaded successfully");
-                Ok(())
-            },
-            Ok(Err(e)) => {
-                tracing::error!("Failed to load manifest: {:?}", e);
-                Err(e)
-            },
-            Err(_) => {
-                tracing::error!("Channel canceled");
-                Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "channel canceled")))
-            },
+/// Waits for the outcome of a `create` or `load` and keeps the parsed manifest of a successful
+/// one for [`MediaPlayer::manifest_info`].
+async fn finish_load(
+    rx: oneshot::Receiver<Result<ManifestInfo, AshinaError>>,
+    manifest_info: Rc<RefCell<Option<Rc<ManifestInfo>>>>,
+) -> Result<(), AshinaError> {
+    let result = rx.await;
+    match result {
+        Ok(Ok(info)) => {
+            tracing::info!("Manifest loaded successfully");
+            manifest_info.replace(Some(Rc::new(info)));
+            Ok(())
+        }
+        Ok(Err(e)) => {
+            tracing::error!("Failed to load manifest: {:?}", e);
+            Err(e)
+        }
+        Err(_) => {
+            tracing::error!("Channel canceled");
+            Err(AshinaError::Internal(AshinaError::message(
+                "channel canceled",
+            )))
         }
     }
+}
+
+impl Default for MediaPlayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Builds a [`MediaPlayer`] from a [`PlayerConfig`], starting from the defaults. Each section
+/// replaces the one set before; the top-level settings come with [`Self::with_config`].
+#[derive(Debug, Default)]
+pub struct MediaPlayerBuilder {
+    config: PlayerConfig,
+}
+
+impl MediaPlayerBuilder {
+    /// Replaces the whole config, sections included.
+    pub fn with_config(mut self, config: PlayerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn with_buffer(mut self, buffer: BufferConfig) -> Self {
+        self.config.buffer = buffer;
+        self
+    }
+
+    pub fn with_abr(mut self, abr: AbrConfig) -> Self {
+        self.config.abr = abr;
+        self
+    }
+
+    pub fn with_drm(mut self, drm: DrmConfig) -> Self {
+        self.config.drm = drm;
+        self
+    }
+
+    pub fn with_network(mut self, network: NetworkConfig) -> Self {
+        self.config.network = network;
+        self
+    }
+
+    pub fn with_live(mut self, live: LiveConfig) -> Self {
+        self.config.live = live;
+        self
+    }
 
-    pub fn tracks(&self) -> Vec<()> {
-        self.cached_track_list.clone().unwrap_or_default()
+    pub fn with_text(mut self, text: TextConfig) -> Self {
+        self.config.text = text;
+        self
     }
 
-    pub fn destroy(mut self) {
-        // Send cleanup command to player thread
-        let _ = self.tx.try_send(PlayerState::Cleanup);
+    /// Validates the config and starts the player. Nothing is started if the config is
+    /// invalid.
+    pub fn build(self) -> Result<MediaPlayer, ConfigError> {
+        self.config.validate()?;
 
-        // The spawned listen loop will handle cleanup on drop
+        Ok(MediaPlayer::with_config(self.config))
     }
 }
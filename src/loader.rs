@@ -0,0 +1,1252 @@
+use crate::abr::SharedEstimator;
+use crate::manifest::TrackId;
+
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::time::Duration;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use bytes::Bytes;
+use displaydoc::Display;
+use futures::future::Either;
+use futures::Stream;
+use futures::StreamExt;
+use futures::TryFutureExt;
+#[cfg(feature = "reqwest")]
+use futures::TryStreamExt;
+use gloo_timers::future::TimeoutFuture;
+use thiserror::Error;
+#[cfg(feature = "fetch-backend")]
+use wasm_bindgen::JsCast;
+#[cfg(feature = "fetch-backend")]
+use wasm_bindgen::JsValue;
+#[cfg(feature = "fetch-backend")]
+use wasm_bindgen_futures::JsFuture;
+
+pub type LoadFuture = Pin<Box<dyn Future<Output = Result<Bytes, LoadError>>>>;
+/// A response body delivered in pieces as it arrives.
+pub type ChunkStream = Pin<Box<dyn Stream<Item = Result<Bytes, LoadError>>>>;
+pub type RequestDecorator = Rc<dyn Fn(&mut SegmentRequest)>;
+/// Told about every finished request, see [`Interceptor`].
+pub type ResponseObserver = Rc<dyn Fn(&ResponseInfo)>;
+pub type DecryptFuture = Pin<Box<dyn Future<Output = Result<Bytes, Box<dyn std::error::Error>>>>>;
+/// Turns a fetched manifest body into MPD XML, for providers that encrypt or wrap their
+/// manifests. Runs between fetch and parse.
+pub type ManifestDecryptor = Rc<dyn Fn(Bytes) -> DecryptFuture>;
+
+pub type SignFuture = Pin<Box<dyn Future<Output = Result<SignedUrl, LoadError>>>>;
+/// Signs an init or media segment URL for CDNs that embed expiring signatures in their URLs.
+pub type UrlSigner = Rc<dyn Fn(String) -> SignFuture>;
+
+/// What a request is fetching, so loaders can treat manifests and segments differently.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RequestKind {
+    Manifest,
+    Init,
+    Media,
+    /// DRM license request, sent as a POST with the key session message as body.
+    License,
+    /// Small request timing how fast a CDN responds.
+    Probe,
+}
+
+impl RequestKind {
+    /// Name used in request tags, see [`RequestTag`].
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Manifest => "manifest",
+            Self::Init => "init",
+            Self::Media => "media",
+            Self::License => "license",
+            Self::Probe => "probe",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            Self::Manifest,
+            Self::Init,
+            Self::Media,
+            Self::License,
+            Self::Probe,
+        ]
+        .into_iter()
+        .find(|kind| kind.as_str() == name)
+    }
+}
+
+/// How soon a request is needed. Requests waiting for the [`SchedulingLoader`] go out in this
+/// order, and in the order they were made within a priority.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RequestPriority {
+    /// Manifests, init segments, licenses, and media segments playback is stalled on.
+    Urgent,
+    /// Media segments close to the playhead.
+    Media,
+    /// Requests that only prepare for later: preloading queued presentations, CDN probes, and
+    /// media segments well ahead of the playhead. They never take the last free slot.
+    Prefetch,
+}
+
+impl RequestPriority {
+    /// The priority requests of `kind` get unless they are given another one.
+    pub fn for_kind(kind: RequestKind) -> Self {
+        match kind {
+            RequestKind::Manifest | RequestKind::Init | RequestKind::License => Self::Urgent,
+            RequestKind::Media => Self::Media,
+            RequestKind::Probe => Self::Prefetch,
+        }
+    }
+}
+
+/// A single HTTP request the player wants to make.
+#[derive(Clone, Debug)]
+pub struct SegmentRequest {
+    pub url: String,
+    pub kind: RequestKind,
+    /// Extra headers sent along with the request.
+    pub headers: Vec<(String, String)>,
+    /// Request body. Requests with a body are sent as POST.
+    pub body: Option<Bytes>,
+    /// Track an init or media segment belongs to.
+    pub track: Option<TrackId>,
+    /// `$Number$` of a media segment.
+    pub segment: Option<usize>,
+    pub priority: RequestPriority,
+}
+
+impl SegmentRequest {
+    pub fn new(url: impl Into<String>, kind: RequestKind) -> Self {
+        Self {
+            url: url.into(),
+            kind,
+            headers: vec![],
+            body: None,
+            track: None,
+            segment: None,
+            priority: RequestPriority::for_kind(kind),
+        }
+    }
+
+    pub fn with_track(mut self, track: TrackId) -> Self {
+        self.track = Some(track);
+        self
+    }
+
+    pub fn with_segment(mut self, segment: usize) -> Self {
+        self.segment = Some(segment);
+        self
+    }
+
+    pub fn with_priority(mut self, priority: RequestPriority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn tag(&self) -> RequestTag {
+        RequestTag {
+            kind: self.kind,
+            track: self.track.as_ref().map(|track| track.to_string()),
+            segment: self.segment,
+        }
+    }
+
+    pub fn add_header(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.headers.push((name.into(), value.into()));
+    }
+
+    /// Appends `key=value` to the query string of the request URL, e.g. for signed CDN URLs.
+    pub fn add_query_param(&mut self, key: &str, value: &str) {
+        match url::Url::parse(&self.url) {
+            Ok(mut url) => {
+                url.query_pairs_mut().append_pair(key, value);
+                self.url = url.into();
+            }
+            Err(error) => tracing::warn!(?error, url = self.url, "Not adding query param."),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Display, Error)]
+pub enum LoadError {
+    /// Request failed: {0}
+    Network(String),
+    /// Server returned HTTP status {0}
+    HttpStatus(u16),
+    /// Failed to read response body: {0}
+    Body(String),
+    /// Failed to sign request URL: {0}
+    Signing(String),
+    /// Request timed out after {0:?}
+    Timeout(Duration),
+    /// Transfer broke off after part of {total} bytes: {error}
+    Interrupted {
+        /// The start of the body, up to where it broke off.
+        received: Bytes,
+        /// Length of the whole body, from `Content-Length`.
+        total: usize,
+        error: String,
+    },
+}
+
+/// How long requests may take, by [`RequestKind`], before they are aborted and fail with
+/// [`LoadError::Timeout`]. `None` waits as long as the browser does. License and probe requests
+/// have no timeout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RequestTimeouts {
+    pub manifest: Option<Duration>,
+    pub init: Option<Duration>,
+    /// For streamed low-latency segments, the longest wait for the next chunk.
+    pub media: Option<Duration>,
+}
+
+impl RequestTimeouts {
+    /// No timeouts at all.
+    pub const NONE: Self = Self {
+        manifest: None,
+        init: None,
+        media: None,
+    };
+
+    pub fn for_kind(&self, kind: RequestKind) -> Option<Duration> {
+        match kind {
+            RequestKind::Manifest => self.manifest,
+            RequestKind::Init => self.init,
+            RequestKind::Media => self.media,
+            RequestKind::License | RequestKind::Probe => None,
+        }
+    }
+}
+
+impl Default for RequestTimeouts {
+    fn default() -> Self {
+        Self {
+            manifest: Some(Duration::from_secs(10)),
+            init: Some(Duration::from_secs(10)),
+            media: Some(Duration::from_secs(20)),
+        }
+    }
+}
+
+/// How many requests the [`SchedulingLoader`] keeps in flight at once. `None` doesn't limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RequestLimits {
+    /// Requests in flight in total.
+    pub max_concurrent: Option<usize>,
+    /// Requests in flight to any one host.
+    pub max_per_host: Option<usize>,
+}
+
+impl RequestLimits {
+    /// No limits, requests go out as soon as they are made.
+    pub const NONE: Self = Self {
+        max_concurrent: None,
+        max_per_host: None,
+    };
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_concurrent: Some(6),
+            max_per_host: None,
+        }
+    }
+}
+
+/// How requests are labelled for service workers and network tooling, see [`RequestTag`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RequestTagging {
+    #[default]
+    Off,
+    /// `X-Ashina-*` headers. Cross-origin requests need CORS to allow them, and get a preflight.
+    Headers,
+    /// Parameters in the URL fragment, which service workers see but servers never receive.
+    Fragment,
+}
+
+/// Header carrying [`RequestTag::kind`].
+pub const KIND_HEADER: &str = "X-Ashina-Kind";
+/// Header carrying [`RequestTag::track`].
+pub const TRACK_HEADER: &str = "X-Ashina-Track";
+/// Header carrying [`RequestTag::segment`].
+pub const SEGMENT_HEADER: &str = "X-Ashina-Segment";
+
+/// Prefix of the fragment parameters, e.g. `#ashina-kind=media&ashina-track=0%2F1&ashina-segment=12`.
+const FRAGMENT_PREFIX: &str = "ashina-";
+
+/// What a player request is for, attached to requests by [`TaggingLoader`] so apps can classify
+/// player traffic, e.g. to cache segments in a service worker. Read it back with
+/// [`Self::from_headers`] or [`Self::from_url`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestTag {
+    pub kind: RequestKind,
+    pub track: Option<String>,
+    pub segment: Option<usize>,
+}
+
+impl RequestTag {
+    /// The tag as `(name, value)` header pairs.
+    pub fn headers(&self) -> Vec<(String, String)> {
+        let mut headers = vec![(KIND_HEADER.into(), self.kind.as_str().into())];
+        headers.extend(self.track.clone().map(|track| (TRACK_HEADER.into(), track)));
+        headers.extend(
+            self.segment
+                .map(|segment| (SEGMENT_HEADER.into(), segment.to_string())),
+        );
+        headers
+    }
+
+    /// The tag as URL fragment, without the `#`.
+    pub fn fragment(&self) -> String {
+        let mut fragment = url::form_urlencoded::Serializer::new(String::new());
+        fragment.append_pair(&format!("{FRAGMENT_PREFIX}kind"), self.kind.as_str());
+        if let Some(track) = &self.track {
+            fragment.append_pair(&format!("{FRAGMENT_PREFIX}track"), track);
+        }
+        if let Some(segment) = self.segment {
+            fragment.append_pair(&format!("{FRAGMENT_PREFIX}segment"), &segment.to_string());
+        }
+        fragment.finish()
+    }
+
+    /// Reads a tag written with [`RequestTagging::Headers`]. Header names are matched
+    /// case-insensitively.
+    pub fn from_headers<'a>(headers: impl IntoIterator<Item = (&'a str, &'a str)>) -> Option<Self> {
+        let mut kind = None;
+        let mut track = None;
+        let mut segment = None;
+
+        for (name, value) in headers {
+            if name.eq_ignore_ascii_case(KIND_HEADER) {
+                kind = RequestKind::from_name(value);
+            } else if name.eq_ignore_ascii_case(TRACK_HEADER) {
+                track = Some(value.to_owned());
+            } else if name.eq_ignore_ascii_case(SEGMENT_HEADER) {
+                segment = value.parse().ok();
+            }
+        }
+
+        Some(Self {
+            kind: kind?,
+            track,
+            segment,
+        })
+    }
+
+    /// Reads a tag written with [`RequestTagging::Fragment`] from a request URL.
+    pub fn from_url(url: &str) -> Option<Self> {
+        let fragment = url::Url::parse(url).ok()?.fragment()?.to_owned();
+        let pairs: Vec<_> = url::form_urlencoded::parse(fragment.as_bytes())
+            .filter_map(|(name, value)| {
+                let name = name.strip_prefix(FRAGMENT_PREFIX)?.to_owned();
+                Some((format!("X-Ashina-{name}"), value.into_owned()))
+            })
+            .collect();
+
+        Self::from_headers(
+            pairs
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        )
+    }
+}
+
+/// Attaches a [`RequestTag`] to every request before handing it to the inner loader.
+pub struct TaggingLoader {
+    inner: Rc<dyn Loader>,
+    tagging: RequestTagging,
+}
+
+impl TaggingLoader {
+    pub fn new(inner: Rc<dyn Loader>, tagging: RequestTagging) -> Self {
+        Self { inner, tagging }
+    }
+
+    fn tag(&self, mut request: SegmentRequest) -> SegmentRequest {
+        let tag = request.tag();
+
+        match self.tagging {
+            RequestTagging::Off => {}
+            RequestTagging::Headers => request.headers.extend(tag.headers()),
+            RequestTagging::Fragment => match url::Url::parse(&request.url) {
+                Ok(mut url) => {
+                    url.set_fragment(Some(&tag.fragment()));
+                    request.url = url.into();
+                }
+                Err(error) => tracing::warn!(?error, url = request.url, "Not tagging request."),
+            },
+        }
+
+        request
+    }
+}
+
+impl Loader for TaggingLoader {
+    fn fetch(&self, request: SegmentRequest) -> LoadFuture {
+        self.inner.fetch(self.tag(request))
+    }
+
+    fn fetch_stream(&self, request: SegmentRequest) -> ChunkStream {
+        self.inner.fetch_stream(self.tag(request))
+    }
+}
+
+/// Fails requests that take longer than their [`RequestTimeouts`]. The inner request is
+/// dropped on timeout, which makes [`HttpLoader`] abort the fetch through its
+/// `AbortController`.
+pub struct TimeoutLoader {
+    inner: Rc<dyn Loader>,
+    timeouts: RequestTimeouts,
+}
+
+impl TimeoutLoader {
+    pub fn new(inner: Rc<dyn Loader>, timeouts: RequestTimeouts) -> Self {
+        Self { inner, timeouts }
+    }
+}
+
+impl Loader for TimeoutLoader {
+    fn fetch(&self, request: SegmentRequest) -> LoadFuture {
+        let Some(timeout) = self.timeouts.for_kind(request.kind) else {
+            return self.inner.fetch(request);
+        };
+
+        let fetch = self.inner.fetch(request);
+        Box::pin(async move { with_timeout(fetch, timeout).await? })
+    }
+
+    fn fetch_stream(&self, request: SegmentRequest) -> ChunkStream {
+        let Some(timeout) = self.timeouts.for_kind(request.kind) else {
+            return self.inner.fetch_stream(request);
+        };
+
+        // Streamed segments take as long as they take to be produced, so only the wait for
+        // each chunk is limited.
+        let chunks = self.inner.fetch_stream(request);
+        Box::pin(futures::stream::unfold(
+            Some(chunks),
+            move |chunks| async move {
+                let mut chunks = chunks?;
+
+                match with_timeout(chunks.next(), timeout).await {
+                    Ok(Some(chunk)) => Some((chunk, Some(chunks))),
+                    Ok(None) => None,
+                    Err(error) => Some((Err(error), None)),
+                }
+            },
+        ))
+    }
+}
+
+async fn with_timeout<T>(
+    future: impl Future<Output = T>,
+    timeout: Duration,
+) -> Result<T, LoadError> {
+    let timer = TimeoutFuture::new(timeout.as_millis().min(u32::MAX as u128) as u32);
+
+    match futures::future::select(core::pin::pin!(future), timer).await {
+        Either::Left((output, _)) => Ok(output),
+        Either::Right(_) => Err(LoadError::Timeout(timeout)),
+    }
+}
+
+/// A URL returned by a [`UrlSigner`].
+#[derive(Clone, Debug)]
+pub struct SignedUrl {
+    pub url: String,
+    /// How long the signature stays valid. `None` for signatures that don't expire.
+    pub valid_for: Option<Duration>,
+}
+
+/// Performs every network request made by the player. Implement this to inject auth headers,
+/// refresh tokens, apply custom CDN logic, or serve canned responses in tests.
+pub trait Loader {
+    fn fetch(&self, request: SegmentRequest) -> LoadFuture;
+
+    /// Like [`Self::fetch`], but yields the body as it arrives instead of once it is complete.
+    /// Used for low-latency segments that are still being produced while they download. The
+    /// default delivers the whole body as a single chunk.
+    fn fetch_stream(&self, request: SegmentRequest) -> ChunkStream {
+        Box::pin(futures::stream::once(self.fetch(request)))
+    }
+}
+
+/// Default loader, see [`FetchLoader`], or [`ReqwestLoader`] with only the `reqwest` feature.
+#[cfg(feature = "fetch-backend")]
+pub type HttpLoader = FetchLoader;
+#[cfg(all(feature = "reqwest", not(feature = "fetch-backend")))]
+pub type HttpLoader = ReqwestLoader;
+
+/// Loader calling the browser's `fetch` through `web-sys`. Behind the `fetch-backend` feature,
+/// on by default.
+#[cfg(feature = "fetch-backend")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FetchLoader;
+
+#[cfg(feature = "fetch-backend")]
+impl FetchLoader {
+    async fn send(request: SegmentRequest) -> Result<(web_sys::Response, AbortOnDrop), LoadError> {
+        let network = |error: JsValue| LoadError::Network(format!("{error:?}"));
+
+        let headers = web_sys::Headers::new().map_err(network)?;
+        for (name, value) in &request.headers {
+            headers.append(name, value).map_err(network)?;
+        }
+
+        let init = web_sys::RequestInit::new();
+        init.set_headers(&headers);
+        if let Some(body) = &request.body {
+            init.set_method("POST");
+            init.set_body(&js_sys::Uint8Array::from(body.as_ref()));
+        }
+
+        let controller = web_sys::AbortController::new().ok();
+        init.set_signal(
+            controller
+                .as_ref()
+                .map(|controller| controller.signal())
+                .as_ref(),
+        );
+        let abort = AbortOnDrop(controller);
+
+        let window = web_sys::window().ok_or(LoadError::Network("no window".into()))?;
+        let response: web_sys::Response =
+            JsFuture::from(window.fetch_with_str_and_init(&request.url, &init))
+                .await
+                .map_err(network)?
+                .unchecked_into();
+
+        // Ranged requests answer with 206.
+        if !response.ok() {
+            return Err(LoadError::HttpStatus(response.status()));
+        }
+
+        Ok((response, abort))
+    }
+
+    /// The body of `response` as it arrives. Holds on to `abort` until the body is complete.
+    fn chunks(response: web_sys::Response, abort: AbortOnDrop) -> ChunkStream {
+        let reader = response.body().map(|body| {
+            body.get_reader()
+                .unchecked_into::<web_sys::ReadableStreamDefaultReader>()
+        });
+
+        Box::pin(futures::stream::unfold(
+            (reader, abort),
+            |(reader, abort)| async move {
+                let read = match JsFuture::from(reader.as_ref()?.read()).await {
+                    Ok(read) => read,
+                    Err(error) => {
+                        let error = LoadError::Body(format!("{error:?}"));
+                        return Some((Err(error), (None, abort)));
+                    }
+                };
+
+                let field = |name: &str| js_sys::Reflect::get(&read, &JsValue::from_str(name));
+                if field("done").is_ok_and(|done| done.is_truthy()) {
+                    return None;
+                }
+                let chunk = field("value")
+                    .ok()?
+                    .unchecked_into::<js_sys::Uint8Array>()
+                    .to_vec();
+
+                Some((Ok(chunk.into()), (reader, abort)))
+            },
+        ))
+    }
+}
+
+#[cfg(feature = "fetch-backend")]
+impl Loader for FetchLoader {
+    fn fetch(&self, request: SegmentRequest) -> LoadFuture {
+        Box::pin(async move {
+            let (response, abort) = Self::send(request).await?;
+            let total = response
+                .headers()
+                .get("content-length")
+                .ok()
+                .flatten()
+                .and_then(|length| length.parse().ok());
+            let mut chunks = Self::chunks(response, abort);
+
+            // Read chunk by chunk, so that what arrived survives a transfer breaking off.
+            let mut body = vec![];
+            while let Some(chunk) = chunks.next().await {
+                match (chunk, total) {
+                    (Ok(chunk), _) => body.extend_from_slice(&chunk),
+                    (Err(LoadError::Body(error)), Some(total)) if !body.is_empty() => {
+                        return Err(LoadError::Interrupted {
+                            received: body.into(),
+                            total,
+                            error,
+                        });
+                    }
+                    (Err(e), _) => return Err(e),
+                }
+            }
+
+            Ok(body.into())
+        })
+    }
+
+    fn fetch_stream(&self, request: SegmentRequest) -> ChunkStream {
+        let body = Self::send(request).map_ok(|(response, abort)| Self::chunks(response, abort));
+
+        Box::pin(body.try_flatten_stream())
+    }
+}
+
+/// Aborts the fetch its `AbortController` belongs to when dropped, e.g. by the
+/// [`TimeoutLoader`]. Aborting a fetch whose body is complete does nothing.
+#[cfg(feature = "fetch-backend")]
+struct AbortOnDrop(Option<web_sys::AbortController>);
+
+#[cfg(feature = "fetch-backend")]
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        if let Some(controller) = &self.0 {
+            controller.abort();
+        }
+    }
+}
+
+/// Loader backed by `reqwest`, which uses the browser's `fetch` on wasm. Behind the `reqwest`
+/// feature, for applications that already pull `reqwest` in.
+#[cfg(feature = "reqwest")]
+#[derive(Clone, Debug, Default)]
+pub struct ReqwestLoader {
+    client: reqwest::Client,
+}
+
+#[cfg(feature = "reqwest")]
+impl ReqwestLoader {
+    fn send(
+        &self,
+        request: SegmentRequest,
+    ) -> impl Future<Output = Result<reqwest::Response, LoadError>> + use<> {
+        let mut builder = match request.body {
+            Some(body) => self.client.post(&request.url).body(body),
+            None => self.client.get(&request.url),
+        };
+
+        for (name, value) in &request.headers {
+            builder = builder.header(name, value);
+        }
+
+        async move {
+            let response = builder
+                .send()
+                .await
+                .map_err(|e| LoadError::Network(e.to_string()))?;
+
+            // Ranged requests answer with 206.
+            if !response.status().is_success() {
+                return Err(LoadError::HttpStatus(response.status().as_u16()));
+            }
+
+            Ok(response)
+        }
+    }
+}
+
+#[cfg(feature = "reqwest")]
+impl Loader for ReqwestLoader {
+    fn fetch(&self, request: SegmentRequest) -> LoadFuture {
+        let response = self.send(request);
+
+        Box::pin(async move {
+            let response = response.await?;
+            let total = response.content_length().map(|length| length as usize);
+            let mut chunks = response.bytes_stream();
+
+            // Read chunk by chunk, so that what arrived survives a transfer breaking off.
+            let mut body = vec![];
+            while let Some(chunk) = chunks.next().await {
+                match (chunk, total) {
+                    (Ok(chunk), _) => body.extend_from_slice(&chunk),
+                    (Err(e), Some(total)) if !body.is_empty() => {
+                        return Err(LoadError::Interrupted {
+                            received: body.into(),
+                            total,
+                            error: e.to_string(),
+                        });
+                    }
+                    (Err(e), _) => return Err(LoadError::Body(e.to_string())),
+                }
+            }
+
+            Ok(body.into())
+        })
+    }
+
+    fn fetch_stream(&self, request: SegmentRequest) -> ChunkStream {
+        let body = self.send(request).map_ok(|response| {
+            response
+                .bytes_stream()
+                .map_err(|e| LoadError::Body(e.to_string()))
+        });
+
+        Box::pin(body.try_flatten_stream())
+    }
+}
+
+/// Applies a [`RequestDecorator`] to every request before handing it to the inner loader.
+pub struct DecoratedLoader {
+    inner: Rc<dyn Loader>,
+    decorator: RequestDecorator,
+}
+
+impl DecoratedLoader {
+    pub fn new(inner: Rc<dyn Loader>, decorator: RequestDecorator) -> Self {
+        Self { inner, decorator }
+    }
+}
+
+impl Loader for DecoratedLoader {
+    fn fetch(&self, mut request: SegmentRequest) -> LoadFuture {
+        (self.decorator)(&mut request);
+        self.inner.fetch(request)
+    }
+
+    fn fetch_stream(&self, mut request: SegmentRequest) -> ChunkStream {
+        (self.decorator)(&mut request);
+        self.inner.fetch_stream(request)
+    }
+}
+
+/// What an [`Interceptor`] learns about a finished request.
+#[derive(Clone, Debug)]
+pub struct ResponseInfo {
+    pub kind: RequestKind,
+    /// URL the request was sent to, after every interceptor rewrote it.
+    pub url: String,
+    /// Headers the request was sent with.
+    pub headers: Vec<(String, String)>,
+    /// From handing the request to the loader until the body was complete or the request
+    /// failed.
+    pub duration: Duration,
+    /// Body bytes received, including those of a transfer that broke off.
+    pub bytes: usize,
+    /// HTTP status, where known. Loaders only hand on the body of successful requests, so those
+    /// report `206` if they asked for a range and `200` otherwise.
+    pub status: Option<u16>,
+    /// Why the request failed, `None` if it succeeded.
+    pub error: Option<LoadError>,
+}
+
+impl ResponseInfo {
+    fn new(request: &SegmentRequest, started: f64, bytes: usize, error: Option<LoadError>) -> Self {
+        let ranged = request
+            .headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("range"));
+        let status = match &error {
+            None if ranged => Some(206),
+            None => Some(200),
+            Some(LoadError::HttpStatus(status)) => Some(*status),
+            Some(_) => None,
+        };
+
+        Self {
+            kind: request.kind,
+            url: request.url.clone(),
+            headers: request.headers.clone(),
+            duration: Duration::from_secs_f64((js_sys::Date::now() - started).max(0.) / 1000.),
+            bytes,
+            status,
+            error,
+        }
+    }
+}
+
+/// Callbacks around every request that goes out, e.g. to rotate CDN tokens, route requests
+/// for A/B tests, or send QoE beacons. Register them through
+/// [`crate::config::NetworkConfig::interceptors`].
+///
+/// ```ignore
+/// let interceptor = Interceptor::new()
+///     .with_on_request(|request| request.add_header("Authorization", token()))
+///     .with_on_response(|response| beacon(response.kind, response.duration, response.bytes));
+/// ```
+#[derive(Clone, Default)]
+pub struct Interceptor {
+    /// Called before a request is sent, and may rewrite it.
+    pub on_request: Option<RequestDecorator>,
+    /// Called once a request completed or failed. Streamed requests report when their stream
+    /// ended or failed, and not at all if it was dropped before.
+    pub on_response: Option<ResponseObserver>,
+}
+
+impl Interceptor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_on_request(mut self, callback: impl Fn(&mut SegmentRequest) + 'static) -> Self {
+        self.on_request = Some(Rc::new(callback));
+        self
+    }
+
+    pub fn with_on_response(mut self, callback: impl Fn(&ResponseInfo) + 'static) -> Self {
+        self.on_response = Some(Rc::new(callback));
+        self
+    }
+}
+
+impl fmt::Debug for Interceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Interceptor")
+            .field("on_request", &self.on_request.is_some())
+            .field("on_response", &self.on_response.is_some())
+            .finish()
+    }
+}
+
+/// Runs [`Interceptor`]s around the inner loader: every `on_request` in order before a request
+/// goes out, then every `on_response` once it finished.
+pub struct InterceptingLoader {
+    inner: Rc<dyn Loader>,
+    interceptors: Rc<[Interceptor]>,
+}
+
+impl InterceptingLoader {
+    pub fn new(inner: Rc<dyn Loader>, interceptors: Vec<Interceptor>) -> Self {
+        Self {
+            inner,
+            interceptors: interceptors.into(),
+        }
+    }
+
+    fn intercept(&self, request: &mut SegmentRequest) {
+        for on_request in self
+            .interceptors
+            .iter()
+            .filter_map(|interceptor| interceptor.on_request.as_ref())
+        {
+            on_request(request);
+        }
+    }
+}
+
+fn report(interceptors: &[Interceptor], response: &ResponseInfo) {
+    for on_response in interceptors
+        .iter()
+        .filter_map(|interceptor| interceptor.on_response.as_ref())
+    {
+        on_response(response);
+    }
+}
+
+impl Loader for InterceptingLoader {
+    fn fetch(&self, mut request: SegmentRequest) -> LoadFuture {
+        self.intercept(&mut request);
+        let interceptors = self.interceptors.clone();
+        let started = js_sys::Date::now();
+        let fetch = self.inner.fetch(request.clone());
+
+        Box::pin(async move {
+            let result = fetch.await;
+            let response = match &result {
+                Ok(data) => ResponseInfo::new(&request, started, data.len(), None),
+                Err(error) => {
+                    let bytes = match error {
+                        LoadError::Interrupted { received, .. } => received.len(),
+                        _ => 0,
+                    };
+                    ResponseInfo::new(&request, started, bytes, Some(error.clone()))
+                }
+            };
+            report(&interceptors, &response);
+
+            result
+        })
+    }
+
+    fn fetch_stream(&self, mut request: SegmentRequest) -> ChunkStream {
+        self.intercept(&mut request);
+        let interceptors = self.interceptors.clone();
+        let started = js_sys::Date::now();
+        // Bytes received so far, `None` once reported. Readers stop at the first error, so
+        // failures are reported right away.
+        let received = Rc::new(RefCell::new(Some(0)));
+
+        let chunks = self.inner.fetch_stream(request.clone()).inspect({
+            let interceptors = interceptors.clone();
+            let request = request.clone();
+            let received = received.clone();
+            move |chunk| {
+                let mut received = received.borrow_mut();
+                match (chunk, received.as_mut()) {
+                    (Ok(chunk), Some(bytes)) => *bytes += chunk.len(),
+                    (Err(error), Some(bytes)) => {
+                        let response =
+                            ResponseInfo::new(&request, started, *bytes, Some(error.clone()));
+                        *received = None;
+                        report(&interceptors, &response);
+                    }
+                    (_, None) => {}
+                }
+            }
+        });
+        let ended = futures::stream::once(async move {
+            let bytes = received.borrow_mut().take();
+            if let Some(bytes) = bytes {
+                report(
+                    &interceptors,
+                    &ResponseInfo::new(&request, started, bytes, None),
+                );
+            }
+        })
+        .filter_map(|()| async { None });
+
+        Box::pin(chunks.chain(ended))
+    }
+}
+
+/// Signatures expiring sooner than this are renewed before use, so that a request doesn't get
+/// rejected mid-flight.
+const SIGNATURE_REFRESH_MARGIN: Duration = Duration::from_secs(10);
+/// Signed URLs kept around; beyond this the cache starts over.
+const MAX_CACHED_SIGNATURES: usize = 256;
+
+struct CachedSignature {
+    url: String,
+    /// JS timestamp in milliseconds.
+    expires_at: Option<f64>,
+}
+
+impl CachedSignature {
+    fn is_fresh(&self, now: f64) -> bool {
+        self.expires_at
+            .is_none_or(|expires_at| expires_at - now > SIGNATURE_REFRESH_MARGIN.as_millis() as f64)
+    }
+}
+
+type SignatureCache = Rc<RefCell<HashMap<String, CachedSignature>>>;
+
+/// Replaces init and media segment URLs with signed ones before handing the request to the inner
+/// loader. Signatures are cached until shortly before they expire, and [`Self::presign`] lets the
+/// player sign upcoming segments ahead of time.
+pub struct SigningLoader {
+    inner: Rc<dyn Loader>,
+    signer: UrlSigner,
+    cache: SignatureCache,
+}
+
+impl SigningLoader {
+    pub fn new(inner: Rc<dyn Loader>, signer: UrlSigner) -> Self {
+        Self {
+            inner,
+            signer,
+            cache: Rc::default(),
+        }
+    }
+
+    /// Signs `url` unless a signature that is still good is cached.
+    pub fn presign(&self, url: String) -> impl Future<Output = ()> + use<> {
+        let signing = sign(self.signer.clone(), self.cache.clone(), url);
+
+        async move {
+            if let Err(error) = signing.await {
+                tracing::warn!(?error, "Failed to sign upcoming segment URL.");
+            }
+        }
+    }
+}
+
+async fn sign(signer: UrlSigner, cache: SignatureCache, url: String) -> Result<String, LoadError> {
+    let now = js_sys::Date::now();
+
+    if let Some(cached) = cache
+        .borrow()
+        .get(&url)
+        .filter(|cached| cached.is_fresh(now))
+    {
+        return Ok(cached.url.clone());
+    }
+
+    let signed = signer(url.clone()).await?;
+    let signed_at = js_sys::Date::now();
+
+    let mut cache = cache.borrow_mut();
+    cache.retain(|_, cached| cached.is_fresh(signed_at));
+    if cache.len() >= MAX_CACHED_SIGNATURES {
+        cache.clear();
+    }
+
+    cache.insert(
+        url,
+        CachedSignature {
+            url: signed.url.clone(),
+            expires_at: signed
+                .valid_for
+                .map(|valid_for| signed_at + valid_for.as_millis() as f64),
+        },
+    );
+
+    Ok(signed.url)
+}
+
+impl Loader for SigningLoader {
+    fn fetch(&self, mut request: SegmentRequest) -> LoadFuture {
+        if !matches!(request.kind, RequestKind::Init | RequestKind::Media) {
+            return self.inner.fetch(request);
+        }
+
+        let inner = self.inner.clone();
+        let signing = sign(self.signer.clone(), self.cache.clone(), request.url.clone());
+
+        Box::pin(async move {
+            request.url = signing.await?;
+            inner.fetch(request).await
+        })
+    }
+
+    fn fetch_stream(&self, mut request: SegmentRequest) -> ChunkStream {
+        if !matches!(request.kind, RequestKind::Init | RequestKind::Media) {
+            return self.inner.fetch_stream(request);
+        }
+
+        let inner = self.inner.clone();
+        let signing = sign(self.signer.clone(), self.cache.clone(), request.url.clone());
+
+        let body = async move {
+            request.url = signing.await?;
+            Ok(inner.fetch_stream(request))
+        };
+
+        Box::pin(body.try_flatten_stream())
+    }
+}
+
+/// Transfers smaller than this finish too quickly to say anything about throughput.
+const MIN_SAMPLE_BYTES: usize = 16 * 1024;
+
+/// Times every init and media segment transfer and feeds it to a bandwidth estimator. A timed
+/// out transfer counts as [`MIN_SAMPLE_BYTES`] over the whole timeout, which pushes the estimate
+/// and with it automatic quality selection down.
+pub struct MeteredLoader {
+    inner: Rc<dyn Loader>,
+    estimator: SharedEstimator,
+}
+
+impl MeteredLoader {
+    pub fn new(inner: Rc<dyn Loader>, estimator: SharedEstimator) -> Self {
+        Self { inner, estimator }
+    }
+}
+
+impl Loader for MeteredLoader {
+    fn fetch(&self, request: SegmentRequest) -> LoadFuture {
+        let metered = matches!(request.kind, RequestKind::Init | RequestKind::Media);
+        let estimator = self.estimator.clone();
+        let fetch = self.inner.fetch(request);
+
+        Box::pin(async move {
+            let started = js_sys::Date::now();
+            let data = match fetch.await {
+                Err(LoadError::Timeout(timeout)) if metered => {
+                    estimator.borrow_mut().sample(MIN_SAMPLE_BYTES, timeout);
+                    return Err(LoadError::Timeout(timeout));
+                }
+                result => result?,
+            };
+            let elapsed = Duration::from_secs_f64((js_sys::Date::now() - started).max(0.) / 1000.);
+
+            if metered && data.len() >= MIN_SAMPLE_BYTES {
+                estimator.borrow_mut().sample(data.len(), elapsed);
+            }
+
+            Ok(data)
+        })
+    }
+
+    // Chunked transfers of segments still being produced arrive at the encoder's pace, which
+    // says nothing about the available bandwidth, so they aren't sampled.
+    fn fetch_stream(&self, request: SegmentRequest) -> ChunkStream {
+        self.inner.fetch_stream(request)
+    }
+}
+
+/// Keeps at most [`RequestLimits`] requests in flight and sends waiting ones by their
+/// [`RequestPriority`], so that prefetching can't hold up the segments playback needs. Streamed
+/// segments keep their slot until the stream is dropped. CDN probes skip the queue, waiting would
+/// count as latency.
+pub struct SchedulingLoader {
+    inner: Rc<dyn Loader>,
+    slots: Rc<RefCell<Slots>>,
+}
+
+impl SchedulingLoader {
+    pub fn new(inner: Rc<dyn Loader>, limits: RequestLimits) -> Self {
+        Self {
+            inner,
+            slots: Rc::new(RefCell::new(Slots {
+                limits,
+                active: 0,
+                per_host: HashMap::new(),
+                waiting: vec![],
+            })),
+        }
+    }
+}
+
+impl Loader for SchedulingLoader {
+    fn fetch(&self, request: SegmentRequest) -> LoadFuture {
+        if request.kind == RequestKind::Probe {
+            return self.inner.fetch(request);
+        }
+
+        let slot = acquire(self.slots.clone(), &request);
+        let inner = self.inner.clone();
+
+        Box::pin(async move {
+            let _slot = slot.await;
+            inner.fetch(request).await
+        })
+    }
+
+    fn fetch_stream(&self, request: SegmentRequest) -> ChunkStream {
+        let slot = acquire(self.slots.clone(), &request);
+        let inner = self.inner.clone();
+
+        let chunks = async move {
+            let slot = slot.await;
+            let chunks = inner.fetch_stream(request).map(move |chunk| {
+                // Keeps the slot for as long as the stream lives.
+                let _slot = &slot;
+                chunk
+            });
+            Ok::<_, LoadError>(chunks)
+        };
+
+        Box::pin(chunks.try_flatten_stream())
+    }
+}
+
+/// Requests in flight and waiting in a [`SchedulingLoader`].
+struct Slots {
+    limits: RequestLimits,
+    active: usize,
+    per_host: HashMap<String, usize>,
+    /// In the order the requests were made.
+    waiting: Vec<Waiter>,
+}
+
+struct Waiter {
+    priority: RequestPriority,
+    host: String,
+    sender: futures::channel::oneshot::Sender<Slot>,
+}
+
+impl Slots {
+    fn fits(&self, priority: RequestPriority, host: &str) -> bool {
+        let free = |active: usize, limit: Option<usize>| {
+            limit.is_none_or(|limit| match priority {
+                RequestPriority::Prefetch if limit > 1 => active + 1 < limit,
+                _ => active < limit,
+            })
+        };
+
+        free(self.active, self.limits.max_concurrent)
+            && free(
+                self.per_host.get(host).copied().unwrap_or_default(),
+                self.limits.max_per_host,
+            )
+    }
+
+    fn take(&mut self, host: &str) {
+        self.active += 1;
+        *self.per_host.entry(host.to_string()).or_default() += 1;
+    }
+
+    fn give_back(&mut self, host: &str) {
+        self.active -= 1;
+        if let Some(count) = self.per_host.get_mut(host) {
+            *count -= 1;
+            if *count == 0 {
+                self.per_host.remove(host);
+            }
+        }
+    }
+
+    /// Removes the most urgent waiting request that fits, taking its slot.
+    fn next_waiter(&mut self) -> Option<Waiter> {
+        // Requests dropped while they waited.
+        self.waiting.retain(|waiter| !waiter.sender.is_canceled());
+
+        let (index, _) = self
+            .waiting
+            .iter()
+            .enumerate()
+            .filter(|(_, waiter)| self.fits(waiter.priority, &waiter.host))
+            .min_by_key(|(index, waiter)| (waiter.priority, *index))?;
+        let waiter = self.waiting.remove(index);
+        self.take(&waiter.host);
+
+        Some(waiter)
+    }
+}
+
+/// A request's place among the ones in flight, given back when dropped.
+struct Slot {
+    slots: Rc<RefCell<Slots>>,
+    host: String,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let next = {
+            let mut slots = self.slots.borrow_mut();
+            slots.give_back(&self.host);
+            slots.next_waiter()
+        };
+
+        if let Some(waiter) = next {
+            let slot = Slot {
+                slots: self.slots.clone(),
+                host: waiter.host,
+            };
+            // A slot that can't be handed over is given back again as it drops.
+            let _ = waiter.sender.send(slot);
+        }
+    }
+}
+
+/// Waits for a free slot for `request`.
+fn acquire(
+    slots: Rc<RefCell<Slots>>,
+    request: &SegmentRequest,
+) -> impl Future<Output = Option<Slot>> + use<> {
+    let priority = request.priority;
+    let host = url::Url::parse(&request.url)
+        .ok()
+        .and_then(|url| url.host_str().map(String::from))
+        .unwrap_or_default();
+
+    async move {
+        let receiver = {
+            let mut state = slots.borrow_mut();
+            if state.fits(priority, &host) {
+                state.take(&host);
+                drop(state);
+                return Some(Slot { slots, host });
+            }
+
+            let (sender, receiver) = futures::channel::oneshot::channel();
+            state.waiting.push(Waiter {
+                priority,
+                host,
+                sender,
+            });
+            receiver
+        };
+
+        receiver.await.ok()
+    }
+}
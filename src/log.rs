@@ -0,0 +1,188 @@
+//! Leveled logging per subsystem. Every record goes to `tracing` if its subsystem's level lets it
+//! through, and optionally to subscribers as [`crate::event::PlayerEvent::Log`], e.g. for an
+//! in-app debug console. Parsing helpers that run outside a player log to `tracing` directly.
+
+use crate::player::InternalEvent;
+
+use core::cell::RefCell;
+use core::fmt;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Severity of a log record, from most to least severe. A level lets through itself and every
+/// more severe one.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub enum LogLevel {
+    /// Nothing at all.
+    Off,
+    Error,
+    Warn,
+    #[default]
+    Info,
+    /// Per-segment details.
+    Debug,
+    /// Per-timeupdate details.
+    Trace,
+}
+
+/// Part of the player a record comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Subsystem {
+    /// Attaching to the element, lifecycle and playback state.
+    Player,
+    /// Loading, refreshing and picking tracks from the manifest.
+    Manifest,
+    /// Source buffers, segment math and appends.
+    Buffer,
+    /// Segment requests and CDN selection.
+    Network,
+    /// Rendition switches.
+    Abr,
+    /// Key systems and licenses.
+    Drm,
+    /// Live edge and latency control.
+    Live,
+}
+
+impl fmt::Display for Subsystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Player => "player",
+            Self::Manifest => "manifest",
+            Self::Buffer => "buffer",
+            Self::Network => "network",
+            Self::Abr => "abr",
+            Self::Drm => "drm",
+            Self::Live => "live",
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// Which records a player logs. Can be replaced while playing with
+/// [`crate::MediaPlayer::set_log_config`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LogConfig {
+    /// Level of every subsystem without an override.
+    pub level: LogLevel,
+    /// Per-subsystem levels, e.g. [`LogLevel::Debug`] for [`Subsystem::Buffer`] only.
+    pub overrides: HashMap<Subsystem, LogLevel>,
+    /// Also publish records that pass the filter as [`crate::event::PlayerEvent::Log`].
+    pub forward_events: bool,
+}
+
+impl LogConfig {
+    pub fn with_level(mut self, level: LogLevel) -> Self {
+        self.level = level;
+        self
+    }
+
+    pub fn with_subsystem_level(mut self, subsystem: Subsystem, level: LogLevel) -> Self {
+        self.overrides.insert(subsystem, level);
+        self
+    }
+
+    pub fn with_forward_events(mut self, forward: bool) -> Self {
+        self.forward_events = forward;
+        self
+    }
+
+    pub fn level_for(&self, subsystem: Subsystem) -> LogLevel {
+        self.overrides
+            .get(&subsystem)
+            .copied()
+            .unwrap_or(self.level)
+    }
+}
+
+/// A log record, as published through [`crate::event::PlayerEvent::Log`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LogRecord {
+    pub subsystem: Subsystem,
+    pub level: LogLevel,
+    pub message: String,
+}
+
+/// Filters and dispatches records for one player. Clones share the config, so the player and its
+/// track buffers pick up changes together.
+#[derive(Clone, Default)]
+pub struct Logger {
+    config: Rc<RefCell<LogConfig>>,
+    events: Option<flume::Sender<InternalEvent>>,
+}
+
+impl Logger {
+    /// Forwarded records are sent to `events`, for the player to publish.
+    pub fn new(config: LogConfig, events: flume::Sender<InternalEvent>) -> Self {
+        Self {
+            config: Rc::new(RefCell::new(config)),
+            events: Some(events),
+        }
+    }
+
+    /// A logger sharing this one's config that forwards records to `events` instead.
+    pub fn with_events(&self, events: flume::Sender<InternalEvent>) -> Self {
+        Self {
+            config: self.config.clone(),
+            events: Some(events),
+        }
+    }
+
+    pub fn set_config(&self, config: LogConfig) {
+        *self.config.borrow_mut() = config;
+    }
+
+    pub fn enabled(&self, subsystem: Subsystem, level: LogLevel) -> bool {
+        level != LogLevel::Off && level <= self.config.borrow().level_for(subsystem)
+    }
+
+    /// Dispatches `message` without checking the level, see [`log`] for the filtered version.
+    pub fn log(&self, subsystem: Subsystem, level: LogLevel, message: String) {
+        match level {
+            LogLevel::Off => return,
+            LogLevel::Error => tracing::error!(%subsystem, "{message}"),
+            LogLevel::Warn => tracing::warn!(%subsystem, "{message}"),
+            LogLevel::Info => tracing::info!(%subsystem, "{message}"),
+            LogLevel::Debug => tracing::debug!(%subsystem, "{message}"),
+            LogLevel::Trace => tracing::trace!(%subsystem, "{message}"),
+        }
+
+        if !self.config.borrow().forward_events {
+            return;
+        }
+
+        if let Some(events) = &self.events {
+            let _ = events.send(InternalEvent::Log(LogRecord {
+                subsystem,
+                level,
+                message,
+            }));
+        }
+    }
+}
+
+/// Logs a `format!` style message through a [`Logger`] if the subsystem's level lets it through.
+/// The message is only formatted in that case.
+///
+/// ```ignore
+/// log!(self.logger, Buffer, Debug, "Fetching segment {path}.");
+/// ```
+macro_rules! log {
+    ($logger:expr, $subsystem:ident, $level:ident, $($arg:tt)+) => {{
+        let logger = &$logger;
+        let subsystem = $crate::log::Subsystem::$subsystem;
+        let level = $crate::log::LogLevel::$level;
+
+        if logger.enabled(subsystem, level) {
+            logger.log(subsystem, level, format!($($arg)+));
+        }
+    }};
+}
+
+pub(crate) use log;
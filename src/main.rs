@@ -4,9 +4,9 @@ use dioxus::prelude::*;
 use wasm_bindgen_futures::spawn_local;
 
 fn app() -> Element {
-    let mut player = use_signal(|| MediaPlayer::new());
-    let mut url = use_signal(|| String::new());
-    let mut error_signal = use_signal(|| String::new());
+    let mut player = use_signal(MediaPlayer::new);
+    let mut url = use_signal(String::new);
+    let mut error_signal = use_signal(String::new);
 
     // Compute error text for display
     let error_text = if error_signal.read().is_empty() {
@@ -53,13 +53,13 @@ fn app() -> Element {
                     onclick: move |_| {
                         spawn_local(async move {
                             let url_val = url.read().clone();
-                            let mut player_guard = player.write();
-                            match player_guard.create("video-player".into(), url_val).await {
+                            let load = player.write().create("video-player".into(), url_val);
+                            match load.await {
                                 Ok(_) => {
                                     *error_signal.write() = String::new();
                                 }
                                 Err(e) => {
-                                    *error_signal.write() = format!("Failed to load video: {}", e);
+                                    *error_signal.write() = player.read().error_message(&e);
                                 }
                             }
                         });
@@ -1,16 +1,54 @@
+use std::fmt;
 use std::str::FromStr;
 use std::time::Duration;
 
 use dash_mpd::AdaptationSet;
+use dash_mpd::ContentProtection;
+use dash_mpd::EssentialProperty;
+use dash_mpd::Period;
 use dash_mpd::Representation;
 use dash_mpd::SegmentTemplate;
 
+use crate::loader::Loader;
+use crate::loader::RequestKind;
+use crate::loader::SegmentRequest;
+use crate::thumbnail::ThumbnailTrack;
+use displaydoc::Display;
+
 use regex::Regex;
+use serde::Deserialize;
+use serde::Serialize;
+use thiserror::Error;
+use url::Url;
 
 pub struct Manifest {
     inner: dash_mpd::MPD,
 }
 
+/// `xlink:href` telling the client to drop the element instead of fetching anything.
+const XLINK_RESOLVE_TO_ZERO: &str = "urn:mpeg:dash:resolve-to-zero:2013";
+/// Rounds of fetching for remote elements that link to further remote elements.
+const MAX_XLINK_DEPTH: usize = 4;
+
+/// When a remote element, one with an `xlink:href`, is to be fetched, per `xlink:actuate`.
+/// Ordered so that resolving up to [`Self::OnRequest`] resolves [`Self::OnLoad`] elements too.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum XlinkActuate {
+    /// While the manifest is processed.
+    OnLoad,
+    /// Once the element is needed. The default.
+    OnRequest,
+}
+
+impl XlinkActuate {
+    fn from_attribute(value: Option<&str>) -> Self {
+        match value {
+            Some("onLoad") => Self::OnLoad,
+            _ => Self::OnRequest,
+        }
+    }
+}
+
 impl FromStr for Manifest {
     type Err = dash_mpd::DashMpdError;
 
@@ -26,14 +64,243 @@ impl Manifest {
         self.inner.mediaPresentationDuration
     }
 
+    /// Where a static presentation ends, in seconds. `None` for live presentations, which
+    /// usually have no `@mediaPresentationDuration`, or if the MPD doesn't give one.
+    pub fn presentation_end(&self) -> Option<f64> {
+        if self.is_live() {
+            return None;
+        }
+
+        Some(self.duration()?.as_secs_f64())
+    }
+
+    /// Whether this is a live (`type="dynamic"`) presentation.
+    pub fn is_live(&self) -> bool {
+        self.inner.mpdtype.as_deref() == Some("dynamic")
+    }
+
+    /// How long the manifest stays valid before a live client should fetch it again.
+    pub fn minimum_update_period(&self) -> Option<Duration> {
+        self.inner.minimumUpdatePeriod
+    }
+
+    /// Seconds between the start of the first period and `now_ms`, a JS timestamp, for live
+    /// presentations with an `@availabilityStartTime`.
+    pub fn live_elapsed(&self, now_ms: f64) -> Option<f64> {
+        let availability_start = self.inner.availabilityStartTime?.timestamp_millis() as f64;
+        let period_start = self
+            .inner
+            .periods
+            .first()
+            .and_then(|period| period.start)
+            .map_or(0., |start| start.as_secs_f64());
+
+        Some((now_ms - availability_start) / 1000. - period_start)
+    }
+
+    /// `MPD@minBufferTime`: how much media has to be buffered before playback can proceed without
+    /// stalling, assuming the advertised bandwidth.
+    pub fn min_buffer_time(&self) -> Option<Duration> {
+        self.inner.minBufferTime
+    }
+
+    /// `MPD@suggestedPresentationDelay`: how far behind the live edge clients should play, so
+    /// that they play in sync with each other.
+    pub fn suggested_presentation_delay(&self) -> Option<Duration> {
+        self.inner.suggestedPresentationDelay
+    }
+
+    /// `MPD@timeShiftBufferDepth`: how far behind the live edge segments stay available.
+    pub fn time_shift_buffer_depth(&self) -> Option<Duration> {
+        self.inner.timeShiftBufferDepth
+    }
+
+    /// `MPD@maxSegmentDuration`: upper bound on the duration of any segment in the presentation.
+    pub fn max_segment_duration(&self) -> Option<Duration> {
+        self.inner.maxSegmentDuration
+    }
+
+    /// MPD-level `BaseURL`s, unresolved. More than one means the content is mirrored on several
+    /// CDNs. The levels below are resolved per track, see [`Track::base_url`].
+    pub fn base_urls(&self) -> Vec<String> {
+        self.inner
+            .base_url
+            .iter()
+            .map(|base_url| base_url.base.clone())
+            .collect()
+    }
+
+    /// Events declared by `EventStream` elements of every period.
+    pub fn events(&self) -> Vec<ManifestEvent> {
+        let mut events = vec![];
+
+        for period in &self.inner.periods {
+            let period_start = period.start.map_or(0., |start| start.as_secs_f64());
+
+            for stream in &period.event_streams {
+                for event in &stream.event {
+                    let timescale = event.timescale.or(stream.timescale).unwrap_or(1).max(1) as f64;
+                    let offset = event
+                        .presentationTimeOffset
+                        .or(stream.presentationTimeOffset)
+                        .unwrap_or(0) as f64;
+                    let time = event.presentationTime.unwrap_or(0) as f64;
+
+                    let payload = event
+                        .messageData
+                        .as_ref()
+                        .or(event.content.as_ref())
+                        .map(|data| data.as_bytes().to_vec())
+                        .unwrap_or_default();
+
+                    events.push(ManifestEvent {
+                        scheme_id_uri: stream.schemeIdUri.clone(),
+                        value: event.value.clone().or(stream.value.clone()),
+                        id: event.id.clone(),
+                        start: period_start + (time - offset) / timescale,
+                        duration: event.duration.map(|duration| duration as f64 / timescale),
+                        message_data: payload,
+                    });
+                }
+            }
+        }
+
+        events
+    }
+
+    /// Fetches the remote `Period` and `AdaptationSet` elements whose actuation is up to
+    /// `actuate` from their `xlink:href`, resolved against `manifest_url`, and splices what they
+    /// contain in their place. Remote elements may link further, up to [`MAX_XLINK_DEPTH`]
+    /// rounds. `resolve-to-zero` links remove the element. Elements that fail to resolve are
+    /// kept without their link if they have content of their own, and removed otherwise.
+    pub async fn resolve_xlinks(
+        &mut self,
+        loader: &dyn Loader,
+        manifest_url: &str,
+        actuate: XlinkActuate,
+    ) {
+        for _ in 0..MAX_XLINK_DEPTH {
+            if !self
+                .resolve_xlink_round(loader, manifest_url, actuate)
+                .await
+            {
+                return;
+            }
+        }
+    }
+
+    /// Resolves every link present now, returning whether there were any.
+    async fn resolve_xlink_round(
+        &mut self,
+        loader: &dyn Loader,
+        manifest_url: &str,
+        actuate: XlinkActuate,
+    ) -> bool {
+        let mut linked = false;
+
+        let mut periods = vec![];
+        for mut period in std::mem::take(&mut self.inner.periods) {
+            let Some(href) = xlink_target(&period.href, &period.actuate, actuate) else {
+                periods.push(period);
+                continue;
+            };
+
+            linked = true;
+            period.href = None;
+            if href == XLINK_RESOLVE_TO_ZERO {
+                continue;
+            }
+
+            match fetch_remote(loader, manifest_url, &href, "{}").await {
+                Ok(remote) => periods.extend(remote.periods),
+                Err(error) => {
+                    tracing::warn!(%href, %error, "Failed to resolve remote period.");
+
+                    if !period.adaptations.is_empty() {
+                        periods.push(period);
+                    }
+                }
+            }
+        }
+
+        for period in &mut periods {
+            let mut adaptations = vec![];
+            for mut adaptation in std::mem::take(&mut period.adaptations) {
+                let Some(href) = xlink_target(&adaptation.href, &adaptation.actuate, actuate)
+                else {
+                    adaptations.push(adaptation);
+                    continue;
+                };
+
+                linked = true;
+                adaptation.href = None;
+                if href == XLINK_RESOLVE_TO_ZERO {
+                    continue;
+                }
+
+                match fetch_remote(loader, manifest_url, &href, "<Period>{}</Period>").await {
+                    Ok(remote) => adaptations.extend(
+                        remote
+                            .periods
+                            .into_iter()
+                            .flat_map(|period| period.adaptations),
+                    ),
+                    Err(error) => {
+                        tracing::warn!(%href, %error, "Failed to resolve remote adaptation set.");
+
+                        if !adaptation.representations.is_empty() {
+                            adaptations.push(adaptation);
+                        }
+                    }
+                }
+            }
+
+            period.adaptations = adaptations;
+        }
+
+        self.inner.periods = periods;
+        linked
+    }
+
+    pub fn info(&self) -> ManifestInfo {
+        ManifestInfo {
+            inner: self.inner.clone(),
+            tracks: vec![],
+            thumbnails: None,
+        }
+    }
+
     pub fn tracks(&self) -> Vec<Track> {
         let mut tracks = vec![];
 
-        for period in &self.inner.periods {
-            for adaptation in &period.adaptations {
+        for (period_index, period) in self.inner.periods.iter().enumerate() {
+            for (adaptation_index, adaptation) in period.adaptations.iter().enumerate() {
+                let track_id = TrackId::new(
+                    period
+                        .id
+                        .clone()
+                        .unwrap_or_else(|| period_index.to_string()),
+                    adaptation
+                        .id
+                        .clone()
+                        .unwrap_or_else(|| adaptation_index.to_string()),
+                );
+
                 for representation in &adaptation.representations {
-                    let mut track = Track::new(representation.clone(), adaptation.clone());
+                    let mut track =
+                        Track::new(track_id.clone(), representation.clone(), adaptation.clone());
                     track.adaptation_segment_template(adaptation.SegmentTemplate.clone());
+                    track.base_path(
+                        [
+                            &period.BaseURL,
+                            &adaptation.BaseURL,
+                            &representation.BaseURL,
+                        ]
+                        .into_iter()
+                        .filter_map(|base_urls| base_urls.first())
+                        .map(|base_url| base_url.base.trim().to_string())
+                        .collect(),
+                    );
 
                     tracks.push(track);
                 }
@@ -44,21 +311,476 @@ impl Manifest {
     }
 }
 
+/// The `xlink:href` of an element to resolve now, given its `xlink:actuate`.
+fn xlink_target(
+    href: &Option<String>,
+    actuate: &Option<String>,
+    up_to: XlinkActuate,
+) -> Option<String> {
+    let href = href.as_deref()?.trim();
+
+    (!href.is_empty() && XlinkActuate::from_attribute(actuate.as_deref()) <= up_to)
+        .then(|| href.to_string())
+}
+
+/// Fetches the XML fragment at `href` and parses it as a manifest by placing it in `wrapper`,
+/// where `{}` stands for the fragment, inside an `MPD` element.
+async fn fetch_remote(
+    loader: &dyn Loader,
+    manifest_url: &str,
+    href: &str,
+    wrapper: &str,
+) -> Result<dash_mpd::MPD, String> {
+    let url = Url::parse(manifest_url)
+        .and_then(|manifest_url| manifest_url.join(href))
+        .map_err(|error| error.to_string())?;
+
+    let body = loader
+        .fetch(SegmentRequest::new(url, RequestKind::Manifest))
+        .await
+        .map_err(|error| error.to_string())?;
+    let xml = String::from_utf8(body.to_vec()).map_err(|error| error.to_string())?;
+
+    // A declaration is only allowed at the very start of a document.
+    let mut fragment = xml.trim_start();
+    if fragment.starts_with("<?xml") {
+        fragment = fragment.split_once("?>").map_or(fragment, |(_, rest)| rest);
+    }
+
+    let document = format!(
+        r#"<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" xmlns:xlink="http://www.w3.org/1999/xlink">{}</MPD>"#,
+        wrapper.replace("{}", fragment)
+    );
+
+    dash_mpd::parse(&document).map_err(|error| error.to_string())
+}
+
+/// Read-only snapshot of the parsed MPD, handed out to applications so that tooling doesn't have to
+/// fetch and parse the manifest a second time.
+#[derive(Clone, Debug)]
+pub struct ManifestInfo {
+    inner: dash_mpd::MPD,
+    tracks: Vec<TrackInfo>,
+    thumbnails: Option<ThumbnailTrack>,
+}
+
+impl ManifestInfo {
+    /// The full parsed MPD with every attribute the parser understands.
+    pub fn mpd(&self) -> &dash_mpd::MPD {
+        &self.inner
+    }
+
+    pub fn periods(&self) -> &[Period] {
+        &self.inner.periods
+    }
+
+    pub fn adaptation_sets(&self) -> impl Iterator<Item = &AdaptationSet> {
+        self.periods()
+            .iter()
+            .flat_map(|period| period.adaptations.iter())
+    }
+
+    pub fn representations(&self) -> impl Iterator<Item = &Representation> {
+        self.adaptation_sets()
+            .flat_map(|adaptation| adaptation.representations.iter())
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        self.inner.mediaPresentationDuration
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.inner.mpdtype.as_deref() == Some("dynamic")
+    }
+
+    /// Every representation in the manifest, including the ones the browser can't decode.
+    pub fn tracks(&self) -> &[TrackInfo] {
+        &self.tracks
+    }
+
+    /// Representations skipped because the browser can't decode their codecs, or because the
+    /// manifest doesn't say what they are.
+    pub fn unsupported_tracks(&self) -> impl Iterator<Item = &TrackInfo> {
+        self.tracks.iter().filter(|track| !track.supported)
+    }
+
+    pub fn set_tracks(&mut self, tracks: Vec<TrackInfo>) {
+        self.tracks = tracks;
+    }
+
+    /// Tiled thumbnails for seek-bar previews, if the manifest has an image adaptation set.
+    pub fn thumbnails(&self) -> Option<&ThumbnailTrack> {
+        self.thumbnails.as_ref()
+    }
+
+    pub fn set_thumbnails(&mut self, thumbnails: Option<ThumbnailTrack>) {
+        self.thumbnails = thumbnails;
+    }
+}
+
+/// `EssentialProperty` scheme of trick mode adaptation sets, valued with the ids of the
+/// adaptation sets they belong to.
+const TRICK_MODE_SCHEME: &str = "http://dashif.org/guidelines/trickmode";
+/// Scheme of the DASH `Role` descriptor.
+const ROLE_SCHEME: &str = "urn:mpeg:dash:role:2011";
+/// `Accessibility` scheme of DVB and HbbTV: `1` is audio description for the visually
+/// impaired, `2` audio for the hard of hearing.
+const AUDIO_PURPOSE_SCHEME: &str = "urn:tva:metadata:cs:AudioPurposeCS:2007";
+/// `Accessibility` scheme announcing CEA-608 captions carried in the video.
+const CEA_608_SCHEME: &str = "urn:scte:dash:cc:cea-608:2015";
+/// `AudioChannelConfiguration` scheme valued with the number of channels.
+const CHANNEL_COUNT_SCHEME: &str = "urn:mpeg:dash:23003:3:audio_channel_configuration:2011";
+/// `AudioChannelConfiguration` scheme valued with a `ChannelConfiguration` index of ISO/IEC
+/// 23091-3.
+const CICP_CHANNEL_SCHEME: &str = "urn:mpeg:mpegB:cicp:ChannelConfiguration";
+/// `AudioChannelConfiguration` schemes of Dolby, valued with a 16 bit channel mask in hex.
+const DOLBY_CHANNEL_SCHEMES: [&str; 2] = [
+    "tag:dolby.com,2014:dash:audio_channel_configuration:2011",
+    "urn:dolby:dash:audio_channel_configuration:2011",
+];
+/// `EssentialProperty` and `SupplementalProperty` schemes valued with a color code point of
+/// ISO/IEC 23091-2.
+const COLOUR_PRIMARIES_SCHEME: &str = "urn:mpeg:mpegB:cicp:ColourPrimaries";
+const TRANSFER_CHARACTERISTICS_SCHEME: &str = "urn:mpeg:mpegB:cicp:TransferCharacteristics";
+const MATRIX_COEFFICIENTS_SCHEME: &str = "urn:mpeg:mpegB:cicp:MatrixCoefficients";
+/// `TransferCharacteristics` of the PQ transfer function of SMPTE ST 2084, used by HDR10.
+const TRANSFER_PQ: u8 = 16;
+/// `TransferCharacteristics` of hybrid log-gamma.
+const TRANSFER_HLG: u8 = 18;
+/// Four-character codes of Dolby Vision.
+const DOLBY_VISION_CODECS: [&str; 5] = ["dvh1", "dvhe", "dav1", "dva1", "dvav"];
+
+/// How well `language` satisfies the `preferred` BCP 47 tag: `2` if they're the same, ignoring
+/// case, `1` if only their primary language, e.g. `en` of `en-GB`, is, and `0` otherwise.
+pub fn language_match(preferred: &str, language: &str) -> u8 {
+    let primary = |tag: &str| {
+        tag.split(['-', '_'])
+            .next()
+            .unwrap_or_default()
+            .to_lowercase()
+    };
+
+    if preferred.eq_ignore_ascii_case(language) {
+        2
+    } else if !preferred.is_empty() && primary(preferred) == primary(language) {
+        1
+    } else {
+        0
+    }
+}
+
+/// What a track is for, from `Role` and `Accessibility` descriptors in the DASH role scheme.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TrackRole {
+    Main,
+    Alternate,
+    Supplementary,
+    Commentary,
+    Dub,
+    Emergency,
+    /// Audio description of the video for the visually impaired.
+    Description,
+    /// Captions for the deaf and hard of hearing, i.e. SDH.
+    Caption,
+    Subtitle,
+    Sign,
+    EnhancedAudioIntelligibility,
+    Karaoke,
+    ForcedSubtitle,
+}
+
+impl TrackRole {
+    /// The role for a `@value` of the DASH role scheme.
+    pub fn from_value(value: &str) -> Option<Self> {
+        match value {
+            "main" => Some(Self::Main),
+            "alternate" => Some(Self::Alternate),
+            "supplementary" => Some(Self::Supplementary),
+            "commentary" => Some(Self::Commentary),
+            "dub" => Some(Self::Dub),
+            "emergency" => Some(Self::Emergency),
+            "description" => Some(Self::Description),
+            "caption" => Some(Self::Caption),
+            "subtitle" => Some(Self::Subtitle),
+            "sign" => Some(Self::Sign),
+            "enhanced-audio-intelligibility" => Some(Self::EnhancedAudioIntelligibility),
+            "karaoke" => Some(Self::Karaoke),
+            "forced-subtitle" | "forced_subtitle" => Some(Self::ForcedSubtitle),
+            _ => None,
+        }
+    }
+}
+
+/// `EssentialProperty` schemes declaring the tile grid of thumbnail images.
+const THUMBNAIL_TILE_SCHEMES: [&str; 2] = [
+    "http://dashif.org/guidelines/thumbnail_tile",
+    "http://dashif.org/thumbnail_tile",
+];
+
+/// Color code points of a video representation, ISO/IEC 23091-2, as signalled by CICP
+/// `EssentialProperty` and `SupplementalProperty` descriptors. `None` where the manifest
+/// doesn't say.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ColorInfo {
+    /// E.g. `1` for BT.709 or `9` for BT.2020.
+    pub colour_primaries: Option<u8>,
+    /// E.g. `1` for BT.709, `16` for PQ or `18` for HLG.
+    pub transfer_characteristics: Option<u8>,
+    pub matrix_coefficients: Option<u8>,
+}
+
+/// Dynamic range of a video representation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DynamicRange {
+    #[default]
+    Sdr,
+    /// The PQ transfer function, with static metadata.
+    Hdr10,
+    /// Hybrid log-gamma.
+    Hlg,
+    /// Signalled by the codecs, whatever the transfer function.
+    DolbyVision,
+}
+
+impl DynamicRange {
+    pub fn is_hdr(self) -> bool {
+        self != Self::Sdr
+    }
+
+    /// How quality menus usually name it, e.g. `HDR10`.
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Sdr => "SDR",
+            Self::Hdr10 => "HDR10",
+            Self::Hlg => "HLG",
+            Self::DolbyVision => "Dolby Vision",
+        }
+    }
+}
+
+/// What the manifest leaves out about a representation that makes it unplayable.
+#[derive(Clone, Copy, Debug, Display, Error, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MissingInfo {
+    /// no mimeType, and none could be inferred from the codecs
+    MimeType,
+    /// no codecs
+    Codecs,
+}
+
+/// Number of channels of a `ChannelConfiguration` index, ISO/IEC 23091-3 table 8.
+fn cicp_channels(index: u32) -> Option<u32> {
+    Some(match index {
+        1..=6 => index,
+        7 | 12 | 14 => 8,
+        9 => 3,
+        10 => 4,
+        11 => 7,
+        13 => 24,
+        15 | 17 | 19 => 12,
+        16 => 10,
+        18 | 20 => 14,
+        _ => return None,
+    })
+}
+
+/// Number of channels of a Dolby channel mask. Some of its bits stand for a pair of speakers.
+fn dolby_channels(mask: u16) -> u32 {
+    // Counted from the most significant bit: Lc/Rc, Lrs/Rrs, Lsd/Rsd, Lw/Rw, Vhl/Vhr, Lts/Rts.
+    const PAIRS: u16 = 0b0000_0110_0111_0100;
+
+    mask.count_ones() + (mask & PAIRS).count_ones()
+}
+
+/// Name of the codec and profile of the first audio codec in a `@codecs` list.
+fn audio_codec_profile(codecs: &str) -> Option<&'static str> {
+    let codec = codecs.split(',').next()?.trim();
+
+    Some(match codec {
+        "mp4a.40.2" => "AAC-LC",
+        "mp4a.40.5" => "HE-AAC",
+        "mp4a.40.29" => "HE-AACv2",
+        "mp4a.40.34" | "mp4a.6B" | "mp4a.69" => "MP3",
+        "ac-3" => "Dolby Digital",
+        "ec-3" => "Dolby Digital Plus",
+        "opus" | "Opus" => "Opus",
+        "flac" | "fLaC" => "FLAC",
+        _ if codec.starts_with("mp4a.40") => "AAC",
+        _ if codec.starts_with("ac-4") => "Dolby AC-4",
+        _ if codec.starts_with("dts") => "DTS",
+        _ if codec.starts_with("mha1") || codec.starts_with("mhm1") => "MPEG-H 3D Audio",
+        _ => return None,
+    })
+}
+
+/// Content type of the first codec in a `@codecs` list, going by its four-character code.
+fn codecs_content_type(codecs: &str) -> Option<&'static str> {
+    let codec = codecs.split(',').next()?.trim();
+
+    match codec.split('.').next()? {
+        "avc1" | "avc3" | "hvc1" | "hev1" | "dvh1" | "dvhe" | "dav1" | "dva1" | "dvav" | "vp08"
+        | "vp09" | "vp8" | "vp9" | "av01" => Some("video"),
+        "mp4a" | "ac-3" | "ec-3" | "ac-4" | "opus" | "Opus" | "flac" | "fLaC" | "dtsc" | "dtse"
+        | "dtsx" | "mha1" | "mhm1" => Some("audio"),
+        "stpp" | "wvtt" => Some("text"),
+        _ => None,
+    }
+}
+
+/// A representation as presented to the embedder.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrackInfo {
+    pub track: TrackId,
+    pub representation: RepresentationId,
+    /// Mime type with codecs, as checked with `MediaSource.isTypeSupported`. `None` if the
+    /// manifest doesn't say.
+    pub content_type: Option<String>,
+    pub bitrate: Option<u64>,
+    pub width: Option<u64>,
+    pub height: Option<u64>,
+    /// Whether the browser can decode this representation. Unsupported ones are never played.
+    pub supported: bool,
+    /// Roles of the adaptation set, from `Role` and `Accessibility` descriptors. Empty if the
+    /// manifest doesn't say.
+    pub roles: Vec<TrackRole>,
+    /// `Label` of the adaptation set, for menus.
+    pub label: Option<String>,
+    /// `@lang` of the representation or its adaptation set.
+    pub language: Option<String>,
+    /// Audio describing the video for the visually impaired.
+    pub audio_description: bool,
+    /// Captions or subtitles for the deaf and hard of hearing (SDH), including CEA-608 captions
+    /// carried in a video track.
+    pub captions: bool,
+    /// Number of audio channels, e.g. `6` for 5.1 surround, see [`Self::channel_layout`].
+    pub channels: Option<u32>,
+    /// Audio sampling rate in Hz.
+    pub sample_rate: Option<u32>,
+    /// Audio codec and profile, e.g. `HE-AAC` or `Dolby Digital Plus`.
+    pub codec_profile: Option<String>,
+    /// Color signalling of video representations.
+    pub color: ColorInfo,
+    pub dynamic_range: DynamicRange,
+}
+
+impl TrackInfo {
+    pub fn new(track: &Track, supported: bool) -> Self {
+        Self {
+            track: track.track_id().clone(),
+            representation: track.id(),
+            content_type: track.mime_type_with_codecs().ok(),
+            bitrate: track.bitrate(),
+            width: track.width(),
+            height: track.height(),
+            supported,
+            roles: track.roles(),
+            label: track.label(),
+            language: track.language().map(String::from),
+            audio_description: track.is_audio_description(),
+            captions: track.has_captions(),
+            channels: track.channels(),
+            sample_rate: track.sample_rate(),
+            codec_profile: track.codec_profile().map(String::from),
+            color: track.color_info(),
+            dynamic_range: track.dynamic_range(),
+        }
+    }
+
+    /// How audio menus usually name the channel count, e.g. `stereo` or `5.1`.
+    pub fn channel_layout(&self) -> Option<String> {
+        Some(match self.channels? {
+            1 => "mono".into(),
+            2 => "stereo".into(),
+            6 => "5.1".into(),
+            8 => "7.1".into(),
+            channels => format!("{channels} channels"),
+        })
+    }
+}
+
+/// An event declared in the manifest by an `EventStream` element.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ManifestEvent {
+    pub scheme_id_uri: String,
+    pub value: Option<String>,
+    pub id: Option<String>,
+    /// Presentation time, in seconds.
+    pub start: f64,
+    /// In seconds.
+    pub duration: Option<f64>,
+    /// `@messageData`, or else the element's text content.
+    pub message_data: Vec<u8>,
+}
+
+/// Identifies a track, i.e. an adaptation set, across manifest refreshes and rendition switches.
+/// Built from the period and adaptation set ids, falling back to their position in the manifest
+/// where the ids are missing.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct TrackId(String);
+
+impl TrackId {
+    fn new(period: String, adaptation: String) -> Self {
+        Self(format!("{period}/{adaptation}"))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for TrackId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// The `Representation@id` of a single rendition.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct RepresentationId(String);
+
+impl RepresentationId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for RepresentationId {
+    fn from(id: String) -> Self {
+        Self(id)
+    }
+}
+
+impl From<&str> for RepresentationId {
+    fn from(id: &str) -> Self {
+        Self(id.to_owned())
+    }
+}
+
+impl fmt::Display for RepresentationId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Track {
+    track_id: TrackId,
     /// Sometimes manifests dont have a segment template in the representation, but in the
     /// adaptation set.
     adaptation_segment_template: Option<SegmentTemplate>,
     representation: Representation,
     adaptation: AdaptationSet,
+    /// `BaseURL`s of the period, adaptation set and representation, outermost first.
+    base_path: Vec<String>,
 }
 
 impl Track {
-    fn new(rep: Representation, adaptation: AdaptationSet) -> Self {
+    fn new(track_id: TrackId, rep: Representation, adaptation: AdaptationSet) -> Self {
         Self {
+            track_id,
             representation: rep,
             adaptation_segment_template: None,
             adaptation,
+            base_path: vec![],
         }
     }
 
@@ -66,8 +788,34 @@ impl Track {
         self.adaptation_segment_template = template;
     }
 
-    pub fn id(&self) -> String {
-        self.representation.id.clone().unwrap_or_default()
+    fn base_path(&mut self, base_path: Vec<String>) {
+        self.base_path = base_path;
+    }
+
+    /// Resolves the period, adaptation set and representation `BaseURL`s, each relative to the
+    /// one above it, starting from `root` (the MPD-level `BaseURL` or the manifest URL).
+    pub fn base_url(&self, root: &Url) -> Url {
+        self.base_path.iter().fold(root.clone(), |base, path| {
+            base.join(path).unwrap_or_else(|error| {
+                tracing::warn!(path, ?error, "Ignoring invalid BaseURL.");
+                base
+            })
+        })
+    }
+
+    /// The adaptation set this rendition belongs to.
+    pub fn track_id(&self) -> &TrackId {
+        &self.track_id
+    }
+
+    pub fn id(&self) -> RepresentationId {
+        RepresentationId(self.representation.id.clone().unwrap_or_default())
+    }
+
+    /// Whether `other` is a representation of the same adaptation set, i.e. a rendition we can
+    /// switch to seamlessly.
+    pub fn same_adaptation(&self, other: &Track) -> bool {
+        self.track_id == other.track_id
     }
 
     pub fn segment_template(&self) -> Option<&SegmentTemplate> {
@@ -77,44 +825,313 @@ impl Track {
     }
 
     pub fn is_video(&self) -> bool {
-        let mime = self.mime();
-        let content_type = self.content_type();
+        self.content_type()
+            .is_some_and(|content_type| content_type.contains("video"))
+    }
 
-        mime.contains("video") || content_type.contains("video")
+    /// Whether this is an I-frame only rendition for fast scrubbing, signalled by the DASH-IF
+    /// `trickmode` `EssentialProperty` or a `trick` role.
+    pub fn is_trick_mode(&self) -> bool {
+        self.trick_mode_property().is_some()
+            || self.adaptation.Role.iter().any(|role| {
+                role.schemeIdUri == ROLE_SCHEME && role.value.as_deref() == Some("trick")
+            })
     }
 
-    pub fn is_audio(&self) -> bool {
-        let mime = self.mime();
-        let content_type = self.content_type();
+    /// Whether this trick mode rendition stands in for `main`. Trick mode adaptation sets name
+    /// the adaptation sets they belong to; ones that don't are taken to fit any video.
+    pub fn is_trick_mode_of(&self, main: &Track) -> bool {
+        if !self.is_trick_mode() || self.track_id == main.track_id {
+            return false;
+        }
+
+        let Some(ids) = self
+            .trick_mode_property()
+            .and_then(|property| property.value.as_deref())
+        else {
+            return true;
+        };
+
+        main.adaptation
+            .id
+            .as_deref()
+            .is_some_and(|id| ids.split_whitespace().any(|candidate| candidate == id))
+    }
+
+    /// Roles from the adaptation set's `Role` descriptors and from its `Accessibility`
+    /// descriptors that use the role scheme, without duplicates.
+    pub fn roles(&self) -> Vec<TrackRole> {
+        let roles = self
+            .adaptation
+            .Role
+            .iter()
+            .map(|role| (&role.schemeIdUri, &role.value));
+        let accessibility = self
+            .adaptation
+            .Accessibility
+            .iter()
+            .map(|accessibility| (&accessibility.schemeIdUri, &accessibility.value));
+
+        let mut found = vec![];
+        for (scheme, value) in roles.chain(accessibility) {
+            let Some(role) = value
+                .as_deref()
+                .filter(|_| scheme == ROLE_SCHEME)
+                .and_then(TrackRole::from_value)
+            else {
+                continue;
+            };
+
+            if !found.contains(&role) {
+                found.push(role);
+            }
+        }
 
-        mime.contains("audio") || content_type.contains("audio")
+        found
     }
 
-    pub fn mime(&self) -> String {
+    /// Text of the first `Label` of the representation or its adaptation set.
+    pub fn label(&self) -> Option<String> {
+        self.representation
+            .Label
+            .iter()
+            .chain(self.adaptation.Label.iter())
+            .map(|label| label.content.trim())
+            .find(|label| !label.is_empty())
+            .map(String::from)
+    }
+
+    /// `@lang` of the representation or its adaptation set.
+    pub fn language(&self) -> Option<&str> {
+        self.representation
+            .lang
+            .as_deref()
+            .or(self.adaptation.lang.as_deref())
+    }
+
+    /// Whether this is audio describing the video, signalled by the `description` role or the
+    /// DVB audio purpose `1`.
+    pub fn is_audio_description(&self) -> bool {
+        self.is_audio()
+            && (self.roles().contains(&TrackRole::Description)
+                || self.has_accessibility(AUDIO_PURPOSE_SCHEME, Some("1")))
+    }
+
+    /// Whether this carries captions for the deaf and hard of hearing: a text track with the
+    /// `caption` role, or a video track announcing CEA-608 captions.
+    pub fn has_captions(&self) -> bool {
+        self.roles().contains(&TrackRole::Caption) || self.has_accessibility(CEA_608_SCHEME, None)
+    }
+
+    /// Whether an `Accessibility` descriptor with `scheme`, and `value` if given, is present.
+    fn has_accessibility(&self, scheme: &str, value: Option<&str>) -> bool {
+        self.adaptation.Accessibility.iter().any(|accessibility| {
+            accessibility.schemeIdUri == scheme
+                && value.is_none_or(|value| accessibility.value.as_deref() == Some(value))
+        })
+    }
+
+    fn trick_mode_property(&self) -> Option<&EssentialProperty> {
+        self.adaptation
+            .essential_property
+            .iter()
+            .find(|property| property.schemeIdUri == TRICK_MODE_SCHEME)
+    }
+
+    /// Number of audio channels, from the first `AudioChannelConfiguration` of the
+    /// representation or its adaptation set in a known scheme.
+    pub fn channels(&self) -> Option<u32> {
+        self.representation
+            .AudioChannelConfiguration
+            .iter()
+            .chain(self.adaptation.AudioChannelConfiguration.iter())
+            .find_map(|configuration| {
+                let value = configuration.value.as_deref()?.trim();
+
+                match configuration.schemeIdUri.as_str() {
+                    CHANNEL_COUNT_SCHEME => value.parse().ok(),
+                    CICP_CHANNEL_SCHEME => cicp_channels(value.parse().ok()?),
+                    scheme if DOLBY_CHANNEL_SCHEMES.contains(&scheme) => {
+                        u16::from_str_radix(value, 16).ok().map(dolby_channels)
+                    }
+                    _ => None,
+                }
+            })
+            .filter(|channels| *channels > 0)
+    }
+
+    /// `@audioSamplingRate` of the representation or its adaptation set, in Hz. Of a range,
+    /// the lowest.
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.representation
+            .audioSamplingRate
+            .as_ref()
+            .or(self.adaptation.audioSamplingRate.as_ref())?
+            .split_whitespace()
+            .next()?
+            .parse()
+            .ok()
+    }
+
+    /// Name of the audio codec and profile, e.g. `AAC-LC`, for audio representations.
+    pub fn codec_profile(&self) -> Option<&'static str> {
+        self.codecs()
+            .filter(|_| self.is_audio())
+            .and_then(|codecs| audio_codec_profile(&codecs))
+    }
+
+    /// Color code points of the representation or its adaptation set, see [`ColorInfo`].
+    pub fn color_info(&self) -> ColorInfo {
+        ColorInfo {
+            colour_primaries: self.cicp(COLOUR_PRIMARIES_SCHEME),
+            transfer_characteristics: self.cicp(TRANSFER_CHARACTERISTICS_SCHEME),
+            matrix_coefficients: self.cicp(MATRIX_COEFFICIENTS_SCHEME),
+        }
+    }
+
+    /// Dynamic range of video representations: Dolby Vision by its codecs, else HDR10 or HLG
+    /// by the transfer characteristics.
+    pub fn dynamic_range(&self) -> DynamicRange {
+        if !self.is_video() {
+            return DynamicRange::Sdr;
+        }
+
+        let dolby_vision = self.codecs().is_some_and(|codecs| {
+            let codec = codecs.split(',').next().unwrap_or_default().trim();
+            DOLBY_VISION_CODECS.contains(&codec.split('.').next().unwrap_or_default())
+        });
+        if dolby_vision {
+            return DynamicRange::DolbyVision;
+        }
+
+        match self.color_info().transfer_characteristics {
+            Some(TRANSFER_PQ) => DynamicRange::Hdr10,
+            Some(TRANSFER_HLG) => DynamicRange::Hlg,
+            _ => DynamicRange::Sdr,
+        }
+    }
+
+    /// The code point of a CICP `scheme` among the essential, then the supplemental properties
+    /// of the representation and its adaptation set.
+    fn cicp(&self, scheme: &str) -> Option<u8> {
+        let essential = self
+            .representation
+            .essential_property
+            .iter()
+            .chain(self.adaptation.essential_property.iter())
+            .map(|property| (&property.schemeIdUri, &property.value));
+        let supplemental = self
+            .representation
+            .supplemental_property
+            .iter()
+            .chain(self.adaptation.supplemental_property.iter())
+            .map(|property| (&property.schemeIdUri, &property.value));
+
+        essential
+            .chain(supplemental)
+            .find(|(scheme_id_uri, _)| *scheme_id_uri == scheme)
+            .and_then(|(_, value)| value.as_deref()?.trim().parse().ok())
+    }
+
+    /// `@frameRate` of the representation or its adaptation set, in frames per second. A
+    /// fraction like `30000/1001` is divided out.
+    pub fn frame_rate(&self) -> Option<f64> {
+        let frame_rate = self
+            .representation
+            .frameRate
+            .as_ref()
+            .or(self.adaptation.frameRate.as_ref())?;
+
+        match frame_rate.split_once('/') {
+            Some((frames, seconds)) => {
+                let seconds: f64 = seconds.trim().parse().ok()?;
+                Some(frames.trim().parse::<f64>().ok()? / seconds).filter(|rate| rate.is_finite())
+            }
+            None => frame_rate.trim().parse().ok(),
+        }
+    }
+
+    /// Thumbnail sprites, e.g. an `image/jpeg` adaptation set.
+    pub fn is_image(&self) -> bool {
+        self.content_type()
+            .is_some_and(|content_type| content_type.contains("image"))
+    }
+
+    /// Columns and rows of the thumbnail grid in every image, from the DASH-IF
+    /// `thumbnail_tile` `EssentialProperty`, e.g. `10x20`.
+    pub fn thumbnail_tiles(&self) -> Option<(u64, u64)> {
+        let property = self
+            .representation
+            .essential_property
+            .iter()
+            .chain(self.adaptation.essential_property.iter())
+            .find(|property| THUMBNAIL_TILE_SCHEMES.contains(&property.schemeIdUri.as_str()))?;
+
+        let (columns, rows) = property.value.as_ref()?.split_once(['x', 'X'])?;
+        Some((columns.trim().parse().ok()?, rows.trim().parse().ok()?))
+    }
+
+    pub fn is_audio(&self) -> bool {
+        self.content_type()
+            .is_some_and(|content_type| content_type.contains("audio"))
+    }
+
+    /// `@mimeType` of the representation or its adaptation set. Packagers that leave it out get
+    /// the fragmented MP4 type matching their codecs.
+    pub fn mime(&self) -> Option<String> {
         self.representation
             .mimeType
             .as_ref()
             .or(self.adaptation.mimeType.as_ref())
             .cloned()
-            .expect("Mime type not set on representation.")
+            .or_else(|| {
+                let content_type = codecs_content_type(&self.codecs()?)?;
+                Some(match content_type {
+                    "text" => "application/mp4".into(),
+                    _ => format!("{content_type}/mp4"),
+                })
+            })
     }
 
-    pub fn codecs(&self) -> String {
+    pub fn codecs(&self) -> Option<String> {
         self.representation
             .codecs
             .as_ref()
             .or(self.adaptation.codecs.as_ref())
             .cloned()
-            .expect("Codecs not set on representation.")
     }
 
-    pub fn content_type(&self) -> String {
-        self.representation
+    /// Content type as passed to `addSourceBuffer`, e.g. `video/mp4; codecs="avc1.64001f"`.
+    /// Fails if the manifest doesn't say enough about the representation to set up a source
+    /// buffer for it.
+    pub fn mime_type_with_codecs(&self) -> Result<String, MissingInfo> {
+        let mime = self.mime().ok_or(MissingInfo::MimeType)?;
+        let codecs = self.codecs().ok_or(MissingInfo::Codecs)?;
+
+        Ok(format!("{mime}; codecs=\"{codecs}\""))
+    }
+
+    /// `@contentType`, e.g. `video`, or where that is missing, what the mime type or codecs say.
+    pub fn content_type(&self) -> Option<String> {
+        if let Some(content_type) = self
+            .representation
             .contentType
             .as_ref()
             .or(self.adaptation.contentType.as_ref())
-            .cloned()
-            .expect("Content-Type not set on representation.")
+        {
+            return Some(content_type.clone());
+        }
+
+        let mime = self
+            .representation
+            .mimeType
+            .as_ref()
+            .or(self.adaptation.mimeType.as_ref());
+
+        match mime.and_then(|mime| mime.split('/').next()) {
+            Some(kind @ ("video" | "audio" | "text" | "image")) => Some(kind.into()),
+            _ => codecs_content_type(&self.codecs()?).map(Into::into),
+        }
     }
 
     pub fn initialization(&self) -> ChunkTemplate {
@@ -135,24 +1152,84 @@ impl Track {
             .into()
     }
 
+    /// Substitutes the identifiers that are fixed for this representation, `$RepresentationID$`
+    /// and `$Bandwidth$`.
+    pub fn resolve_identifiers(&self, template: &mut ChunkTemplate) {
+        template.set_id(self.id().to_string());
+
+        if let Some(bandwidth) = self.bitrate() {
+            template.set_bandwidth(bandwidth);
+        }
+    }
+
+    /// The media template with the representation-level identifiers resolved, ready for
+    /// per-segment use.
+    pub fn media_url_template(&self) -> SegmentUrlTemplate {
+        let mut media = self.media();
+        self.resolve_identifiers(&mut media);
+
+        SegmentUrlTemplate::from(&media)
+    }
+
+    /// Number of the first segment, 1 when the template doesn't say.
     pub fn start_number(&self) -> usize {
         self.segment_template()
-            .as_ref()
-            .unwrap()
-            .startNumber
-            .unwrap() as _
+            .and_then(|x| x.startNumber)
+            .unwrap_or(1) as _
     }
 
-    pub fn segment_duration(&self) -> Option<f64> {
-        // Optional timescale
-        let timescale = self
-            .segment_template()
+    fn timescale(&self) -> f64 {
+        self.segment_template()
             .and_then(|x| x.timescale)
-            .unwrap_or(1);
+            .unwrap_or(1) as _
+    }
 
+    pub fn segment_duration(&self) -> Option<f64> {
         self.segment_template()
             .and_then(|x| x.duration)
-            .map(|duration| duration / timescale as f64)
+            .map(|duration| duration / self.timescale())
+    }
+
+    /// `@availabilityTimeOffset`: how many seconds before their end segments can be requested.
+    /// Low-latency streams set this so the segment still being encoded can be fetched.
+    pub fn availability_time_offset(&self) -> f64 {
+        self.segment_template()
+            .and_then(|x| x.availabilityTimeOffset)
+            .unwrap_or(0.)
+    }
+
+    /// `@availabilityTimeComplete`: `false` means segments are delivered in chunks while they
+    /// are still being produced.
+    pub fn availability_time_complete(&self) -> bool {
+        self.segment_template()
+            .and_then(|x| x.availabilityTimeComplete)
+            .unwrap_or(true)
+    }
+
+    /// `@presentationTimeOffset` in seconds: the media time at which the period starts.
+    pub fn presentation_time_offset(&self) -> f64 {
+        self.segment_template()
+            .and_then(|x| x.presentationTimeOffset)
+            .map_or(0., |offset| offset as f64 / self.timescale())
+    }
+
+    /// Segment number math for templates with a fixed `@duration`.
+    pub fn segment_index(&self) -> Option<SegmentIndex> {
+        Some(SegmentIndex::new(
+            self.start_number(),
+            self.segment_duration()?,
+            self.presentation_time_offset(),
+        ))
+    }
+
+    /// `ContentProtection` descriptors from both the representation and its adaptation set.
+    pub fn content_protection(&self) -> Vec<ContentProtection> {
+        self.adaptation
+            .ContentProtection
+            .iter()
+            .chain(self.representation.ContentProtection.iter())
+            .cloned()
+            .collect()
     }
 
     pub fn bitrate(&self) -> Option<u64> {
@@ -168,6 +1245,64 @@ impl Track {
     }
 }
 
+/// Float noise tolerated when a time falls on a segment boundary, in seconds, so that the start
+/// of a segment maps to that segment and not the one before.
+const SEGMENT_BOUNDARY_TOLERANCE: f64 = 1e-6;
+
+/// Maps between media time and the `$Number$` of fixed-duration segments, taking `@startNumber`
+/// and `@presentationTimeOffset` into account.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SegmentIndex {
+    start_number: usize,
+    /// Segment duration in seconds.
+    duration: f64,
+    /// Media time of the start of the first segment, in seconds.
+    presentation_time_offset: f64,
+}
+
+impl SegmentIndex {
+    pub fn new(start_number: usize, duration: f64, presentation_time_offset: f64) -> Self {
+        Self {
+            start_number,
+            duration,
+            presentation_time_offset,
+        }
+    }
+
+    pub fn start_number(&self) -> usize {
+        self.start_number
+    }
+
+    /// The segment containing media time `time`. Times before the first segment map to the
+    /// first segment.
+    pub fn segment_at(&self, time: f64) -> usize {
+        let elapsed = ((time - self.presentation_time_offset + SEGMENT_BOUNDARY_TOLERANCE)
+            / self.duration)
+            .floor();
+        self.start_number + elapsed.max(0.) as usize
+    }
+
+    /// The newest segment that can be requested `elapsed` seconds into the period, when segments
+    /// become available `availability_time_offset` seconds before they are complete.
+    pub fn live_edge(&self, elapsed: f64, availability_time_offset: f64) -> usize {
+        // An offset beyond one segment (even INF) can't make segments of the future available.
+        let offset = availability_time_offset.clamp(0., self.duration);
+        let available = ((elapsed + offset) / self.duration).floor() - 1.;
+        self.start_number + available.max(0.) as usize
+    }
+
+    /// Duration of every segment, in seconds.
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    /// Media time at which segment `number` starts.
+    pub fn segment_start(&self, number: usize) -> f64 {
+        let elapsed = number.saturating_sub(self.start_number) as f64;
+        self.presentation_time_offset + elapsed * self.duration
+    }
+}
+
 pub struct ChunkTemplate {
     template: String,
 }
@@ -177,11 +1312,112 @@ impl ChunkTemplate {
         self.template = resolve_url_template(&self.template, ("RepresentationID", id));
     }
 
+    pub fn set_bandwidth(&mut self, bandwidth: u64) {
+        self.template = resolve_url_template(&self.template, ("Bandwidth", bandwidth.to_string()));
+    }
+
     pub fn set_number(&mut self, number: usize) {
         self.template = resolve_url_template(&self.template, ("Number", number.to_string()));
     }
 }
 
+/// A media template with the per-representation identifiers already resolved, split into parts
+/// so that building a segment URL is plain string concatenation instead of regex work.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SegmentUrlTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TemplatePart {
+    Literal(String),
+    Number { width: usize },
+    Time { width: usize },
+}
+
+impl SegmentUrlTemplate {
+    /// URL of the segment with the given `$Number$`.
+    pub fn number(&self, number: usize) -> String {
+        self.render(number as u64)
+    }
+
+    /// URL of the segment with the given `$Time$`.
+    pub fn time(&self, time: u64) -> String {
+        self.render(time)
+    }
+
+    fn render(&self, value: u64) -> String {
+        let mut url = String::new();
+
+        for part in &self.parts {
+            match part {
+                TemplatePart::Literal(literal) => url.push_str(literal),
+                TemplatePart::Number { width } | TemplatePart::Time { width } => {
+                    url.push_str(&format!("{value:0>width$}"))
+                }
+            }
+        }
+
+        url
+    }
+}
+
+impl From<&ChunkTemplate> for SegmentUrlTemplate {
+    fn from(template: &ChunkTemplate) -> Self {
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut rest = template.as_ref();
+
+        while let Some(start) = rest.find('$') {
+            literal.push_str(&rest[..start]);
+            rest = &rest[start + 1..];
+
+            let Some(end) = rest.find('$') else {
+                // Unterminated identifier, keep it verbatim.
+                literal.push('$');
+                break;
+            };
+
+            let identifier = &rest[..end];
+            rest = &rest[end + 1..];
+
+            let (name, width) = match identifier.split_once("%0") {
+                Some((name, format)) => (
+                    name,
+                    format.trim_end_matches('d').parse::<usize>().unwrap_or(1),
+                ),
+                None => (identifier, 1),
+            };
+
+            let part = match name {
+                "" => {
+                    // `$$` is an escaped dollar sign.
+                    literal.push('$');
+                    continue;
+                }
+                "Number" => TemplatePart::Number { width },
+                "Time" => TemplatePart::Time { width },
+                _ => {
+                    literal.push_str(&format!("${identifier}$"));
+                    continue;
+                }
+            };
+
+            if !literal.is_empty() {
+                parts.push(TemplatePart::Literal(std::mem::take(&mut literal)));
+            }
+            parts.push(part);
+        }
+
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(TemplatePart::Literal(literal));
+        }
+
+        Self { parts }
+    }
+}
+
 impl From<String> for ChunkTemplate {
     fn from(template: String) -> ChunkTemplate {
         Self { template }
@@ -229,6 +1465,113 @@ fn resolve_url_template(template: &str, params: (&str, String)) -> String {
             result = result[..m.start()].to_owned() + &count + &result[m.end()..];
         }
     }
-    tracing::info!(result);
-    dbg!(result)
+    tracing::trace!(result);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIVE_MPD: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="dynamic"
+    availabilityStartTime="2024-01-01T00:00:00Z" minimumUpdatePeriod="PT2S"
+    timeShiftBufferDepth="PT30S" minBufferTime="PT2S" profiles="urn:mpeg:dash:profile:isoff-live:2011">
+  <Period id="0" start="PT0S">
+    <AdaptationSet mimeType="video/mp4" segmentAlignment="true">
+      <SegmentTemplate timescale="1000" duration="2000" startNumber="1"
+          initialization="$RepresentationID$/init.mp4" media="$RepresentationID$/$Number$.m4s"/>
+      <Representation id="v1" codecs="avc1.64001f" bandwidth="1000000" width="1280" height="720"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+
+    #[test]
+    fn live_manifest_without_duration_has_no_end() {
+        let manifest: Manifest = LIVE_MPD.parse().unwrap();
+
+        assert!(manifest.is_live());
+        assert_eq!(manifest.duration(), None);
+        assert_eq!(manifest.presentation_end(), None);
+        assert_eq!(
+            manifest.time_shift_buffer_depth(),
+            Some(Duration::from_secs(30))
+        );
+    }
+
+    #[test]
+    fn static_manifest_ends_at_its_duration() {
+        let manifest: Manifest = LIVE_MPD
+            .replace(
+                r#"type="dynamic""#,
+                r#"type="static" mediaPresentationDuration="PT1M""#,
+            )
+            .parse()
+            .unwrap();
+
+        assert!(!manifest.is_live());
+        assert_eq!(manifest.presentation_end(), Some(60.));
+    }
+
+    #[test]
+    fn segment_at_maps_times_to_numbers() {
+        let index = SegmentIndex::new(1, 4., 0.);
+
+        assert_eq!(index.segment_at(0.), 1);
+        assert_eq!(index.segment_at(3.9), 1);
+        assert_eq!(index.segment_at(10.), 3);
+    }
+
+    #[test]
+    fn segment_at_boundary_starts_next_segment() {
+        let index = SegmentIndex::new(1, 4., 0.);
+
+        assert_eq!(index.segment_at(4.), 2);
+        assert_eq!(index.segment_at(8.), 3);
+    }
+
+    #[test]
+    fn segment_at_respects_start_number_and_offset() {
+        let index = SegmentIndex::new(10, 2., 100.);
+
+        assert_eq!(index.segment_at(100.), 10);
+        assert_eq!(index.segment_at(105.), 12);
+    }
+
+    #[test]
+    fn segment_at_clamps_times_before_first_segment() {
+        let index = SegmentIndex::new(5, 2., 10.);
+
+        assert_eq!(index.segment_at(0.), 5);
+        assert_eq!(index.segment_at(-3.), 5);
+    }
+
+    #[test]
+    fn segment_start_maps_numbers_to_times() {
+        let index = SegmentIndex::new(10, 2., 100.);
+
+        assert_eq!(index.segment_start(10), 100.);
+        assert_eq!(index.segment_start(13), 106.);
+        // Numbers before `@startNumber` start with the first segment.
+        assert_eq!(index.segment_start(3), 100.);
+    }
+
+    #[test]
+    fn segment_start_and_segment_at_round_trip() {
+        let index = SegmentIndex::new(1, 3.2, 7.5);
+
+        for number in 1..100 {
+            assert_eq!(index.segment_at(index.segment_start(number)), number);
+        }
+    }
+
+    #[test]
+    fn live_edge_lags_one_segment_behind() {
+        let index = SegmentIndex::new(1, 2., 0.);
+
+        assert_eq!(index.live_edge(0., 0.), 1);
+        assert_eq!(index.live_edge(10., 0.), 5);
+        assert_eq!(index.live_edge(10., 1.), 5);
+        assert_eq!(index.live_edge(10., f64::INFINITY), 6);
+    }
 }
@@ -0,0 +1,93 @@
+use crate::drm::DrmError;
+use crate::error::AshinaError;
+use crate::loader::LoadError;
+use crate::player::Error;
+
+use std::collections::HashMap;
+
+use displaydoc::Display;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Coarse classification of everything that can go wrong, used to pick a user-presentable
+/// message. The technical error is still logged as-is.
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ErrorCode {
+    /// manifest could not be downloaded
+    ManifestFetch,
+    /// manifest could not be parsed
+    ManifestParse,
+    /// stream uses features or codecs this browser cannot play
+    Unsupported,
+    /// network failure while loading media
+    Network,
+    /// media could not be decoded or buffered
+    Media,
+    /// protected content could not be decrypted
+    Drm,
+    /// internal player error
+    Internal,
+}
+
+impl ErrorCode {
+    /// Best-effort classification of an error surfaced by [`crate::MediaPlayer::create`].
+    pub fn of(error: &(dyn std::error::Error + 'static)) -> Self {
+        if let Some(error) = error.downcast_ref::<AshinaError>() {
+            return error.code();
+        }
+
+        if let Some(error) = error.downcast_ref::<Error>() {
+            return error.code();
+        }
+
+        if error.is::<dash_mpd::DashMpdError>() {
+            return Self::ManifestParse;
+        }
+
+        if error.is::<DrmError>() {
+            return Self::Drm;
+        }
+
+        if error.is::<LoadError>() {
+            return Self::ManifestFetch;
+        }
+
+        Self::Internal
+    }
+}
+
+/// Catalog of user-facing error messages. Implement this to localize or reword the messages
+/// shown to viewers.
+pub trait ErrorMessages {
+    fn message(&self, code: ErrorCode) -> String;
+}
+
+/// English messages used when the application doesn't provide its own catalog.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultMessages;
+
+impl ErrorMessages for DefaultMessages {
+    fn message(&self, code: ErrorCode) -> String {
+        match code {
+            ErrorCode::ManifestFetch => {
+                "This video couldn't be loaded. Check your connection and try again."
+            }
+            ErrorCode::ManifestParse => "This video is not available right now.",
+            ErrorCode::Unsupported => "This video can't be played on your browser.",
+            ErrorCode::Network => "Playback was interrupted by a network problem.",
+            ErrorCode::Media => "Something went wrong while playing this video.",
+            ErrorCode::Drm => "This video is protected and can't be played on this device.",
+            ErrorCode::Internal => "An unexpected error occurred.",
+        }
+        .into()
+    }
+}
+
+/// A plain map works as a catalog; codes missing from it fall back to [`DefaultMessages`].
+impl ErrorMessages for HashMap<ErrorCode, String> {
+    fn message(&self, code: ErrorCode) -> String {
+        self.get(&code)
+            .cloned()
+            .unwrap_or_else(|| DefaultMessages.message(code))
+    }
+}
@@ -0,0 +1,227 @@
+use crate::player::Error;
+
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsCast;
+use wasm_bindgen::JsValue;
+use web_sys::SourceBuffer;
+
+use core::cell::RefCell;
+use core::future::Future;
+use core::ops::RangeInclusive;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use futures::channel::oneshot;
+
+/// Something that mutates a source buffer and completes with `updateend`.
+#[derive(Debug)]
+pub enum Operation {
+    Append(Vec<u8>),
+    Remove(RangeInclusive<f64>),
+    /// Takes effect immediately, but only once everything queued before it is done.
+    SetTimestampOffset(f64),
+    /// Frames before this presentation time are dropped by appends queued after it. Same timing
+    /// as [`Self::SetTimestampOffset`].
+    SetAppendWindowStart(f64),
+    /// Switches the source buffer to another content type via `changeType`. Synchronous like
+    /// [`Self::SetTimestampOffset`].
+    ChangeType(String),
+}
+
+struct Pending {
+    operation: Operation,
+    done: oneshot::Sender<Result<(), Error>>,
+}
+
+struct Inner {
+    source_buffer: SourceBuffer,
+    queue: VecDeque<Pending>,
+    /// Completion of the operation the source buffer is busy with.
+    in_flight: Option<oneshot::Sender<Result<(), Error>>>,
+    /// Set by [`OperationQueue::close`]; nothing reaches the source buffer afterwards.
+    closed: bool,
+}
+
+impl Inner {
+    /// Starts queued operations until one is accepted by the source buffer. Operations rejected
+    /// synchronously complete with their error right away.
+    fn start_next(&mut self) {
+        while self.in_flight.is_none() {
+            let Some(Pending { operation, done }) = self.queue.pop_front() else {
+                return;
+            };
+
+            let started = match operation {
+                Operation::Append(mut data) => {
+                    // NOTE: Don't be tempted to use append_buffer_async_* as no browsers support
+                    // this.
+                    self.source_buffer.append_buffer_with_u8_array(&mut data)
+                }
+                Operation::Remove(range) => self.source_buffer.remove(*range.start(), *range.end()),
+                Operation::SetTimestampOffset(offset) => {
+                    // No `updateend` follows, so this completes right away.
+                    self.source_buffer.set_timestamp_offset(offset);
+                    let _ = done.send(Ok(()));
+                    continue;
+                }
+                Operation::SetAppendWindowStart(start) => {
+                    self.source_buffer.set_append_window_start(start);
+                    let _ = done.send(Ok(()));
+                    continue;
+                }
+                Operation::ChangeType(content_type) => {
+                    let changed = self
+                        .source_buffer
+                        .change_type(&content_type)
+                        .map_err(operation_error);
+                    let _ = done.send(changed);
+                    continue;
+                }
+            };
+
+            match started {
+                Ok(()) => self.in_flight = Some(done),
+                Err(error) => {
+                    let _ = done.send(Err(operation_error(error)));
+                }
+            }
+        }
+    }
+
+    fn finish(&mut self, result: Result<(), Error>) {
+        if let Some(done) = self.in_flight.take() {
+            let _ = done.send(result);
+        }
+    }
+}
+
+/// Serializes appends and removals on a source buffer. Each operation only starts once the
+/// previous one fired `updateend`, so the source buffer never sees a call while it is still
+/// `updating`, which would throw `InvalidStateError`.
+pub struct OperationQueue {
+    inner: Rc<RefCell<Inner>>,
+    on_updateend: Closure<dyn FnMut()>,
+    on_error: Closure<dyn FnMut()>,
+}
+
+impl OperationQueue {
+    pub fn new(source_buffer: SourceBuffer) -> Self {
+        let inner = Rc::new(RefCell::new(Inner {
+            source_buffer: source_buffer.clone(),
+            queue: VecDeque::new(),
+            in_flight: None,
+            closed: false,
+        }));
+
+        let on_updateend = {
+            let inner = inner.clone();
+            Closure::<dyn FnMut()>::new(move || {
+                let mut inner = inner.borrow_mut();
+                inner.finish(Ok(()));
+                inner.start_next();
+            })
+        };
+
+        // `error` fires right before `updateend`, which then finds nothing in flight. The
+        // browser failed to parse or decode the append and ends the media source with an error.
+        let on_error = {
+            let inner = inner.clone();
+            Closure::<dyn FnMut()>::new(move || {
+                inner.borrow_mut().finish(Err(Error::DecodeError));
+            })
+        };
+
+        source_buffer
+            .add_event_listener_with_callback("updateend", on_updateend.as_ref().unchecked_ref())
+            .unwrap();
+        source_buffer
+            .add_event_listener_with_callback("error", on_error.as_ref().unchecked_ref())
+            .unwrap();
+
+        Self {
+            inner,
+            on_updateend,
+            on_error,
+        }
+    }
+
+    /// Queues `operation`. The returned future resolves once the source buffer finished it.
+    pub fn push(&self, operation: Operation) -> impl Future<Output = Result<(), Error>> + use<> {
+        push(&self.inner, operation)
+    }
+
+    /// A handle for queueing operations from background work, e.g. a download appending data as
+    /// it arrives.
+    pub fn handle(&self) -> OperationHandle {
+        OperationHandle {
+            inner: self.inner.clone(),
+        }
+    }
+
+    /// Removes the listeners. Queued operations complete with [`Error::Cancelled`].
+    pub fn close(self) {
+        let mut inner = self.inner.borrow_mut();
+
+        let _ = inner.source_buffer.remove_event_listener_with_callback(
+            "updateend",
+            self.on_updateend.as_ref().unchecked_ref(),
+        );
+        let _ = inner
+            .source_buffer
+            .remove_event_listener_with_callback("error", self.on_error.as_ref().unchecked_ref());
+
+        inner.queue.clear();
+        inner.in_flight = None;
+        inner.closed = true;
+    }
+}
+
+/// Queues operations on an [`OperationQueue`] it doesn't own. Operations pushed after the queue
+/// was closed complete with [`Error::Cancelled`].
+#[derive(Clone)]
+pub struct OperationHandle {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl OperationHandle {
+    pub fn push(&self, operation: Operation) -> impl Future<Output = Result<(), Error>> + use<> {
+        push(&self.inner, operation)
+    }
+}
+
+fn push(
+    inner: &Rc<RefCell<Inner>>,
+    operation: Operation,
+) -> impl Future<Output = Result<(), Error>> + use<> {
+    let (done, result) = oneshot::channel();
+
+    {
+        let mut inner = inner.borrow_mut();
+
+        if !inner.closed {
+            inner.queue.push_back(Pending { operation, done });
+
+            if !inner.source_buffer.updating() {
+                inner.start_next();
+            }
+        }
+    }
+
+    async move { result.await.unwrap_or(Err(Error::Cancelled)) }
+}
+
+fn operation_error(error: JsValue) -> Error {
+    let name = error
+        .dyn_ref::<js_sys::Error>()
+        .and_then(|error| error.name().as_string());
+
+    match name.as_deref() {
+        Some("QuotaExceededError") => Error::QuotaExceededError,
+        Some("NotSupportedError") => Error::UnsupportedCodec,
+        Some("InvalidStateError") => Error::InvalidState,
+        error => {
+            tracing::error!(?error, "Unexpected source buffer error.");
+            Error::DataError
+        }
+    }
+}
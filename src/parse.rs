@@ -1,191 +1,1096 @@
-use mp4::box_start;
-use mp4::skip_box;
-use mp4::skip_bytes_to;
-use mp4::BoxHeader;
-use mp4::BoxType;
-use mp4::MoofBox;
-use mp4::Mp4Box;
-use mp4::ReadBox;
-use mp4::Result;
-use mp4::HEADER_EXT_SIZE;
-use mp4::HEADER_SIZE;
-
-use std::io::BufReader;
-use std::io::Cursor;
-use std::io::Read;
-use std::io::Seek;
-use std::io::SeekFrom;
+//! Fragmented MP4 as far as the player needs it: a zero-copy walk over box headers, and readers
+//! for the few boxes it looks into, `sidx`, `mfhd`, `tfhd`, `tfdt`, `trun` and `emsg`, plus the
+//! timing boxes of init segments.
+
+pub mod cea;
+pub mod id3;
+
 use std::time::Duration;
 
-use byteorder::BigEndian;
-use byteorder::ReadBytesExt;
+use displaydoc::Display;
+use serde::Deserialize;
+use serde::Serialize;
+use thiserror::Error;
+
+/// Most samples a `trun` box without per-sample fields may declare. Far more than any fragment
+/// holds, but small enough to allocate.
+const MAX_RUN_SAMPLES: u32 = 1 << 18;
+
+/// Why [`SegmentMetadata::parse`] couldn't tell the timing of a media segment.
+#[derive(Clone, Debug, Display, Error, PartialEq, Eq)]
+pub enum ParseError {
+    /// segment has no `{0}` box
+    BoxNotFound(&'static str),
+    /// {0}
+    InvalidData(&'static str),
+}
 
-#[derive(Debug, Clone, PartialEq, Default, serde::Serialize)]
-struct SidxBox {
-    version: u8,
-    flags: u32,
-    reference_id: u32,
+/// The timing of the subsegments a `sidx` box indexes.
+struct SegmentIndex {
     timescale: u32,
     earliest_presentation_time: u64,
-    first_offset: u64,
+    /// Sum of the subsegment durations.
+    total_duration: u64,
+}
+
+impl SegmentIndex {
+    fn parse(payload: &[u8]) -> Option<Self> {
+        let version = *payload.first()?;
+        // Version and flags, then the reference id.
+        let mut rest = payload.get(8..)?;
+        let timescale = read_u32(&mut rest)?;
+
+        let (earliest_presentation_time, _first_offset) = match version {
+            0 => (read_u32(&mut rest)? as u64, read_u32(&mut rest)? as u64),
+            _ => (read_u64(&mut rest)?, read_u64(&mut rest)?),
+        };
 
-    subseg_durations: Vec<u32>,
+        // Reserved, then the reference count.
+        let references = u16::from_be_bytes(rest.get(2..4)?.try_into().ok()?);
+        let mut rest = rest.get(4..)?;
+
+        let mut total_duration = 0;
+        for _ in 0..references {
+            // Reference type and size, duration, then the SAP fields.
+            read_u32(&mut rest)?;
+            total_duration += read_u32(&mut rest)? as u64;
+            read_u32(&mut rest)?;
+        }
+
+        Some(Self {
+            timescale,
+            earliest_presentation_time,
+            total_duration,
+        })
+    }
 }
 
-impl SidxBox {
-    pub fn get_type(&self) -> BoxType {
-        unimplemented!()
+#[derive(Clone, Copy, Debug)]
+pub struct SegmentMetadata {
+    pub segment_number: usize,
+    pub earliest_presentation_time: f64,
+    pub timescale: f64,
+    pub total_duration: f64,
+}
+
+impl SegmentMetadata {
+    /// Reads the timing of a media segment from its `sidx`, or, for packagers that omit it, from
+    /// the `tfdt` and `trun` boxes of its fragments. The latter needs the timing information of
+    /// the init segment, see [`InitMetadata`].
+    pub fn parse(data: &[u8], init: &InitMetadata) -> Result<Self, ParseError> {
+        let mut issues = vec![];
+        let boxes = walk_boxes(data, 0, &mut issues);
+        let moofs: Vec<_> = boxes.iter().filter(|b| &b.kind == b"moof").collect();
+
+        let moof = moofs.first().ok_or(ParseError::BoxNotFound("moof"))?;
+        let children = walk_boxes(moof.payload, moof.payload_offset(), &mut issues);
+        let segment_number = children
+            .iter()
+            .find(|b| &b.kind == b"mfhd")
+            // Version and flags, then the sequence number.
+            .and_then(|mfhd| read_u32(&mut mfhd.payload.get(4..)?))
+            .ok_or(ParseError::BoxNotFound("mfhd"))? as usize;
+
+        if let Some(sidx) = boxes.iter().find(|b| &b.kind == b"sidx") {
+            let sidx = SegmentIndex::parse(sidx.payload)
+                .ok_or(ParseError::InvalidData("malformed sidx"))?;
+
+            return Ok(Self {
+                segment_number,
+                earliest_presentation_time: sidx.earliest_presentation_time as _,
+                timescale: sidx.timescale as _,
+                total_duration: sidx.total_duration as _,
+            });
+        }
+
+        let timescale = init
+            .track_timescale
+            .filter(|timescale| *timescale > 0)
+            .ok_or(ParseError::InvalidData("no sidx and no track timescale"))?;
+        let tfdt = children
+            .iter()
+            .find(|b| &b.kind == b"traf")
+            .and_then(|traf| {
+                walk_boxes(traf.payload, traf.payload_offset(), &mut issues)
+                    .iter()
+                    .find(|b| &b.kind == b"tfdt")
+                    .and_then(|tfdt| base_media_decode_time(tfdt.payload))
+            })
+            .ok_or(ParseError::BoxNotFound("tfdt"))?;
+
+        // Low-latency segments hold one fragment per chunk.
+        let total_duration: u64 = moofs
+            .iter()
+            .map(|moof| fragment_duration(moof, init.default_sample_duration))
+            .sum();
+
+        Ok(Self {
+            segment_number,
+            earliest_presentation_time: tfdt as _,
+            timescale: timescale as _,
+            total_duration: total_duration as _,
+        })
     }
 
-    pub fn total_duration(&self) -> u32 {
-        self.subseg_durations.iter().sum()
+    pub fn pts(&self) -> f64 {
+        self.earliest_presentation_time / self.timescale
     }
 
-    pub fn get_size(&self) -> u64 {
-        let sub_hdr_sz = match self.version {
-            0 => 8,
-            _ => 16,
-        };
+    pub fn duration(&self) -> Duration {
+        let duration = (self.total_duration / self.timescale) * 1000.0;
+        Duration::from_millis(duration as _)
+    }
+
+    pub fn segment_number(&self) -> usize {
+        self.segment_number
+    }
+}
+
+/// Problems found by [`verify_segment`] while walking the box structure of a media segment.
+#[derive(Clone, Debug, PartialEq, Display, Serialize, Deserialize)]
+pub enum SegmentIssue {
+    /// box header at offset {offset} is truncated
+    TruncatedHeader { offset: usize },
+    /// box `{kind}` at offset {offset} declares {size} bytes but only {available} remain
+    Overrun {
+        kind: String,
+        offset: usize,
+        size: u64,
+        available: usize,
+    },
+    /// box `{kind}` at offset {offset} declares an invalid size of {size} bytes
+    InvalidSize {
+        kind: String,
+        offset: usize,
+        size: u64,
+    },
+    /// box sizes sum to {sum} bytes but the segment is {len} bytes long
+    LengthMismatch { sum: usize, len: usize },
+    /// required box `{kind}` is missing from `{parent}`
+    MissingBox { kind: String, parent: String },
+    /// trun at offset {offset} declares {sample_count} samples ({expected} bytes) but holds {actual} bytes
+    TrunSampleCount {
+        offset: usize,
+        sample_count: u32,
+        expected: u64,
+        actual: usize,
+    },
+    /// trun at offset {offset} references sample data up to byte {end} past the end of mdat at {mdat_end}
+    TrunDataOutOfBounds {
+        offset: usize,
+        end: u64,
+        mdat_end: usize,
+    },
+}
+
+struct RawBox<'a> {
+    kind: [u8; 4],
+    offset: usize,
+    size: usize,
+    payload: &'a [u8],
+}
+
+impl RawBox<'_> {
+    fn name(&self) -> String {
+        String::from_utf8_lossy(&self.kind).into_owned()
+    }
+
+    fn payload_offset(&self) -> usize {
+        self.offset + self.size - self.payload.len()
+    }
+}
+
+/// Walks sibling boxes in `data`, `base` being the absolute offset of `data` in the segment.
+fn walk_boxes<'a>(data: &'a [u8], base: usize, issues: &mut Vec<SegmentIssue>) -> Vec<RawBox<'a>> {
+    let mut boxes = vec![];
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let offset = base + pos;
+        let rest = &data[pos..];
+
+        if rest.len() < 8 {
+            issues.push(SegmentIssue::TruncatedHeader { offset });
+            break;
+        }
+
+        let mut size = u32::from_be_bytes(rest[0..4].try_into().unwrap()) as u64;
+        let kind: [u8; 4] = rest[4..8].try_into().unwrap();
+        let kind_name = String::from_utf8_lossy(&kind).into_owned();
+        let mut header = 8;
+
+        if size == 1 {
+            if rest.len() < 16 {
+                issues.push(SegmentIssue::TruncatedHeader { offset });
+                break;
+            }
+            size = u64::from_be_bytes(rest[8..16].try_into().unwrap());
+            header = 16;
+        } else if size == 0 {
+            // Box extends to the end of the enclosing container.
+            size = rest.len() as u64;
+        }
+
+        if size < header as u64 {
+            issues.push(SegmentIssue::InvalidSize {
+                kind: kind_name,
+                offset,
+                size,
+            });
+            break;
+        }
 
-        HEADER_SIZE
-            + HEADER_EXT_SIZE
-            + 4
-            + 8
-            + sub_hdr_sz
-            + (self.subseg_durations.len() as u64 * 12)
+        if size > rest.len() as u64 {
+            issues.push(SegmentIssue::Overrun {
+                kind: kind_name,
+                offset,
+                size,
+                available: rest.len(),
+            });
+            break;
+        }
+
+        let size = size as usize;
+        boxes.push(RawBox {
+            kind,
+            offset,
+            size,
+            payload: &rest[header..size],
+        });
+
+        pos += size;
     }
+
+    boxes
 }
 
-impl Mp4Box for SidxBox {
-    fn box_type(&self) -> BoxType {
-        self.get_type()
+fn require<'a, 'b>(
+    boxes: &'b [RawBox<'a>],
+    kind: &[u8; 4],
+    parent: &str,
+    issues: &mut Vec<SegmentIssue>,
+) -> Option<&'b RawBox<'a>> {
+    let found = boxes.iter().find(|b| &b.kind == kind);
+
+    if found.is_none() {
+        issues.push(SegmentIssue::MissingBox {
+            kind: String::from_utf8_lossy(kind).into_owned(),
+            parent: parent.to_string(),
+        });
     }
 
-    fn box_size(&self) -> u64 {
-        self.get_size()
+    found
+}
+
+/// Checks the trun sample table against the box size and, when possible, that the referenced
+/// sample data lies within the mdat that follows the moof.
+fn verify_trun(
+    trun: &RawBox,
+    moof_offset: usize,
+    mdat_end: Option<usize>,
+    issues: &mut Vec<SegmentIssue>,
+) {
+    let payload = trun.payload;
+    if payload.len() < 8 {
+        issues.push(SegmentIssue::InvalidSize {
+            kind: trun.name(),
+            offset: trun.offset,
+            size: trun.size as u64,
+        });
+        return;
     }
 
-    fn to_json(&self) -> Result<String> {
-        unimplemented!();
+    let flags = u32::from_be_bytes([0, payload[1], payload[2], payload[3]]);
+    let sample_count = u32::from_be_bytes(payload[4..8].try_into().unwrap());
+
+    let mut fixed = 8;
+    let data_offset = if flags & 0x01 != 0 && payload.len() >= fixed + 4 {
+        let value = i32::from_be_bytes(payload[fixed..fixed + 4].try_into().unwrap());
+        Some(value)
+    } else {
+        None
+    };
+    if flags & 0x01 != 0 {
+        fixed += 4;
+    }
+    if flags & 0x04 != 0 {
+        fixed += 4;
     }
 
-    fn summary(&self) -> Result<String> {
-        Ok(String::new())
+    let per_sample = [0x100, 0x200, 0x400, 0x800]
+        .iter()
+        .filter(|flag| flags & *flag != 0)
+        .count()
+        * 4;
+
+    // Declared sizes are computed in 64 bits, so huge counts can't wrap around on wasm32.
+    let expected = u64::from(sample_count)
+        .checked_mul(per_sample as u64)
+        .and_then(|size| size.checked_add(fixed as u64))
+        .unwrap_or(u64::MAX);
+    if usize::try_from(expected) != Ok(payload.len()) {
+        issues.push(SegmentIssue::TrunSampleCount {
+            offset: trun.offset,
+            sample_count,
+            expected,
+            actual: payload.len(),
+        });
+        return;
+    }
+
+    let (Some(data_offset), Some(mdat_end)) = (data_offset, mdat_end) else {
+        return;
+    };
+
+    if flags & 0x200 == 0 {
+        return;
     }
+
+    // The sample size follows the sample duration when both are present.
+    let at = if flags & 0x100 != 0 { 4 } else { 0 };
+    let total = payload[fixed..]
+        .chunks_exact(per_sample)
+        .map(|sample| u32::from_be_bytes(sample[at..at + 4].try_into().unwrap()))
+        .try_fold(0u64, |total, size| total.checked_add(size.into()));
+
+    // A data offset reaching before the start of the segment is out of bounds as well.
+    let end = i64::try_from(moof_offset)
+        .ok()
+        .and_then(|moof_offset| moof_offset.checked_add(data_offset.into()))
+        .and_then(|start| u64::try_from(start).ok())
+        .zip(total)
+        .and_then(|(start, total)| start.checked_add(total))
+        .unwrap_or(u64::MAX);
+    if usize::try_from(end).is_ok_and(|end| end <= mdat_end) {
+        return;
+    }
+
+    issues.push(SegmentIssue::TrunDataOutOfBounds {
+        offset: trun.offset,
+        end,
+        mdat_end,
+    });
 }
 
-impl<R: Read + Seek> ReadBox<&mut R> for SidxBox {
-    fn read_box(reader: &mut R, size: u64) -> Result<Self> {
-        let start = box_start(reader)?;
+/// Sum of the sample durations of the first track fragment of a movie fragment, in the track
+/// timescale. `trex_default` is the default sample duration from the init segment.
+fn fragment_duration(moof: &RawBox, trex_default: Option<u32>) -> u64 {
+    let mut issues = vec![];
+    let Some(traf) = walk_boxes(moof.payload, moof.payload_offset(), &mut issues)
+        .into_iter()
+        .find(|b| &b.kind == b"traf")
+    else {
+        return 0;
+    };
+    let children = walk_boxes(traf.payload, traf.payload_offset(), &mut issues);
 
-        let version = reader.read_u8()?;
-        let flags = reader.read_u24::<BigEndian>()?;
+    let default = children
+        .iter()
+        .find(|b| &b.kind == b"tfhd")
+        .and_then(|tfhd| TrackFragmentHeader::parse(tfhd.payload))
+        .and_then(|tfhd| tfhd.default_sample_duration)
+        .or(trex_default)
+        .unwrap_or(0);
 
-        let reference_id = reader.read_u32::<BigEndian>()?;
-        let timescale = reader.read_u32::<BigEndian>()?;
+    children
+        .iter()
+        .filter(|b| &b.kind == b"trun")
+        .filter_map(|trun| TrackRun::parse(trun.payload))
+        .flat_map(|run| run.samples)
+        .map(|sample| sample.duration.unwrap_or(default) as u64)
+        .sum()
+}
 
-        let earliest_presentation_time = match version {
-            0 => reader.read_u32::<BigEndian>()? as u64,
-            _ => reader.read_u64::<BigEndian>()?,
-        };
+/// The `tfhd` fields sample data is located with.
+struct TrackFragmentHeader {
+    base_data_offset: Option<u64>,
+    default_sample_duration: Option<u32>,
+    default_sample_size: Option<u32>,
+}
+
+impl TrackFragmentHeader {
+    fn parse(payload: &[u8]) -> Option<Self> {
+        let flags = u32::from_be_bytes([0, *payload.get(1)?, *payload.get(2)?, *payload.get(3)?]);
+        // Version and flags, then the track id.
+        let mut rest = payload.get(8..)?;
 
-        let first_offset = match version {
-            0 => reader.read_u32::<BigEndian>()? as u64,
-            _ => reader.read_u64::<BigEndian>()?,
+        let base_data_offset = match flags & 0x01 {
+            0 => None,
+            _ => Some(read_u64(&mut rest)?),
         };
+        if flags & 0x02 != 0 {
+            read_u32(&mut rest)?;
+        }
+        let default_sample_duration = match flags & 0x08 {
+            0 => None,
+            _ => Some(read_u32(&mut rest)?),
+        };
+        let default_sample_size = match flags & 0x10 {
+            0 => None,
+            _ => Some(read_u32(&mut rest)?),
+        };
+
+        Some(Self {
+            base_data_offset,
+            default_sample_duration,
+            default_sample_size,
+        })
+    }
+}
+
+/// `baseMediaDecodeTime` of a `tfdt` box.
+fn base_media_decode_time(payload: &[u8]) -> Option<u64> {
+    let mut rest = payload.get(4..)?;
 
-        let _reserved = reader.read_u16::<BigEndian>()?;
-        let ref_count = reader.read_u16::<BigEndian>()?;
+    match payload.first()? {
+        0 => read_u32(&mut rest).map(u64::from),
+        _ => read_u64(&mut rest),
+    }
+}
 
-        let mut subseg_durations = Vec::new();
-        for idx in 1..=ref_count {
-            let _ = reader.read_u32::<BigEndian>()?;
-            let duration = reader.read_u32::<BigEndian>()?;
-            tracing::info!(idx, "got here.");
+struct TrackRun {
+    data_offset: Option<i32>,
+    samples: Vec<RunSample>,
+}
 
-            let _ = reader.read_u32::<BigEndian>()?;
+struct RunSample {
+    duration: Option<u32>,
+    size: Option<u32>,
+    composition_offset: i64,
+}
 
-            subseg_durations.push(duration);
+impl TrackRun {
+    fn parse(payload: &[u8]) -> Option<Self> {
+        let version = *payload.first()?;
+        let flags = u32::from_be_bytes([0, *payload.get(1)?, *payload.get(2)?, *payload.get(3)?]);
+        let mut rest = payload.get(4..)?;
+        let sample_count = read_u32(&mut rest)?;
+
+        let data_offset = match flags & 0x01 {
+            0 => None,
+            _ => Some(read_u32(&mut rest)? as i32),
+        };
+        if flags & 0x04 != 0 {
+            read_u32(&mut rest)?;
         }
 
-        skip_bytes_to(reader, start + size)?;
+        // A corrupt sample count mustn't allocate more samples than the box can hold. Samples
+        // without fields of their own take no bytes, so their count is capped instead.
+        let per_sample = [0x100, 0x200, 0x400, 0x800]
+            .into_iter()
+            .filter(|flag| flags & flag != 0)
+            .count() as u64
+            * 4;
+        let bounded = match per_sample {
+            0 => sample_count <= MAX_RUN_SAMPLES,
+            _ => u64::from(sample_count) * per_sample <= rest.len() as u64,
+        };
+        if !bounded {
+            return None;
+        }
 
-        Ok(Self {
-            version,
-            flags,
-            reference_id,
-            timescale,
-            earliest_presentation_time,
-            first_offset,
-            subseg_durations,
+        let optional = |rest: &mut &[u8], flag: u32| match flags & flag {
+            0 => Some(None),
+            _ => read_u32(rest).map(Some),
+        };
+
+        let mut samples = Vec::with_capacity(sample_count.min(4096) as usize);
+        for _ in 0..sample_count {
+            let duration = optional(&mut rest, 0x100)?;
+            let size = optional(&mut rest, 0x200)?;
+            optional(&mut rest, 0x400)?;
+            let composition_offset = match optional(&mut rest, 0x800)? {
+                Some(offset) if version == 0 => offset as i64,
+                Some(offset) => offset as i32 as i64,
+                None => 0,
+            };
+
+            samples.push(RunSample {
+                duration,
+                size,
+                composition_offset,
+            });
+        }
+
+        Some(Self {
+            data_offset,
+            samples,
         })
     }
 }
 
-const SIDX_BOX: u32 = 0x73696478;
+/// Timing information from an init segment, needed to interpret media segments without `sidx`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InitMetadata {
+    /// `moov/mvhd` timescale.
+    pub movie_timescale: Option<u32>,
+    /// `moov/trak/mdia/mdhd` timescale of the first track, the one sample times are in.
+    pub track_timescale: Option<u32>,
+    /// `moov/mvex/trex` default sample duration, for fragments that don't list durations.
+    pub default_sample_duration: Option<u32>,
+}
 
-#[derive(Clone, Copy, Debug)]
-pub struct SegmentMetadata {
-    pub segment_number: usize,
-    pub earliest_presentation_time: f64,
-    pub timescale: f64,
-    pub total_duration: f64,
+impl InitMetadata {
+    /// Reads whatever of the timing boxes the init segment has.
+    pub fn parse(init: &[u8]) -> Self {
+        Self {
+            movie_timescale: find_box(init, &[b"moov", b"mvhd"])
+                .and_then(|mvhd| header_timescale(mvhd.payload)),
+            track_timescale: find_box(init, &[b"moov", b"trak", b"mdia", b"mdhd"])
+                .and_then(|mdhd| header_timescale(mdhd.payload)),
+            default_sample_duration: find_box(init, &[b"moov", b"mvex", b"trex"]).and_then(
+                |trex| {
+                    // Version and flags, track id and default sample description index.
+                    let mut rest = trex.payload.get(12..)?;
+                    read_u32(&mut rest)
+                },
+            ),
+        }
+    }
 }
 
-impl SegmentMetadata {
-    #[track_caller]
-    pub fn parse(data: &[u8]) -> Result<Self> {
-        let cursor = Cursor::new(data);
-        let mut rdr = BufReader::new(cursor);
-        let mut current = rdr.seek(SeekFrom::Current(0))?;
-
-        let mut sidx = None;
-        let mut moof = None;
-
-        while current < data.len() as _ {
-            let header = BoxHeader::read(&mut rdr)?;
-
-            match header.name {
-                BoxType::UnknownBox(SIDX_BOX) => {
-                    tracing::info!("Parsing sidx");
-                    sidx = Some(SidxBox::read_box(&mut rdr, header.size)?);
-                    tracing::info!("Parsed sidx");
-                }
-                BoxType::MoofBox => {
-                    tracing::info!("Parsing moof");
-                    moof = Some(MoofBox::read_box(&mut rdr, header.size)?);
-                    tracing::info!("Parsed moof");
+/// Follows `path` down the box tree, taking the first box of each kind.
+fn find_box<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<RawBox<'a>> {
+    let mut issues = vec![];
+    let (first, rest) = path.split_first()?;
+
+    let mut found = walk_boxes(data, 0, &mut issues)
+        .into_iter()
+        .find(|b| &b.kind == *first)?;
+
+    for kind in rest {
+        found = walk_boxes(found.payload, found.payload_offset(), &mut issues)
+            .into_iter()
+            .find(|b| &b.kind == *kind)?;
+    }
+
+    Some(found)
+}
+
+/// Timescale of an `mvhd` or `mdhd` box.
+fn header_timescale(payload: &[u8]) -> Option<u32> {
+    // Version and flags, then creation and modification times of 32 or 64 bits.
+    let mut rest = match payload.first()? {
+        0 => payload.get(12..)?,
+        _ => payload.get(20..)?,
+    };
+
+    read_u32(&mut rest)
+}
+
+/// When an `emsg` event starts, in the box's timescale.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventTime {
+    /// Version 0: relative to the earliest presentation time of the segment carrying the box.
+    Delta(u32),
+    /// Version 1: absolute media time.
+    Absolute(u64),
+}
+
+/// An in-band event carried by an `emsg` box, e.g. an SCTE-35 splice marker.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EventMessage {
+    pub scheme_id_uri: String,
+    pub value: String,
+    pub timescale: u32,
+    pub time: EventTime,
+    /// In `timescale` units, `0xFFFFFFFF` if unknown.
+    pub event_duration: u32,
+    pub id: u32,
+    pub message_data: Vec<u8>,
+}
+
+impl EventMessage {
+    /// Media time the event starts at, in seconds. `segment_start` is the earliest presentation
+    /// time of the segment the box came with, needed for version 0 boxes.
+    pub fn start(&self, segment_start: f64) -> f64 {
+        let timescale = self.timescale.max(1) as f64;
+
+        match self.time {
+            EventTime::Delta(delta) => segment_start + delta as f64 / timescale,
+            EventTime::Absolute(time) => time as f64 / timescale,
+        }
+    }
+
+    /// In seconds, `None` if unknown.
+    pub fn duration(&self) -> Option<f64> {
+        (self.event_duration != u32::MAX)
+            .then(|| self.event_duration as f64 / self.timescale.max(1) as f64)
+    }
+
+    fn parse(payload: &[u8]) -> Option<Self> {
+        let (&version, rest) = payload.split_first()?;
+        // Skip the flags.
+        let mut rest = rest.get(3..)?;
+
+        let message = match version {
+            0 => {
+                let scheme_id_uri = read_cstring(&mut rest)?;
+                let value = read_cstring(&mut rest)?;
+                let timescale = read_u32(&mut rest)?;
+                let delta = read_u32(&mut rest)?;
+
+                Self {
+                    scheme_id_uri,
+                    value,
+                    timescale,
+                    time: EventTime::Delta(delta),
+                    event_duration: read_u32(&mut rest)?,
+                    id: read_u32(&mut rest)?,
+                    message_data: vec![],
                 }
-                rest => {
-                    tracing::info!(?rest, "Unknown box type.");
-                    skip_box(&mut rdr, header.size)?;
+            }
+            1 => {
+                let timescale = read_u32(&mut rest)?;
+                let time = read_u64(&mut rest)?;
+                let event_duration = read_u32(&mut rest)?;
+                let id = read_u32(&mut rest)?;
+
+                Self {
+                    scheme_id_uri: read_cstring(&mut rest)?,
+                    value: read_cstring(&mut rest)?,
+                    timescale,
+                    time: EventTime::Absolute(time),
+                    event_duration,
+                    id,
+                    message_data: vec![],
                 }
             }
+            _ => return None,
+        };
 
-            current = rdr.seek(SeekFrom::Current(0))?;
+        Some(Self {
+            message_data: rest.to_vec(),
+            ..message
+        })
+    }
+}
+
+/// Splits a media segment arriving piece by piece into CMAF chunks, each ending with a complete
+/// `mdat`, so that they can be appended as soon as they are complete. Boxes in front of a `moof`,
+/// like `styp`, `prft` or `emsg`, go out with the chunk they precede.
+#[derive(Debug, Default)]
+pub struct ChunkParser {
+    data: Vec<u8>,
+    /// End of the complete boxes at the start of `data`.
+    scanned: usize,
+}
+
+impl ChunkParser {
+    /// Adds the next piece of the segment. Returns the chunks it completed.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        self.data.extend_from_slice(bytes);
+        let mut chunks = vec![];
+
+        while let Some((kind, size)) = box_header(&self.data[self.scanned..]) {
+            if size > self.data.len() - self.scanned {
+                break;
+            }
+            self.scanned += size;
+
+            if &kind == b"mdat" {
+                let rest = self.data.split_off(self.scanned);
+                chunks.push(core::mem::replace(&mut self.data, rest));
+                self.scanned = 0;
+            }
         }
 
-        let sidx = sidx.expect("No Sidx box found.");
-        let moof = moof.expect("No moof box found.");
+        chunks
+    }
 
-        Ok(Self {
-            segment_number: moof.mfhd.sequence_number as _,
-            earliest_presentation_time: sidx.earliest_presentation_time as _,
-            timescale: sidx.timescale as _,
-            total_duration: sidx.total_duration() as _,
+    /// What is left once the whole segment arrived: boxes after the last `mdat`, or data whose
+    /// box sizes don't add up, for the source buffer to make sense of.
+    pub fn finish(&mut self) -> Vec<u8> {
+        self.scanned = 0;
+        core::mem::take(&mut self.data)
+    }
+}
+
+/// Type and size of the box `data` starts with, once its header arrived. `None` for boxes
+/// extending to the end of the segment, whose size is only known at the end, and for invalid
+/// sizes.
+fn box_header(data: &[u8]) -> Option<([u8; 4], usize)> {
+    let mut header = data;
+    let size = read_u32(&mut header)?;
+    let (kind, mut header) = header.split_first_chunk::<4>()?;
+
+    let (size, header_size) = match size {
+        0 => return None,
+        1 => (read_u64(&mut header)?, 16),
+        size => (size as u64, 8),
+    };
+    if size < header_size {
+        return None;
+    }
+
+    Some((*kind, usize::try_from(size).ok()?))
+}
+
+fn read_u32(data: &mut &[u8]) -> Option<u32> {
+    let (value, rest) = data.split_first_chunk::<4>()?;
+    *data = rest;
+    Some(u32::from_be_bytes(*value))
+}
+
+fn read_u64(data: &mut &[u8]) -> Option<u64> {
+    let (value, rest) = data.split_first_chunk::<8>()?;
+    *data = rest;
+    Some(u64::from_be_bytes(*value))
+}
+
+fn read_cstring(data: &mut &[u8]) -> Option<String> {
+    let end = data.iter().position(|byte| *byte == 0)?;
+    let value = String::from_utf8_lossy(&data[..end]).into_owned();
+    *data = &data[end + 1..];
+    Some(value)
+}
+
+/// Segments at least this large are parsed in several tasks with the `yielding-parse` feature.
+/// Smaller ones parse quicker than a task switch takes.
+#[cfg(feature = "yielding-parse")]
+const YIELD_SEGMENT_BYTES: usize = 256 * 1024;
+
+/// Lets the browser render and handle input before the next parsing pass over a segment of
+/// `size` bytes. Returns right away for small segments, and without the `yielding-parse`
+/// feature.
+pub async fn yield_to_browser(size: usize) {
+    #[cfg(feature = "yielding-parse")]
+    if size >= YIELD_SEGMENT_BYTES {
+        gloo_timers::future::TimeoutFuture::new(0).await;
+    }
+
+    #[cfg(not(feature = "yielding-parse"))]
+    let _ = size;
+}
+
+/// Every well-formed top-level `emsg` box in a media segment, in order.
+pub fn parse_event_messages(data: &[u8]) -> Vec<EventMessage> {
+    let mut issues = vec![];
+
+    walk_boxes(data, 0, &mut issues)
+        .iter()
+        .filter(|b| &b.kind == b"emsg")
+        .filter_map(|b| {
+            let message = EventMessage::parse(b.payload);
+            if message.is_none() {
+                tracing::warn!(offset = b.offset, "Ignoring malformed emsg box.");
+            }
+            message
         })
+        .collect()
+}
+
+/// Validates the box structure of a media segment before it is handed to MSE. Returns every
+/// problem found, an empty list meaning the segment looks sane.
+pub fn verify_segment(data: &[u8]) -> Vec<SegmentIssue> {
+    let mut issues = vec![];
+    let boxes = walk_boxes(data, 0, &mut issues);
+
+    let sum: usize = boxes.iter().map(|b| b.size).sum();
+    if issues.is_empty() && sum != data.len() {
+        issues.push(SegmentIssue::LengthMismatch {
+            sum,
+            len: data.len(),
+        });
     }
 
-    pub fn pts(&self) -> f64 {
-        self.earliest_presentation_time / self.timescale
+    let moof = require(&boxes, b"moof", "segment", &mut issues);
+    let mdat = require(&boxes, b"mdat", "segment", &mut issues);
+
+    let Some(moof) = moof else {
+        return issues;
+    };
+
+    let mdat_end = mdat.map(|mdat| mdat.offset + mdat.size);
+    let children = walk_boxes(moof.payload, moof.payload_offset(), &mut issues);
+
+    require(&children, b"mfhd", "moof", &mut issues);
+    require(&children, b"traf", "moof", &mut issues);
+
+    for traf in children.iter().filter(|b| &b.kind == b"traf") {
+        let traf_children = walk_boxes(traf.payload, traf.payload_offset(), &mut issues);
+
+        require(&traf_children, b"tfhd", "traf", &mut issues);
+        require(&traf_children, b"trun", "traf", &mut issues);
+
+        for trun in traf_children.iter().filter(|b| &b.kind == b"trun") {
+            verify_trun(trun, moof.offset, mdat_end, &mut issues);
+        }
     }
 
-    pub fn duration(&self) -> Duration {
-        let duration = (self.total_duration / self.timescale) * 1000.0;
-        Duration::from_millis(duration as _)
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn boxed(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(payload);
+        data
     }
 
-    pub fn segment_number(&self) -> usize {
-        self.segment_number
+    /// A `trun` with a data offset and sample sizes.
+    fn trun(sample_count: u32, data_offset: i32, sizes: &[u32]) -> Vec<u8> {
+        let mut payload = vec![0, 0, 0x02, 0x01];
+        payload.extend_from_slice(&sample_count.to_be_bytes());
+        payload.extend_from_slice(&data_offset.to_be_bytes());
+        for size in sizes {
+            payload.extend_from_slice(&size.to_be_bytes());
+        }
+        boxed(b"trun", &payload)
+    }
+
+    /// A media segment of one fragment whose `trun` is built by `trun` from the data offset of
+    /// the `mdat` payload, followed by an `mdat` of `mdat_len` bytes.
+    fn segment(trun: impl Fn(i32) -> Vec<u8>, mdat_len: usize) -> Vec<u8> {
+        let mfhd = boxed(b"mfhd", &[0, 0, 0, 0, 0, 0, 0, 1]);
+        let tfhd = boxed(b"tfhd", &[0, 2, 0, 0, 0, 0, 0, 1]);
+        // The size of the `trun` doesn't depend on the data offset it holds.
+        let moof_len = 8 + mfhd.len() + 8 + tfhd.len() + trun(0).len();
+        let data_offset = (moof_len + 8) as i32;
+
+        let traf = boxed(b"traf", &[tfhd, trun(data_offset)].concat());
+        let moof = boxed(b"moof", &[mfhd, traf].concat());
+        [moof, boxed(b"mdat", &vec![0; mdat_len])].concat()
+    }
+
+    #[test]
+    fn walks_sibling_boxes() {
+        let data = [boxed(b"styp", b"iso6"), boxed(b"free", &[])].concat();
+        let mut issues = vec![];
+
+        let boxes = walk_boxes(&data, 0, &mut issues);
+
+        assert!(issues.is_empty());
+        assert_eq!(boxes.len(), 2);
+        assert_eq!(boxes[0].name(), "styp");
+        assert_eq!(boxes[0].payload, b"iso6");
+        assert_eq!(boxes[1].offset, 12);
+        assert_eq!(boxes[1].payload_offset(), 20);
+    }
+
+    #[test]
+    fn reports_truncated_box_header() {
+        let data = [boxed(b"free", &[]), vec![0, 0, 0]].concat();
+        let mut issues = vec![];
+
+        let boxes = walk_boxes(&data, 0, &mut issues);
+
+        assert_eq!(boxes.len(), 1);
+        assert_eq!(issues, [SegmentIssue::TruncatedHeader { offset: 8 }]);
+    }
+
+    #[test]
+    fn size_zero_box_extends_to_the_end() {
+        let mut data = boxed(b"free", &[]);
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        data.extend_from_slice(b"mdat");
+        data.extend_from_slice(&[1, 2, 3]);
+        let mut issues = vec![];
+
+        let boxes = walk_boxes(&data, 0, &mut issues);
+
+        assert!(issues.is_empty());
+        assert_eq!(boxes[1].size, 11);
+        assert_eq!(boxes[1].payload, [1, 2, 3]);
+        // Chunked appends can't know where such a box ends until the segment did.
+        assert_eq!(box_header(&data[8..]), None);
+    }
+
+    #[test]
+    fn size_one_box_has_a_largesize() {
+        let mut data = vec![0, 0, 0, 1];
+        data.extend_from_slice(b"mdat");
+        data.extend_from_slice(&20u64.to_be_bytes());
+        data.extend_from_slice(&[1, 2, 3, 4]);
+        let mut issues = vec![];
+
+        let boxes = walk_boxes(&data, 0, &mut issues);
+
+        assert!(issues.is_empty());
+        assert_eq!(boxes[0].size, 20);
+        assert_eq!(boxes[0].payload, [1, 2, 3, 4]);
+        assert_eq!(box_header(&data), Some((*b"mdat", 20)));
+    }
+
+    #[test]
+    fn reports_truncated_largesize() {
+        let mut data = vec![0, 0, 0, 1];
+        data.extend_from_slice(b"mdat");
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        let mut issues = vec![];
+
+        assert!(walk_boxes(&data, 0, &mut issues).is_empty());
+        assert_eq!(issues, [SegmentIssue::TruncatedHeader { offset: 0 }]);
+    }
+
+    #[test]
+    fn reports_invalid_and_overrunning_sizes() {
+        let mut issues = vec![];
+        walk_boxes(&[0, 0, 0, 4, b'f', b'r', b'e', b'e'], 0, &mut issues);
+        assert_eq!(
+            issues,
+            [SegmentIssue::InvalidSize {
+                kind: "free".into(),
+                offset: 0,
+                size: 4,
+            }]
+        );
+
+        let mut data = vec![0, 0, 0, 1];
+        data.extend_from_slice(b"mdat");
+        data.extend_from_slice(&u64::MAX.to_be_bytes());
+        let mut issues = vec![];
+        walk_boxes(&data, 0, &mut issues);
+        assert_eq!(
+            issues,
+            [SegmentIssue::Overrun {
+                kind: "mdat".into(),
+                offset: 0,
+                size: u64::MAX,
+                available: 16,
+            }]
+        );
+    }
+
+    #[test]
+    fn accepts_well_formed_segment() {
+        let data = segment(|offset| trun(2, offset, &[10, 20]), 30);
+
+        assert_eq!(verify_segment(&data), []);
+    }
+
+    #[test]
+    fn reports_missing_boxes() {
+        let issues = verify_segment(&boxed(b"mdat", &[]));
+
+        assert_eq!(
+            issues,
+            [SegmentIssue::MissingBox {
+                kind: "moof".into(),
+                parent: "segment".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_oversized_trun_sample_count() {
+        let data = segment(|offset| trun(u32::MAX, offset, &[10, 20]), 30);
+
+        let issues = verify_segment(&data);
+
+        assert!(matches!(
+            issues[..],
+            [SegmentIssue::TrunSampleCount {
+                sample_count: u32::MAX,
+                expected,
+                actual: 20,
+                ..
+            }] if expected == 12 + u64::from(u32::MAX) * 4
+        ));
+    }
+
+    #[test]
+    fn reports_sample_data_past_mdat() {
+        let data = segment(|offset| trun(2, offset, &[10, 20]), 29);
+
+        assert!(matches!(
+            verify_segment(&data)[..],
+            [SegmentIssue::TrunDataOutOfBounds { .. }]
+        ));
+    }
+
+    #[test]
+    fn reports_sample_data_before_segment() {
+        let data = segment(|_| trun(1, i32::MIN, &[10]), 10);
+
+        assert!(matches!(
+            verify_segment(&data)[..],
+            [SegmentIssue::TrunDataOutOfBounds { end: u64::MAX, .. }]
+        ));
+    }
+
+    #[test]
+    fn track_run_bounds_sample_count() {
+        let payload = |flags: u8, sample_count: u32| {
+            let mut payload = vec![0, 0, flags, 0];
+            payload.extend_from_slice(&sample_count.to_be_bytes());
+            payload
+        };
+
+        // Sample sizes, but no bytes for them.
+        assert!(TrackRun::parse(&payload(0x02, u32::MAX)).is_none());
+        // No per-sample fields, so nothing but the cap bounds the count.
+        assert!(TrackRun::parse(&payload(0, u32::MAX)).is_none());
+        let run = TrackRun::parse(&payload(0, MAX_RUN_SAMPLES)).unwrap();
+        assert_eq!(run.samples.len(), MAX_RUN_SAMPLES as usize);
+
+        let mut sized = payload(0x02, 2);
+        sized.extend_from_slice(&[0, 0, 0, 10, 0, 0, 0, 20]);
+        let run = TrackRun::parse(&sized).unwrap();
+        let sizes: Vec<_> = run.samples.iter().map(|sample| sample.size).collect();
+        assert_eq!(sizes, [Some(10), Some(20)]);
+    }
+
+    /// A CMAF segment of two chunks and a trailing box, and the chunks it splits into.
+    fn chunked_segment() -> (Vec<u8>, Vec<Vec<u8>>) {
+        let first = [
+            boxed(b"styp", b"cmfc"),
+            boxed(b"moof", &[1; 16]),
+            boxed(b"mdat", &[2; 32]),
+        ]
+        .concat();
+        let second = [
+            boxed(b"prft", &[3; 4]),
+            boxed(b"moof", &[4; 16]),
+            boxed(b"mdat", &[5; 64]),
+        ]
+        .concat();
+        let rest = boxed(b"free", &[]);
+
+        let data = [first.clone(), second.clone(), rest.clone()].concat();
+        (data, vec![first, second, rest])
+    }
+
+    #[test]
+    fn chunk_parser_splits_at_every_boundary() {
+        let (data, expected) = chunked_segment();
+
+        for split in 0..=data.len() {
+            let mut parser = ChunkParser::default();
+            let mut chunks = parser.push(&data[..split]);
+            chunks.extend(parser.push(&data[split..]));
+            chunks.push(parser.finish());
+
+            assert_eq!(chunks, expected, "split at {split}");
+        }
+    }
+
+    #[test]
+    fn chunk_parser_takes_byte_by_byte_pieces() {
+        let (data, expected) = chunked_segment();
+        let mut parser = ChunkParser::default();
+
+        let mut chunks: Vec<_> = data.iter().flat_map(|byte| parser.push(&[*byte])).collect();
+        chunks.push(parser.finish());
+
+        assert_eq!(chunks, expected);
+    }
+
+    #[test]
+    fn chunk_parser_hands_on_unparseable_data_at_the_end() {
+        let moof = boxed(b"moof", &[1; 16]);
+        // An `mdat` extending to the end of the segment has no size to wait for.
+        let mut mdat = boxed(b"mdat", &[2; 32]);
+        mdat[..4].copy_from_slice(&0u32.to_be_bytes());
+        let data = [moof, mdat].concat();
+
+        let mut parser = ChunkParser::default();
+        assert!(parser.push(&data).is_empty());
+        assert_eq!(parser.finish(), data);
+        assert!(parser.finish().is_empty());
     }
 }
@@ -0,0 +1,1149 @@
+//! In-band closed captions: CEA-608 and CEA-708 data carried as ATSC A/53 `GA94` user data in
+//! SEI NAL units of H.264 and H.265 samples. [`extract_cc_data`] pulls the caption bytes out of
+//! a media segment and a [`CaptionDecoder`] turns them into the text each channel shows.
+//!
+//! Decoding covers what's needed to render text cues: pop-on, roll-up and paint-on CEA-608
+//! captions with their special and extended characters, and the text of CEA-708 windows with
+//! their visibility. Positioning, colors and styles are dropped.
+
+use super::base_media_decode_time;
+use super::find_box;
+use super::header_timescale;
+use super::read_u32;
+use super::walk_boxes;
+use super::TrackFragmentHeader;
+use super::TrackRun;
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Size of the fields of a visual sample entry before its child boxes.
+const VISUAL_SAMPLE_ENTRY_SIZE: usize = 78;
+
+/// How the video samples of a representation carry captions, read from its init segment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CaptionFormat {
+    /// H.265 rather than H.264 NAL unit headers.
+    hevc: bool,
+    /// Bytes of the length prefix of every NAL unit.
+    length_size: usize,
+    /// `mdhd` timescale sample times are in.
+    timescale: u32,
+    /// `trex` default sample duration, for fragments that don't list durations.
+    default_sample_duration: Option<u32>,
+}
+
+impl CaptionFormat {
+    /// `None` unless the first track of `init` is H.264 or H.265, encrypted or not.
+    pub fn parse(init: &[u8]) -> Option<Self> {
+        let mut issues = vec![];
+        let stsd = find_box(
+            init,
+            &[b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsd"],
+        )?;
+        // Version and flags, then the entry count.
+        let entries = stsd.payload.get(8..)?;
+        let entry = walk_boxes(entries, 0, &mut issues).into_iter().next()?;
+        let children = entry.payload.get(VISUAL_SAMPLE_ENTRY_SIZE..)?;
+
+        let (hevc, length_size) =
+            walk_boxes(children, 0, &mut issues)
+                .into_iter()
+                .find_map(|child| match &child.kind {
+                    b"avcC" => Some((false, child.payload.get(4)?)),
+                    b"hvcC" => Some((true, child.payload.get(21)?)),
+                    _ => None,
+                })?;
+
+        let timescale = find_box(init, &[b"moov", b"trak", b"mdia", b"mdhd"])
+            .and_then(|mdhd| header_timescale(mdhd.payload))
+            .filter(|timescale| *timescale > 0)?;
+        let default_sample_duration =
+            find_box(init, &[b"moov", b"mvex", b"trex"]).and_then(|trex| {
+                let mut rest = trex.payload.get(12..)?;
+                read_u32(&mut rest)
+            });
+
+        Some(Self {
+            hevc,
+            length_size: (*length_size & 0x03) as usize + 1,
+            timescale,
+            default_sample_duration,
+        })
+    }
+}
+
+/// One `cc_data` construct: a byte pair of a CEA-608 field or of a CEA-708 packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CcData {
+    /// `0` and `1` for the two 608 fields, `3` to start a 708 packet and `2` to continue it.
+    pub cc_type: u8,
+    pub data: [u8; 2],
+}
+
+/// The caption data of one video sample.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CcPacket {
+    /// Presentation time of the sample, in seconds of media time.
+    pub time: f64,
+    pub data: Vec<CcData>,
+}
+
+/// The caption data of every sample of a media segment, in presentation order. Samples without
+/// captions are left out.
+pub fn extract_cc_data(segment: &[u8], format: &CaptionFormat) -> Vec<CcPacket> {
+    let mut issues = vec![];
+    let mut packets = vec![];
+
+    for moof in walk_boxes(segment, 0, &mut issues)
+        .iter()
+        .filter(|b| &b.kind == b"moof")
+    {
+        let children = walk_boxes(moof.payload, moof.payload_offset(), &mut issues);
+        let Some(traf) = children.iter().find(|b| &b.kind == b"traf") else {
+            continue;
+        };
+        let traf_children = walk_boxes(traf.payload, traf.payload_offset(), &mut issues);
+
+        let Some(tfhd) = traf_children
+            .iter()
+            .find(|b| &b.kind == b"tfhd")
+            .and_then(|tfhd| TrackFragmentHeader::parse(tfhd.payload))
+        else {
+            continue;
+        };
+        let mut decode_time = traf_children
+            .iter()
+            .find(|b| &b.kind == b"tfdt")
+            .and_then(|tfdt| base_media_decode_time(tfdt.payload))
+            .unwrap_or(0);
+
+        let mut position = tfhd.base_data_offset.unwrap_or(moof.offset as u64);
+        for trun in traf_children.iter().filter(|b| &b.kind == b"trun") {
+            let Some(run) = TrackRun::parse(trun.payload) else {
+                break;
+            };
+            if let Some(data_offset) = run.data_offset {
+                let base = tfhd.base_data_offset.unwrap_or(moof.offset as u64);
+                position = (base as i64).saturating_add(data_offset.into()) as u64;
+            }
+
+            for sample in run.samples {
+                let duration = sample
+                    .duration
+                    .or(tfhd.default_sample_duration)
+                    .or(format.default_sample_duration)
+                    .unwrap_or(0);
+                let size = sample.size.or(tfhd.default_sample_size).unwrap_or(0);
+                let start = position;
+                position = position.saturating_add(size.into());
+
+                let data = usize::try_from(start)
+                    .ok()
+                    .and_then(|start| segment.get(start..start.checked_add(size as usize)?))
+                    .map(|sample| sample_cc_data(sample, format))
+                    .unwrap_or_default();
+                if !data.is_empty() {
+                    let time = (decode_time as i64).saturating_add(sample.composition_offset);
+                    packets.push(CcPacket {
+                        time: time as f64 / format.timescale as f64,
+                        data,
+                    });
+                }
+
+                decode_time = decode_time.saturating_add(duration.into());
+            }
+        }
+    }
+
+    packets.sort_by(|a, b| a.time.total_cmp(&b.time));
+    packets
+}
+
+/// The `cc_data` of every caption SEI message in a length-prefixed sample.
+fn sample_cc_data(mut sample: &[u8], format: &CaptionFormat) -> Vec<CcData> {
+    let mut data = vec![];
+
+    while sample.len() > format.length_size {
+        let length = sample[..format.length_size]
+            .iter()
+            .fold(0usize, |length, byte| length << 8 | *byte as usize);
+        // A length running past the sample leaves no NAL unit after it to find.
+        let Some(end) = format
+            .length_size
+            .checked_add(length)
+            .filter(|end| *end <= sample.len())
+        else {
+            break;
+        };
+        let nal = &sample[format.length_size..end];
+        sample = &sample[end..];
+
+        let Some(header) = nal.first() else {
+            continue;
+        };
+        let sei = match format.hevc {
+            // Prefix and suffix SEI.
+            true if matches!((header >> 1) & 0x3F, 39 | 40) => nal.get(2..),
+            false if header & 0x1F == 6 => nal.get(1..),
+            _ => None,
+        };
+
+        if let Some(sei) = sei {
+            parse_sei(&unescape_rbsp(sei), &mut data);
+        }
+    }
+
+    data
+}
+
+/// Removes the emulation prevention bytes of a NAL unit payload.
+fn unescape_rbsp(data: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(data.len());
+    let mut zeros = 0;
+
+    for byte in data {
+        if zeros >= 2 && *byte == 0x03 {
+            zeros = 0;
+            continue;
+        }
+
+        zeros = if *byte == 0 { zeros + 1 } else { 0 };
+        rbsp.push(*byte);
+    }
+
+    rbsp
+}
+
+fn parse_sei(mut rbsp: &[u8], data: &mut Vec<CcData>) {
+    // Stop at the trailing bits.
+    while rbsp.len() > 1 {
+        let (Some(payload_type), Some(payload_size)) = (sei_value(&mut rbsp), sei_value(&mut rbsp))
+        else {
+            return;
+        };
+        let Some(payload) = rbsp.get(..payload_size) else {
+            return;
+        };
+        rbsp = &rbsp[payload_size..];
+
+        // `user_data_registered_itu_t_t35`.
+        if payload_type == 4 {
+            parse_t35(payload, data);
+        }
+    }
+}
+
+/// A payload type or size: `0xFF` bytes adding up, ended by a smaller one.
+fn sei_value(rbsp: &mut &[u8]) -> Option<usize> {
+    let mut value = 0;
+
+    loop {
+        let (byte, rest) = rbsp.split_first()?;
+        *rbsp = rest;
+        value += *byte as usize;
+
+        if *byte != 0xFF {
+            return Some(value);
+        }
+    }
+}
+
+/// Collects the valid constructs of ATSC A/53 caption data.
+fn parse_t35(payload: &[u8], data: &mut Vec<CcData>) {
+    // United States country code, ATSC provider code, `GA94` and the cc_data type code.
+    let Some(rest) = payload.strip_prefix(&[0xB5, 0x00, 0x31, b'G', b'A', b'9', b'4', 0x03]) else {
+        return;
+    };
+    let Some((flags, rest)) = rest.split_first() else {
+        return;
+    };
+    if flags & 0x40 == 0 {
+        return;
+    }
+
+    let count = (flags & 0x1F) as usize;
+    // Skips `em_data`.
+    let constructs = rest.get(1..).unwrap_or_default();
+
+    for construct in constructs.chunks_exact(3).take(count) {
+        if construct[0] & 0x04 == 0 {
+            continue;
+        }
+
+        data.push(CcData {
+            cc_type: construct[0] & 0x03,
+            data: [construct[1], construct[2]],
+        });
+    }
+}
+
+/// A caption channel: one of the four CEA-608 channels or a CEA-708 service.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum CaptionChannel {
+    Cc1,
+    Cc2,
+    Cc3,
+    Cc4,
+    /// Service number, `1` being the primary caption service.
+    Service(u8),
+}
+
+impl CaptionChannel {
+    /// Name of the channel's text track, e.g. `CC1` or `Service 1`.
+    pub fn label(&self) -> String {
+        match self {
+            Self::Cc1 => "CC1".into(),
+            Self::Cc2 => "CC2".into(),
+            Self::Cc3 => "CC3".into(),
+            Self::Cc4 => "CC4".into(),
+            Self::Service(number) => format!("Service {number}"),
+        }
+    }
+}
+
+/// From `time` on, `channel` shows `text`. An empty text clears the channel.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CaptionUpdate {
+    pub channel: CaptionChannel,
+    /// In whatever time [`CcPacket::time`] was in.
+    pub time: f64,
+    pub text: String,
+}
+
+/// Turns the caption data of consecutive samples into what each channel shows. Data has to be
+/// fed in presentation order; [`Self::reset`] when it stops being contiguous, e.g. after a seek.
+#[derive(Debug, Default)]
+pub struct CaptionDecoder {
+    fields: [Field608; 2],
+    channels: [Channel608; 4],
+    services: BTreeMap<u8, Service708>,
+    /// The CEA-708 packet being assembled.
+    packet: Vec<u8>,
+}
+
+impl CaptionDecoder {
+    pub fn decode(&mut self, packets: &[CcPacket]) -> Vec<CaptionUpdate> {
+        let mut updates = vec![];
+
+        for packet in packets {
+            for cc in &packet.data {
+                match cc.cc_type {
+                    field @ (0 | 1) => {
+                        self.decode_608(field as usize, cc.data, packet.time, &mut updates)
+                    }
+                    3 => {
+                        self.finish_packet(packet.time, &mut updates);
+                        self.packet = cc.data.to_vec();
+                    }
+                    _ if !self.packet.is_empty() => {
+                        self.packet.extend(cc.data);
+                        if self.packet.len() >= packet_size(self.packet[0]) {
+                            self.finish_packet(packet.time, &mut updates);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // Text still being typed shows as of its last change.
+        for (index, channel) in self.channels.iter_mut().enumerate() {
+            channel.flush(CHANNELS_608[index], &mut updates);
+        }
+        for (number, service) in &mut self.services {
+            service.flush(CaptionChannel::Service(*number), &mut updates);
+        }
+
+        updates
+    }
+
+    /// Forgets all state, as if no caption data was seen yet.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    fn decode_608(
+        &mut self,
+        field: usize,
+        data: [u8; 2],
+        time: f64,
+        updates: &mut Vec<CaptionUpdate>,
+    ) {
+        // Strips the parity bits.
+        let (c1, c2) = (data[0] & 0x7F, data[1] & 0x7F);
+        let state = &mut self.fields[field];
+
+        if c1 == 0 && c2 == 0 {
+            return;
+        }
+
+        // Extended data services on field 2 aren't captions.
+        if field == 1 && (0x01..=0x0F).contains(&c1) {
+            state.xds = c1 != 0x0F;
+            return;
+        }
+        if state.xds && c1 < 0x10 {
+            return;
+        }
+        state.xds = false;
+
+        if (0x10..=0x1F).contains(&c1) {
+            // Control codes are sent twice for robustness.
+            if state.last_control == Some((c1, c2)) {
+                state.last_control = None;
+                return;
+            }
+            state.last_control = Some((c1, c2));
+            state.active = ((c1 & 0x08) >> 3) as usize;
+
+            let index = field * 2 + state.active;
+            self.channels[index].control(c1 & 0x77, c2, time, CHANNELS_608[index], updates);
+        } else {
+            state.last_control = None;
+
+            let index = field * 2 + state.active;
+            let channel = &mut self.channels[index];
+            if c1 >= 0x20 {
+                channel.write(basic_char(c1), time);
+            }
+            if c2 >= 0x20 {
+                channel.write(basic_char(c2), time);
+            }
+        }
+    }
+
+    fn finish_packet(&mut self, time: f64, updates: &mut Vec<CaptionUpdate>) {
+        let packet = std::mem::take(&mut self.packet);
+        let Some(header) = packet.first() else {
+            return;
+        };
+        let size = packet_size(*header).min(packet.len());
+        let mut rest = &packet[1..size];
+
+        while let Some((header, tail)) = rest.split_first() {
+            let mut number = header >> 5;
+            let block_size = (header & 0x1F) as usize;
+            rest = tail;
+
+            if number == 7 && block_size != 0 {
+                let Some((extended, tail)) = rest.split_first() else {
+                    return;
+                };
+                number = extended & 0x3F;
+                rest = tail;
+            }
+            // The null service pads the rest of the packet.
+            if number == 0 {
+                return;
+            }
+
+            let Some(block) = rest.get(..block_size) else {
+                return;
+            };
+            rest = &rest[block_size..];
+
+            self.services.entry(number).or_default().decode(
+                block,
+                time,
+                CaptionChannel::Service(number),
+                updates,
+            );
+        }
+    }
+}
+
+const CHANNELS_608: [CaptionChannel; 4] = [
+    CaptionChannel::Cc1,
+    CaptionChannel::Cc2,
+    CaptionChannel::Cc3,
+    CaptionChannel::Cc4,
+];
+
+/// Bytes of a CEA-708 packet, header included.
+fn packet_size(header: u8) -> usize {
+    match header & 0x3F {
+        0 => 128,
+        code => code as usize * 2,
+    }
+}
+
+/// Joins the non-empty rows of a caption.
+fn caption_text<'a>(rows: impl Iterator<Item = &'a String>) -> String {
+    rows.map(|row| row.trim_end())
+        .filter(|row| !row.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Reports what a channel shows whenever it changed since the last report.
+#[derive(Debug, Default)]
+struct Shown {
+    text: String,
+    /// Time of the first change not reported yet.
+    pending: Option<f64>,
+}
+
+impl Shown {
+    fn report(
+        &mut self,
+        channel: CaptionChannel,
+        time: f64,
+        text: String,
+        updates: &mut Vec<CaptionUpdate>,
+    ) {
+        self.pending = None;
+        if text == self.text {
+            return;
+        }
+
+        self.text = text.clone();
+        updates.push(CaptionUpdate {
+            channel,
+            time,
+            text,
+        });
+    }
+
+    fn changed(&mut self, time: f64) {
+        self.pending.get_or_insert(time);
+    }
+}
+
+#[derive(Debug, Default)]
+struct Field608 {
+    /// Data channel of the field printable characters go to.
+    active: usize,
+    last_control: Option<(u8, u8)>,
+    /// Inside an extended data services packet.
+    xds: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Mode608 {
+    /// Text is composed off screen and shown at once.
+    #[default]
+    PopOn,
+    /// Text appears as it comes in at the bottom row, scrolling up by a row on carriage
+    /// return and keeping this many rows.
+    RollUp(u8),
+    /// Text appears as it comes in, wherever it is placed.
+    PaintOn,
+    /// Text mode service data, which isn't captions.
+    Text,
+}
+
+/// Rows of a CEA-608 caption memory, numbered from 1 at the top to 15.
+type Screen = BTreeMap<u8, String>;
+
+#[derive(Debug, Default)]
+struct Channel608 {
+    mode: Mode608,
+    displayed: Screen,
+    non_displayed: Screen,
+    row: u8,
+    shown: Shown,
+}
+
+impl Channel608 {
+    fn write(&mut self, c: char, time: f64) {
+        let row = self.row.max(1);
+        match self.mode {
+            Mode608::Text => {}
+            Mode608::PopOn => self.non_displayed.entry(row).or_default().push(c),
+            Mode608::RollUp(_) | Mode608::PaintOn => {
+                self.displayed.entry(row).or_default().push(c);
+                self.shown.changed(time);
+            }
+        }
+    }
+
+    /// Replaces the character written last, for extended characters that follow their basic
+    /// fallback.
+    fn replace(&mut self, c: char, time: f64) {
+        self.backspace(time);
+        self.write(c, time);
+    }
+
+    fn backspace(&mut self, time: f64) {
+        let row = self.row.max(1);
+        let memory = match self.mode {
+            Mode608::Text => return,
+            Mode608::PopOn => &mut self.non_displayed,
+            Mode608::RollUp(_) | Mode608::PaintOn => {
+                self.shown.changed(time);
+                &mut self.displayed
+            }
+        };
+
+        if let Some(text) = memory.get_mut(&row) {
+            text.pop();
+        }
+    }
+
+    /// Handles a control code, `c1` with the channel bit cleared.
+    fn control(
+        &mut self,
+        c1: u8,
+        c2: u8,
+        time: f64,
+        channel: CaptionChannel,
+        updates: &mut Vec<CaptionUpdate>,
+    ) {
+        match (c1, c2) {
+            // Preamble address code.
+            (_, 0x40..=0x7F) => {
+                let rows = PAC_ROWS[(c1 & 0x07) as usize];
+                let row = rows[(c2 & 0x20 != 0) as usize];
+                // Roll-up captions stay at the bottom row.
+                if !matches!(self.mode, Mode608::RollUp(_)) {
+                    self.row = row;
+                }
+            }
+            (0x14 | 0x15, 0x20..=0x2F) => self.command(c2, time, channel, updates),
+            // Mid-row style changes show as a space.
+            (0x11, 0x20..=0x2F) => self.write(' ', time),
+            (0x11, 0x30..=0x3F) => self.write(SPECIAL_CHARS[(c2 - 0x30) as usize], time),
+            (0x12, 0x20..=0x3F) => self.replace(EXTENDED_CHARS[0][(c2 - 0x20) as usize], time),
+            (0x13, 0x20..=0x3F) => self.replace(EXTENDED_CHARS[1][(c2 - 0x20) as usize], time),
+            // Tab offsets, attributes and anything unassigned.
+            _ => {}
+        }
+    }
+
+    fn command(
+        &mut self,
+        code: u8,
+        time: f64,
+        channel: CaptionChannel,
+        updates: &mut Vec<CaptionUpdate>,
+    ) {
+        match code {
+            // Resume caption loading.
+            0x20 => self.mode = Mode608::PopOn,
+            // Backspace.
+            0x21 => self.backspace(time),
+            // Roll-up captions with 2, 3 or 4 rows.
+            0x25..=0x27 => {
+                let rows = code - 0x23;
+                if !matches!(self.mode, Mode608::RollUp(_)) {
+                    self.flush(channel, updates);
+                    self.displayed.clear();
+                    self.non_displayed.clear();
+                    self.shown.report(channel, time, String::new(), updates);
+                    self.row = 15;
+                }
+                self.mode = Mode608::RollUp(rows);
+            }
+            // Resume direct captioning.
+            0x29 => self.mode = Mode608::PaintOn,
+            // Text restart and resume text display.
+            0x2A | 0x2B => self.mode = Mode608::Text,
+            // Erase displayed memory.
+            0x2C => {
+                self.flush(channel, updates);
+                self.displayed.clear();
+                self.shown.report(channel, time, String::new(), updates);
+            }
+            // Carriage return.
+            0x2D => {
+                let Mode608::RollUp(rows) = self.mode else {
+                    return;
+                };
+                self.flush(channel, updates);
+
+                let bottom = self.row.max(1);
+                let top = bottom.saturating_sub(rows) + 1;
+                self.displayed = std::mem::take(&mut self.displayed)
+                    .into_iter()
+                    .filter(|(row, _)| *row > top && *row <= bottom)
+                    .map(|(row, text)| (row - 1, text))
+                    .collect();
+
+                let text = caption_text(self.displayed.values());
+                self.shown.report(channel, time, text, updates);
+            }
+            // Erase non-displayed memory.
+            0x2E => self.non_displayed.clear(),
+            // End of caption: flips the memories.
+            0x2F => {
+                self.flush(channel, updates);
+                std::mem::swap(&mut self.displayed, &mut self.non_displayed);
+                self.mode = Mode608::PopOn;
+
+                let text = caption_text(self.displayed.values());
+                self.shown.report(channel, time, text, updates);
+            }
+            // Delete to end of row and flash on need the cursor column, which isn't tracked.
+            _ => {}
+        }
+    }
+
+    /// Reports text typed straight to the screen as of its first change not reported yet.
+    fn flush(&mut self, channel: CaptionChannel, updates: &mut Vec<CaptionUpdate>) {
+        if let Some(time) = self.shown.pending {
+            let text = caption_text(self.displayed.values());
+            self.shown.report(channel, time, text, updates);
+        }
+    }
+}
+
+/// Rows of the preamble address codes by the low bits of their first byte, for second bytes up
+/// to `0x5F` and from `0x60`.
+const PAC_ROWS: [[u8; 2]; 8] = [
+    [11, 11],
+    [1, 2],
+    [3, 4],
+    [12, 13],
+    [14, 15],
+    [5, 6],
+    [7, 8],
+    [9, 10],
+];
+
+/// A character of the basic set, which is ASCII but for a few accented letters and symbols.
+fn basic_char(byte: u8) -> char {
+    match byte {
+        0x2A => 'á',
+        0x5C => 'é',
+        0x5E => 'í',
+        0x5F => 'ó',
+        0x60 => 'ú',
+        0x7B => 'ç',
+        0x7C => '÷',
+        0x7D => 'Ñ',
+        0x7E => 'ñ',
+        0x7F => '█',
+        _ => byte as char,
+    }
+}
+
+/// Special characters, second bytes `0x30` to `0x3F`. The transparent space shows as a space.
+const SPECIAL_CHARS: [char; 16] = [
+    '®', '°', '½', '¿', '™', '¢', '£', '♪', 'à', ' ', 'è', 'â', 'ê', 'î', 'ô', 'û',
+];
+
+/// Extended Spanish, French and miscellaneous characters after `0x12`, and Portuguese, German
+/// and Danish characters after `0x13`, second bytes `0x20` to `0x3F`.
+const EXTENDED_CHARS: [[char; 32]; 2] = [
+    [
+        'Á', 'É', 'Ó', 'Ú', 'Ü', 'ü', '‘', '¡', '*', '\'', '—', '©', '℠', '•', '“', '”', 'À', 'Â',
+        'Ç', 'È', 'Ê', 'Ë', 'ë', 'Î', 'Ï', 'ï', 'Ô', 'Ù', 'ù', 'Û', '«', '»',
+    ],
+    [
+        'Ã', 'ã', 'Í', 'Ì', 'ì', 'Ò', 'ò', 'Õ', 'õ', '{', '}', '\\', '^', '_', '|', '~', 'Ä', 'ä',
+        'Ö', 'ö', 'ß', '¥', '¤', '│', 'Å', 'å', 'Ø', 'ø', '┌', '┐', '└', '┘',
+    ],
+];
+
+#[derive(Debug, Default)]
+struct Window708 {
+    defined: bool,
+    visible: bool,
+    /// Rows the window holds before scrolling.
+    row_count: usize,
+    rows: Vec<String>,
+}
+
+impl Window708 {
+    fn text(&self) -> String {
+        caption_text(self.rows.iter())
+    }
+
+    fn clear(&mut self) {
+        self.rows.clear();
+    }
+}
+
+#[derive(Debug, Default)]
+struct Service708 {
+    windows: [Window708; 8],
+    current: usize,
+    shown: Shown,
+}
+
+impl Service708 {
+    fn decode(
+        &mut self,
+        mut block: &[u8],
+        time: f64,
+        channel: CaptionChannel,
+        updates: &mut Vec<CaptionUpdate>,
+    ) {
+        while let Some((code, rest)) = block.split_first() {
+            block = rest;
+            let code = *code;
+
+            // Parameter bytes of the command, skipped unless it needs them.
+            let parameters = match code {
+                0x10 => return self.extended(block, time, channel, updates),
+                0x11..=0x17 => 1,
+                0x18..=0x1F => 2,
+                0x88..=0x8D => 1,
+                0x90 | 0x92 => 2,
+                0x91 => 3,
+                0x97 => 4,
+                0x98..=0x9F => 6,
+                _ => 0,
+            };
+            let Some(parameters) = block.get(..parameters) else {
+                return;
+            };
+            block = &block[parameters.len()..];
+
+            match code {
+                // End of text.
+                0x03 => self.flush(channel, updates),
+                // Backspace.
+                0x08 => {
+                    if let Some(row) = self.window().rows.last_mut() {
+                        row.pop();
+                    }
+                    self.changed(time);
+                }
+                // Form feed clears the window.
+                0x0C => {
+                    self.window().clear();
+                    self.changed(time);
+                }
+                // Carriage return.
+                0x0D => {
+                    let window = self.window();
+                    window.rows.push(String::new());
+                    let excess = window.rows.len().saturating_sub(window.row_count.max(1));
+                    window.rows.drain(..excess);
+                    self.changed(time);
+                }
+                // Horizontal carriage return clears the row.
+                0x0E => {
+                    if let Some(row) = self.window().rows.last_mut() {
+                        row.clear();
+                    }
+                    self.changed(time);
+                }
+                0x20..=0x7E | 0xA0..=0xFF => self.write(code as char, time),
+                0x7F => self.write('♪', time),
+                // Sets the current window.
+                0x80..=0x87 => self.current = (code & 0x07) as usize,
+                // Clear, display, hide, toggle and delete windows.
+                0x88..=0x8C => self.update_windows(code, parameters[0], time, channel, updates),
+                // Reset.
+                0x8F => {
+                    self.flush(channel, updates);
+                    self.windows = Default::default();
+                    self.shown.report(channel, time, String::new(), updates);
+                }
+                // Define window.
+                0x98..=0x9F => {
+                    self.flush(channel, updates);
+                    self.current = (code & 0x07) as usize;
+
+                    let window = self.window();
+                    window.defined = true;
+                    window.visible = parameters[0] & 0x20 != 0;
+                    window.row_count = (parameters[3] & 0x0F) as usize + 1;
+
+                    self.report(channel, time, updates);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Handles a code of the extended sets following `EXT1`. Returns on variable length
+    /// commands, which end the block.
+    fn extended(
+        &mut self,
+        block: &[u8],
+        time: f64,
+        channel: CaptionChannel,
+        updates: &mut Vec<CaptionUpdate>,
+    ) {
+        let Some((code, rest)) = block.split_first() else {
+            return;
+        };
+
+        let parameters = match code {
+            0x00..=0x07 => 0,
+            0x08..=0x0F => 1,
+            0x10..=0x17 => 2,
+            0x18..=0x1F => 3,
+            0x20..=0x7F | 0xA0..=0xFF => {
+                // The G2 characters. Unassigned codes and the icons of G3 show as underscores.
+                let c = match code {
+                    0x20 | 0x21 => ' ',
+                    0x25 => '…',
+                    0x2A => 'Š',
+                    0x2C => 'Œ',
+                    0x30 => '█',
+                    0x31 => '‘',
+                    0x32 => '’',
+                    0x33 => '“',
+                    0x34 => '”',
+                    0x35 => '•',
+                    0x39 => '™',
+                    0x3A => 'š',
+                    0x3C => 'œ',
+                    0x3D => '℠',
+                    0x3F => 'Ÿ',
+                    0x76 => '⅛',
+                    0x77 => '⅜',
+                    0x78 => '⅝',
+                    0x79 => '⅞',
+                    0x7A => '│',
+                    0x7B => '┐',
+                    0x7C => '└',
+                    0x7D => '─',
+                    0x7E => '┘',
+                    0x7F => '┌',
+                    _ => '_',
+                };
+                self.write(c, time);
+                0
+            }
+            0x80..=0x87 => 4,
+            0x88..=0x8F => 5,
+            _ => return,
+        };
+
+        if let Some(rest) = rest.get(parameters..) {
+            self.decode(rest, time, channel, updates);
+        }
+    }
+
+    fn window(&mut self) -> &mut Window708 {
+        &mut self.windows[self.current]
+    }
+
+    fn write(&mut self, c: char, time: f64) {
+        let window = self.window();
+        match window.rows.last_mut() {
+            Some(row) => row.push(c),
+            None => window.rows.push(c.to_string()),
+        }
+        self.changed(time);
+    }
+
+    /// Notes a change of the current window, which only shows once it's visible.
+    fn changed(&mut self, time: f64) {
+        let window = &self.windows[self.current];
+        if window.defined && window.visible {
+            self.shown.changed(time);
+        }
+    }
+
+    fn update_windows(
+        &mut self,
+        code: u8,
+        windows: u8,
+        time: f64,
+        channel: CaptionChannel,
+        updates: &mut Vec<CaptionUpdate>,
+    ) {
+        self.flush(channel, updates);
+
+        for (index, window) in self.windows.iter_mut().enumerate() {
+            if windows & (1 << index) == 0 {
+                continue;
+            }
+
+            match code {
+                0x88 => window.clear(),
+                0x89 => window.visible = true,
+                0x8A => window.visible = false,
+                0x8B => window.visible = !window.visible,
+                _ => *window = Window708::default(),
+            }
+        }
+
+        self.report(channel, time, updates);
+    }
+
+    fn text(&self) -> String {
+        self.windows
+            .iter()
+            .filter(|window| window.defined && window.visible)
+            .map(Window708::text)
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn report(&mut self, channel: CaptionChannel, time: f64, updates: &mut Vec<CaptionUpdate>) {
+        let text = self.text();
+        self.shown.report(channel, time, text, updates);
+    }
+
+    /// Reports changes of visible windows as of the first one not reported yet.
+    fn flush(&mut self, channel: CaptionChannel, updates: &mut Vec<CaptionUpdate>) {
+        if let Some(time) = self.shown.pending {
+            self.report(channel, time, updates);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMAT: CaptionFormat = CaptionFormat {
+        hevc: false,
+        length_size: 4,
+        timescale: 90_000,
+        default_sample_duration: None,
+    };
+
+    /// An H.264 SEI NAL unit with the A/53 caption data `constructs`, length prefixed.
+    fn caption_nal(constructs: &[(u8, [u8; 2])]) -> Vec<u8> {
+        let mut t35 = vec![0xB5, 0x00, 0x31, b'G', b'A', b'9', b'4', 0x03];
+        t35.push(0x40 | constructs.len() as u8);
+        t35.push(0xFF);
+        for (cc_type, data) in constructs {
+            t35.extend([0xFC | cc_type, data[0], data[1]]);
+        }
+
+        let mut nal = vec![0x06, 4, t35.len() as u8];
+        nal.extend(t35);
+        nal.push(0x80);
+        prefixed(&nal)
+    }
+
+    fn prefixed(nal: &[u8]) -> Vec<u8> {
+        let mut data = (nal.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(nal);
+        data
+    }
+
+    fn packet(time: f64, constructs: &[(u8, [u8; 2])]) -> CcPacket {
+        CcPacket {
+            time,
+            data: constructs
+                .iter()
+                .map(|(cc_type, data)| CcData {
+                    cc_type: *cc_type,
+                    data: *data,
+                })
+                .collect(),
+        }
+    }
+
+    fn update(channel: CaptionChannel, time: f64, text: &str) -> CaptionUpdate {
+        CaptionUpdate {
+            channel,
+            time,
+            text: text.into(),
+        }
+    }
+
+    #[test]
+    fn extracts_cc_data_from_sei() {
+        let sample = [
+            // An IDR slice, which carries no captions.
+            prefixed(&[0x65, 0x88, 0x84]),
+            caption_nal(&[(0, [0x94, 0x20]), (1, [0x80, 0x80])]),
+        ]
+        .concat();
+
+        assert_eq!(
+            sample_cc_data(&sample, &FORMAT),
+            [
+                CcData {
+                    cc_type: 0,
+                    data: [0x94, 0x20],
+                },
+                CcData {
+                    cc_type: 1,
+                    data: [0x80, 0x80],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn stops_at_nal_length_past_sample_end() {
+        let mut sample = caption_nal(&[(0, [0x94, 0x20])]);
+        sample.extend_from_slice(&1000u32.to_be_bytes());
+        sample.extend(caption_nal(&[(0, [0x94, 0x2F])]));
+
+        let data = sample_cc_data(&sample, &FORMAT);
+        assert_eq!(data.len(), 1);
+        assert_eq!(data[0].data, [0x94, 0x20]);
+
+        let sample = [u32::MAX.to_be_bytes().to_vec(), vec![0x06; 8]].concat();
+        assert!(sample_cc_data(&sample, &FORMAT).is_empty());
+    }
+
+    #[test]
+    fn removes_emulation_prevention_bytes() {
+        assert_eq!(unescape_rbsp(&[0, 0, 3, 1, 0, 0, 3]), [0, 0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn decodes_608_pop_on_captions() {
+        let mut decoder = CaptionDecoder::default();
+
+        let updates = decoder.decode(&[
+            // Resume caption loading, sent twice as control codes are.
+            packet(0., &[(0, [0x94, 0x20])]),
+            packet(0.1, &[(0, [0x94, 0x20])]),
+            packet(0.2, &[(0, [b'H', b'I'])]),
+            packet(0.3, &[(0, [0x7E, 0x80])]),
+            // End of caption.
+            packet(1., &[(0, [0x94, 0x2F])]),
+            packet(1.1, &[(0, [0x94, 0x2F])]),
+            // Erase displayed memory.
+            packet(2., &[(0, [0x94, 0x2C])]),
+        ]);
+
+        assert_eq!(
+            updates,
+            [
+                update(CaptionChannel::Cc1, 1., "HIñ"),
+                update(CaptionChannel::Cc1, 2., ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_608_roll_up_captions() {
+        let mut decoder = CaptionDecoder::default();
+
+        let updates = decoder.decode(&[
+            // Roll-up with two rows, on the second data channel of field 1.
+            packet(0., &[(0, [0x1C, 0x25])]),
+            packet(0.5, &[(0, [b'A', b'B'])]),
+            // Carriage return.
+            packet(1., &[(0, [0x1C, 0x2D])]),
+            packet(1.5, &[(0, [b'C', 0])]),
+        ]);
+
+        assert_eq!(
+            updates,
+            [
+                update(CaptionChannel::Cc2, 0.5, "AB"),
+                update(CaptionChannel::Cc2, 1.5, "AB\nC"),
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_708_window_text() {
+        // Service 1: define a visible window of one row, write "OK" and end the text.
+        let block = [0x98, 0x20, 0, 0, 0, 0, 0, b'O', b'K', 0x03];
+        let mut bytes = vec![0x06, (1 << 5) | block.len() as u8];
+        bytes.extend(block);
+
+        let constructs: Vec<_> = bytes
+            .chunks_exact(2)
+            .enumerate()
+            .map(|(index, pair)| (if index == 0 { 3 } else { 2 }, [pair[0], pair[1]]))
+            .collect();
+
+        let mut decoder = CaptionDecoder::default();
+        let updates = decoder.decode(&[packet(3., &constructs)]);
+
+        assert_eq!(updates, [update(CaptionChannel::Service(1), 3., "OK")]);
+    }
+}
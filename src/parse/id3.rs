@@ -0,0 +1,419 @@
+//! ID3v2.3 and ID3v2.4 tags, as carried in `emsg` boxes by audio streams, e.g. the title and
+//! artist of the current song of a radio-style live stream.
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Size of the tag header and of every frame header.
+const HEADER_SIZE: usize = 10;
+
+/// A frame of an ID3 tag.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Id3Frame {
+    /// A text information frame, e.g. `TIT2` for the title or `TPE1` for the artist. Several
+    /// values are joined with `/`.
+    Text { id: String, value: String },
+    /// `TXXX`, text the broadcaster described itself.
+    UserText { description: String, value: String },
+    /// A URL link frame, e.g. `WOAF`, or `WXXX`, which also has a description.
+    Url {
+        id: String,
+        description: Option<String>,
+        url: String,
+    },
+    /// `PRIV`, data of the application `owner` identifies.
+    Private { owner: String, data: Vec<u8> },
+    /// Any other frame, including compressed and encrypted ones, undecoded.
+    Other { id: String, data: Vec<u8> },
+}
+
+impl Id3Frame {
+    /// The value of the text frame `id` among `frames`, e.g. `TIT2` for the title.
+    pub fn text<'a>(frames: &'a [Id3Frame], id: &str) -> Option<&'a str> {
+        frames.iter().find_map(|frame| match frame {
+            Self::Text {
+                id: frame_id,
+                value,
+            } if frame_id == id => Some(value.as_str()),
+            _ => None,
+        })
+    }
+}
+
+/// The frames of the ID3 tag `data` starts with. Empty if it doesn't start with one.
+pub fn parse_id3(data: &[u8]) -> Vec<Id3Frame> {
+    let Some(header) = data
+        .get(..HEADER_SIZE)
+        .filter(|header| header.starts_with(b"ID3"))
+    else {
+        return vec![];
+    };
+
+    let version = header[3];
+    let flags = header[5];
+    if !matches!(version, 3 | 4) {
+        tracing::debug!(version, "Ignoring ID3 tag of unsupported version.");
+        return vec![];
+    }
+
+    let Some(size) = syncsafe(&header[6..10]) else {
+        return vec![];
+    };
+    let Some(body) = HEADER_SIZE
+        .checked_add(size)
+        .and_then(|end| data.get(HEADER_SIZE..end))
+    else {
+        tracing::warn!("Ignoring truncated ID3 tag.");
+        return vec![];
+    };
+
+    // Version 3 unsynchronises the whole tag, version 4 every frame on its own.
+    let body = match flags & 0x80 != 0 && version == 3 {
+        true => resynchronise(body),
+        false => body.to_vec(),
+    };
+
+    let mut frames = body.as_slice();
+    if flags & 0x40 != 0 {
+        let Some(extended) = frames.get(..4) else {
+            return vec![];
+        };
+        // Version 3 counts the size bytes out, version 4 in.
+        let skip = match version {
+            3 => (u32::from_be_bytes(extended.try_into().unwrap()) as usize).checked_add(4),
+            _ => syncsafe(extended),
+        };
+        let Some(rest) = skip.and_then(|skip| frames.get(skip..)) else {
+            return vec![];
+        };
+        frames = rest;
+    }
+
+    let mut parsed = vec![];
+    while frames.len() >= HEADER_SIZE && frames[0] != 0 {
+        let id = String::from_utf8_lossy(&frames[..4]).into_owned();
+        let size = match version {
+            3 => Some(u32::from_be_bytes(frames[4..8].try_into().unwrap()) as usize),
+            _ => syncsafe(&frames[4..8]),
+        };
+        let format = frames[9];
+
+        let Some(data) = size
+            .and_then(|size| HEADER_SIZE.checked_add(size))
+            .and_then(|end| frames.get(HEADER_SIZE..end))
+        else {
+            tracing::warn!(id, "Ignoring truncated ID3 frame.");
+            break;
+        };
+        frames = &frames[HEADER_SIZE + data.len()..];
+
+        parsed.push(match frame_data(data, version, format) {
+            Some(data) => parse_frame(id, &data),
+            None => Id3Frame::Other {
+                id,
+                data: data.to_vec(),
+            },
+        });
+    }
+
+    parsed
+}
+
+/// Frame contents without the additions its format flags announce. `None` for compressed and
+/// encrypted frames.
+fn frame_data(data: &[u8], version: u8, format: u8) -> Option<Vec<u8>> {
+    match version {
+        3 => {
+            if format & 0xC0 != 0 {
+                return None;
+            }
+            // Group identifier.
+            let skip = if format & 0x20 != 0 { 1 } else { 0 };
+            data.get(skip..).map(<[u8]>::to_vec)
+        }
+        _ => {
+            if format & 0x0C != 0 {
+                return None;
+            }
+            // Group identifier and data length indicator.
+            let skip = (format & 0x40 != 0) as usize + (format & 0x01 != 0) as usize * 4;
+            let data = data.get(skip..)?;
+
+            Some(match format & 0x02 != 0 {
+                true => resynchronise(data),
+                false => data.to_vec(),
+            })
+        }
+    }
+}
+
+fn parse_frame(id: String, data: &[u8]) -> Id3Frame {
+    let parsed = match id.as_str() {
+        "TXXX" => data.split_first().and_then(|(encoding, rest)| {
+            let (description, value) = split_text(*encoding, rest);
+            Some(Id3Frame::UserText {
+                description: decode_text(*encoding, description)?,
+                value: decode_text(*encoding, value)?,
+            })
+        }),
+        "WXXX" => data.split_first().and_then(|(encoding, rest)| {
+            let (description, url) = split_text(*encoding, rest);
+            Some(Id3Frame::Url {
+                id: id.clone(),
+                description: Some(decode_text(*encoding, description)?),
+                url: latin1(split_text(0, url).0),
+            })
+        }),
+        "PRIV" => {
+            let (owner, data) = split_text(0, data);
+            Some(Id3Frame::Private {
+                owner: latin1(owner),
+                data: data.to_vec(),
+            })
+        }
+        _ if id.starts_with('T') => data.split_first().and_then(|(encoding, rest)| {
+            Some(Id3Frame::Text {
+                id: id.clone(),
+                value: decode_text(*encoding, rest)?,
+            })
+        }),
+        _ if id.starts_with('W') => Some(Id3Frame::Url {
+            id: id.clone(),
+            description: None,
+            url: latin1(split_text(0, data).0),
+        }),
+        _ => None,
+    };
+
+    parsed.unwrap_or(Id3Frame::Other {
+        id,
+        data: data.to_vec(),
+    })
+}
+
+/// Splits `data` after the first string terminator of `encoding`.
+fn split_text(encoding: u8, data: &[u8]) -> (&[u8], &[u8]) {
+    let end = match encoding {
+        // UTF-16 strings end with two zero bytes at an even offset.
+        1 | 2 => data
+            .chunks_exact(2)
+            .position(|pair| pair == [0, 0])
+            .map(|index| (index * 2, index * 2 + 2)),
+        _ => data
+            .iter()
+            .position(|byte| *byte == 0)
+            .map(|index| (index, index + 1)),
+    };
+
+    match end {
+        Some((end, rest)) => (&data[..end], &data[rest..]),
+        None => (data, &[]),
+    }
+}
+
+/// Decodes the strings of a text frame, joining several with `/`. `None` for unknown encodings.
+fn decode_text(encoding: u8, data: &[u8]) -> Option<String> {
+    let text = match encoding {
+        0 => latin1(data),
+        1 | 2 => {
+            let mut units: Vec<u16> = data
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            // A byte order mark tells big from little endian. Without one it's big endian.
+            if units.first() == Some(&0xFFFE) {
+                units.iter_mut().for_each(|unit| *unit = unit.swap_bytes());
+            }
+            String::from_utf16_lossy(&units).replace('\u{FEFF}', "")
+        }
+        3 => String::from_utf8_lossy(data).into_owned(),
+        _ => return None,
+    };
+
+    Some(
+        text.split('\0')
+            .filter(|value| !value.is_empty())
+            .collect::<Vec<_>>()
+            .join("/"),
+    )
+}
+
+fn latin1(data: &[u8]) -> String {
+    data.iter().map(|byte| *byte as char).collect()
+}
+
+/// A 28 bit size stored in the low 7 bits of each of 4 bytes.
+fn syncsafe(data: &[u8]) -> Option<usize> {
+    if data.len() != 4 || data.iter().any(|byte| byte & 0x80 != 0) {
+        return None;
+    }
+
+    Some(
+        data.iter()
+            .fold(0, |size, byte| size << 7 | (*byte as usize)),
+    )
+}
+
+/// Undoes unsynchronisation, which puts a zero byte after every `0xFF`.
+fn resynchronise(data: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(data.len());
+    let mut previous = 0;
+
+    for byte in data {
+        if !(previous == 0xFF && *byte == 0) {
+            bytes.push(*byte);
+        }
+        previous = *byte;
+    }
+
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn syncsafe_bytes(size: usize) -> [u8; 4] {
+        [21, 14, 7, 0].map(|shift| (size >> shift & 0x7F) as u8)
+    }
+
+    fn tag(version: u8, flags: u8, body: &[u8]) -> Vec<u8> {
+        let mut tag = vec![b'I', b'D', b'3', version, 0, flags];
+        tag.extend(syncsafe_bytes(body.len()));
+        tag.extend_from_slice(body);
+        tag
+    }
+
+    fn frame(version: u8, id: &str, format: u8, data: &[u8]) -> Vec<u8> {
+        let mut frame = id.as_bytes().to_vec();
+        match version {
+            3 => frame.extend((data.len() as u32).to_be_bytes()),
+            _ => frame.extend(syncsafe_bytes(data.len())),
+        }
+        frame.extend([0, format]);
+        frame.extend_from_slice(data);
+        frame
+    }
+
+    fn title(value: &str) -> Id3Frame {
+        Id3Frame::Text {
+            id: "TIT2".into(),
+            value: value.into(),
+        }
+    }
+
+    #[test]
+    fn reads_syncsafe_sizes() {
+        assert_eq!(syncsafe(&[0, 0, 1, 0x7F]), Some(255));
+        assert_eq!(syncsafe(&[0x7F; 4]), Some((1 << 28) - 1));
+        assert_eq!(syncsafe(&[0, 0, 0x80, 0]), None);
+        assert_eq!(syncsafe(&[0, 0, 1]), None);
+    }
+
+    #[test]
+    fn parses_frames_with_syncsafe_sizes() {
+        // 200 bytes need the second size byte in version 4, where the size is syncsafe.
+        let value = "a".repeat(199);
+        let data = [&[3], value.as_bytes()].concat();
+
+        for version in [3, 4] {
+            let frames = parse_id3(&tag(version, 0, &frame(version, "TIT2", 0, &data)));
+            assert_eq!(frames, [title(&value)], "version {version}");
+        }
+    }
+
+    #[test]
+    fn skips_extended_headers() {
+        let frames = frame(3, "TIT2", 0, b"\0Song");
+        // Version 3 counts the 4 size bytes out of the extended header size.
+        let extended = [0, 0, 0, 6, 0, 0, 0, 0, 0, 0];
+        let body = [&extended[..], &frames].concat();
+        assert_eq!(parse_id3(&tag(3, 0x40, &body)), [title("Song")]);
+
+        let frames = frame(4, "TIT2", 0, b"\0Song");
+        // Version 4 counts them in, and stores the size syncsafe.
+        let extended = [0, 0, 0, 6, 1, 0];
+        let body = [&extended[..], &frames].concat();
+        assert_eq!(parse_id3(&tag(4, 0x40, &body)), [title("Song")]);
+
+        // An extended header larger than the tag.
+        let body = [&[0, 0, 1, 0][..], &frames].concat();
+        assert!(parse_id3(&tag(4, 0x40, &body)).is_empty());
+    }
+
+    #[test]
+    fn ignores_truncated_tags() {
+        let tag = tag(4, 0, &frame(4, "TIT2", 0, b"\0Song"));
+
+        assert!(parse_id3(&tag[..tag.len() - 1]).is_empty());
+        assert!(parse_id3(&tag[..HEADER_SIZE - 1]).is_empty());
+        assert!(parse_id3(b"ID2\x04\0\0\0\0\0\0").is_empty());
+    }
+
+    #[test]
+    fn stops_at_truncated_frames() {
+        let mut body = frame(4, "TIT2", 0, b"\0Song");
+        let mut truncated = frame(4, "TPE1", 0, b"\0Artist");
+        // Claim more data than the tag holds.
+        truncated[7] = 0x7F;
+        body.extend(truncated);
+
+        assert_eq!(parse_id3(&tag(4, 0, &body)), [title("Song")]);
+
+        // A version 3 size past the end of memory.
+        let mut body = frame(3, "TIT2", 0, b"\0Song");
+        body[4..8].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(parse_id3(&tag(3, 0, &body)).is_empty());
+    }
+
+    #[test]
+    fn stops_at_padding() {
+        let mut body = frame(4, "TIT2", 0, b"\0Song");
+        body.extend([0; 32]);
+
+        assert_eq!(parse_id3(&tag(4, 0, &body)), [title("Song")]);
+    }
+
+    #[test]
+    fn resynchronises_frames() {
+        // Version 4 marks unsynchronised frames, version 3 the whole tag.
+        let frame_v4 = frame(4, "PRIV", 0x02, b"owner\0\xFF\0\xE0");
+        let private = Id3Frame::Private {
+            owner: "owner".into(),
+            data: vec![0xFF, 0xE0],
+        };
+        assert_eq!(
+            parse_id3(&tag(4, 0, &frame_v4)),
+            std::slice::from_ref(&private)
+        );
+
+        // The frame size counts the resynchronised data.
+        let frame_v3 = frame(3, "PRIV", 0, b"owner\0\xFF\xE0");
+        let unsynchronised = [&frame_v3[..16], &[0xFF, 0, 0xE0]].concat();
+        assert_eq!(parse_id3(&tag(3, 0x80, &unsynchronised)), [private]);
+    }
+
+    #[test]
+    fn decodes_text_encodings() {
+        let utf16 = [1, 0xFF, 0xFE, b'H', 0, b'i', 0];
+        let utf16_be = [2, 0, b'H', 0, b'i'];
+        for data in [&b"\0Hi"[..], &utf16, &utf16_be, b"\x03Hi"] {
+            assert_eq!(parse_frame("TIT2".into(), data), title("Hi"));
+        }
+
+        assert_eq!(
+            parse_frame("TPE1".into(), b"\x03A\0B"),
+            Id3Frame::Text {
+                id: "TPE1".into(),
+                value: "A/B".into(),
+            }
+        );
+        assert_eq!(
+            parse_frame("TIT2".into(), b"\x09Hi"),
+            Id3Frame::Other {
+                id: "TIT2".into(),
+                data: b"\x09Hi".to_vec(),
+            }
+        );
+    }
+}
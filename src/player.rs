@@ -1,32 +1,336 @@
+use crate::abr::lowest_rendition;
+use crate::abr::AbrContext;
+use crate::abr::AbrController;
+use crate::abr::DroppedFramesRule;
+use crate::abr::QualitySelection;
+use crate::abr::SharedEstimator;
+use crate::abr::BANDWIDTH_SAFETY_FACTOR;
+use crate::ads::AdBreak;
+use crate::ads::AdCue;
+use crate::ads::AdPosition;
+use crate::ads::AdScheduler;
+use crate::buffer::init_segment_url;
 use crate::buffer::TrackBufferManager;
+use crate::cache::CachingLoader;
+use crate::cache::SegmentCache;
+use crate::capabilities::DecodingInfo;
+use crate::cdn::CdnSelector;
+use crate::cdn::CdnSwitchReason;
+use crate::config::BackgroundPolicy;
+use crate::config::PlayerConfig;
+use crate::config::PreloadPolicy;
+use crate::connection::ConnectionInfo;
+use crate::diagnostics::AbrDecision;
+use crate::diagnostics::Diagnostics;
+use crate::diagnostics::DiagnosticsLog;
+use crate::diagnostics::ManifestSummary;
+use crate::diagnostics::TrackDiagnostics;
+use crate::download::CacheStorageLoader;
+use crate::drm::DrmManager;
+use crate::drm::DrmTask;
+use crate::drm::KeyStatus;
+use crate::drm::LicenseMessageType;
+use crate::error::AshinaError;
+use crate::event::PlaybackState;
+use crate::event::PlayerEvent;
+use crate::event::RecoveryAction;
+use crate::event::SwitchReason;
+use crate::hotkeys::HotkeyAction;
+use crate::hotkeys::HotkeyTarget;
+use crate::latency::LatencyAction;
+use crate::latency::LatencyController;
+use crate::loader::LoadError;
+use crate::loader::Loader;
+use crate::loader::ManifestDecryptor;
+use crate::loader::MeteredLoader;
+use crate::loader::RequestKind;
+use crate::loader::RequestLimits;
+use crate::loader::RequestPriority;
+use crate::loader::SchedulingLoader;
+use crate::loader::SegmentRequest;
+use crate::loader::SigningLoader;
+use crate::log::log;
+use crate::log::LogRecord;
+use crate::log::Logger;
+use crate::manifest::language_match;
 use crate::manifest::Manifest;
+use crate::manifest::ManifestInfo;
+use crate::manifest::RepresentationId;
 use crate::manifest::Track;
+use crate::manifest::TrackId;
+use crate::manifest::TrackInfo;
+use crate::manifest::XlinkActuate;
+use crate::messages::ErrorCode;
+use crate::parse::cea::CaptionDecoder;
+use crate::parse::id3::parse_id3;
+use crate::parse::yield_to_browser;
+use crate::range::NRangeInclusive;
+use crate::stats::PlaybackStats;
+use crate::stats::QualityHistogram;
+use crate::task::TaskGroup;
+use crate::task::TaskId;
+use crate::task::TaskKind;
+use crate::text::apply_text_offset;
+use crate::text::select_text_track;
+use crate::text::CaptionTracks;
+use crate::thumbnail::ThumbnailTrack;
 use crate::PlayerState;
+use crate::QueryKind;
+use crate::QueryResult;
 
 use wasm_bindgen::closure::Closure;
 use wasm_bindgen::JsCast;
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::JsFuture;
 
+use web_sys::Event;
+use web_sys::EventTarget;
 use web_sys::HtmlVideoElement;
 
+use futures::channel::mpsc;
 use futures::channel::mpsc::Receiver;
 use futures::future::FutureExt;
-use futures::stream::FuturesUnordered;
 use futures::StreamExt;
 
 use gloo_timers::future::TimeoutFuture;
 
-use core::future::Future;
-use core::pin::Pin;
+use core::fmt;
 use core::time::Duration;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 
+use bytes::Bytes;
 use displaydoc::Display;
 use thiserror::Error;
 
-pub type BoxError = Box<dyn std::error::Error>;
-pub type ScheduledEvent = Pin<Box<dyn Future<Output = InternalEvent>>>;
+/// How long before a live manifest expires we start fetching its replacement.
+const MANIFEST_PREFETCH_LEAD: Duration = Duration::from_millis(500);
+/// How often the stall watchdog looks at the playhead.
+const STALL_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+/// How often live latency is measured and corrected.
+const LATENCY_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+/// Fraction of the segment duration waited between fetches once the rebuffer goal is met.
+const FETCH_PACING: f64 = 0.1;
+const MIN_FETCH_INTERVAL: Duration = Duration::from_millis(50);
+const MAX_FETCH_INTERVAL: Duration = Duration::from_secs(2);
+/// Fraction of the segment duration waited before fetching or appending again after a failure.
+const RETRY_PACING: f64 = 0.25;
+const MIN_RETRY_INTERVAL: Duration = Duration::from_millis(250);
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(4);
+/// Longest wait before retrying a segment fetch that keeps failing. The wait doubles with every
+/// failure up to here.
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+/// How close to the end of a VOD presentation a stopped playhead has to be to count as ended.
+/// Until the media source is ended, playback halts at the end of the shortest track instead,
+/// which can be a little before the duration.
+const END_TOLERANCE: f64 = 0.5;
+/// Media segments past the one being fetched whose URLs are signed ahead of time.
+const PRESIGN_AHEAD: usize = 2;
+/// Buffered time, in seconds, below which a range that disappeared isn't reported as evicted.
+const MIN_EVICTED_RANGE: f64 = 0.01;
+/// How far into the next buffered range a gap jump lands, so rounding doesn't leave the playhead
+/// just short of it.
+const GAP_JUMP_EPSILON: f64 = 0.01;
+
+/// Seeks within [`SCRUB_WINDOW`] that count as scrubbing.
+const SCRUB_SEEKS: usize = 3;
+const SCRUB_WINDOW: Duration = Duration::from_secs(1);
+/// Time without seeks after which scrubbing is over.
+const SCRUB_IDLE: Duration = Duration::from_millis(750);
+/// Seeks closer together than this are buffered for as one, at the last position.
+const SEEK_DEBOUNCE: Duration = Duration::from_millis(150);
+/// Media errors recovered from per load before the player gives up and reports them.
+const MAX_RECOVERY_ATTEMPTS: u32 = 3;
+/// Wait before appending again to a source buffer that was busy.
+const RECOVERY_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Attribute marking the video element a player is attached to, holding the [`PlayerId`].
+const PLAYER_ATTRIBUTE: &str = "data-ashina-player";
+/// How often the video element is looked for while it isn't mounted yet.
+const ELEMENT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+static NEXT_PLAYER_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Distinguishes the players on a page, e.g. in logs and on the video elements they claim.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PlayerId(u64);
+
+impl PlayerId {
+    fn next() -> Self {
+        Self(NEXT_PLAYER_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl fmt::Display for PlayerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Detects rapid seeking, during which video comes from a trick mode rendition.
+#[derive(Default)]
+struct Scrub {
+    /// JS timestamps of the seeks within [`SCRUB_WINDOW`].
+    seeks: Vec<f64>,
+    /// The regular rendition to return to, while scrubbing.
+    main: Option<Track>,
+}
+
+/// Holds playback back until every track has media at the start position.
+#[derive(Default)]
+struct Readiness {
+    /// Whether [`PlayerEvent::PlaybackReady`] was sent since attaching.
+    ready: bool,
+    /// Whether the element's `autoplay` attribute was removed until then.
+    autoplay_held: bool,
+}
+
+/// An ad break playing in the ad element.
+struct ActiveAd {
+    id: String,
+    position: AdPosition,
+    /// The player of the ad element.
+    player: mpsc::Sender<PlayerState>,
+    /// Whether the main content plays once the break is over.
+    resume_playing: bool,
+}
+
+/// Where the playhead was last seen moving.
+#[derive(Default)]
+struct StallWatch {
+    position: Option<f64>,
+    /// When the playhead was first seen at `position`, as a JS timestamp in milliseconds.
+    since: f64,
+    /// Whether [`PlayerEvent::BufferStalled`] was sent for the current stall.
+    reported: bool,
+}
+
+/// Identifies a published [`PlayerEvent::TimedMetadata`]. Events without an id are told apart
+/// by their timing, in milliseconds, instead.
+#[derive(PartialEq, Eq, Hash)]
+struct EventKey {
+    scheme: String,
+    value: Option<String>,
+    id: Option<String>,
+    timing: Option<(i64, Option<i64>)>,
+}
+
+struct Listener {
+    target: EventTarget,
+    event: &'static str,
+    callback: Closure<dyn FnMut(Event)>,
+}
+
+/// Owns the closures of DOM event listeners, so they aren't leaked with `Closure::forget`, and
+/// removes the listeners again. Without this, every load would add another set of listeners to
+/// the same video element.
+#[derive(Default)]
+pub struct EventListenerRegistry {
+    listeners: Vec<Listener>,
+}
+
+impl EventListenerRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Listens for `event` on `target` until [`Self::remove_all`] or until the registry is
+    /// dropped.
+    pub fn add(
+        &mut self,
+        target: &EventTarget,
+        event: &'static str,
+        callback: impl Fn() + 'static,
+    ) {
+        self.add_with_event(target, event, move |_| callback());
+    }
+
+    /// Like [`Self::add`], for callbacks that need the event itself.
+    pub fn add_with_event(
+        &mut self,
+        target: &EventTarget,
+        event: &'static str,
+        callback: impl Fn(Event) + 'static,
+    ) {
+        let callback: Closure<dyn FnMut(Event)> = Closure::new(Box::new(callback));
+
+        if let Err(error) =
+            target.add_event_listener_with_callback(event, callback.as_ref().unchecked_ref())
+        {
+            tracing::error!(?error, event, "Failed to add event listener.");
+            return;
+        }
+
+        self.listeners.push(Listener {
+            target: target.clone(),
+            event,
+            callback,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.listeners.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.listeners.is_empty()
+    }
+
+    pub fn remove_all(&mut self) {
+        for listener in self.listeners.drain(..) {
+            let _ = listener.target.remove_event_listener_with_callback(
+                listener.event,
+                listener.callback.as_ref().unchecked_ref(),
+            );
+        }
+    }
+}
+
+impl Drop for EventListenerRegistry {
+    fn drop(&mut self) {
+        self.remove_all();
+    }
+}
 
 pub struct Player {
+    id: PlayerId,
+    config: PlayerConfig,
+    /// The configured loader with the request decorator applied, metered for bandwidth estimation
+    /// and behind the segment cache.
+    loader: Rc<dyn Loader>,
+    /// Recently fetched segments, served by `loader` unless disabled.
+    segment_cache: SegmentCache,
+    /// Signs segment URLs ahead of time, when a URL signer is configured.
+    signing: Option<Rc<SigningLoader>>,
+    /// Throughput estimate fed by every segment download.
+    estimator: SharedEstimator,
+    /// Estimate a previous session stored for the manifest's origin, used until the estimator
+    /// has one of its own. See [`crate::config::AbrConfig::persist_bandwidth`].
+    stored_estimate: Option<f64>,
+    /// How the video rendition is picked.
+    quality: QualitySelection,
+    /// Rules picking the rendition with [`QualitySelection::Auto`].
+    abr: AbrController,
+    /// When the current manifest started loading, as a JS timestamp in milliseconds. Cleared once
+    /// the first frame is in.
+    load_started: Option<f64>,
+    /// Milliseconds from loading the manifest to the first frame.
+    time_to_first_frame: Option<f64>,
+    /// Video segments still to be fetched at the lowest rendition before quality selection
+    /// starts, see [`crate::config::AbrConfig::fast_start_segments`].
+    fast_start_remaining: usize,
+    /// Subtitle delay in seconds, kept across loads.
+    text_offset: f64,
+    /// Audio delay in seconds, kept across loads.
+    audio_offset: f64,
+    /// Which of the manifest's `BaseURL`s segments are fetched from.
+    cdn: CdnSelector,
     video_id: Option<String>,
     manifest_url: Option<String>,
     manifest: Option<Manifest>,
@@ -38,75 +342,367 @@ pub struct Player {
 
     video_element: Option<HtmlVideoElement>,
     media_source: web_sys::MediaSource,
+    /// Object URL of `media_source` set as the video element's `src`, revoked on detach.
+    object_url: Option<String>,
+    /// Listeners on the video element and media source, removed on detach.
+    listeners: EventListenerRegistry,
 
-    scheduled_events: FuturesUnordered<ScheduledEvent>,
-    active_tracks: HashMap<usize, TrackBufferManager>,
-    result_tx: Option<futures::channel::oneshot::Sender<Result<(), Box<dyn std::error::Error>>>>,
+    /// Child tasks: manifest loads and refreshes, fetches, DRM work and timers. Cancelled on
+    /// detach, so no background work outlives the media it was started for.
+    tasks: TaskGroup,
+    active_tracks: HashMap<TrackId, TrackBufferManager>,
+    result_tx: Option<futures::channel::oneshot::Sender<Result<ManifestInfo, AshinaError>>>,
+    /// Time played at each rendition this session.
+    quality_histogram: QualityHistogram,
+    /// Playback position seen on the previous timeupdate, used to measure time played.
+    last_position: Option<f64>,
+    /// Stall watchdog state.
+    stall: StallWatch,
+    /// Volume and mute state to apply to the element, `None` if never set.
+    volume: Option<f64>,
+    muted: Option<bool>,
+    /// Adaptation set to play audio from, the first one if `None` or not in the manifest.
+    audio_track: Option<TrackId>,
+    /// Whether video is buffered, see [`PlayerConfig::audio_only`].
+    video_enabled: bool,
+    /// Position and playing state to restore after reloading the media.
+    resume: Option<(f64, bool)>,
+    /// Position the load in progress starts at, applied once the manifest shows it's VOD.
+    requested_start: Option<f64>,
+    /// Where buffering starts once the track buffers are set up, taken from `resume`.
+    start_position: Option<f64>,
+    /// Rapid seeking state.
+    scrub: Scrub,
+    /// Gates playback until every track is buffered at the start position.
+    readiness: Readiness,
+    /// Media errors recovered from since the manifest was loaded, see [`MAX_RECOVERY_ATTEMPTS`].
+    recovery_attempts: u32,
+    /// Whether a text track matching the configured preferences has been shown since attaching.
+    text_track_selected: bool,
+    /// Counts seeks, so only the last of a burst settles, see [`SEEK_DEBOUNCE`].
+    seek_generation: u64,
+    /// Whether buffering waits for the current burst of seeks to settle.
+    seek_pending: bool,
+    /// Task of the media segment fetch in flight per track, so it can be pre-empted by a seek.
+    segment_fetches: HashMap<TrackId, TaskId>,
+    /// Tracks that appended the last segment of a VOD presentation. Once all have, the media
+    /// source is ended.
+    tracks_at_end: HashSet<TrackId>,
+    /// What may be fetched before playback is requested. Raised to `Auto` on `play`.
+    preload: PreloadPolicy,
+    /// Whether the page is hidden, see [`crate::config::BufferConfig::background_policy`].
+    hidden: bool,
+    /// What `navigator.connection` reported last, see
+    /// [`crate::config::AbrConfig::network_information`].
+    connection: Option<ConnectionInfo>,
+    /// What `mediaCapabilities` reported per video representation of the current manifest,
+    /// see [`crate::config::AbrConfig::media_capabilities`].
+    decoding: HashMap<(TrackId, RepresentationId), DecodingInfo>,
+    /// Whether the video element is the fullscreen element.
+    fullscreen: bool,
+    /// Manifests to play after the current presentation, see [`PlayerState::Enqueue`].
+    queue: VecDeque<String>,
+    /// The front of `queue`, fetched and parsed ahead of time.
+    preloaded: Option<(String, Manifest)>,
+    /// The task preloading the front of `queue`.
+    preloading: Option<TaskId>,
+    /// Where playback stands. [`PlaybackState::Ended`] once [`PlayerEvent::Ended`] was sent
+    /// for the playhead's current stop at the end.
+    state: PlaybackState,
+    /// Ad breaks still to play in the current presentation.
+    ads: AdScheduler,
+    ad_break: Option<ActiveAd>,
+    /// Recent history for [`Self::diagnostics`], kept across loads.
+    diagnostics: DiagnosticsLog,
+    /// Whether the playhead still has to jump to the live edge once media is buffered there.
+    live_seek_pending: bool,
+    /// Keeps live playback at [`crate::config::LiveConfig::target_latency`], if configured.
+    latency: Option<LatencyController>,
+    /// Every [`PlayerEvent::TimedMetadata`] published since attaching and not behind the buffer
+    /// or live window yet, with the time it ends at.
+    seen_events: HashMap<EventKey, f64>,
+    /// Decodes the in-band captions of appended video segments.
+    captions: CaptionDecoder,
+    caption_tracks: CaptionTracks,
+    /// EME state for encrypted content.
+    drm: DrmManager,
+    /// Everyone listening for [`PlayerEvent`]s, dropped once their receiver goes away.
+    subscribers: Vec<flume::Sender<PlayerEvent>>,
+    /// Shared with every track buffer.
+    logger: Logger,
 }
 
 impl Player {
-    pub fn new() -> Self {
+    pub fn new(config: PlayerConfig) -> Self {
         let (sndr, rcvr) = flume::unbounded();
         let media_source = web_sys::MediaSource::new().unwrap();
+        let drm = DrmManager::new(config.drm.clone()).with_clearkey_keys(&config.drm.clearkey_keys);
+        let estimator: SharedEstimator = Rc::new(RefCell::new((config.abr.bandwidth_estimator)()));
+        let signing = config
+            .network
+            .url_signer
+            .clone()
+            .map(|signer| Rc::new(SigningLoader::new(config.network.network_loader(), signer)));
+        let unsigned: Rc<dyn Loader> = match &signing {
+            Some(signing) => signing.clone(),
+            None => config.network.network_loader(),
+        };
+        let metered: Rc<dyn Loader> = Rc::new(MeteredLoader::new(
+            config.network.decorated(unsigned),
+            estimator.clone(),
+        ));
+        // Outside the metering, so that waiting for a slot doesn't count as download time.
+        let scheduled: Rc<dyn Loader> = match config.network.request_limits {
+            RequestLimits::NONE => metered,
+            limits => Rc::new(SchedulingLoader::new(metered, limits)),
+        };
+        // Outside the metering, so that cache hits don't count as impossibly fast downloads, and
+        // outside the scheduling, so that they don't wait for a slot.
+        let segment_cache = SegmentCache::new(config.network.segment_cache_bytes);
+        let mut loader: Rc<dyn Loader> = match config.network.segment_cache_bytes {
+            0 => scheduled,
+            _ => Rc::new(CachingLoader::new(scheduled, segment_cache.clone())),
+        };
+        if let Some(cache_name) = &config.network.offline_cache {
+            loader = Rc::new(CacheStorageLoader::new(loader, cache_name.clone()));
+        }
+
+        let video_enabled = !config.audio_only;
+        let diagnostics = DiagnosticsLog::new(config.diagnostics_capacity);
+        let logger = Logger::new(config.log.clone(), sndr.clone());
+        let latency = config
+            .live
+            .target_latency
+            .map(|target| LatencyController::new(target.as_secs_f64()));
 
         Self {
+            id: PlayerId::next(),
+            loader,
+            segment_cache,
+            signing,
+            estimator,
+            stored_estimate: None,
+            quality: QualitySelection::default(),
+            abr: AbrController::new((config.abr.rules)()),
+            load_started: None,
+            time_to_first_frame: None,
+            fast_start_remaining: 0,
+            text_offset: 0.,
+            audio_offset: 0.,
+            cdn: CdnSelector::new(vec![]),
+            config,
             video_id: None,
             manifest_url: None,
             manifest: None,
-            scheduled_events: FuturesUnordered::new(),
+            tasks: TaskGroup::new(),
             video_element: None,
             active_tracks: HashMap::new(),
             sndr,
             rcvr,
             media_source,
+            object_url: None,
+            listeners: EventListenerRegistry::new(),
             result_tx: None,
+            quality_histogram: QualityHistogram::default(),
+            last_position: None,
+            stall: StallWatch::default(),
+            live_seek_pending: false,
+            preload: PreloadPolicy::Auto,
+            hidden: false,
+            connection: None,
+            decoding: HashMap::new(),
+            fullscreen: false,
+            queue: VecDeque::new(),
+            preloaded: None,
+            preloading: None,
+            state: PlaybackState::Idle,
+            ads: AdScheduler::default(),
+            ad_break: None,
+            diagnostics,
+            scrub: Scrub::default(),
+            readiness: Readiness::default(),
+            recovery_attempts: 0,
+            text_track_selected: false,
+            seek_generation: 0,
+            seek_pending: false,
+            segment_fetches: HashMap::new(),
+            tracks_at_end: HashSet::new(),
+            volume: None,
+            muted: None,
+            audio_track: None,
+            video_enabled,
+            resume: None,
+            requested_start: None,
+            start_position: None,
+            latency,
+            seen_events: HashMap::new(),
+            captions: CaptionDecoder::default(),
+            caption_tracks: CaptionTracks::default(),
+            drm,
+            subscribers: vec![],
+            logger,
         }
     }
 
-    pub async fn listen(&mut self, mut cx: Receiver<PlayerState>) -> Result<(), BoxError> {
+    pub fn id(&self) -> PlayerId {
+        self.id
+    }
+
+    pub async fn listen(&mut self, mut cx: Receiver<PlayerState>) -> Result<(), AshinaError> {
         loop {
             futures::select_biased! {
                 event = cx.next() => {
                     let Some(event) = event else {
-                        tracing::info!("Breaking because events dropped.");
+                        // The `MediaPlayer` is gone, nobody can issue `Cleanup` anymore.
+                        log!(self.logger, Player, Info, "Breaking because events dropped.");
+                        self.cancel_pending_create();
+                        self.detach();
                         break;
                     };
 
                     match event {
-                        PlayerState::Created { manifest, id, tx } => {
+                        PlayerState::Created {
+                            manifest,
+                            id,
+                            start,
+                            tx,
+                        } => {
+                            self.cancel_pending_create();
                             self.detach();
                             self.manifest_url = Some(manifest);
                             self.video_id = Some(id);
                             self.result_tx = tx;
+                            self.recovery_attempts = 0;
+                            self.requested_start = start.or(self.config.start_position);
+                            self.reset_ads();
+
+                            self.load_manifest();
+                        }
+                        PlayerState::Load { manifest, tx } => {
+                            if self.video_id.is_none() {
+                                if let Some(tx) = tx {
+                                    let _ = tx.send(Err(AshinaError::Internal(
+                                        AshinaError::message("load called before create"),
+                                    )));
+                                }
+                                continue;
+                            }
+
+                            self.cancel_pending_create();
+                            self.detach();
+                            self.manifest_url = Some(manifest);
+                            self.result_tx = tx;
+                            self.recovery_attempts = 0;
+                            self.requested_start = self.config.start_position;
+                            self.reset_ads();
+
+                            self.load_manifest();
+                        }
+                        PlayerState::SetTextOffset(ms) => {
+                            self.text_offset = ms as f64 / 1000.;
+                            self.apply_text_offset();
+                        }
+                        PlayerState::SetAudioOffset(ms) => {
+                            self.audio_offset = ms as f64 / 1000.;
+                            self.apply_audio_offset().await;
+                        }
+                        PlayerState::SetVolume(volume) => {
+                            self.volume = Some(volume.clamp(0., 1.));
+                            self.apply_volume();
+                        }
+                        PlayerState::SetMuted(muted) => {
+                            self.muted = Some(muted);
+                            self.apply_volume();
+                        }
+                        PlayerState::SetVideoEnabled(enabled) => {
+                            self.set_video_enabled(enabled);
+                        }
+                        PlayerState::SetPictureInPicture(active) => {
+                            self.set_picture_in_picture(active);
+                        }
+                        PlayerState::SetFullscreen(active) => {
+                            self.set_fullscreen(active);
+                        }
+                        PlayerState::SetAudioTrack(track) => {
+                            self.set_audio_track(track);
+                        }
+                        PlayerState::SetLogConfig(config) => {
+                            self.logger.set_config(config);
+                        }
+                        PlayerState::SetQuality(selection) => {
+                            self.quality = selection;
 
-                            if let Err(e) = self.load_manifest().await {
-                                tracing::error!(error = ?e, "Load manifest failed.");
-                                if let Some(tx) = self.result_tx.take() { let _ = tx.send(Err(e)); }
-                            } else if let Err(e) = self.attach().await {
-                                tracing::error!(error = ?e, "Attach failed.");
-                                if let Some(tx) = self.result_tx.take() { let _ = tx.send(Err(e)); }
-                            } else {
-                                // Success
-                                if let Some(tx) = self.result_tx.take() { let _ = tx.send(Ok(())); }
+                            // Tracks with a fetch in flight pick this up at their next boundary.
+                            let idle: Vec<_> = self
+                                .active_tracks
+                                .iter()
+                                .filter(|(_, manager)| !manager.is_fetching())
+                                .map(|(track, _)| track.clone())
+                                .collect();
+
+                            for track in idle {
+                                self.apply_quality_selection(&track, SwitchReason::Selection);
+                            }
+                        }
+                        PlayerState::Enqueue(manifest) => {
+                            log!(self.logger, Player, Info, "Queued {manifest}.");
+                            self.queue.push_back(manifest);
+                            self.preload_next();
+                        }
+                        PlayerState::Next => self.advance_queue().await,
+                        PlayerState::ScheduleAdBreak(ad_break) => {
+                            log!(
+                                self.logger,
+                                Player,
+                                Info,
+                                "Scheduled ad break {} at {:?}.",
+                                ad_break.id,
+                                ad_break.position
+                            );
+                            self.ads.schedule(ad_break);
+                        }
+                        PlayerState::ClearQueue => {
+                            self.queue.clear();
+                            self.preloaded = None;
+                            if let Some(task) = self.preloading.take() {
+                                self.tasks.cancel_task(task);
+                            }
+                        }
+                        PlayerState::Cancel => {
+                            if self.cancel_pending_create() {
+                                self.detach();
                             }
                         }
-                        PlayerState::Cleanup => {
+                        PlayerState::Subscribe { tx } => {
+                            self.subscribers.push(tx);
+                        }
+                        PlayerState::Query { kind, reply } => {
+                            let _ = reply.send(self.query(kind));
+                        }
+                        PlayerState::Cleanup { tx } => {
+                            self.cancel_pending_create();
+                            self.detach();
+
+                            if let Some(tx) = tx {
+                                let _ = tx.send(());
+                            }
+
                             break;
                         }
                     }
                 }
                 event = self.rcvr.recv_async() => {
                     let Ok(event) = event else {
-                        tracing::info!("Breaking because internal_events dropped.");
+                        log!(self.logger, Player, Info, "Breaking because internal_events dropped.");
                         break;
                     };
 
-                    self.process_internal_event(event).await?;
+                    self.supervise(event).await;
                 }
                 // FIXME: FutUnord when polled empty might return None, which
-                event = self.scheduled_events.next() => {
+                event = self.tasks.next() => {
                     if let Some(event) = event {
-                        self.process_internal_event(event).await?;
+                        self.supervise(event).await;
                     }
                 }
             }
@@ -115,247 +711,2785 @@ impl Player {
         Ok(())
     }
 
-    pub async fn process_internal_event(&mut self, event: InternalEvent) -> Result<(), BoxError> {
+    /// Handles an event without letting a failure take down the event loop: a failed fetch or
+    /// append is logged and the player keeps serving commands, including `Cleanup`.
+    async fn supervise(&mut self, event: InternalEvent) {
+        if let Err(error) = self.process_internal_event(event).await {
+            log!(
+                self.logger,
+                Player,
+                Error,
+                "Failed to process internal event: {error:?}."
+            );
+            self.emit(PlayerEvent::Error(error));
+        }
+    }
+
+    pub async fn process_internal_event(
+        &mut self,
+        event: InternalEvent,
+    ) -> Result<(), AshinaError> {
         match event {
             InternalEvent::SourceOpen => self.on_source_open().await?,
             InternalEvent::Seeking => self.on_seeking().await?,
+            InternalEvent::Play => self.on_play().await,
+            InternalEvent::PlaybackChanged => self.follow_media(),
+            InternalEvent::VisibilityChanged => self.on_visibility_changed(),
+            InternalEvent::ConnectionChanged => self.on_connection_changed(),
+            InternalEvent::PictureInPictureChanged(active) => {
+                log!(self.logger, Player, Info, "Picture-in-picture: {active}.");
+                self.emit(PlayerEvent::PictureInPictureChanged { active });
+            }
+            InternalEvent::FullscreenChanged => self.on_fullscreen_changed(),
+            InternalEvent::Ended => self.on_ended().await,
+            InternalEvent::AdBreakFinished { id, error } => {
+                self.on_ad_break_finished(id, error).await
+            }
+            InternalEvent::Preloaded {
+                manifest_url,
+                manifest,
+            } => self.on_preloaded(manifest_url, manifest),
+            InternalEvent::Hotkey(action) => self.on_hotkey(action),
+            InternalEvent::FirstFrame => {
+                if let Some(started) = self.load_started.take() {
+                    self.time_to_first_frame = Some(js_sys::Date::now() - started);
+                }
+            }
+            InternalEvent::Log(record) => self.emit(PlayerEvent::Log(record)),
+            InternalEvent::MediaError => self.on_media_error().await,
+            InternalEvent::SeekStarted => {
+                self.reset_captions();
+                self.on_seek_started();
+                self.debounce_seek().await?;
+            }
+            InternalEvent::SeekSettled(generation) => self.on_seek_settled(generation).await?,
+            InternalEvent::ScrubCheck => self.on_scrub_check().await,
+            InternalEvent::Heartbeat => self.on_heartbeat(),
+            InternalEvent::StallCheck => self.on_stall_check(),
+            InternalEvent::LatencyCheck => self.on_latency_check(),
+            InternalEvent::TextTracksChanged => {
+                self.select_preferred_text_track();
+                self.apply_text_offset();
+            }
+            InternalEvent::ProbeCdns => self.probe_cdns(),
+            InternalEvent::CdnProbed { index, latency_ms } => self.on_cdn_probed(index, latency_ms),
+            InternalEvent::BufferUpdated { track } => self.on_buffer_updated(track),
+            InternalEvent::ManifestLoaded(manifest) => self.on_manifest_loaded(manifest).await,
+            InternalEvent::VideoElementFound(video_element) => {
+                self.on_video_element_found(video_element).await
+            }
+            InternalEvent::ManifestRefreshed(manifest) => self.on_manifest_refreshed(manifest),
+            InternalEvent::Encrypted {
+                init_data_type,
+                init_data,
+            } => {
+                let task = self
+                    .drm
+                    .on_encrypted(init_data_type, init_data, self.sndr.clone())?;
+                self.spawn_drm_task(task);
+            }
+            InternalEvent::LicenseMessage {
+                session,
+                message,
+                message_type,
+            } => self.request_license(session, message, message_type),
+            InternalEvent::KeyStatusesChanged { session } => self.on_key_statuses_changed(session),
+            InternalEvent::LicenseLoaded { session, license } => match license {
+                Ok(license) => {
+                    let task = self.drm.on_license_response(session, license)?;
+                    self.spawn_drm_task(task);
+                }
+                Err(error) => log!(
+                    self.logger,
+                    Drm,
+                    Error,
+                    "License request {session} failed: {error:?}."
+                ),
+            },
             InternalEvent::TryLoadSegment {
                 track,
                 next_segment,
-            } => self.try_load_segment(track, next_segment).await?,
+            } => self.try_load_segment(track, next_segment),
+            InternalEvent::InitSegmentLoaded { track, data } => {
+                self.on_init_segment_loaded(track, data).await?
+            }
+            InternalEvent::SegmentLoaded {
+                track,
+                next_segment,
+                data,
+            } => self.on_segment_loaded(track, next_segment, data).await?,
+            InternalEvent::SegmentStreamed {
+                track,
+                next_segment,
+                started,
+                bytes,
+            } => self.on_segment_streamed(track, next_segment, started, bytes),
         }
 
         Ok(())
     }
 
-    async fn load_manifest(&mut self) -> Result<(), BoxError> {
-        let manifest_url = self.manifest_url.as_ref().unwrap();
-
-        tracing::info!(manifest_url, "Loading manifest...");
+    /// Starts fetching and parsing the manifest in the background. The event loop keeps running
+    /// meanwhile, so a newer [`PlayerState::Created`] or a [`PlayerState::Cancel`] can drop the
+    /// fetch before it completes.
+    fn load_manifest(&mut self) {
+        let manifest_url = self.manifest_url().to_string();
+        self.load_started = Some(js_sys::Date::now());
+        self.time_to_first_frame = None;
+        self.set_state(PlaybackState::Loading);
 
-        let xml = reqwest::get(manifest_url).await?.text().await?;
-
-        self.manifest = Some(xml.parse()?);
+        log!(
+            self.logger,
+            Manifest,
+            Info,
+            "Loading manifest {manifest_url}."
+        );
 
-        tracing::info!("Manifest parsed...");
+        let request = SegmentRequest::new(manifest_url, RequestKind::Manifest);
+        let loader = self.loader.clone();
+        let decryptor = self.config.network.manifest_decryptor.clone();
 
-        Ok(())
+        self.tasks.spawn_event(TaskKind::Manifest, async move {
+            InternalEvent::ManifestLoaded(
+                fetch_manifest(loader, request, decryptor)
+                    .await
+                    .map(Box::new),
+            )
+        });
     }
 
-    async fn attach(&mut self) -> Result<(), BoxError> {
-        tracing::info!("Attaching to player");
+    async fn on_manifest_loaded(&mut self, manifest: Result<Box<Manifest>, AshinaError>) {
+        let manifest = match manifest {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                log!(self.logger, Manifest, Error, "Load manifest failed: {e:?}.");
+                self.set_state(PlaybackState::Errored);
+                self.emit(PlayerEvent::Error(e.clone()));
+                self.resolve_create(Err(e));
+                return;
+            }
+        };
 
-        let video_element = web_sys::window()
-            .unwrap()
-            .document()
-            .unwrap()
-            .get_element_by_id(self.video_id())
-            .unwrap()
-            .dyn_into::<web_sys::HtmlVideoElement>()
-            .unwrap();
+        log!(self.logger, Manifest, Info, "Manifest parsed.");
 
-        self.video_element = Some(video_element.clone());
+        let start = self.requested_start.take();
+        if self.resume.is_none() && !manifest.is_live() {
+            self.resume = start.map(|position| (position, false));
+        }
 
-        // TODO: Add event handler for current time update.
-        let sndr = self.sndr.clone();
+        self.manifest = Some(*manifest);
+        self.stored_estimate = self
+            .bandwidth_origin()
+            .and_then(|origin| crate::abr::stored_estimate(&origin));
+        self.cdn = CdnSelector::new(self.base_urls());
+        self.schedule_manifest_refresh();
+        self.emit_manifest_events();
+        self.query_capabilities().await;
 
-        self.add_event_listener("seeking", move || {
-            let _ = sndr.send(InternalEvent::Seeking);
+        // The element may be mounted after `create`, wait for it without holding off commands.
+        let id = self.video_id().to_string();
+        let timeout = self.config.element_timeout;
+        self.tasks.spawn_event(TaskKind::Manifest, async move {
+            InternalEvent::VideoElementFound(find_video_element(&id, timeout).await)
         });
+    }
 
-        let sndr = self.sndr.clone();
+    /// Attaches to the video element looked up once the manifest loaded and resolves `create`.
+    async fn on_video_element_found(&mut self, video_element: Option<HtmlVideoElement>) {
+        if let Err(e) = self.attach(video_element).await {
+            log!(self.logger, Player, Error, "Attach failed: {e:?}.");
+            self.set_state(PlaybackState::Errored);
+            self.emit(PlayerEvent::Error(e.clone()));
+            self.resolve_create(Err(e));
+            return;
+        }
 
-        self.add_event_listener("timeupdate", move || {
-            let _ = sndr.send(InternalEvent::Seeking);
-        });
+        self.report_incomplete_tracks();
 
-        let sndr = self.sndr.clone();
+        let mut info = self.manifest.as_ref().unwrap().info();
+        info.set_tracks(self.track_infos());
+        info.set_thumbnails(self.thumbnails());
+        self.resolve_create(Ok(info));
 
-        let event_listener = Closure::once(Box::new(move || {
-            tracing::info!("Sending SourceOpen");
+        if let Some(ad_break) = self.ads.pre_roll() {
+            let video = self.video();
+            let playing = !video.paused() || video.autoplay() || self.readiness.autoplay_held;
+            self.start_ad_break(ad_break, playing);
+        }
 
-            let _ = sndr.send(InternalEvent::SourceOpen);
-        }));
+        self.preload_next();
+    }
 
-        self.media_source
-            .add_event_listener_with_callback(
-                "sourceopen",
-                &event_listener.as_ref().unchecked_ref(),
-            )
-            .unwrap();
+    /// Starts fetching the manifest at the front of the queue in the background, unless it is
+    /// already preloaded or nothing is playing yet. Preloading waits for the current manifest so
+    /// that it doesn't compete with startup.
+    fn preload_next(&mut self) {
+        if self.preloading.is_some() || self.manifest.is_none() {
+            return;
+        }
+
+        let Some(manifest_url) = self.queue.front().cloned() else {
+            return;
+        };
+        if self
+            .preloaded
+            .as_ref()
+            .is_some_and(|(preloaded, _)| *preloaded == manifest_url)
+        {
+            return;
+        }
 
-        event_listener.forget();
+        log!(
+            self.logger,
+            Manifest,
+            Info,
+            "Preloading queued manifest {manifest_url}."
+        );
 
-        let url = web_sys::Url::create_object_url_with_source(&self.media_source).unwrap();
-        video_element.set_src(&url);
+        let request = SegmentRequest::new(manifest_url.clone(), RequestKind::Manifest)
+            .with_priority(RequestPriority::Prefetch);
+        let loader = self.loader.clone();
+        let decryptor = self.config.network.manifest_decryptor.clone();
 
-        Ok(())
+        let task = self.tasks.spawn_event(TaskKind::Preload, async move {
+            InternalEvent::Preloaded {
+                manifest: fetch_manifest(loader, request, decryptor)
+                    .await
+                    .map(Box::new),
+                manifest_url,
+            }
+        });
+        self.preloading = Some(task);
     }
 
-    fn detach(&mut self) {
-        // First we clear scheduled events and mem-swap the internal receivers.
-        self.scheduled_events = FuturesUnordered::new();
-        let (sndr, rcvr) = flume::unbounded();
+    /// Keeps a preloaded manifest for the switch and warms the segment cache with the init
+    /// segments of the renditions the player is going to start it with.
+    fn on_preloaded(&mut self, manifest_url: String, manifest: Result<Box<Manifest>, AshinaError>) {
+        self.preloading = None;
 
-        self.sndr = sndr;
-        self.rcvr = rcvr;
+        // The queue changed while the manifest loaded.
+        if self.queue.front() != Some(&manifest_url) {
+            self.preload_next();
+            return;
+        }
 
-        for (_, track) in self.active_tracks.drain() {
-            track.cleanup();
+        let manifest = match manifest {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                // It is fetched again when its turn comes, which reports the error.
+                log!(
+                    self.logger,
+                    Manifest,
+                    Warn,
+                    "Preloading {manifest_url} failed: {e:?}."
+                );
+                return;
+            }
+        };
+
+        for request in self.startup_init_requests(&manifest, &manifest_url) {
+            let fetch = self
+                .loader
+                .fetch(request.with_priority(RequestPriority::Prefetch));
+            self.tasks.spawn(TaskKind::Preload, async move {
+                let _ = fetch.await;
+                None
+            });
         }
+
+        self.preloaded = Some((manifest_url, *manifest));
     }
 
-    fn schedule(&mut self, event: InternalEvent, deadline: Duration) {
-        self.scheduled_events.push(
-            async move {
-                TimeoutFuture::new(deadline.as_millis() as _).await;
-                event
-            }
-            .boxed_local(),
+    /// Init segment requests of the renditions [`Self::on_source_open`] would pick from
+    /// `manifest`, identical to the ones the track buffers send so that they hit the cache.
+    fn startup_init_requests(
+        &self,
+        manifest: &Manifest,
+        manifest_url: &str,
+    ) -> Vec<SegmentRequest> {
+        let Ok(manifest_url) = url::Url::parse(manifest_url) else {
+            return vec![];
+        };
+        let root = manifest
+            .base_urls()
+            .first()
+            .and_then(|base_url| manifest_url.join(base_url.trim()).ok())
+            .unwrap_or(manifest_url);
+
+        let tracks: Vec<_> = manifest
+            .tracks()
+            .into_iter()
+            .filter(|track| is_type_supported(track) && !track.is_trick_mode())
+            .collect();
+
+        let mut selected = vec![];
+        if let Some(video) = tracks
+            .iter()
+            .find(|track| track.is_video())
+            .filter(|_| self.video_enabled)
+        {
+            let renditions: Vec<_> = tracks
+                .iter()
+                .filter(|candidate| candidate.same_adaptation(video))
+                .cloned()
+                .collect();
+            selected.extend(
+                self.startup_rendition(&renditions, self.fast_start_enabled())
+                    .or(Some(video))
+                    .cloned(),
+            );
+        }
+
+        let audio: Vec<_> = tracks.iter().filter(|track| track.is_audio()).collect();
+        let audio_track = self
+            .audio_track
+            .as_ref()
+            .filter(|selected| audio.iter().any(|track| track.track_id() == *selected))
+            .or_else(|| self.preferred_audio(&audio).map(|track| track.track_id()));
+        selected.extend(
+            audio
+                .iter()
+                .find(|track| Some(track.track_id()) == audio_track)
+                .map(|track| (*track).clone()),
         );
+
+        selected
+            .iter()
+            .map(|track| {
+                SegmentRequest::new(init_segment_url(track, &root), RequestKind::Init)
+                    .with_track(track.track_id().clone())
+            })
+            .collect()
     }
 
-    fn base_url(&self) -> url::Url {
-        let mut url = url::Url::parse(self.manifest_url()).expect("Invalid manifest url.");
+    /// Replaces the current presentation with the next queued one, from its start. A preloaded
+    /// manifest is used as-is instead of being fetched again.
+    async fn advance_queue(&mut self) {
+        let Some(manifest_url) = self.queue.pop_front() else {
+            return;
+        };
+        let preloaded = self
+            .preloaded
+            .take()
+            .filter(|(preloaded, _)| *preloaded == manifest_url)
+            .map(|(_, manifest)| manifest);
+
+        log!(
+            self.logger,
+            Player,
+            Info,
+            "Advancing the queue to {manifest_url}."
+        );
 
-        url.path_segments_mut().unwrap().pop();
+        self.cancel_pending_create();
+        self.detach();
+        self.manifest_url = Some(manifest_url.clone());
+        self.recovery_attempts = 0;
+        self.resume = Some((0., true));
+        self.reset_ads();
+        self.emit(PlayerEvent::QueueAdvanced {
+            manifest: manifest_url,
+        });
 
-        url
+        match preloaded {
+            Some(manifest) => {
+                self.load_started = Some(js_sys::Date::now());
+                self.time_to_first_frame = None;
+                self.set_state(PlaybackState::Loading);
+                self.on_manifest_loaded(Ok(Box::new(manifest))).await;
+            }
+            None => self.load_manifest(),
+        }
     }
 
-    fn add_event_listener(&mut self, event: &str, callback: impl Fn() + 'static) {
+    /// Whether playback of a VOD presentation stopped at its end, see [`END_TOLERANCE`].
+    fn at_end(&mut self, position: f64) -> bool {
+        if self
+            .manifest
+            .as_ref()
+            .is_none_or(|manifest| manifest.is_live())
+        {
+            return false;
+        }
+
         let video = self.video();
-        let callback: Closure<dyn FnMut()> = Closure::new(Box::new(callback));
+        let duration = video.duration();
+
+        video.ended()
+            || (duration.is_finite()
+                && position >= duration - END_TOLERANCE
+                && video.ready_state() < web_sys::HtmlMediaElement::HAVE_FUTURE_DATA)
+    }
 
-        video
-            .add_event_listener_with_callback(event, &callback.as_ref().unchecked_ref())
-            .unwrap();
+    /// Publishes [`PlayerEvent::Ended`] once per stop at the end, then plays the post-rolls and
+    /// moves on to the next queued manifest.
+    async fn on_ended(&mut self) {
+        if self.state == PlaybackState::Ended || self.ad_break.is_some() {
+            return;
+        }
+
+        log!(self.logger, Player, Info, "Playback ended.");
+        self.set_state(PlaybackState::Ended);
+        self.emit(PlayerEvent::Ended);
+
+        if let Some(ad_break) = self.ads.post_roll() {
+            self.start_ad_break(ad_break, false);
+            return;
+        }
 
-        callback.forget();
+        self.advance_queue().await;
     }
 
-    async fn on_source_open(&mut self) -> Result<(), BoxError> {
-        let duration = self
-            .manifest
+    /// Breaks of [`PlayerConfig::ads`], for a new presentation.
+    fn reset_ads(&mut self) {
+        let breaks = self
+            .config
+            .ads
             .as_ref()
-            .unwrap()
-            .duration()
-            .unwrap()
-            .as_secs_f64();
+            .map(|ads| ads.breaks.clone())
+            .unwrap_or_default();
 
-        self.media_source.set_duration(duration);
+        self.ads = AdScheduler::new(breaks);
+    }
 
-        // FIXME: Handle multiple video tracks gracefully.
-        for (index, track) in self.tracks().into_iter().enumerate() {
-            tracing::info!(?track);
-            if track.is_video() {
-                let manager = TrackBufferManager::new(self.media_source.clone(), track)
-                    .with_base_url(self.base_url());
+    /// Pauses the main content and plays `ad_break` in the ad element through a player of its
+    /// own, which reports back with [`InternalEvent::AdBreakFinished`]. Main content buffering
+    /// stops until then.
+    fn start_ad_break(&mut self, ad_break: AdBreak, resume_playing: bool) {
+        let Some(element_id) = self.config.ads.as_ref().map(|ads| ads.element_id.clone()) else {
+            log!(
+                self.logger,
+                Player,
+                Warn,
+                "Dropping ad break {} without an ad element.",
+                ad_break.id
+            );
+            return;
+        };
 
-                self.active_tracks.insert(index, manager);
+        let Some(element) = web_sys::window()
+            .and_then(|window| window.document())
+            .and_then(|document| document.get_element_by_id(&element_id))
+            .and_then(|element| element.dyn_into::<HtmlVideoElement>().ok())
+        else {
+            log!(
+                self.logger,
+                Player,
+                Warn,
+                "Dropping ad break {}, no video element with id {element_id}.",
+                ad_break.id
+            );
+            return;
+        };
 
-                break;
-            }
-        }
+        log!(
+            self.logger,
+            Player,
+            Info,
+            "Starting ad break {} with {}.",
+            ad_break.id,
+            ad_break.manifest
+        );
 
-        // FIXME: Handle multiple audio tracks gracefully.
-        for (index, track) in self.tracks().into_iter().enumerate() {
-            tracing::info!(?track);
-            if track.is_audio() {
-                let manager = TrackBufferManager::new(self.media_source.clone(), track)
-                    .with_base_url(self.base_url());
+        let _ = self.video().pause();
+        // The ad player starts it as soon as it can, like any element with `autoplay`.
+        element.set_autoplay(true);
 
-                self.active_tracks.insert(index, manager);
+        let mut config = self.config.clone();
+        config.ads = None;
+        config.hotkeys = None;
+        config.start_position = None;
 
-                break;
+        let mut player = Player::new(config);
+        let (mut tx, rx) = mpsc::channel(16);
+        let (events_tx, events) = flume::unbounded();
+        let _ = tx.try_send(PlayerState::Subscribe { tx: events_tx });
+        let _ = tx.try_send(PlayerState::Created {
+            id: element_id,
+            manifest: ad_break.manifest.clone(),
+            start: None,
+            tx: None,
+        });
+
+        // Outside the task group, so that detaching can still send it `Cleanup`.
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Err(e) = player.listen(rx).await {
+                tracing::error!("Ad player listen failed: {e:?}");
             }
-        }
+        });
 
-        tracing::info!("Prepared track buffers.");
+        let id = ad_break.id.clone();
+        self.tasks.spawn_event(TaskKind::Ad, async move {
+            let error = loop {
+                match events.recv_async().await {
+                    Ok(PlayerEvent::Ended) => break None,
+                    Ok(PlayerEvent::Error(error)) => break Some(error),
+                    Ok(_) => {}
+                    Err(_) => {
+                        break Some(AshinaError::Internal(AshinaError::message(
+                            "ad player stopped",
+                        )));
+                    }
+                }
+            };
 
-        self.load_init().await?;
+            InternalEvent::AdBreakFinished { id, error }
+        });
 
-        Ok(())
+        self.emit(PlayerEvent::AdBreakStarted {
+            id: ad_break.id.clone(),
+            manifest: ad_break.manifest,
+        });
+        self.ad_break = Some(ActiveAd {
+            id: ad_break.id,
+            position: ad_break.position,
+            player: tx,
+            resume_playing,
+        });
     }
 
-    async fn load_init(&mut self) -> Result<(), BoxError> {
-        for (track_id, track) in self.active_tracks.iter_mut() {
-            tracing::info!(track_id, "Loading init segment.");
-            // TODO: Spawn on executor so we dont block event processing.
-            let init = track.fetch_init_segment().await?;
-            track.append_init_segment(init)?;
+    /// Tears the ad player down, then plays the next break at the same position or resumes the
+    /// main content.
+    async fn on_ad_break_finished(&mut self, id: String, error: Option<AshinaError>) {
+        let Some(mut ad) = self.ad_break.take_if(|ad| ad.id == id) else {
+            return;
+        };
+        let _ = ad.player.try_send(PlayerState::Cleanup { tx: None });
 
-            self.sndr
-                .send_async(InternalEvent::TryLoadSegment {
-                    track: *track_id,
-                    next_segment: None,
-                })
-                .await?;
+        match &error {
+            Some(error) => log!(
+                self.logger,
+                Player,
+                Warn,
+                "Ad break {id} failed: {error:?}."
+            ),
+            None => log!(self.logger, Player, Info, "Ad break {id} ended."),
         }
+        self.emit(PlayerEvent::AdBreakEnded { id, error });
 
-        Ok(())
+        let next = match ad.position {
+            AdPosition::PreRoll => self.ads.pre_roll(),
+            AdPosition::MidRoll(position) => self.ads.at(position),
+            AdPosition::PostRoll => self.ads.post_roll(),
+        };
+        if let Some(next) = next {
+            self.start_ad_break(next, ad.resume_playing);
+            return;
+        }
+
+        if self.state == PlaybackState::Ended {
+            self.advance_queue().await;
+            return;
+        }
+
+        if ad.resume_playing {
+            let _ = self.video().play();
+        }
+
+        let tracks: Vec<_> = self.active_tracks.keys().cloned().collect();
+        for track in tracks {
+            self.try_load_segment(track, None);
+        }
     }
 
-    async fn try_load_segment(
-        &mut self,
-        track: usize,
-        next_segment: Option<usize>,
-    ) -> Result<(), BoxError> {
-        let manager = self.active_tracks.get_mut(&track).unwrap();
+    /// Publishes an ad break for `event` if [`crate::ads::AdConfig::cue_handler`] wants one
+    /// there. Cues for positions already played are ignored.
+    fn schedule_cue_ad(&mut self, event: &PlayerEvent) {
+        let Some(handler) = self
+            .config
+            .ads
+            .as_ref()
+            .and_then(|ads| ads.cue_handler.clone())
+        else {
+            return;
+        };
 
-        let Ok(segment) = manager.fetch_segment(next_segment).await else {
-            tracing::info!("Failed to fetch segment");
-            return Ok(());
+        let PlayerEvent::TimedMetadata {
+            scheme,
+            value,
+            id,
+            start,
+            duration,
+            payload,
+            ..
+        } = event
+        else {
+            return;
         };
 
-        // TODO: Handle timestamp in segment is out of range error.
-        match manager.append_segment(segment).await {
-            Err(Error::QuotaExceededError) => {
-                tracing::error!("Got a Quota error during append.");
-                // Schedule append for later.
-                self.schedule(
-                    InternalEvent::TryLoadSegment {
-                        track,
-                        next_segment: None,
-                    },
-                    Duration::from_millis(1000),
-                );
-            }
-            Err(Error::OutOfRange { next_segment }) => {
-                tracing::error!("Guessed segment not within range, fetching next one.");
-                self.sndr
-                    .send_async(InternalEvent::TryLoadSegment {
-                        track,
-                        next_segment: Some(next_segment),
-                    })
-                    .await?;
-            }
-            Err(error) => return Err(Box::new(error)),
-            Ok(()) => {
-                self.schedule(
-                    InternalEvent::TryLoadSegment {
-                        track,
-                        next_segment: None,
-                    },
-                    Duration::from_millis(200),
-                );
-            }
+        if *start < self.last_position.unwrap_or_default() {
+            return;
         }
 
-        Ok(())
+        let cue = AdCue {
+            scheme: scheme.clone(),
+            value: value.clone(),
+            id: id.clone(),
+            start: *start,
+            duration: *duration,
+            payload: payload.clone(),
+        };
+        let Some(manifest) = handler(&cue) else {
+            return;
+        };
+
+        let id = id.clone().unwrap_or(format!("{scheme}@{start}"));
+        log!(self.logger, Player, Info, "Cue {id} schedules an ad break.");
+        self.ads
+            .schedule(AdBreak::new(id, AdPosition::MidRoll(*start), manifest));
     }
 
-    async fn on_seeking(&mut self) -> Result<(), Error> {
+    fn resolve_create(&mut self, result: Result<ManifestInfo, AshinaError>) {
+        if let Some(tx) = self.result_tx.take() {
+            let _ = tx.send(result);
+        }
+    }
+
+    /// Resolves a still pending [`crate::MediaPlayer::create`] with [`Error::Cancelled`]. Returns
+    /// whether there was one.
+    fn cancel_pending_create(&mut self) -> bool {
+        if self.result_tx.is_none() {
+            return false;
+        }
+
+        log!(self.logger, Player, Info, "Cancelling pending create.");
+        self.resolve_create(Err(Error::Cancelled.into()));
+
+        true
+    }
+
+    /// For live streams, schedules the next manifest fetch to happen slightly before the current
+    /// one expires. The fetch and parse happen inside the scheduled future, so the event loop only
+    /// sees the finished [`InternalEvent::ManifestRefreshed`].
+    fn schedule_manifest_refresh(&mut self) {
+        let Some(update_period) = self
+            .manifest
+            .as_ref()
+            .filter(|manifest| manifest.is_live())
+            .and_then(|manifest| manifest.minimum_update_period())
+        else {
+            return;
+        };
+
+        let delay = update_period
+            .saturating_sub(MANIFEST_PREFETCH_LEAD)
+            .max(MANIFEST_PREFETCH_LEAD);
+        let request = SegmentRequest::new(self.manifest_url(), RequestKind::Manifest);
+        let loader = self.loader.clone();
+        let decryptor = self.config.network.manifest_decryptor.clone();
+
+        self.tasks
+            .spawn_event(TaskKind::ManifestRefresh, async move {
+                TimeoutFuture::new(delay.as_millis() as _).await;
+                InternalEvent::ManifestRefreshed(
+                    fetch_manifest(loader, request, decryptor)
+                        .await
+                        .map(Box::new),
+                )
+            });
+    }
+
+    fn on_manifest_refreshed(&mut self, manifest: Result<Box<Manifest>, AshinaError>) {
+        match manifest {
+            Ok(manifest) => {
+                log!(self.logger, Manifest, Debug, "Live manifest refreshed.");
+
+                let tracks = manifest.tracks();
+                for (track_id, manager) in self.active_tracks.iter_mut() {
+                    if let Some(track) = tracks
+                        .iter()
+                        .find(|track| track.track_id() == track_id && track.id() == manager.id())
+                    {
+                        manager.update_track(track.clone());
+                    }
+                }
+
+                self.manifest = Some(*manifest);
+                self.schedule_manifest_refresh();
+                self.emit_manifest_events();
+            }
+            Err(error) => {
+                log!(
+                    self.logger,
+                    Manifest,
+                    Error,
+                    "Live manifest refresh failed, keeping the old one: {error:?}."
+                );
+                self.schedule_manifest_refresh();
+            }
+        }
+    }
+
+    async fn attach(&mut self, video_element: Option<HtmlVideoElement>) -> Result<(), AshinaError> {
+        log!(self.logger, Player, Info, "Attaching to player.");
+
+        let Some(video_element) = video_element else {
+            log!(
+                self.logger,
+                Player,
+                Error,
+                "No video element with id {} is mounted.",
+                self.video_id()
+            );
+            return Err(Error::ElementNotFound.into());
+        };
+
+        // Two players feeding one element would fight over its `src`.
+        let id = self.id.to_string();
+        if let Some(owner) = video_element
+            .get_attribute(PLAYER_ATTRIBUTE)
+            .filter(|owner| *owner != id)
+        {
+            return Err(AshinaError::Internal(AshinaError::message(format!(
+                "video element {} is already used by player {owner}",
+                self.video_id()
+            ))));
+        }
+
+        let _ = video_element.set_attribute(PLAYER_ATTRIBUTE, &id);
+        self.video_element = Some(video_element.clone());
+
+        // A media source that was attached before is closed and can't be reused.
+        self.media_source = web_sys::MediaSource::new().map_err(|error| {
+            AshinaError::Unsupported(AshinaError::message(format!("{error:?}")))
+        })?;
+
+        let tracks = self.tracks();
+        self.drm
+            .attach(&video_element, &tracks, self.sndr.clone())
+            .await?;
+
+        // TODO: Add event handler for current time update.
+        let sndr = self.sndr.clone();
+
+        self.listeners.add(&video_element, "seeking", move || {
+            let _ = sndr.send(InternalEvent::SeekStarted);
+        });
+
+        self.preload = if video_element.autoplay() {
+            PreloadPolicy::Auto
+        } else {
+            self.config.buffer.preload
+        };
+
+        // The browser would start as soon as any track can play, see `check_ready`.
+        if video_element.autoplay() {
+            video_element.set_autoplay(false);
+            self.readiness.autoplay_held = true;
+        }
+
+        let sndr = self.sndr.clone();
+
+        self.listeners.add(&video_element, "play", move || {
+            let _ = sndr.send(InternalEvent::Play);
+        });
+
+        if let Some(document) = web_sys::window().and_then(|window| window.document()) {
+            self.hidden = document.hidden();
+            let sndr = self.sndr.clone();
+
+            self.listeners.add(&document, "visibilitychange", move || {
+                let _ = sndr.send(InternalEvent::VisibilityChanged);
+            });
+
+            // Fired at the element going fullscreen, which may be a container of the video.
+            let sndr = self.sndr.clone();
+
+            self.listeners.add(&document, "fullscreenchange", move || {
+                let _ = sndr.send(InternalEvent::FullscreenChanged);
+            });
+        }
+
+        self.connection = None;
+        if self.config.abr.network_information {
+            self.connection = ConnectionInfo::current();
+
+            if let Some(connection) = crate::connection::connection() {
+                let sndr = self.sndr.clone();
+
+                self.listeners.add(&connection, "change", move || {
+                    let _ = sndr.send(InternalEvent::ConnectionChanged);
+                });
+            }
+        }
+
+        if let Some(hotkeys) = self.config.hotkeys.clone() {
+            let target: Option<EventTarget> = match hotkeys.target {
+                HotkeyTarget::Video => Some(video_element.clone().into()),
+                HotkeyTarget::Document => web_sys::window()
+                    .and_then(|window| window.document())
+                    .map(Into::into),
+            };
+            let sndr = self.sndr.clone();
+
+            if let Some(target) = target {
+                self.listeners
+                    .add_with_event(&target, "keydown", move |event: Event| {
+                        let Some(action) = event
+                            .dyn_ref::<web_sys::KeyboardEvent>()
+                            .and_then(|event| hotkeys.action_for(event))
+                        else {
+                            return;
+                        };
+
+                        // Keeps space and the arrows from scrolling the page.
+                        event.prevent_default();
+                        let _ = sndr.send(InternalEvent::Hotkey(action));
+                    });
+            }
+        }
+
+        for (event, active) in [
+            ("enterpictureinpicture", true),
+            ("leavepictureinpicture", false),
+        ] {
+            let sndr = self.sndr.clone();
+
+            self.listeners.add(&video_element, event, move || {
+                let _ = sndr.send(InternalEvent::PictureInPictureChanged(active));
+            });
+        }
+
+        for event in ["playing", "pause", "waiting", "seeking", "seeked"] {
+            let sndr = self.sndr.clone();
+
+            self.listeners.add(&video_element, event, move || {
+                let _ = sndr.send(InternalEvent::PlaybackChanged);
+            });
+        }
+
+        let sndr = self.sndr.clone();
+
+        self.listeners.add(&video_element, "loadeddata", move || {
+            let _ = sndr.send(InternalEvent::FirstFrame);
+        });
+
+        let sndr = self.sndr.clone();
+
+        self.listeners.add(&video_element, "error", move || {
+            let _ = sndr.send(InternalEvent::MediaError);
+        });
+
+        let sndr = self.sndr.clone();
+
+        self.listeners.add(&video_element, "ended", move || {
+            let _ = sndr.send(InternalEvent::Ended);
+        });
+
+        let sndr = self.sndr.clone();
+
+        self.listeners.add(&video_element, "timeupdate", move || {
+            let _ = sndr.send(InternalEvent::Seeking);
+        });
+
+        if let Some(text_tracks) = video_element.text_tracks() {
+            let sndr = self.sndr.clone();
+
+            self.listeners.add(&text_tracks, "addtrack", move || {
+                let _ = sndr.send(InternalEvent::TextTracksChanged);
+            });
+
+            // Tracks declared in the markup were added before the listener.
+            if text_tracks.length() > 0 {
+                let _ = self.sndr.send(InternalEvent::TextTracksChanged);
+            }
+        }
+
+        let sndr = self.sndr.clone();
+        let media_source = self.media_source.clone();
+
+        self.listeners.add(&media_source, "sourceopen", move || {
+            tracing::info!("Sending SourceOpen");
+
+            let _ = sndr.send(InternalEvent::SourceOpen);
+        });
+
+        let url = web_sys::Url::create_object_url_with_source(&self.media_source).unwrap();
+        video_element.set_src(&url);
+        self.object_url = Some(url);
+
+        self.apply_volume();
+
+        if let Some((position, playing)) = self.resume.take() {
+            // Applied once metadata is loaded.
+            video_element.set_current_time(position);
+            self.start_position = Some(position);
+
+            if playing {
+                let _ = video_element.play();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn query(&self, kind: QueryKind) -> QueryResult {
+        match kind {
+            QueryKind::Tracks => QueryResult::Tracks(match self.manifest {
+                Some(_) => self.track_infos(),
+                None => vec![],
+            }),
+            QueryKind::Stats => QueryResult::Stats(self.stats()),
+            QueryKind::Buffered => QueryResult::Buffered(self.buffered()),
+            QueryKind::State => QueryResult::State(self.state),
+            QueryKind::Diagnostics => QueryResult::Diagnostics(Box::new(self.diagnostics())),
+        }
+    }
+
+    /// Buffered ranges of the video element, which only covers time every track has media for.
+    fn buffered(&self) -> Vec<(f64, f64)> {
+        let Some(ranges) = self.video_element.as_ref().map(|video| video.buffered()) else {
+            return vec![];
+        };
+
+        (0..ranges.length())
+            .filter_map(|index| Some((ranges.start(index).ok()?, ranges.end(index).ok()?)))
+            .collect()
+    }
+
+    fn diagnostics(&self) -> Diagnostics {
+        let tracks = self
+            .active_tracks
+            .iter()
+            .map(|(track, manager)| TrackDiagnostics {
+                track: track.to_string(),
+                representation: manager.id().to_string(),
+                bitrate: manager.track().bitrate(),
+                buffered: manager
+                    .buffered()
+                    .ranges()
+                    .iter()
+                    .map(|range| (*range.start(), *range.end()))
+                    .collect(),
+                buffered_ahead: manager.buffered_ahead(),
+                segment_duration: manager.segment_duration(),
+                fetching: manager.is_fetching(),
+            })
+            .collect();
+
+        let mut diagnostics = Diagnostics {
+            player: self.id.to_string(),
+            manifest_url: self.manifest_url.clone(),
+            state: self.state,
+            position: self
+                .video_element
+                .as_ref()
+                .map(|video| video.current_time()),
+            manifest: self.manifest.as_ref().map(ManifestSummary::new),
+            tracks,
+            bandwidth_estimate: self.estimator.borrow().estimate(),
+            ..Diagnostics::default()
+        };
+        self.diagnostics.fill(&mut diagnostics);
+
+        diagnostics
+    }
+
+    fn apply_volume(&mut self) {
+        let Some(video) = self.video_element.as_ref() else {
+            return;
+        };
+
+        if let Some(volume) = self.volume {
+            video.set_volume(volume);
+        }
+
+        if let Some(muted) = self.muted {
+            video.set_muted(muted);
+        }
+    }
+
+    fn set_video_enabled(&mut self, enabled: bool) {
+        if self.video_enabled == enabled {
+            return;
+        }
+
+        self.video_enabled = enabled;
+
+        if self.video_element.is_none() || self.manifest.is_none() {
+            return;
+        }
+
+        if !enabled {
+            let video: Vec<_> = self
+                .active_tracks
+                .iter()
+                .filter(|(_, manager)| manager.track().is_video())
+                .map(|(track, _)| track.clone())
+                .collect();
+
+            for track in video {
+                log!(
+                    self.logger,
+                    Player,
+                    Info,
+                    "Video disabled, dropping video buffer of {track}."
+                );
+                // Fetches and retries still in flight would otherwise come back for a track
+                // that is gone. Cleaning up drops the operations queued on its source buffer.
+                self.tasks.cancel_track(&track);
+                self.segment_fetches.remove(&track);
+                if let Some(manager) = self.active_tracks.remove(&track) {
+                    manager.cleanup();
+                }
+            }
+
+            return;
+        }
+
+        // Source buffers can't be added once the media has loaded, so start over.
+        log!(self.logger, Player, Info, "Video enabled, reloading.");
+        self.reload();
+    }
+
+    /// Calls `requestPictureInPicture` on the video element or `exitPictureInPicture` on the
+    /// document. Both are looked up at runtime, as not every browser has them.
+    fn set_picture_in_picture(&mut self, active: bool) {
+        let Some(video) = self.video_element.clone() else {
+            log!(
+                self.logger,
+                Player,
+                Warn,
+                "Picture-in-picture needs a video element."
+            );
+            return;
+        };
+        let Some(document) = web_sys::window().and_then(|window| window.document()) else {
+            return;
+        };
+
+        let (target, method): (&JsValue, _) = match active {
+            true => (&video, "requestPictureInPicture"),
+            false => (&document, "exitPictureInPicture"),
+        };
+        if !active
+            && js_sys::Reflect::get(&document, &"pictureInPictureElement".into())
+                .map_or(true, |element| element.is_null() || element.is_undefined())
+        {
+            return;
+        }
+
+        let promise = js_sys::Reflect::get(target, &method.into())
+            .and_then(|function| function.dyn_into::<js_sys::Function>())
+            .and_then(|function| function.call0(target))
+            .and_then(|promise| promise.dyn_into::<js_sys::Promise>());
+
+        let promise = match promise {
+            Ok(promise) => promise,
+            Err(error) => {
+                log!(
+                    self.logger,
+                    Player,
+                    Warn,
+                    "{method} is unavailable: {error:?}."
+                );
+                return;
+            }
+        };
+
+        let logger = self.logger.clone();
+        self.tasks.spawn(TaskKind::Display, async move {
+            if let Err(error) = JsFuture::from(promise).await {
+                log!(logger, Player, Warn, "{method} failed: {error:?}.");
+            }
+            None
+        });
+    }
+
+    /// Shows the video element fullscreen, or leaves fullscreen if it is the fullscreen
+    /// element.
+    fn set_fullscreen(&mut self, active: bool) {
+        let Some(video) = self.video_element.clone() else {
+            log!(
+                self.logger,
+                Player,
+                Warn,
+                "Fullscreen needs a video element."
+            );
+            return;
+        };
+        let Some(document) = web_sys::window().and_then(|window| window.document()) else {
+            return;
+        };
+
+        if !active {
+            if document.fullscreen_element().as_ref() == Some(video.as_ref()) {
+                document.exit_fullscreen();
+            }
+            return;
+        }
+
+        if let Err(error) = video.request_fullscreen() {
+            log!(
+                self.logger,
+                Player,
+                Warn,
+                "Fullscreen request failed: {error:?}."
+            );
+        }
+    }
+
+    /// Carries out a key press, through the same paths as the matching commands so volume and
+    /// mute are kept across loads.
+    fn on_hotkey(&mut self, action: HotkeyAction) {
+        let Some(video) = self.video_element.clone() else {
+            return;
+        };
+        let Some(hotkeys) = self.config.hotkeys.as_ref() else {
+            return;
+        };
+        let (seek_step, volume_step) = (hotkeys.seek_step, hotkeys.volume_step);
+
+        log!(self.logger, Player, Debug, "Hotkey {action:?}.");
+
+        match action {
+            HotkeyAction::TogglePlay if video.paused() => {
+                let _ = video.play();
+            }
+            HotkeyAction::TogglePlay => {
+                let _ = video.pause();
+            }
+            HotkeyAction::SeekBackward | HotkeyAction::SeekForward => {
+                let step = match action {
+                    HotkeyAction::SeekBackward => -seek_step,
+                    _ => seek_step,
+                };
+                let duration = video.duration();
+                let mut target = (video.current_time() + step).max(0.);
+                if duration.is_finite() {
+                    target = target.min(duration);
+                }
+
+                video.set_current_time(target);
+            }
+            HotkeyAction::VolumeUp | HotkeyAction::VolumeDown => {
+                let step = match action {
+                    HotkeyAction::VolumeDown => -volume_step,
+                    _ => volume_step,
+                };
+                self.volume = Some((video.volume() + step).clamp(0., 1.));
+                // Changing the volume of a muted element is meant to be heard.
+                if video.muted() {
+                    self.muted = Some(false);
+                }
+                self.apply_volume();
+            }
+            HotkeyAction::ToggleMute => {
+                self.muted = Some(!video.muted());
+                self.apply_volume();
+            }
+            HotkeyAction::ToggleFullscreen => self.set_fullscreen(!self.fullscreen),
+        }
+    }
+
+    /// Publishes whether the video element is the fullscreen element, when that changed.
+    fn on_fullscreen_changed(&mut self) {
+        let Some(video) = self.video_element.clone() else {
+            return;
+        };
+        let active = web_sys::window()
+            .and_then(|window| window.document())
+            .and_then(|document| document.fullscreen_element())
+            .is_some_and(|element| element == *video.as_ref());
+
+        if active == self.fullscreen {
+            return;
+        }
+        self.fullscreen = active;
+
+        log!(self.logger, Player, Info, "Fullscreen: {active}.");
+        self.emit(PlayerEvent::FullscreenChanged { active });
+    }
+
+    fn set_audio_track(&mut self, track: TrackId) {
+        if self.audio_track.as_ref() == Some(&track) {
+            return;
+        }
+
+        let playing = self
+            .active_tracks
+            .values()
+            .any(|manager| manager.track().is_audio() && *manager.track().track_id() == track);
+        self.audio_track = Some(track.clone());
+
+        if playing || self.video_element.is_none() || self.manifest.is_none() {
+            return;
+        }
+
+        // A source buffer only takes the codecs it was created for, so start over.
+        log!(
+            self.logger,
+            Player,
+            Info,
+            "Audio track changed to {track}, reloading."
+        );
+        self.reload();
+    }
+
+    /// Loads the current manifest again from scratch, resuming at the same position.
+    fn reload(&mut self) {
+        let video = self.video();
+        let resume = (video.current_time(), !video.paused());
+
+        self.cancel_pending_create();
+        self.detach();
+        self.resume = Some(resume);
+        self.load_manifest();
+    }
+
+    fn detach(&mut self) {
+        self.persist_bandwidth_estimate();
+
+        // First we cancel all child tasks and mem-swap the internal receivers.
+        self.tasks.cancel_all();
+        let (sndr, rcvr) = flume::unbounded();
+
+        self.logger = self.logger.with_events(sndr.clone());
+        self.sndr = sndr;
+        self.rcvr = rcvr;
+
+        for (_, track) in self.active_tracks.drain() {
+            track.cleanup();
+        }
+
+        self.listeners.remove_all();
+
+        self.drm.detach();
+        self.cdn = CdnSelector::new(vec![]);
+        self.fast_start_remaining = 0;
+        self.quality_histogram.clear();
+        self.last_position = None;
+        self.stall = StallWatch::default();
+        self.live_seek_pending = false;
+        self.seen_events.clear();
+        self.reset_captions();
+        self.scrub = Scrub::default();
+        let readiness = std::mem::take(&mut self.readiness);
+        if let Some(video) = self
+            .video_element
+            .as_ref()
+            .filter(|_| readiness.autoplay_held)
+        {
+            video.set_autoplay(true);
+        }
+        self.seek_pending = false;
+        self.segment_fetches.clear();
+        self.tracks_at_end.clear();
+        self.resume = None;
+        self.start_position = None;
+        // Cancelled with the other tasks; a kept `preloaded` manifest stays usable.
+        self.preloading = None;
+        self.set_state(PlaybackState::Idle);
+        if let Some(mut ad) = self.ad_break.take() {
+            let _ = ad.player.try_send(PlayerState::Cleanup { tx: None });
+        }
+        self.text_track_selected = false;
+        self.fullscreen = false;
+
+        if let Some(latency) = self.latency.as_mut() {
+            latency.reset();
+
+            if let Some(video) = self.video_element.as_ref() {
+                video.set_playback_rate(1.);
+            }
+        }
+
+        // Let go of the media source, so the element doesn't keep the old media around.
+        if let Some(video) = self.video_element.take() {
+            let _ = video.remove_attribute("src");
+            let _ = video.remove_attribute(PLAYER_ATTRIBUTE);
+            video.load();
+        }
+
+        if let Some(url) = self.object_url.take() {
+            let _ = web_sys::Url::revoke_object_url(&url);
+        }
+    }
+
+    fn request_license(
+        &mut self,
+        session: usize,
+        message: Vec<u8>,
+        message_type: LicenseMessageType,
+    ) {
+        if let Some(task) = self.drm.clearkey_license(session, &message) {
+            log!(
+                self.logger,
+                Drm,
+                Info,
+                "Answering key request {session} with configured ClearKey keys."
+            );
+            self.spawn_drm_task(Some(task));
+            return;
+        }
+
+        let Some(request) = self
+            .drm
+            .license_request(&*self.loader, session, message, message_type)
+        else {
+            log!(
+                self.logger,
+                Drm,
+                Error,
+                "No license server to send key request {session} to."
+            );
+            return;
+        };
+
+        self.tasks.spawn_event(TaskKind::Fetch, async move {
+            InternalEvent::LicenseLoaded {
+                session,
+                license: request.await,
+            }
+        });
+    }
+
+    fn on_key_statuses_changed(&mut self, session: usize) {
+        let statuses = self.drm.key_statuses(session);
+
+        for (key_id, status) in &statuses {
+            if matches!(
+                status,
+                KeyStatus::Expired | KeyStatus::OutputRestricted | KeyStatus::InternalError
+            ) {
+                log!(
+                    self.logger,
+                    Drm,
+                    Warn,
+                    "Key {key_id} of session {session} is {status:?}."
+                );
+            }
+        }
+
+        self.emit(PlayerEvent::KeyStatusesChanged { session, statuses });
+    }
+
+    /// Signs `urls` in the background so the fetches don't wait for the signer, and so that
+    /// signatures about to expire are renewed before they're needed.
+    fn presign(&mut self, urls: Vec<String>) {
+        let Some(signing) = self.signing.clone() else {
+            return;
+        };
+
+        for url in urls {
+            // Sign the URL as it will be requested.
+            let mut request = SegmentRequest::new(url, RequestKind::Media);
+            if let Some(decorator) = &self.config.network.request_decorator {
+                decorator(&mut request);
+            }
+
+            self.tasks
+                .spawn(TaskKind::Fetch, signing.presign(request.url).map(|()| None));
+        }
+    }
+
+    fn spawn_drm_task(&mut self, task: Option<DrmTask>) {
+        if let Some(task) = task {
+            self.tasks.spawn(TaskKind::Drm, task.map(|()| None));
+        }
+    }
+
+    fn emit(&mut self, event: PlayerEvent) {
+        self.diagnostics.record_event(&event);
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    fn schedule(&mut self, event: InternalEvent, deadline: Duration) {
+        let track = event_track(&event);
+        let task = async move {
+            TimeoutFuture::new(deadline.as_millis() as _).await;
+            event
+        };
+
+        match track {
+            Some(track) => self.tasks.spawn_for_track(TaskKind::Timer, track, task),
+            None => self.tasks.spawn_event(TaskKind::Timer, task),
+        };
+    }
+
+    /// Root that segment URLs are resolved against: the selected CDN, or the manifest URL itself
+    /// if the manifest has no `BaseURL`.
+    fn base_url(&self) -> url::Url {
+        if let Some(url) = self.cdn.current() {
+            return url.clone();
+        }
+
+        url::Url::parse(self.manifest_url()).expect("Invalid manifest url.")
+    }
+
+    /// The manifest's MPD-level `BaseURL`s resolved against the manifest URL. Unparseable
+    /// entries are skipped.
+    fn base_urls(&self) -> Vec<url::Url> {
+        let manifest_url = url::Url::parse(self.manifest_url()).expect("Invalid manifest url.");
+
+        self.manifest
+            .as_ref()
+            .unwrap()
+            .base_urls()
+            .iter()
+            .filter_map(|base_url| manifest_url.join(base_url.trim()).ok())
+            .collect()
+    }
+
+    /// Times a small request against every CDN, using the start of the video init segment so
+    /// that only the time to first byte is measured. Reschedules itself.
+    fn probe_cdns(&mut self) {
+        let Some(interval) = self.config.network.cdn_probe_interval else {
+            return;
+        };
+
+        let Some(manager) = self
+            .active_tracks
+            .values()
+            .find(|manager| manager.track().is_video())
+        else {
+            return;
+        };
+
+        for (index, base_url) in self.cdn.base_urls().enumerate() {
+            let mut request =
+                SegmentRequest::new(manager.init_segment_url(base_url), RequestKind::Probe)
+                    .with_track(manager.track_id().clone());
+            request.add_header("Range", "bytes=0-0");
+
+            let fetch = self.loader.fetch(request);
+            self.tasks.spawn_event(TaskKind::Fetch, async move {
+                let started = js_sys::Date::now();
+                let latency_ms = fetch.await.ok().map(|_| js_sys::Date::now() - started);
+
+                InternalEvent::CdnProbed { index, latency_ms }
+            });
+        }
+
+        self.schedule(InternalEvent::ProbeCdns, interval);
+    }
+
+    fn on_cdn_probed(&mut self, index: usize, latency_ms: Option<f64>) {
+        self.cdn.record(index, latency_ms);
+
+        let Some(base_url) = self.cdn.reselect().cloned() else {
+            return;
+        };
+
+        log!(self.logger, Network, Info, "Switching CDN to {base_url}.");
+
+        for manager in self.active_tracks.values_mut() {
+            manager.set_base_url(base_url.clone());
+        }
+
+        self.emit(PlayerEvent::CdnSwitched {
+            base_url: base_url.to_string(),
+            reason: CdnSwitchReason::Latency,
+        });
+    }
+
+    async fn on_source_open(&mut self) -> Result<(), AshinaError> {
+        // Live presentations have no end to tell the media source about.
+        let duration = self.presentation_end().unwrap_or(f64::INFINITY);
+        self.media_source.set_duration(duration);
+
+        let max_segment_duration = self.manifest.as_ref().unwrap().max_segment_duration();
+
+        let fast_start = self.fast_start_enabled();
+        self.fast_start_remaining = if fast_start {
+            self.config.abr.fast_start_segments
+        } else {
+            0
+        };
+
+        let tracks = self.tracks();
+
+        // FIXME: Handle multiple video tracks gracefully.
+        for track in tracks.iter().cloned() {
+            log!(self.logger, Manifest, Debug, "Considering track {track:?}.");
+            if track.is_video() && self.video_enabled {
+                let renditions: Vec<_> = tracks
+                    .iter()
+                    .filter(|candidate| candidate.same_adaptation(&track))
+                    .cloned()
+                    .collect();
+                let track = self
+                    .startup_rendition(&renditions, fast_start)
+                    .cloned()
+                    .unwrap_or(track);
+
+                let track_id = track.track_id().clone();
+                let manager = TrackBufferManager::new(self.media_source.clone(), track)
+                    .with_base_url(self.base_url())
+                    .with_loader(self.loader.clone())
+                    .with_logger(self.logger.clone())
+                    .with_max_segment_duration(max_segment_duration)
+                    .with_low_latency(self.config.live.low_latency)
+                    .with_update_events(self.sndr.clone());
+
+                self.active_tracks.insert(track_id, manager);
+
+                break;
+            }
+        }
+
+        let audio: Vec<_> = tracks.iter().filter(|track| track.is_audio()).collect();
+        let audio_track = self
+            .audio_track
+            .as_ref()
+            .filter(|selected| audio.iter().any(|track| track.track_id() == *selected))
+            .or_else(|| self.preferred_audio(&audio).map(|track| track.track_id()))
+            .cloned();
+
+        for track in self.tracks() {
+            log!(self.logger, Manifest, Debug, "Considering track {track:?}.");
+            if track.is_audio() && Some(track.track_id()) == audio_track.as_ref() {
+                let track_id = track.track_id().clone();
+                let manager = TrackBufferManager::new(self.media_source.clone(), track)
+                    .with_base_url(self.base_url())
+                    .with_loader(self.loader.clone())
+                    .with_logger(self.logger.clone())
+                    .with_max_segment_duration(max_segment_duration)
+                    .with_low_latency(self.config.live.low_latency)
+                    .with_gap_tolerance(self.config.buffer.audio_gap_tolerance)
+                    .with_presentation_offset(self.audio_offset)
+                    .with_update_events(self.sndr.clone());
+
+                self.active_tracks.insert(track_id, manager);
+
+                break;
+            }
+        }
+
+        // Otherwise the first segments fetched would be the ones at zero, only to seek away.
+        if let Some(position) = self.start_position.take() {
+            for manager in self.active_tracks.values_mut() {
+                manager.current_time(position);
+            }
+        }
+
+        log!(self.logger, Player, Info, "Prepared track buffers.");
+
+        if self.config.live.low_latency {
+            self.start_at_live_edge();
+        }
+
+        if self.cdn.has_alternatives() {
+            self.probe_cdns();
+        }
+
+        self.load_init();
+        self.schedule(InternalEvent::Heartbeat, self.config.heartbeat_interval);
+        self.schedule(InternalEvent::StallCheck, STALL_CHECK_INTERVAL);
+
+        if self.latency.is_some() && self.manifest.as_ref().unwrap().is_live() {
+            self.schedule(InternalEvent::LatencyCheck, LATENCY_CHECK_INTERVAL);
+        }
+
+        Ok(())
+    }
+
+    /// Points every track at the segment [`Self::live_delay`] behind the live edge of a live
+    /// stream, and has the playhead follow once media arrives.
+    fn start_at_live_edge(&mut self) {
+        let Some(elapsed) = self
+            .manifest
+            .as_ref()
+            .filter(|manifest| manifest.is_live())
+            .and_then(|manifest| manifest.live_elapsed(js_sys::Date::now()))
+        else {
+            return;
+        };
+
+        let delay = self.live_delay().as_secs_f64();
+        log!(
+            self.logger,
+            Live,
+            Debug,
+            "Starting {delay} s behind the live edge."
+        );
+
+        for manager in self.active_tracks.values_mut() {
+            manager.start_at_live_edge((elapsed - delay).max(0.));
+        }
+
+        self.live_seek_pending = true;
+    }
+
+    /// Moves the playhead to the first media buffered at the live edge.
+    fn seek_to_live_edge(&mut self, buffered: &NRangeInclusive<f64>) {
+        let Some(start) = buffered.ranges().first().map(|range| *range.start()) else {
+            return;
+        };
+
+        self.live_seek_pending = false;
+
+        if !buffered.contains(&self.video().current_time()) {
+            log!(self.logger, Live, Info, "Seeking to live edge at {start}.");
+            self.video().set_current_time(start);
+        }
+    }
+
+    /// Whether startup begins with the lowest rendition, see
+    /// [`crate::config::AbrConfig::fast_start_segments`].
+    fn fast_start_enabled(&self) -> bool {
+        self.config.abr.fast_start_segments > 0 && self.quality == QualitySelection::Auto
+    }
+
+    /// The rendition a video track starts with. Automatic selection starts low, as nothing is
+    /// known about the bandwidth yet: at the lowest rendition during fast start, otherwise at
+    /// the best one within [`crate::config::AbrConfig::max_startup_bitrate`].
+    fn startup_rendition<'a>(
+        &self,
+        renditions: &'a [Track],
+        fast_start: bool,
+    ) -> Option<&'a Track> {
+        if self.quality != QualitySelection::Auto {
+            return self.quality.select(renditions, self.bandwidth_estimate());
+        }
+
+        if fast_start {
+            return lowest_rendition(renditions);
+        }
+
+        let capable: Vec<_> = self
+            .within_decoding_capabilities(renditions.to_vec())
+            .iter()
+            .map(Track::id)
+            .collect();
+        let renditions: Vec<_> = renditions
+            .iter()
+            .filter(|track| capable.contains(&track.id()))
+            .collect();
+
+        let limit = match self.bandwidth_estimate() {
+            Some(estimate) => (estimate * BANDWIDTH_SAFETY_FACTOR) as u64,
+            None => self.config.abr.max_startup_bitrate?,
+        };
+        let limit = self.metered_cap().map_or(limit, |cap| limit.min(cap));
+        renditions
+            .iter()
+            .filter(|track| track.bitrate().unwrap_or_default() <= limit)
+            .max_by_key(|track| track.bitrate().unwrap_or_default())
+            .or_else(|| {
+                renditions
+                    .iter()
+                    .min_by_key(|track| track.bitrate().unwrap_or_default())
+            })
+            .copied()
+    }
+
+    fn load_init(&mut self) {
+        let tracks: Vec<_> = self.active_tracks.keys().cloned().collect();
+
+        for track in tracks {
+            log!(
+                self.logger,
+                Buffer,
+                Debug,
+                "Loading init segment of {track}."
+            );
+            self.try_load_segment(track, None);
+        }
+    }
+
+    fn fetch_init_segment(&mut self, track: TrackId) {
+        let Some(manager) = self.active_tracks.get_mut(&track) else {
+            return;
+        };
+        let fetch = manager.fetch_init_segment();
+
+        self.tasks
+            .spawn_for_track(TaskKind::Fetch, track.clone(), async move {
+                InternalEvent::InitSegmentLoaded {
+                    track,
+                    data: fetch.await,
+                }
+            });
+    }
+
+    async fn on_init_segment_loaded(
+        &mut self,
+        track: TrackId,
+        data: Result<Vec<u8>, AshinaError>,
+    ) -> Result<(), AshinaError> {
+        let Some(manager) = self.active_tracks.get_mut(&track) else {
+            return Ok(());
+        };
+        manager.fetch_finished();
+        manager.append_init_segment(data?).await?;
+
+        self.sndr
+            .send_async(InternalEvent::TryLoadSegment {
+                track,
+                next_segment: None,
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// Starts fetching the next segment for `track` in the background, unless a fetch for the
+    /// track is already in flight. The result comes back as [`InternalEvent::SegmentLoaded`], or
+    /// as [`InternalEvent::InitSegmentLoaded`] if the representation's init segment is missing.
+    fn try_load_segment(&mut self, track: TrackId, next_segment: Option<usize>) {
+        let presentation_end = self.presentation_end();
+        let Some(manager) = self.active_tracks.get_mut(&track) else {
+            return;
+        };
+
+        if manager.is_fetching() || self.ad_break.is_some() {
+            return;
+        }
+
+        match (self.preload, manager.needs_init()) {
+            (PreloadPolicy::None, _) | (PreloadPolicy::Metadata, false) => return,
+            (_, true) => {
+                self.fetch_init_segment(track);
+                return;
+            }
+            (PreloadPolicy::Auto, false) => {}
+        }
+
+        if presentation_end.is_some_and(|end| manager.next_segment_starts_after(next_segment, end))
+        {
+            if self.tracks_at_end.insert(track.clone()) {
+                log!(
+                    self.logger,
+                    Buffer,
+                    Debug,
+                    "{track} appended the last segment of the presentation."
+                );
+            }
+            self.end_stream(&track);
+            return;
+        }
+        self.tracks_at_end.remove(&track);
+
+        if self.hidden
+            && self.config.buffer.background_policy == BackgroundPolicy::PauseVideo
+            && manager.track().is_video()
+        {
+            return;
+        }
+
+        if manager.streams_segments() {
+            let started = js_sys::Date::now();
+            let stream = manager.stream_segment(next_segment);
+            let upcoming = manager.upcoming_segment_urls(PRESIGN_AHEAD);
+
+            self.tasks
+                .spawn_for_track(TaskKind::Fetch, track.clone(), async move {
+                    InternalEvent::SegmentStreamed {
+                        track,
+                        next_segment,
+                        started,
+                        bytes: stream.await,
+                    }
+                });
+
+            self.presign(upcoming);
+            return;
+        }
+
+        let fetch = manager.fetch_segment(next_segment);
+        let upcoming = manager.upcoming_segment_urls(PRESIGN_AHEAD);
+
+        let task = self.tasks.spawn_for_track(TaskKind::Fetch, track.clone(), {
+            let track = track.clone();
+            async move {
+                InternalEvent::SegmentLoaded {
+                    track,
+                    next_segment,
+                    data: fetch.await,
+                }
+            }
+        });
+        self.segment_fetches.insert(track, task);
+
+        self.presign(upcoming);
+    }
+
+    async fn on_segment_loaded(
+        &mut self,
+        track: TrackId,
+        next_segment: Option<usize>,
+        data: Result<Vec<u8>, Error>,
+    ) -> Result<(), AshinaError> {
+        self.segment_fetches.remove(&track);
+        let Some(manager) = self.active_tracks.get_mut(&track) else {
+            return Ok(());
+        };
+        manager.fetch_finished();
+
+        let segment = match data {
+            Ok(segment) => {
+                manager.fetch_succeeded();
+                segment
+            }
+            Err(error) => {
+                log!(
+                    self.logger,
+                    Network,
+                    Warn,
+                    "Failed to fetch segment: {error:?}."
+                );
+                self.on_segment_fetch_failed(track, next_segment);
+                return Ok(());
+            }
+        };
+
+        if self.config.buffer.verify_segments {
+            yield_to_browser(segment.len()).await;
+            let issues = crate::parse::verify_segment(&segment);
+
+            if !issues.is_empty() {
+                let segment = manager.requested_segment();
+                log!(
+                    self.logger,
+                    Buffer,
+                    Error,
+                    "Segment {segment} of {track} failed verification, not appending: {issues:?}."
+                );
+
+                // Fetching the same bytes again won't fix them, so playback fails.
+                self.emit(PlayerEvent::MalformedSegment {
+                    track: track.clone(),
+                    segment,
+                    issues,
+                });
+                self.set_state(PlaybackState::Errored);
+                self.emit(PlayerEvent::Error(Error::DataError.into()));
+
+                return Ok(());
+            }
+        }
+
+        yield_to_browser(segment.len()).await;
+        let Some(manager) = self.active_tracks.get(&track) else {
+            return Ok(());
+        };
+        let segment_start = manager.requested_segment_start();
+        let messages: Vec<_> = crate::parse::parse_event_messages(&segment)
+            .into_iter()
+            .map(|message| (manager.element_time(message.start(segment_start)), message))
+            .collect();
+        for (start, message) in messages {
+            self.emit_timed_metadata(PlayerEvent::TimedMetadata {
+                start,
+                duration: message.duration(),
+                scheme: message.scheme_id_uri,
+                value: Some(message.value),
+                id: Some(message.id.to_string()),
+                id3: parse_id3(&message.message_data),
+                payload: message.message_data,
+            });
+        }
+
+        if self.config.text.in_band_captions {
+            yield_to_browser(segment.len()).await;
+        }
+        self.decode_captions(&track, &segment);
+
+        let Some(manager) = self.active_tracks.get_mut(&track) else {
+            return Ok(());
+        };
+        let is_video = manager.track().is_video();
+
+        // TODO: Handle timestamp in segment is out of range error.
+        match manager.append_segment(segment).await {
+            Err(Error::QuotaExceededError) => {
+                log!(
+                    self.logger,
+                    Buffer,
+                    Error,
+                    "Got a Quota error during append."
+                );
+                // Schedule append for later.
+                let delay = self.retry_delay(&track);
+                self.schedule(
+                    InternalEvent::TryLoadSegment {
+                        track,
+                        next_segment: None,
+                    },
+                    delay,
+                );
+            }
+            Err(Error::OutOfRange { next_segment }) => {
+                log!(
+                    self.logger,
+                    Buffer,
+                    Debug,
+                    "Guessed segment not within range, fetching next one."
+                );
+                self.sndr
+                    .send_async(InternalEvent::TryLoadSegment {
+                        track,
+                        next_segment: Some(next_segment),
+                    })
+                    .await?;
+            }
+            // The media element fires `error` as well, recovery starts from there.
+            Err(Error::DecodeError) => log!(
+                self.logger,
+                Buffer,
+                Warn,
+                "Failed to decode segment of {track}."
+            ),
+            Err(error @ (Error::InvalidState | Error::DataError)) => {
+                self.recover(Some(track), error).await
+            }
+            Err(error) => return Err(error.into()),
+            Ok(()) => self.on_segment_appended(track, is_video),
+        }
+
+        Ok(())
+    }
+
+    /// The video element failed. Decode errors are recovered from by reloading, anything else
+    /// is reported.
+    async fn on_media_error(&mut self) {
+        let Some(error) = self.video_element.as_ref().and_then(|video| video.error()) else {
+            return;
+        };
+
+        log!(
+            self.logger,
+            Player,
+            Error,
+            "Media element error {}: {}.",
+            error.code(),
+            error.message()
+        );
+
+        if error.code() == web_sys::MediaError::MEDIA_ERR_DECODE {
+            self.recover(None, Error::DecodeError).await;
+        } else {
+            self.set_state(PlaybackState::Errored);
+            self.emit(PlayerEvent::Error(AshinaError::Media(
+                AshinaError::message(error.message()),
+            )));
+        }
+    }
+
+    /// Picks the least disruptive way to get past `error`, escalating to a reload when the
+    /// media source is no longer usable.
+    fn recovery_action(&self, error: Error) -> RecoveryAction {
+        let usable = self.media_source.ready_state() == web_sys::MediaSourceReadyState::Open
+            && self
+                .video_element
+                .as_ref()
+                .is_none_or(|video| video.error().is_none());
+
+        match error {
+            Error::InvalidState if usable => RecoveryAction::Retry,
+            Error::DataError if usable => RecoveryAction::ResetSourceBuffer,
+            _ => RecoveryAction::Reload,
+        }
+    }
+
+    /// Recovers from a media error of `track`, or of the element if `None`, and publishes
+    /// [`PlayerEvent::MediaRecovery`]. After [`MAX_RECOVERY_ATTEMPTS`] the error is reported
+    /// instead.
+    async fn recover(&mut self, track: Option<TrackId>, error: Error) {
+        if self.recovery_attempts >= MAX_RECOVERY_ATTEMPTS {
+            log!(
+                self.logger,
+                Player,
+                Error,
+                "Giving up after {} recoveries: {error}.",
+                self.recovery_attempts
+            );
+            self.set_state(PlaybackState::Errored);
+            self.emit(PlayerEvent::Error(error.into()));
+            return;
+        }
+        self.recovery_attempts += 1;
+
+        let action = self.recovery_action(error);
+        log!(
+            self.logger,
+            Player,
+            Warn,
+            "Recovering from {error} with {action:?}."
+        );
+        self.emit(PlayerEvent::MediaRecovery {
+            track: track.clone(),
+            action,
+            error: error.into(),
+        });
+
+        let Some(track) = track.filter(|_| action != RecoveryAction::Reload) else {
+            self.reload();
+            return;
+        };
+        let Some(manager) = self.active_tracks.get_mut(&track) else {
+            return;
+        };
+
+        if action == RecoveryAction::Retry {
+            let next_segment = Some(manager.requested_segment());
+            self.schedule(
+                InternalEvent::TryLoadSegment {
+                    track,
+                    next_segment,
+                },
+                RECOVERY_RETRY_DELAY,
+            );
+            return;
+        }
+
+        if let Err(error) = manager.reset().await {
+            log!(
+                self.logger,
+                Buffer,
+                Error,
+                "Failed to reset source buffer of {track}, reloading: {error}."
+            );
+            self.reload();
+            return;
+        }
+
+        let _ = self.sndr.send(InternalEvent::TryLoadSegment {
+            track,
+            next_segment: None,
+        });
+    }
+
+    /// A media segment streamed by [`TrackBufferManager::stream_segment`] is complete.
+    fn on_segment_streamed(
+        &mut self,
+        track: TrackId,
+        next_segment: Option<usize>,
+        started: f64,
+        bytes: Result<usize, Error>,
+    ) {
+        let Some(manager) = self.active_tracks.get_mut(&track) else {
+            return;
+        };
+        manager.fetch_finished();
+
+        let bytes = match bytes {
+            Ok(bytes) => bytes,
+            Err(error) => {
+                log!(
+                    self.logger,
+                    Network,
+                    Warn,
+                    "Failed to stream segment: {error:?}."
+                );
+                self.on_segment_fetch_failed(track, next_segment);
+                return;
+            }
+        };
+
+        manager.fetch_succeeded();
+        let append = manager.segment_streamed(bytes, started);
+        let is_video = manager.track().is_video();
+
+        self.emit(PlayerEvent::SegmentAppended {
+            track: track.clone(),
+            number: append.number,
+            pts: append.pts,
+            duration: append.duration,
+            bytes: append.bytes,
+            append_ms: js_sys::Date::now() - append.started,
+        });
+
+        self.on_segment_appended(track, is_video);
+    }
+
+    /// Picks the rendition for and schedules the next segment after one was appended.
+    fn on_segment_appended(&mut self, track: TrackId, is_video: bool) {
+        if is_video {
+            self.fast_start_remaining = self.fast_start_remaining.saturating_sub(1);
+        }
+
+        // Fast start holds off quality selection until its last segment is in.
+        if self.fast_start_remaining == 0 {
+            let reason = match self.quality {
+                QualitySelection::Auto => SwitchReason::Bandwidth,
+                _ => SwitchReason::Selection,
+            };
+            self.apply_quality_selection(&track, reason);
+        }
+
+        let delay = self.next_fetch_delay(&track);
+        self.schedule(
+            InternalEvent::TryLoadSegment {
+                track,
+                next_segment: None,
+            },
+            delay,
+        );
+    }
+
+    /// Moves to `state`, publishing [`PlayerEvent::StateChanged`] if that's a change.
+    fn set_state(&mut self, state: PlaybackState) {
+        if self.state == state {
+            return;
+        }
+
+        log!(
+            self.logger,
+            Player,
+            Debug,
+            "State {:?} -> {state:?}.",
+            self.state
+        );
+        let from = std::mem::replace(&mut self.state, state);
+        self.emit(PlayerEvent::StateChanged { from, to: state });
+    }
+
+    /// Follows the video element seeking, playing, pausing and waiting for media, once the start
+    /// position is buffered. Failed playback stays failed until the next load, and ended
+    /// playback ended while the playhead stays at the end.
+    fn follow_media(&mut self) {
+        if !self.readiness.ready || self.state == PlaybackState::Errored {
+            return;
+        }
+
+        let current = self.state;
+        let position = self.video().current_time();
+        let at_end = self.at_end(position);
+
+        let video = self.video();
+        let state = if video.seeking() {
+            PlaybackState::Seeking
+        } else if current == PlaybackState::Ended && at_end {
+            PlaybackState::Ended
+        } else if video.paused() {
+            match current {
+                // Playback hasn't started yet.
+                PlaybackState::Ready => PlaybackState::Ready,
+                _ => PlaybackState::Paused,
+            }
+        } else if video.ready_state() < web_sys::HtmlMediaElement::HAVE_FUTURE_DATA {
+            PlaybackState::Buffering
+        } else {
+            PlaybackState::Playing
+        };
+
+        self.set_state(state);
+    }
+
+    /// Publishes [`PlayerEvent::PlaybackReady`] and releases a held `autoplay` once every active
+    /// track has [`crate::config::BufferConfig::ready_buffer`] buffered at the playhead. Small leading gaps the
+    /// playhead would jump count as buffered.
+    fn check_ready(&mut self) {
+        if self.readiness.ready
+            || self.live_seek_pending
+            || self.active_tracks.is_empty()
+            || self.ad_break.is_some()
+        {
+            return;
+        }
+
+        let video = self.video();
+        let position = video.current_time();
+        let duration = video.duration();
+
+        let mut until = position + self.config.buffer.ready_buffer.as_secs_f64();
+        if duration.is_finite() {
+            until = until.min(duration);
+        }
+
+        let lead = self.config.buffer.max_gap_jump;
+        let ready = self.active_tracks.values().all(|manager| {
+            manager.buffered().ranges().iter().any(|range| {
+                *range.start() <= position + lead && *range.end() >= until - GAP_JUMP_EPSILON
+            })
+        });
+        if !ready {
+            return;
+        }
+
+        log!(
+            self.logger,
+            Player,
+            Info,
+            "Every track is buffered up to {until}, ready to play at {position}."
+        );
+        self.readiness.ready = true;
+        self.set_state(PlaybackState::Ready);
+        self.emit(PlayerEvent::PlaybackReady { position });
+
+        if std::mem::take(&mut self.readiness.autoplay_held) {
+            let video = self.video();
+            video.set_autoplay(true);
+            let _ = video.play();
+        }
+    }
+
+    /// How long to wait before fetching the next segment of `track`. Below the rebuffer goal the
+    /// next segment is needed right away; above it, fetches are paced relative to the segment
+    /// duration so that short and long segments alike are polled at a sensible rate.
+    fn next_fetch_delay(&self, track: &TrackId) -> Duration {
+        let Some(manager) = self.active_tracks.get(track) else {
+            return MIN_FETCH_INTERVAL;
+        };
+
+        let segment_duration = manager.segment_duration();
+        if manager.buffered_ahead() < self.rebuffer_goal(segment_duration) {
+            return Duration::ZERO;
+        }
+
+        Duration::from_secs_f64(segment_duration * FETCH_PACING)
+            .clamp(MIN_FETCH_INTERVAL, MAX_FETCH_INTERVAL)
+    }
+
+    /// How long to wait before trying `track` again after a failed fetch or append, relative to
+    /// the segment duration: playback has to free space or the server publish a segment.
+    fn retry_delay(&self, track: &TrackId) -> Duration {
+        let Some(manager) = self.active_tracks.get(track) else {
+            return MIN_RETRY_INTERVAL;
+        };
+
+        Duration::from_secs_f64(manager.segment_duration() * RETRY_PACING)
+            .clamp(MIN_RETRY_INTERVAL, MAX_RETRY_INTERVAL)
+    }
+
+    /// Seconds of media that should be buffered ahead of the playhead: `MPD@minBufferTime`, but at
+    /// least one segment.
+    fn rebuffer_goal(&self, segment_duration: f64) -> f64 {
+        self.manifest
+            .as_ref()
+            .and_then(|manifest| manifest.min_buffer_time())
+            .map_or(0., |min_buffer_time| min_buffer_time.as_secs_f64())
+            .max(segment_duration)
+    }
+
+    /// Moves the track to the next CDN once its fetches failed
+    /// [`crate::config::NetworkConfig::failover_after`] times in a row, and retries the segment.
+    fn on_segment_fetch_failed(&mut self, track: TrackId, next_segment: Option<usize>) {
+        let Some(manager) = self.active_tracks.get_mut(&track) else {
+            return;
+        };
+
+        let failover = if manager.fetch_failed() >= self.config.network.failover_after {
+            self.cdn.next_after(manager.base_url()).cloned()
+        } else {
+            None
+        };
+
+        let retries = manager.retries();
+        if retries > self.config.network.max_segment_retries {
+            log!(
+                self.logger,
+                Network,
+                Error,
+                "Giving up on segment {} of {track} after {} retries.",
+                manager.requested_segment(),
+                retries - 1
+            );
+            self.set_state(PlaybackState::Errored);
+            self.emit(PlayerEvent::Error(Error::FetchError.into()));
+            return;
+        }
+
+        if let Some(base_url) = failover {
+            log!(
+                self.logger,
+                Network,
+                Warn,
+                "Segment fetches of {track} keep failing, switching CDN to {base_url}."
+            );
+
+            manager.set_base_url(base_url.clone());
+            manager.switched_cdn();
+
+            self.emit(PlayerEvent::CdnSwitched {
+                base_url: base_url.to_string(),
+                reason: CdnSwitchReason::Failover,
+            });
+        }
+
+        let delay = self
+            .retry_delay(&track)
+            .saturating_mul(2u32.saturating_pow(retries - 1))
+            .min(MAX_RETRY_BACKOFF);
+        self.schedule(
+            InternalEvent::TryLoadSegment {
+                track,
+                next_segment,
+            },
+            delay,
+        );
+    }
+
+    /// Media element time at which a VOD presentation ends. `None` for live presentations,
+    /// whose timeline keeps growing.
+    fn presentation_end(&self) -> Option<f64> {
+        self.manifest.as_ref()?.presentation_end()
+    }
+
+    /// Ends the media source once every track appended its last segment, so that the media
+    /// element plays to the very end and fires `ended`. Appending again, e.g. after seeking
+    /// back, opens the media source again.
+    fn end_stream(&mut self, track: &TrackId) {
+        if self.media_source.ready_state() != web_sys::MediaSourceReadyState::Open
+            || self
+                .active_tracks
+                .keys()
+                .any(|track| !self.tracks_at_end.contains(track))
+        {
+            return;
+        }
+
+        log!(
+            self.logger,
+            Buffer,
+            Info,
+            "Every track appended its last segment, ending the stream."
+        );
+        // Fails while a source buffer is updating, e.g. evicting.
+        if let Err(error) = self.media_source.end_of_stream() {
+            log!(
+                self.logger,
+                Buffer,
+                Warn,
+                "Failed to end the stream, trying again: {error:?}."
+            );
+            self.schedule(
+                InternalEvent::TryLoadSegment {
+                    track: track.clone(),
+                    next_segment: None,
+                },
+                RECOVERY_RETRY_DELAY,
+            );
+        }
+    }
+
+    /// Switches a video track to the rendition the current [`QualitySelection`] asks for. Called
+    /// at segment boundaries, so the switch takes effect with the next segment fetched.
+    fn apply_quality_selection(&mut self, track: &TrackId, mut reason: SwitchReason) {
+        let Some(manager) = self.active_tracks.get(track) else {
+            return;
+        };
+
+        if !manager.track().is_video() || self.scrub.main.is_some() {
+            return;
+        }
+
+        let candidates: Vec<_> = self
+            .tracks()
+            .into_iter()
+            .filter(|candidate| candidate.same_adaptation(manager.track()))
+            .collect();
+
+        // A metered connection and the device's decoder cap automatic selection, not what the
+        // application asks for.
+        let candidates = match self.quality {
+            QualitySelection::Auto => {
+                self.within_decoding_capabilities(self.within_metered_cap(candidates))
+            }
+            _ => candidates,
+        };
+
+        let estimate = self.bandwidth_estimate();
+        let target = match &self.quality {
+            _ if self.hidden
+                && self.config.buffer.background_policy == BackgroundPolicy::LowestQuality =>
+            {
+                lowest_rendition(&candidates)
+            }
+            QualitySelection::Auto => {
+                let quality = self
+                    .video_element
+                    .as_ref()
+                    .map(|video| video.get_video_playback_quality());
+
+                let target = self.abr.select(&AbrContext {
+                    candidates: &candidates,
+                    current: manager.track(),
+                    bandwidth_estimate: estimate,
+                    buffer_level: manager.buffered_ahead(),
+                    segment_duration: manager.segment_duration(),
+                    dropped_frames: quality
+                        .as_ref()
+                        .map_or(0, |quality| quality.dropped_video_frames()),
+                    total_frames: quality.map_or(0, |quality| quality.total_video_frames()),
+                });
+                if self.abr.limited_by() == Some(DroppedFramesRule::NAME) {
+                    reason = SwitchReason::DroppedFrames;
+                }
+                target
+            }
+            selection => selection.select(&candidates, estimate),
+        };
+        let Some(target) = target else {
+            return;
+        };
+
+        if target.id() == manager.id() {
+            return;
+        }
+
+        log!(
+            self.logger,
+            Abr,
+            Info,
+            "Switching rendition of {track} from {} to {}.",
+            manager.id(),
+            target.id()
+        );
+
+        self.diagnostics.record_decision(AbrDecision {
+            time: js_sys::Date::now(),
+            track: track.to_string(),
+            from: manager.id().to_string(),
+            to: target.id().to_string(),
+            reason: format!("{reason:?}"),
+            bandwidth_estimate: estimate,
+            buffer_level: manager.buffered_ahead(),
+        });
+
+        let event = PlayerEvent::QualityChanged {
+            track: track.clone(),
+            representation: target.id(),
+            bitrate: target.bitrate(),
+            reason,
+        };
+
+        let target = target.clone();
+        self.active_tracks
+            .get_mut(track)
+            .unwrap()
+            .switch_track(target);
+        self.emit(event);
+    }
+
+    fn on_buffer_updated(&mut self, track: TrackId) {
+        let Some(manager) = self.active_tracks.get_mut(&track) else {
+            return;
+        };
+
+        let appended = manager.take_completed_append();
+        let previous = manager.refresh_buffered();
+        let current = manager.buffered().clone();
+
+        if self.live_seek_pending && manager.track().is_video() {
+            self.seek_to_live_edge(&current);
+        }
+
+        self.check_ready();
+
+        if let Some(append) = appended {
+            self.emit(PlayerEvent::SegmentAppended {
+                track: track.clone(),
+                number: append.number,
+                pts: append.pts,
+                duration: append.duration,
+                bytes: append.bytes,
+                append_ms: js_sys::Date::now() - append.started,
+            });
+        }
+
+        self.prune_seen_events();
+
+        let Some(previous) = previous else {
+            return;
+        };
+
+        for range in previous.difference(&current) {
+            // Float noise between two reads of the same range.
+            if range.end() - range.start() < MIN_EVICTED_RANGE {
+                continue;
+            }
+
+            self.emit(PlayerEvent::RangeEvicted {
+                track: track.clone(),
+                range,
+            });
+        }
+
+        self.emit(PlayerEvent::BufferedChanged {
+            track,
+            ranges: current.ranges().to_vec(),
+        });
+    }
+
+    /// Publishes the manifest's `EventStream` events that weren't published before.
+    fn emit_manifest_events(&mut self) {
+        let events = self.manifest.as_ref().unwrap().events();
+
+        for event in events {
+            self.emit_timed_metadata(PlayerEvent::TimedMetadata {
+                scheme: event.scheme_id_uri,
+                value: event.value,
+                id: event.id,
+                start: event.start,
+                duration: event.duration,
+                id3: parse_id3(&event.message_data),
+                payload: event.message_data,
+            });
+        }
+    }
+
+    /// Publishes a [`PlayerEvent::TimedMetadata`] unless an event with the same scheme, value and
+    /// id, or timing if it has no id, was published already. Manifest refreshes and every track's
+    /// segments repeat events.
+    fn emit_timed_metadata(&mut self, event: PlayerEvent) {
+        let PlayerEvent::TimedMetadata {
+            scheme,
+            value,
+            id,
+            start,
+            duration,
+            ..
+        } = &event
+        else {
+            return;
+        };
+
+        let millis = |seconds: f64| (seconds * 1000.).round() as i64;
+        let key = EventKey {
+            scheme: scheme.clone(),
+            value: value.clone(),
+            id: id.clone(),
+            timing: id.is_none().then(|| (millis(*start), duration.map(millis))),
+        };
+        let end = start + duration.unwrap_or_default();
+
+        if self.seen_events.insert(key, end).is_none() {
+            self.schedule_cue_ad(&event);
+            self.emit(event);
+        }
+    }
+
+    /// Forgets published events that ended before the live window, or for on-demand streams
+    /// before the buffered media, so that the set doesn't grow over a long session. Events
+    /// still listed in a live manifest are within its window.
+    fn prune_seen_events(&mut self) {
+        let horizon = match &self.manifest {
+            Some(manifest) if manifest.is_live() => self
+                .live_edge()
+                .zip(manifest.time_shift_buffer_depth())
+                .map(|(edge, depth)| edge - depth.as_secs_f64()),
+            Some(_) => self
+                .active_tracks
+                .values()
+                .filter_map(|manager| manager.buffered().ranges().first())
+                .map(|range| *range.start())
+                .min_by(f64::total_cmp),
+            None => None,
+        };
+
+        if let Some(horizon) = horizon {
+            self.seen_events.retain(|_, end| *end >= horizon);
+        }
+    }
+
+    /// Run every [`LATENCY_CHECK_INTERVAL`] for live streams: nudges the playback rate, or
+    /// seeks, to keep the distance to the live edge at the target latency.
+    fn on_latency_check(&mut self) {
+        self.schedule(InternalEvent::LatencyCheck, LATENCY_CHECK_INTERVAL);
+
+        let Some(live_edge) = self.live_edge() else {
+            return;
+        };
+
+        let video = self.video().clone();
+        let Some(controller) = self.latency.as_mut() else {
+            return;
+        };
+
+        if video.paused() || video.seeking() {
+            return;
+        }
+
+        let position = video.current_time();
+        let latency = live_edge - position;
+
+        match controller.update(latency) {
+            Some(LatencyAction::SetRate(rate)) => {
+                log!(
+                    self.logger,
+                    Live,
+                    Info,
+                    "Adjusting playback rate to {rate} for latency {latency}."
+                );
+                video.set_playback_rate(rate);
+            }
+            Some(LatencyAction::Seek(drift)) => {
+                log!(
+                    self.logger,
+                    Live,
+                    Info,
+                    "Latency {latency} too far behind live edge, seeking {drift} ahead."
+                );
+                video.set_playback_rate(1.);
+                video.set_current_time(position + drift);
+            }
+            None => {}
+        }
+    }
+
+    /// How far behind the live edge playback starts: [`crate::config::LiveConfig::target_latency`], else
+    /// `MPD@suggestedPresentationDelay`, else right at the edge.
+    fn live_delay(&self) -> Duration {
+        self.config
+            .live
+            .target_latency
+            .or_else(|| {
+                self.manifest
+                    .as_ref()
+                    .and_then(|manifest| manifest.suggested_presentation_delay())
+            })
+            .unwrap_or_default()
+    }
+
+    /// Media element time at the live edge, from the wall clock.
+    fn live_edge(&self) -> Option<f64> {
+        self.manifest
+            .as_ref()
+            .filter(|manifest| manifest.is_live())?
+            .live_elapsed(js_sys::Date::now())
+    }
+
+    /// Watchdog run every [`STALL_CHECK_INTERVAL`]. A playhead that hasn't moved for
+    /// [`crate::config::BufferConfig::stall_timeout`] while the element lacks data is a stall: report it, skip
+    /// tiny gaps and make sure every track without media at the playhead is fetching.
+    fn on_stall_check(&mut self) {
+        self.schedule(InternalEvent::StallCheck, STALL_CHECK_INTERVAL);
+
+        let position = self.video().current_time();
+        if self.at_end(position) {
+            let _ = self.sndr.send(InternalEvent::Ended);
+            return;
+        }
+
+        let video = self.video();
+        let now = js_sys::Date::now();
+
+        let waiting = !video.paused()
+            && !video.ended()
+            && video.ready_state() < web_sys::HtmlMediaElement::HAVE_FUTURE_DATA;
+
+        if !waiting || self.stall.position != Some(position) {
+            self.stall = StallWatch {
+                position: Some(position),
+                since: now,
+                reported: false,
+            };
+            return;
+        }
+
+        if now - self.stall.since < self.config.buffer.stall_timeout.as_millis() as f64 {
+            return;
+        }
+
+        if !self.stall.reported {
+            log!(self.logger, Player, Warn, "Playback stalled at {position}.");
+            self.stall.reported = true;
+            self.emit(PlayerEvent::BufferStalled { position });
+        }
+
+        // Retry recovery every timeout for as long as the stall lasts.
+        self.stall.since = now;
+        self.recover_stall(position);
+    }
+
+    fn recover_stall(&mut self, position: f64) {
+        if self.jump_gap(position) {
+            return;
+        }
+
+        let stalled: Vec<_> = self
+            .active_tracks
+            .iter()
+            .filter(|(_, manager)| !manager.buffered().contains(&position))
+            .map(|(track, _)| track.clone())
+            .collect();
+
+        for track in stalled {
+            log!(
+                self.logger,
+                Buffer,
+                Info,
+                "Forcing segment fetch for stalled track {track}."
+            );
+            self.try_load_segment(track, None);
+        }
+    }
+
+    /// Moves the playhead past a hole in the buffered ranges at `position` no larger than
+    /// [`crate::config::BufferConfig::max_gap_jump`]. Every track without media at `position` has to have a
+    /// buffered range after it. Returns whether the playhead was moved.
+    fn jump_gap(&mut self, position: f64) -> bool {
+        let mut gap = None;
+
+        for manager in self.active_tracks.values() {
+            let buffered = manager.buffered();
+            if buffered.contains(&position) {
+                continue;
+            }
+
+            let Some(size) = buffered.gap_at(&position) else {
+                return false;
+            };
+
+            gap = Some(gap.map_or(size, |gap: f64| gap.max(size)));
+        }
+
+        let Some(gap) = gap.filter(|gap| *gap <= self.config.buffer.max_gap_jump) else {
+            return false;
+        };
+
+        log!(
+            self.logger,
+            Buffer,
+            Info,
+            "Jumping over gap of {gap} in buffered ranges at {position}."
+        );
+        self.video()
+            .set_current_time(position + gap + GAP_JUMP_EPSILON);
+
+        true
+    }
+
+    async fn on_seeking(&mut self) -> Result<(), Error> {
         let video = self.video();
         let current_time = video.current_time();
 
-        tracing::info!(timestamp = video.current_time(), "Timeupdate / Seeking...");
+        log!(
+            self.logger,
+            Player,
+            Trace,
+            "Timeupdate / Seeking at {current_time}."
+        );
+
+        self.record_playback(current_time);
+
+        if self.at_end(current_time) {
+            self.on_ended().await;
+            return Ok(());
+        }
+        if self.state == PlaybackState::Ended {
+            self.follow_media();
+        }
+
+        let due = match self.ad_break {
+            Some(_) => None,
+            None => self.ads.due(current_time),
+        };
+        if let Some(ad_break) = due {
+            let playing = !self.video().paused();
+            self.start_ad_break(ad_break, playing);
+            return Ok(());
+        }
+
+        // Settling seeks take care of buffering once the playhead comes to rest.
+        if self.seek_pending {
+            return Ok(());
+        }
+
+        if self.jump_gap(current_time) {
+            // The jump fires another seeking event which takes care of fetching.
+            return Ok(());
+        }
 
         for (id, track) in self.active_tracks.iter_mut() {
             if !track.current_time(current_time) {
                 self.sndr
                     .send_async(InternalEvent::TryLoadSegment {
-                        track: *id,
+                        track: id.clone(),
                         next_segment: None,
                     })
                     .await
@@ -366,6 +3500,498 @@ impl Player {
         Ok(())
     }
 
+    /// Buffers for the first of a burst of seeks right away, then holds off until no seek came
+    /// for [`SEEK_DEBOUNCE`], so scrubbing doesn't start a fetch for every position passed.
+    async fn debounce_seek(&mut self) -> Result<(), Error> {
+        self.seek_generation += 1;
+        self.schedule(
+            InternalEvent::SeekSettled(self.seek_generation),
+            SEEK_DEBOUNCE,
+        );
+
+        if self.seek_pending {
+            return Ok(());
+        }
+
+        self.preempt_stale_fetches();
+        self.on_seeking().await?;
+        self.seek_pending = true;
+
+        Ok(())
+    }
+
+    async fn on_seek_settled(&mut self, generation: u64) -> Result<(), Error> {
+        if generation != self.seek_generation {
+            return Ok(());
+        }
+
+        self.seek_pending = false;
+        self.preempt_stale_fetches();
+        self.on_seeking().await
+    }
+
+    /// Cancels media segment fetches for positions the playhead has left, so the segment at the
+    /// new position isn't queued behind them.
+    fn preempt_stale_fetches(&mut self) {
+        let position = self.video().current_time();
+
+        for (track, manager) in self.active_tracks.iter_mut() {
+            if !manager.is_fetching()
+                || manager.buffered().contains(&position)
+                || manager.requested_covers(position)
+            {
+                continue;
+            }
+
+            let Some(task) = self.segment_fetches.remove(track) else {
+                continue;
+            };
+
+            log!(
+                self.logger,
+                Buffer,
+                Debug,
+                "Cancelling fetch of segment {} of {track}, the playhead moved to {position}.",
+                manager.requested_segment()
+            );
+            self.tasks.cancel_task(task);
+            manager.fetch_finished();
+        }
+    }
+
+    /// Applies [`crate::config::BufferConfig::background_policy`] when the page is hidden, and lifts it when
+    /// the page is shown again.
+    fn on_visibility_changed(&mut self) {
+        let Some(document) = web_sys::window().and_then(|window| window.document()) else {
+            return;
+        };
+
+        let hidden = document.hidden();
+        if hidden == self.hidden {
+            return;
+        }
+        self.hidden = hidden;
+
+        let policy = self.config.buffer.background_policy;
+        log!(
+            self.logger,
+            Player,
+            Info,
+            "Page {}, background policy {policy:?}.",
+            if hidden { "hidden" } else { "visible" }
+        );
+        if policy == BackgroundPolicy::Continue {
+            return;
+        }
+
+        let video: Vec<_> = self
+            .active_tracks
+            .iter()
+            .filter(|(_, manager)| manager.track().is_video())
+            .map(|(track, _)| track.clone())
+            .collect();
+
+        for track in video {
+            self.apply_quality_selection(&track, SwitchReason::Visibility);
+
+            // Fetching stopped while hidden, nothing else would start it again.
+            if !hidden && policy == BackgroundPolicy::PauseVideo {
+                self.try_load_segment(track, None);
+            }
+        }
+    }
+
+    /// Starts buffering whatever the preload policy held back.
+    async fn on_play(&mut self) {
+        self.stop_scrubbing().await;
+
+        if self.preload == PreloadPolicy::Auto {
+            return;
+        }
+
+        log!(
+            self.logger,
+            Player,
+            Info,
+            "Playback requested, buffering after preload {:?}.",
+            self.preload
+        );
+        self.preload = PreloadPolicy::Auto;
+        self.load_init();
+    }
+
+    /// Moves video to the trick mode rendition once seeks come in quick succession.
+    fn on_seek_started(&mut self) {
+        let now = js_sys::Date::now();
+        let window = SCRUB_WINDOW.as_secs_f64() * 1000.;
+
+        self.scrub.seeks.retain(|seek| now - seek <= window);
+        self.scrub.seeks.push(now);
+
+        if self.scrub.main.is_some() || self.scrub.seeks.len() < SCRUB_SEEKS {
+            return;
+        }
+
+        let Some((track, manager)) = self
+            .active_tracks
+            .iter()
+            .find(|(_, manager)| manager.track().is_video())
+        else {
+            return;
+        };
+
+        let main = manager.track().clone();
+        let Some(trick) = self
+            .manifest
+            .as_ref()
+            .unwrap()
+            .tracks()
+            .into_iter()
+            .filter(|candidate| {
+                candidate.is_trick_mode_of(&main)
+                    && is_type_supported(candidate)
+                    && self.is_decodable(candidate)
+            })
+            .min_by_key(|candidate| candidate.bitrate())
+        else {
+            return;
+        };
+
+        log!(
+            self.logger,
+            Abr,
+            Info,
+            "Scrubbing, switching {track} to trick mode {}.",
+            trick.id()
+        );
+
+        let track = track.clone();
+        self.emit(PlayerEvent::QualityChanged {
+            track: track.clone(),
+            representation: trick.id(),
+            bitrate: trick.bitrate(),
+            reason: SwitchReason::Scrubbing,
+        });
+
+        self.active_tracks
+            .get_mut(&track)
+            .unwrap()
+            .switch_track(trick);
+        self.scrub.main = Some(main);
+        self.schedule(InternalEvent::ScrubCheck, SCRUB_IDLE);
+    }
+
+    async fn on_scrub_check(&mut self) {
+        if self.scrub.main.is_none() {
+            return;
+        }
+
+        let idle = self
+            .scrub
+            .seeks
+            .last()
+            .map_or(f64::INFINITY, |seek| js_sys::Date::now() - seek);
+
+        if idle < SCRUB_IDLE.as_secs_f64() * 1000. {
+            self.schedule(InternalEvent::ScrubCheck, SCRUB_IDLE);
+            return;
+        }
+
+        self.stop_scrubbing().await;
+    }
+
+    /// Moves video back to the regular rendition. The I-frames buffered while scrubbing are
+    /// dropped so they aren't played.
+    async fn stop_scrubbing(&mut self) {
+        let Some(main) = self.scrub.main.take() else {
+            return;
+        };
+        self.scrub.seeks.clear();
+
+        let Some((track, manager)) = self
+            .active_tracks
+            .iter_mut()
+            .find(|(_, manager)| manager.track().is_video())
+        else {
+            return;
+        };
+
+        log!(
+            self.logger,
+            Abr,
+            Info,
+            "Scrubbing stopped, switching {track} back to {}.",
+            main.id()
+        );
+
+        let track = track.clone();
+        let event = PlayerEvent::QualityChanged {
+            track: track.clone(),
+            representation: main.id(),
+            bitrate: main.bitrate(),
+            reason: SwitchReason::Scrubbing,
+        };
+
+        manager.switch_track(main);
+        if let Err(error) = manager.flush().await {
+            log!(
+                self.logger,
+                Buffer,
+                Error,
+                "Failed to drop trick mode frames of {track}: {error:?}."
+            );
+        }
+
+        self.emit(event);
+        self.apply_quality_selection(&track, SwitchReason::Selection);
+        self.try_load_segment(track, None);
+    }
+
+    /// Attributes the playback progress since the last timeupdate to the renditions buffered at
+    /// the playhead. Jumps backwards or of more than a second are seeks, not playback.
+    fn record_playback(&mut self, position: f64) {
+        let previous = self.last_position.replace(position);
+
+        let Some(elapsed) = previous.map(|previous| position - previous) else {
+            return;
+        };
+
+        if elapsed <= 0. || elapsed > 1. {
+            return;
+        }
+
+        for manager in self.active_tracks.values() {
+            if let Some(rendition) = manager.rendition_at(position) {
+                self.quality_histogram.record(rendition, elapsed);
+            }
+        }
+    }
+
+    fn stats(&self) -> PlaybackStats {
+        PlaybackStats {
+            time_at_quality: self.quality_histogram.snapshot(),
+            bandwidth_estimate: self.estimator.borrow().estimate(),
+            cache_hits: self.segment_cache.hits(),
+            cache_misses: self.segment_cache.misses(),
+            time_to_first_frame: self
+                .time_to_first_frame
+                .map(|ms| Duration::from_secs_f64(ms / 1000.)),
+        }
+    }
+
+    /// Moves the audio tracks to the current audio delay and refetches their media.
+    async fn apply_audio_offset(&mut self) {
+        let offset = self.audio_offset;
+        let mut refetch = vec![];
+
+        for (track, manager) in self.active_tracks.iter_mut() {
+            if !manager.track().is_audio() {
+                continue;
+            }
+
+            match manager.set_presentation_offset(offset).await {
+                Ok(()) => refetch.push(track.clone()),
+                Err(error) => log!(
+                    self.logger,
+                    Buffer,
+                    Error,
+                    "Failed to apply audio offset to {track}: {error:?}."
+                ),
+            }
+        }
+
+        for track in refetch {
+            self.try_load_segment(track, None);
+        }
+    }
+
+    /// Adds the in-band captions of a video segment about to be appended to their text tracks.
+    /// Segments streamed in chunks aren't searched for captions.
+    fn decode_captions(&mut self, track: &TrackId, segment: &[u8]) {
+        if !self.config.text.in_band_captions {
+            return;
+        }
+
+        let Some(manager) = self.active_tracks.get(track) else {
+            return;
+        };
+        let Some(format) = manager.caption_format() else {
+            return;
+        };
+
+        let mut packets = crate::parse::cea::extract_cc_data(segment, &format);
+        for packet in &mut packets {
+            packet.time = manager.element_time(packet.time);
+        }
+
+        let updates = self.captions.decode(&packets);
+        if updates.is_empty() {
+            return;
+        }
+
+        let offset = self.text_offset;
+        if let Some(video) = self.video_element.as_ref() {
+            self.caption_tracks.update(video, updates, offset);
+        }
+    }
+
+    /// Starts decoding captions afresh, for segments that don't continue the ones decoded so far.
+    fn reset_captions(&mut self) {
+        self.captions.reset();
+        self.caption_tracks.close();
+    }
+
+    /// Applies the subtitle delay to cues loaded since the last call. Cues of side-loaded
+    /// tracks arrive asynchronously, so this also runs on every heartbeat.
+    fn apply_text_offset(&mut self) {
+        let offset = self.text_offset;
+
+        if let Some(video) = self.video_element.as_ref() {
+            apply_text_offset(video, offset);
+        }
+    }
+
+    /// The audio track to start with: the best match for the preferred language, then one the
+    /// device has enough channels for, then the preferred role, then described audio only if
+    /// asked for, as it narrates over the main mix. Ties go to the first listed.
+    fn preferred_audio<'a>(&self, audio: &[&'a Track]) -> Option<&'a Track> {
+        let config = &self.config;
+        let score = |track: &Track| {
+            let language = config
+                .preferred_audio_language
+                .as_deref()
+                .zip(track.language())
+                .map_or(0, |(preferred, language)| {
+                    language_match(preferred, language)
+                });
+            let role = config
+                .preferred_audio_role
+                .is_some_and(|role| track.roles().contains(&role));
+            let channels = config
+                .max_audio_channels
+                .zip(track.channels())
+                .is_none_or(|(max, channels)| channels <= max);
+            let description = track.is_audio_description() == config.prefer_audio_description;
+
+            (language, channels, role, description)
+        };
+
+        audio.iter().rev().max_by_key(|track| score(track)).copied()
+    }
+
+    /// Shows the text track matching the configured preferences, once per attach so a track
+    /// picked by the viewer isn't overridden when more are added.
+    fn select_preferred_text_track(&mut self) {
+        let language = self.config.text.preferred_language.clone();
+        let role = self.config.text.preferred_role;
+        if self.text_track_selected || (language.is_none() && role.is_none()) {
+            return;
+        }
+
+        if let Some(video) = self.video_element.as_ref() {
+            self.text_track_selected = select_text_track(video, language.as_deref(), role);
+        }
+
+        if self.text_track_selected {
+            log!(
+                self.logger,
+                Player,
+                Debug,
+                "Showing the preferred text track."
+            );
+        }
+    }
+
+    /// The measured bandwidth estimate, else the one stored by an earlier session, else the
+    /// browser's, bounded by the connection type.
+    fn bandwidth_estimate(&self) -> Option<f64> {
+        let estimate = self.estimator.borrow().estimate().or(self.stored_estimate);
+
+        match &self.connection {
+            Some(connection) => connection.bound(estimate),
+            None => estimate,
+        }
+    }
+
+    /// [`crate::config::AbrConfig::metered_max_bitrate`], while the connection is metered.
+    fn metered_cap(&self) -> Option<u64> {
+        self.connection
+            .as_ref()
+            .filter(|connection| connection.is_metered())
+            .and(self.config.abr.metered_max_bitrate)
+    }
+
+    /// The renditions of `candidates` within [`Self::metered_cap`], the lowest one if none is.
+    fn within_metered_cap(&self, candidates: Vec<Track>) -> Vec<Track> {
+        let Some(cap) = self.metered_cap() else {
+            return candidates;
+        };
+
+        let capped: Vec<_> = candidates
+            .iter()
+            .filter(|track| track.bitrate().unwrap_or_default() <= cap)
+            .cloned()
+            .collect();
+
+        match capped.is_empty() {
+            true => lowest_rendition(&candidates).cloned().into_iter().collect(),
+            false => capped,
+        }
+    }
+
+    /// Reselects the video rendition for the new connection.
+    fn on_connection_changed(&mut self) {
+        if !self.config.abr.network_information {
+            return;
+        }
+
+        self.connection = ConnectionInfo::current();
+        log!(
+            self.logger,
+            Abr,
+            Info,
+            "Connection changed: {:?}.",
+            self.connection
+        );
+
+        let video: Vec<_> = self
+            .active_tracks
+            .iter()
+            .filter(|(_, manager)| manager.track().is_video())
+            .map(|(track, _)| track.clone())
+            .collect();
+
+        for track in video {
+            self.apply_quality_selection(&track, SwitchReason::Connection);
+        }
+    }
+
+    /// Origin of the manifest, under which the bandwidth estimate is persisted, if enabled.
+    fn bandwidth_origin(&self) -> Option<String> {
+        if !self.config.abr.persist_bandwidth {
+            return None;
+        }
+
+        let url = url::Url::parse(self.manifest_url.as_deref()?).ok()?;
+        Some(url.origin().ascii_serialization())
+    }
+
+    /// Stores the measured bandwidth estimate for the next session, if enabled.
+    fn persist_bandwidth_estimate(&self) {
+        let estimate = self.estimator.borrow().estimate();
+
+        if let (Some(origin), Some(estimate)) = (self.bandwidth_origin(), estimate) {
+            crate::abr::store_estimate(&origin, estimate);
+        }
+    }
+
+    fn on_heartbeat(&mut self) {
+        self.persist_bandwidth_estimate();
+        self.apply_text_offset();
+        self.emit(PlayerEvent::Heartbeat(self.stats()));
+        self.schedule(InternalEvent::Heartbeat, self.config.heartbeat_interval);
+    }
+
     fn video(&mut self) -> &HtmlVideoElement {
         self.video_element.as_ref().unwrap()
     }
@@ -373,23 +3999,404 @@ impl Player {
     fn manifest_url(&self) -> &str {
         self.manifest_url.as_ref().unwrap()
     }
-    
+
     fn video_id(&self) -> &str {
         self.video_id.as_ref().unwrap()
     }
 
+    /// Representations the browser can decode. Everything else is left out of track setup and
+    /// quality selection.
     fn tracks(&self) -> Vec<Track> {
-        self.manifest.as_ref().unwrap().tracks()
+        self.manifest
+            .as_ref()
+            .unwrap()
+            .tracks()
+            .into_iter()
+            .filter(|track| {
+                is_type_supported(track) && self.is_decodable(track) && !track.is_trick_mode()
+            })
+            .collect()
+    }
+
+    /// Asks `mediaCapabilities` about the video representations of the manifest. The ones the
+    /// device can't decode, and HDR ones on a display without high dynamic range, are left out
+    /// from then on, see [`Self::is_decodable`]. Automatic selection avoids the ones it can't
+    /// play smoothly, see [`Self::within_decoding_capabilities`].
+    async fn query_capabilities(&mut self) {
+        self.decoding.clear();
+        if !self.config.abr.media_capabilities {
+            return;
+        }
+
+        let video = self
+            .manifest
+            .as_ref()
+            .unwrap()
+            .tracks()
+            .into_iter()
+            .filter(|track| track.is_video() && is_type_supported(track))
+            .collect::<Vec<_>>();
+        if video.is_empty() {
+            return;
+        }
+
+        let infos = futures::future::join_all(video.iter().map(DecodingInfo::query)).await;
+
+        // The browser tone-maps HDR for displays that can't show it, so HDR is only left out
+        // where an SDR rendition can take its place.
+        let sdr_display = crate::capabilities::hdr_display() == Some(false);
+        let sdr_available = video.iter().zip(&infos).any(|(track, info)| {
+            !track.dynamic_range().is_hdr() && info.is_none_or(|info| info.supported)
+        });
+        if sdr_display && !sdr_available && video.iter().any(|track| track.dynamic_range().is_hdr())
+        {
+            log!(
+                self.logger,
+                Manifest,
+                Info,
+                "The display can't show HDR and there is no SDR rendition, playing HDR tone-mapped."
+            );
+        }
+
+        for (track, info) in video.iter().zip(infos) {
+            let Some(mut info) = info else {
+                continue;
+            };
+            let dynamic_range = track.dynamic_range();
+            if dynamic_range.is_hdr() && sdr_display && sdr_available {
+                info.supported = false;
+            }
+
+            if !info.supported {
+                log!(
+                    self.logger,
+                    Manifest,
+                    Warn,
+                    "Skipping {} representation {} of {}, which this device can't play.",
+                    dynamic_range.label(),
+                    track.id(),
+                    track.track_id()
+                );
+            } else if !info.smooth {
+                log!(
+                    self.logger,
+                    Abr,
+                    Info,
+                    "Representation {} of {} doesn't play smoothly on this device.",
+                    track.id(),
+                    track.track_id()
+                );
+            }
+
+            self.decoding
+                .insert((track.track_id().clone(), track.id()), info);
+        }
+    }
+
+    /// Whether `mediaCapabilities` didn't rule out `track`, see [`Self::query_capabilities`].
+    fn is_decodable(&self, track: &Track) -> bool {
+        self.decoding
+            .get(&(track.track_id().clone(), track.id()))
+            .is_none_or(|info| info.supported)
+    }
+
+    /// The renditions of `candidates` `mediaCapabilities` didn't report to drop frames and,
+    /// with [`crate::config::AbrConfig::prefer_power_efficient`], the power efficient ones
+    /// among them, if any is. The lowest rendition if none plays smoothly.
+    fn within_decoding_capabilities(&self, candidates: Vec<Track>) -> Vec<Track> {
+        let info = |track: &Track| {
+            self.decoding
+                .get(&(track.track_id().clone(), track.id()))
+                .copied()
+        };
+
+        let smooth: Vec<_> = candidates
+            .iter()
+            .filter(|track| info(track).is_none_or(|info| info.smooth))
+            .cloned()
+            .collect();
+        if smooth.is_empty() {
+            return lowest_rendition(&candidates).cloned().into_iter().collect();
+        }
+        if !self.config.abr.prefer_power_efficient {
+            return smooth;
+        }
+
+        let efficient: Vec<_> = smooth
+            .iter()
+            .filter(|track| info(track).is_none_or(|info| info.power_efficient))
+            .cloned()
+            .collect();
+
+        match efficient.is_empty() {
+            true => smooth,
+            false => efficient,
+        }
+    }
+
+    /// Warns about representations the manifest doesn't describe well enough to play, or with
+    /// codecs the browser can't decode.
+    fn report_incomplete_tracks(&mut self) {
+        for track in self.manifest.as_ref().unwrap().tracks() {
+            // Thumbnails aren't played through a source buffer and have no codecs.
+            if track.is_image() {
+                continue;
+            }
+
+            let missing = match track.mime_type_with_codecs() {
+                Ok(content_type) => {
+                    if !is_type_supported(&track) {
+                        log!(
+                            self.logger,
+                            Manifest,
+                            Warn,
+                            "Skipping representation {} of {} with unsupported codecs {content_type}.",
+                            track.id(),
+                            track.track_id()
+                        );
+                    }
+                    continue;
+                }
+                Err(missing) => missing,
+            };
+
+            log!(
+                self.logger,
+                Manifest,
+                Warn,
+                "Skipping incomplete representation {} of {}: {missing}.",
+                track.id(),
+                track.track_id()
+            );
+
+            self.emit(PlayerEvent::TrackSkipped {
+                track: track.track_id().clone(),
+                representation: track.id(),
+                missing,
+            });
+        }
+    }
+
+    /// The largest tiled thumbnail representation, if any.
+    fn thumbnails(&self) -> Option<ThumbnailTrack> {
+        let root = self.base_url();
+
+        self.manifest
+            .as_ref()
+            .unwrap()
+            .tracks()
+            .iter()
+            .filter(|track| track.is_image())
+            .max_by_key(|track| track.width())
+            .and_then(|track| ThumbnailTrack::new(track, &root))
+    }
+
+    /// Every representation, marked with whether it is playable.
+    fn track_infos(&self) -> Vec<TrackInfo> {
+        self.manifest
+            .as_ref()
+            .unwrap()
+            .tracks()
+            .iter()
+            .map(|track| {
+                TrackInfo::new(track, is_type_supported(track) && self.is_decodable(track))
+            })
+            .collect()
+    }
+}
+
+pub(crate) fn is_type_supported(track: &Track) -> bool {
+    track
+        .mime_type_with_codecs()
+        .is_ok_and(|content_type| web_sys::MediaSource::is_type_supported(&content_type))
+}
+
+/// The video element with id `id`, waiting up to `timeout` for it to be mounted. `None` if it
+/// doesn't show up, or isn't a video element.
+async fn find_video_element(id: &str, timeout: Duration) -> Option<HtmlVideoElement> {
+    let deadline = js_sys::Date::now() + timeout.as_millis() as f64;
+
+    loop {
+        let element = web_sys::window()
+            .and_then(|window| window.document())
+            .and_then(|document| document.get_element_by_id(id));
+        if let Some(element) = element {
+            return element.dyn_into().ok();
+        }
+
+        if js_sys::Date::now() >= deadline {
+            return None;
+        }
+        TimeoutFuture::new(ELEMENT_POLL_INTERVAL.as_millis() as u32).await;
+    }
+}
+
+async fn fetch_manifest(
+    loader: Rc<dyn Loader>,
+    request: SegmentRequest,
+    decryptor: Option<ManifestDecryptor>,
+) -> Result<Manifest, AshinaError> {
+    let manifest_url = request.url.clone();
+    let body = loader
+        .fetch(request)
+        .await
+        .map_err(|error| AshinaError::ManifestFetch(Arc::new(error)))?;
+
+    let mut manifest = parse_manifest(body, decryptor).await?;
+    // Tracks of every period are set up as soon as the manifest loads, so that is when
+    // `onRequest` elements are needed as well.
+    manifest
+        .resolve_xlinks(loader.as_ref(), &manifest_url, XlinkActuate::OnRequest)
+        .await;
+
+    Ok(manifest)
+}
+
+/// Decrypts a fetched manifest body if needed and parses it.
+pub(crate) async fn parse_manifest(
+    mut body: Bytes,
+    decryptor: Option<ManifestDecryptor>,
+) -> Result<Manifest, AshinaError> {
+    if let Some(decryptor) = decryptor {
+        body = decryptor(body)
+            .await
+            .map_err(|error| AshinaError::ManifestFetch(AshinaError::message(error.to_string())))?;
+    }
+
+    let xml = String::from_utf8(body.to_vec())
+        .map_err(|error| AshinaError::ManifestParse(Arc::new(error)))?;
+
+    Ok(xml.parse()?)
+}
+
+/// Track an event is about, so that timers scheduling it are cancelled with the track's work.
+fn event_track(event: &InternalEvent) -> Option<TrackId> {
+    match event {
+        InternalEvent::TryLoadSegment { track, .. }
+        | InternalEvent::InitSegmentLoaded { track, .. }
+        | InternalEvent::SegmentLoaded { track, .. }
+        | InternalEvent::SegmentStreamed { track, .. }
+        | InternalEvent::BufferUpdated { track } => Some(track.clone()),
+        _ => None,
+    }
+}
+
+impl Default for Player {
+    fn default() -> Self {
+        Self::new(PlayerConfig::default())
     }
 }
 
 pub enum InternalEvent {
     SourceOpen,
     TryLoadSegment {
-        track: usize,
+        track: TrackId,
+        next_segment: Option<usize>,
+    },
+    /// A background init segment fetch for `track` completed.
+    InitSegmentLoaded {
+        track: TrackId,
+        data: Result<Vec<u8>, AshinaError>,
+    },
+    /// A background media segment fetch for `track` completed.
+    SegmentLoaded {
+        track: TrackId,
+        next_segment: Option<usize>,
+        data: Result<Vec<u8>, Error>,
+    },
+    /// A low-latency segment of `track`, appended while it downloaded, is complete.
+    SegmentStreamed {
+        track: TrackId,
         next_segment: Option<usize>,
+        /// When the download started, as a JS timestamp in milliseconds.
+        started: f64,
+        bytes: Result<usize, Error>,
     },
     Seeking,
+    /// The video element started playing, or was asked to.
+    Play,
+    /// The video element fired `playing`, `pause`, `waiting`, `seeking` or `seeked`.
+    PlaybackChanged,
+    /// The document fired `visibilitychange`.
+    VisibilityChanged,
+    /// `navigator.connection` fired `change`.
+    ConnectionChanged,
+    /// The video element fired `enterpictureinpicture` or `leavepictureinpicture`.
+    PictureInPictureChanged(bool),
+    /// The document fired `fullscreenchange`.
+    FullscreenChanged,
+    /// The video element fired `ended`, or the stall watchdog found playback stopped at the end.
+    Ended,
+    /// The player of the ad element finished the break with this id, `error` if the ad failed.
+    AdBreakFinished {
+        id: String,
+        error: Option<AshinaError>,
+    },
+    /// The next queued manifest was fetched and parsed ahead of time.
+    Preloaded {
+        manifest_url: String,
+        manifest: Result<Box<Manifest>, AshinaError>,
+    },
+    /// A key bound in [`PlayerConfig::hotkeys`] was pressed.
+    Hotkey(HotkeyAction),
+    /// The video element has the first frame at the playback position, `loadeddata`.
+    FirstFrame,
+    /// The video element fired `seeking`.
+    SeekStarted,
+    /// No seek followed the one with this generation for [`SEEK_DEBOUNCE`].
+    SeekSettled(u64),
+    /// Time to check whether scrubbing stopped.
+    ScrubCheck,
+    /// Time to publish a statistics heartbeat.
+    Heartbeat,
+    /// A text track was added to the video element.
+    TextTracksChanged,
+    /// A log record to publish, see [`crate::log::LogConfig::forward_events`].
+    Log(LogRecord),
+    /// The video element fired `error`.
+    MediaError,
+    /// Time to run the stall watchdog.
+    StallCheck,
+    /// Time to compare the live latency against the target.
+    LatencyCheck,
+    /// Time to measure the latency of every CDN again.
+    ProbeCdns,
+    /// A CDN latency probe finished. `latency_ms` is `None` if the probe failed.
+    CdnProbed {
+        index: usize,
+        latency_ms: Option<f64>,
+    },
+    /// A track's source buffer fired `updateend`.
+    BufferUpdated {
+        track: TrackId,
+    },
+    /// The manifest requested by [`PlayerState::Created`] was fetched and parsed.
+    ManifestLoaded(Result<Box<Manifest>, AshinaError>),
+    /// The video element was looked up after the manifest loaded. `None` if it wasn't mounted
+    /// within [`crate::config::PlayerConfig::element_timeout`].
+    VideoElementFound(Option<HtmlVideoElement>),
+    /// A background live manifest refresh completed.
+    ManifestRefreshed(Result<Box<Manifest>, AshinaError>),
+    /// The video element hit encrypted media and needs a key session.
+    Encrypted {
+        init_data_type: String,
+        init_data: Vec<u8>,
+    },
+    /// A key session wants a license request sent to the license server.
+    LicenseMessage {
+        session: usize,
+        message: Vec<u8>,
+        message_type: LicenseMessageType,
+    },
+    /// The status of keys in a key session changed.
+    KeyStatusesChanged {
+        session: usize,
+    },
+    /// The license server answered a key session's request.
+    LicenseLoaded {
+        session: usize,
+        license: Result<Bytes, LoadError>,
+    },
 }
 
 #[derive(Clone, Copy, Debug, Display, Error)]
@@ -402,6 +4409,34 @@ pub enum Error {
     DataError,
     /// Server returned non 200 code
     HttpCode,
+    /// The request took longer than its timeout
+    Timeout,
     /// The given segment is out of range for our timestamp
     OutOfRange { next_segment: usize },
+    /// The operation was cancelled before it completed
+    Cancelled,
+    /// The browser can't play the codecs of the representation
+    UnsupportedCodec,
+    /// The source buffer is busy or no longer part of an open media source
+    InvalidState,
+    /// The browser failed to decode the media
+    DecodeError,
+    /// No video element with the given id is mounted
+    ElementNotFound,
+}
+
+impl Error {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::QuotaExceededError
+            | Self::UnsupportedCodec
+            | Self::InvalidState
+            | Self::DecodeError
+            | Self::DataError => ErrorCode::Media,
+            Self::FetchError | Self::HttpCode | Self::Timeout => ErrorCode::Network,
+            Self::OutOfRange { .. } | Self::Cancelled | Self::ElementNotFound => {
+                ErrorCode::Internal
+            }
+        }
+    }
 }
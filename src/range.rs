@@ -1,5 +1,9 @@
+use core::cmp::Ordering;
+use core::ops::Add;
 use core::ops::RangeInclusive;
+use core::ops::Sub;
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct NRangeInclusive<Idx> {
     ranges: Vec<RangeInclusive<Idx>>,
 }
@@ -16,6 +20,10 @@ where
         self.ranges.push(range);
     }
 
+    pub fn ranges(&self) -> &[RangeInclusive<Idx>] {
+        &self.ranges
+    }
+
     pub fn contains(&self, item: &Idx) -> bool {
         for range in &self.ranges {
             if range.contains(item) {
@@ -26,3 +34,258 @@ where
         false
     }
 }
+
+impl<Idx> NRangeInclusive<Idx>
+where
+    Idx: PartialOrd<Idx> + Copy + Sub<Output = Idx>,
+{
+    /// The range starting closest after `item`, if `item` is not within any range.
+    pub fn next_range(&self, item: &Idx) -> Option<&RangeInclusive<Idx>> {
+        if self.contains(item) {
+            return None;
+        }
+
+        self.ranges
+            .iter()
+            .filter(|range| range.start() > item)
+            .fold(
+                None,
+                |nearest: Option<&RangeInclusive<Idx>>, range| match nearest {
+                    Some(nearest) if nearest.start() <= range.start() => Some(nearest),
+                    _ => Some(range),
+                },
+            )
+    }
+
+    /// Parts of these ranges not covered by any range in `other`.
+    pub fn difference(&self, other: &Self) -> Vec<RangeInclusive<Idx>> {
+        let mut covering: Vec<_> = other.ranges.iter().collect();
+        covering.sort_by(|a, b| a.start().partial_cmp(b.start()).unwrap_or(Ordering::Equal));
+
+        let mut uncovered = vec![];
+
+        for range in &self.ranges {
+            let mut cursor = *range.start();
+
+            for cover in &covering {
+                if cover.end() < &cursor || cover.start() > range.end() {
+                    continue;
+                }
+
+                if cover.start() > &cursor {
+                    uncovered.push(cursor..=*cover.start());
+                }
+
+                cursor = *cover.end();
+            }
+
+            if &cursor < range.end() {
+                uncovered.push(cursor..=*range.end());
+            }
+        }
+
+        uncovered
+    }
+
+    /// Distance from `item` to the start of the [`Self::next_range`].
+    pub fn gap_at(&self, item: &Idx) -> Option<Idx> {
+        self.next_range(item).map(|range| *range.start() - *item)
+    }
+
+    /// The earliest range start after `item`, whether or not `item` is buffered.
+    pub fn nearest_start_after(&self, item: &Idx) -> Option<Idx> {
+        self.ranges
+            .iter()
+            .map(|range| *range.start())
+            .filter(|start| start > item)
+            .min_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+    }
+
+    /// Sorts the ranges and merges the ones that overlap or touch.
+    pub fn normalize(&mut self) {
+        self.ranges
+            .sort_by(|a, b| a.start().partial_cmp(b.start()).unwrap_or(Ordering::Equal));
+
+        let mut merged: Vec<RangeInclusive<Idx>> = Vec::with_capacity(self.ranges.len());
+
+        for range in self.ranges.drain(..) {
+            match merged.last_mut() {
+                Some(last) if range.start() <= last.end() => {
+                    if range.end() > last.end() {
+                        *last = *last.start()..=*range.end();
+                    }
+                }
+                _ => merged.push(range),
+            }
+        }
+
+        self.ranges = merged;
+    }
+
+    /// Everything covered by either set of ranges, normalized.
+    pub fn union(&self, other: &Self) -> Self {
+        let mut union = Self {
+            ranges: self.ranges.iter().chain(&other.ranges).cloned().collect(),
+        };
+        union.normalize();
+
+        union
+    }
+
+    /// Everything covered by both sets of ranges, normalized.
+    pub fn intersect(&self, other: &Self) -> Self {
+        let mut intersection = Self::new();
+
+        for a in &self.ranges {
+            for b in &other.ranges {
+                let start = if a.start() > b.start() {
+                    *a.start()
+                } else {
+                    *b.start()
+                };
+                let end = if a.end() < b.end() {
+                    *a.end()
+                } else {
+                    *b.end()
+                };
+
+                if start <= end {
+                    intersection.push(start..=end);
+                }
+            }
+        }
+
+        intersection.normalize();
+        intersection
+    }
+
+    /// Parts of `bounds` not covered by any range.
+    pub fn gaps_within(&self, bounds: RangeInclusive<Idx>) -> Vec<RangeInclusive<Idx>> {
+        Self {
+            ranges: vec![bounds],
+        }
+        .difference(self)
+    }
+}
+
+impl<Idx> NRangeInclusive<Idx>
+where
+    Idx: PartialOrd<Idx> + Copy + Sub<Output = Idx> + Add<Output = Idx> + Default,
+{
+    /// Total length covered, counting overlapping parts once.
+    pub fn total_len(&self) -> Idx {
+        let mut normalized = self.clone();
+        normalized.normalize();
+
+        normalized
+            .ranges
+            .iter()
+            .fold(Idx::default(), |total, range| {
+                total + (*range.end() - *range.start())
+            })
+    }
+}
+
+impl<Idx> Default for NRangeInclusive<Idx>
+where
+    Idx: PartialOrd<Idx>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::prelude::*;
+
+    /// Ranges with integer bounds, so that points between them (`x.5`) are never on a boundary.
+    fn ranges() -> impl Strategy<Value = NRangeInclusive<f64>> {
+        prop::collection::vec((0..100i32, 0..20i32), 0..8).prop_map(|bounds| NRangeInclusive {
+            ranges: bounds
+                .into_iter()
+                .map(|(start, len)| start as f64..=(start + len) as f64)
+                .collect(),
+        })
+    }
+
+    fn points() -> impl Iterator<Item = f64> {
+        (-1..125).map(|point| point as f64 + 0.5)
+    }
+
+    proptest! {
+        #[test]
+        fn normalize_is_idempotent(ranges in ranges()) {
+            let mut once = ranges.clone();
+            once.normalize();
+            let mut twice = once.clone();
+            twice.normalize();
+
+            prop_assert_eq!(once, twice);
+        }
+
+        #[test]
+        fn normalize_sorts_and_separates(mut ranges in ranges()) {
+            ranges.normalize();
+
+            for pair in ranges.ranges().windows(2) {
+                prop_assert!(pair[0].end() < pair[1].start());
+            }
+        }
+
+        #[test]
+        fn normalize_preserves_membership(ranges in ranges()) {
+            let mut normalized = ranges.clone();
+            normalized.normalize();
+
+            for point in points().chain((0..125).map(f64::from)) {
+                prop_assert_eq!(normalized.contains(&point), ranges.contains(&point));
+            }
+        }
+
+        #[test]
+        fn union_contains_either(a in ranges(), b in ranges()) {
+            let union = a.union(&b);
+
+            for point in points() {
+                prop_assert_eq!(union.contains(&point), a.contains(&point) || b.contains(&point));
+            }
+        }
+
+        #[test]
+        fn intersection_contains_both(a in ranges(), b in ranges()) {
+            let intersection = a.intersect(&b);
+
+            for point in points() {
+                prop_assert_eq!(
+                    intersection.contains(&point),
+                    a.contains(&point) && b.contains(&point)
+                );
+            }
+        }
+
+        #[test]
+        fn difference_contains_only_uncovered(a in ranges(), b in ranges()) {
+            let difference = NRangeInclusive {
+                ranges: a.difference(&b),
+            };
+
+            for point in points() {
+                prop_assert_eq!(
+                    difference.contains(&point),
+                    a.contains(&point) && !b.contains(&point)
+                );
+            }
+        }
+
+        #[test]
+        fn lengths_add_up(a in ranges(), b in ranges()) {
+            prop_assert_eq!(
+                a.union(&b).total_len() + a.intersect(&b).total_len(),
+                a.total_len() + b.total_len()
+            );
+        }
+    }
+}
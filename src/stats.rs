@@ -0,0 +1,69 @@
+use crate::manifest::RepresentationId;
+use crate::manifest::Track;
+
+use core::time::Duration;
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Snapshot of playback statistics, published periodically through
+/// [`crate::event::PlayerEvent::Heartbeat`].
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PlaybackStats {
+    /// Cumulative playback time spent at each rendition during this session.
+    pub time_at_quality: Vec<QualityTime>,
+    /// Current network throughput estimate in bits per second.
+    pub bandwidth_estimate: Option<f64>,
+    /// Segment requests answered from the segment cache, see
+    /// [`crate::config::NetworkConfig::segment_cache_bytes`].
+    pub cache_hits: u64,
+    /// Segment requests that had to go to the network.
+    pub cache_misses: u64,
+    /// Time from loading the manifest to the first frame of video, `None` until it's in.
+    pub time_to_first_frame: Option<Duration>,
+}
+
+/// Time spent playing a single rendition.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QualityTime {
+    /// Representation id from the manifest.
+    pub representation: RepresentationId,
+    pub bitrate: Option<u64>,
+    pub width: Option<u64>,
+    pub height: Option<u64>,
+    /// Seconds of media played at this rendition.
+    pub seconds: f64,
+}
+
+/// Accumulates the time played at each rendition, keyed by representation id.
+#[derive(Default)]
+pub struct QualityHistogram {
+    entries: HashMap<RepresentationId, QualityTime>,
+}
+
+impl QualityHistogram {
+    pub fn record(&mut self, track: &Track, seconds: f64) {
+        self.entries
+            .entry(track.id())
+            .or_insert_with(|| QualityTime {
+                representation: track.id(),
+                bitrate: track.bitrate(),
+                width: track.width(),
+                height: track.height(),
+                seconds: 0.,
+            })
+            .seconds += seconds;
+    }
+
+    /// Entries ordered by ascending bitrate.
+    pub fn snapshot(&self) -> Vec<QualityTime> {
+        let mut entries: Vec<_> = self.entries.values().cloned().collect();
+        entries.sort_by_key(|entry| entry.bitrate.unwrap_or_default());
+        entries
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
@@ -0,0 +1,181 @@
+use crate::manifest::TrackId;
+use crate::player::InternalEvent;
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::Context;
+use core::task::Poll;
+use std::collections::HashMap;
+
+use futures::future::AbortHandle;
+use futures::future::Abortable;
+use futures::stream::FusedStream;
+use futures::stream::FuturesUnordered;
+use futures::FutureExt;
+use futures::Stream;
+use futures::StreamExt;
+
+/// Identifies a single child task, see [`TaskGroup::cancel_task`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TaskId(u64);
+
+type ChildTask = Pin<Box<dyn Future<Output = (u64, Option<InternalEvent>)>>>;
+
+/// What a child task of the player is doing, so related work can be cancelled together.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TaskKind {
+    /// Fetching and parsing the manifest requested by `create`, and waiting for the video
+    /// element.
+    Manifest,
+    /// Waiting for and fetching the next live manifest.
+    ManifestRefresh,
+    /// Segment, probe and license requests.
+    Fetch,
+    /// Key session work handed to the browser.
+    Drm,
+    /// Picture-in-picture and fullscreen requests handed to the browser.
+    Display,
+    /// Fetching the manifest and init segments of the next queued presentation.
+    Preload,
+    /// Waiting for an ad break to end.
+    Ad,
+    /// A delayed [`InternalEvent`].
+    Timer,
+}
+
+struct Handle {
+    kind: TaskKind,
+    /// Track the task works for, see [`TaskGroup::cancel_track`].
+    track: Option<TrackId>,
+    abort: AbortHandle,
+}
+
+/// The background work owned by a player. Every child task is polled by the player's event loop
+/// and can be cancelled individually by kind or all at once; nothing outlives the group, so
+/// dropping or [`TaskGroup::cancel_all`]ing it stops all work of the player.
+#[derive(Default)]
+pub struct TaskGroup {
+    tasks: FuturesUnordered<ChildTask>,
+    handles: HashMap<u64, Handle>,
+    next_id: u64,
+}
+
+impl TaskGroup {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a child task. The event it resolves with, if any, is yielded by the group.
+    pub fn spawn(
+        &mut self,
+        kind: TaskKind,
+        task: impl Future<Output = Option<InternalEvent>> + 'static,
+    ) -> TaskId {
+        self.spawn_inner(kind, None, task)
+    }
+
+    /// Starts a child task resolving with `event` on behalf of `track`, so that it is cancelled
+    /// with the track's other work, see [`Self::cancel_track`].
+    pub fn spawn_for_track(
+        &mut self,
+        kind: TaskKind,
+        track: TrackId,
+        task: impl Future<Output = InternalEvent> + 'static,
+    ) -> TaskId {
+        self.spawn_inner(kind, Some(track), task.map(Some))
+    }
+
+    fn spawn_inner(
+        &mut self,
+        kind: TaskKind,
+        track: Option<TrackId>,
+        task: impl Future<Output = Option<InternalEvent>> + 'static,
+    ) -> TaskId {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let (abort, registration) = AbortHandle::new_pair();
+        let task = Abortable::new(task, registration);
+
+        self.handles.insert(id, Handle { kind, track, abort });
+        self.tasks
+            .push(async move { (id, task.await.ok().flatten()) }.boxed_local());
+
+        TaskId(id)
+    }
+
+    /// Starts a child task resolving with `event`.
+    pub fn spawn_event(
+        &mut self,
+        kind: TaskKind,
+        task: impl Future<Output = InternalEvent> + 'static,
+    ) -> TaskId {
+        self.spawn(kind, task.map(Some))
+    }
+
+    /// Cancels a single task. Does nothing if it already finished.
+    pub fn cancel_task(&mut self, id: TaskId) {
+        if let Some(handle) = self.handles.remove(&id.0) {
+            handle.abort.abort();
+        }
+    }
+
+    /// Cancels every running task of `kind`.
+    pub fn cancel(&mut self, kind: TaskKind) {
+        self.handles.retain(|_, handle| {
+            if handle.kind == kind {
+                handle.abort.abort();
+            }
+
+            handle.kind != kind
+        });
+    }
+
+    /// Cancels every running task spawned for `track`, of any kind.
+    pub fn cancel_track(&mut self, track: &TrackId) {
+        self.handles.retain(|_, handle| {
+            let owned = handle.track.as_ref() == Some(track);
+            if owned {
+                handle.abort.abort();
+            }
+
+            !owned
+        });
+    }
+
+    pub fn cancel_all(&mut self) {
+        for handle in self.handles.values() {
+            handle.abort.abort();
+        }
+
+        self.handles.clear();
+        self.tasks = FuturesUnordered::new();
+    }
+}
+
+impl Stream for TaskGroup {
+    type Item = InternalEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match self.tasks.poll_next_unpin(cx) {
+                Poll::Ready(Some((id, event))) => {
+                    self.handles.remove(&id);
+
+                    // Tasks without a result, and cancelled ones, don't surface.
+                    if let Some(event) = event {
+                        return Poll::Ready(Some(event));
+                    }
+                }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl FusedStream for TaskGroup {
+    fn is_terminated(&self) -> bool {
+        self.tasks.is_terminated()
+    }
+}
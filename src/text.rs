@@ -0,0 +1,243 @@
+use crate::manifest::language_match;
+use crate::manifest::TrackRole;
+use crate::parse::cea::CaptionChannel;
+use crate::parse::cea::CaptionUpdate;
+
+use wasm_bindgen::JsCast;
+use wasm_bindgen::JsValue;
+use web_sys::HtmlVideoElement;
+use web_sys::TextTrack;
+use web_sys::TextTrackCue;
+use web_sys::TextTrackKind;
+use web_sys::TextTrackMode;
+use web_sys::VttCue;
+
+use std::collections::HashMap;
+
+/// Property on each cue recording the offset, in seconds, its times are currently shifted by, so
+/// that applying an offset again only moves cues by the difference.
+const APPLIED_OFFSET: &str = "__ashinaTextOffset";
+
+/// Properties on each shifted cue recording its times as authored, as cues shifted to straddle
+/// or end before 0 are clamped to 0 and can't be shifted back from there.
+const AUTHORED_START: &str = "__ashinaAuthoredStart";
+const AUTHORED_END: &str = "__ashinaAuthoredEnd";
+
+/// Property marking the text tracks the player added for in-band captions, so that a later
+/// player on the same element reuses them. Tracks can't be removed from an element.
+const CAPTION_CHANNEL: &str = "__ashinaCaptionChannel";
+
+/// Seconds a caption stays up unless the stream ends it earlier, as decoders clear captions
+/// after a while without caption data.
+const CAPTION_TIMEOUT: f64 = 16.;
+
+/// Shifts every loaded cue of every text track on `video` so that it ends up `offset` seconds
+/// later than authored. Safe to call repeatedly: cues that already carry `offset` are left alone
+/// and cues loaded since the last call are picked up. Tracks that are `disabled` expose no cues
+/// and are skipped until they're enabled.
+pub fn apply_text_offset(video: &HtmlVideoElement, offset: f64) {
+    let Some(tracks) = video.text_tracks() else {
+        return;
+    };
+
+    for track in (0..tracks.length()).filter_map(|index| tracks.get(index)) {
+        let Some(cues) = track.cues() else {
+            continue;
+        };
+
+        for cue in (0..cues.length()).filter_map(|index| cues.get(index)) {
+            shift_cue(&cue, offset);
+        }
+    }
+}
+
+/// Shows the text track on `video` that best matches `language` and `role`, language first,
+/// and disables any other track that is showing. Tracks matching neither are never shown.
+/// Returns whether a track was shown.
+pub fn select_text_track(
+    video: &HtmlVideoElement,
+    language: Option<&str>,
+    role: Option<TrackRole>,
+) -> bool {
+    let Some(tracks) = video.text_tracks() else {
+        return false;
+    };
+    let tracks: Vec<_> = (0..tracks.length())
+        .filter_map(|index| tracks.get(index))
+        .collect();
+
+    let score = |track: &web_sys::TextTrack| {
+        let language = language.map_or(0, |language| language_match(language, &track.language()));
+        let role = role.is_some_and(|role| role_kind(role) == Some(track.kind()));
+        (language, role)
+    };
+
+    // `max_by_key` keeps the last of equals, reversing keeps the first listed.
+    let Some(selected) = tracks
+        .iter()
+        .rev()
+        .filter(|track| match language {
+            Some(_) => score(track).0 > 0,
+            None => score(track).1,
+        })
+        .max_by_key(|track| score(track))
+    else {
+        return false;
+    };
+
+    for track in &tracks {
+        if track == selected {
+            track.set_mode(TextTrackMode::Showing);
+        } else if track.mode() == TextTrackMode::Showing {
+            track.set_mode(TextTrackMode::Disabled);
+        }
+    }
+
+    true
+}
+
+/// Renders in-band captions as `captions` text tracks, one per channel, added to the element
+/// once the channel first shows text. Each caption is a cue that the next update of its channel
+/// ends.
+#[derive(Debug, Default)]
+pub struct CaptionTracks {
+    tracks: HashMap<CaptionChannel, TextTrack>,
+    /// Cue each channel shows, until the next update ends it.
+    open: HashMap<CaptionChannel, VttCue>,
+}
+
+impl CaptionTracks {
+    /// Adds cues for `updates`, in element time, and shifts them by the subtitle delay
+    /// `offset`. Captions loaded before are kept, so a segment appended again doesn't add
+    /// its captions twice.
+    pub fn update(&mut self, video: &HtmlVideoElement, updates: Vec<CaptionUpdate>, offset: f64) {
+        for update in updates {
+            let shifted = update.time + offset;
+
+            if let Some(cue) = self.open.remove(&update.channel) {
+                if shifted > cue.start_time() {
+                    set_cue_end(&cue, shifted.min(cue.end_time()));
+                } else if let Some(track) = self.tracks.get(&update.channel) {
+                    let _ = track.remove_cue(&cue);
+                }
+            }
+
+            if update.text.is_empty() {
+                continue;
+            }
+
+            let track = self
+                .tracks
+                .entry(update.channel)
+                .or_insert_with(|| caption_track(video, update.channel));
+
+            let cue = match find_cue(track, shifted, &update.text) {
+                Some(cue) => cue,
+                None => {
+                    let Ok(cue) =
+                        VttCue::new(update.time, update.time + CAPTION_TIMEOUT, &update.text)
+                    else {
+                        continue;
+                    };
+                    shift_cue(&cue, offset);
+                    track.add_cue(&cue);
+                    cue
+                }
+            };
+
+            self.open.insert(update.channel, cue);
+        }
+    }
+
+    /// Leaves the cues shown now to their timeout, for when caption data stops being
+    /// contiguous, e.g. after a seek.
+    pub fn close(&mut self) {
+        self.open.clear();
+    }
+}
+
+/// The player's caption track for `channel` on `video`, added if there's none yet.
+fn caption_track(video: &HtmlVideoElement, channel: CaptionChannel) -> TextTrack {
+    let label = channel.label();
+    let existing = video.text_tracks().and_then(|tracks| {
+        (0..tracks.length())
+            .filter_map(|index| tracks.get(index))
+            .find(|track| {
+                js_sys::Reflect::get(track, &JsValue::from_str(CAPTION_CHANNEL))
+                    .ok()
+                    .and_then(|value| value.as_string())
+                    .is_some_and(|value| value == label)
+            })
+    });
+    if let Some(track) = existing {
+        return track;
+    }
+
+    let track = video.add_text_track_with_label(TextTrackKind::Captions, &label);
+    let _ = js_sys::Reflect::set(
+        &track,
+        &JsValue::from_str(CAPTION_CHANNEL),
+        &JsValue::from_str(&label),
+    );
+
+    track
+}
+
+/// A cue of `track` starting at `start` with `text`, from a segment appended before.
+fn find_cue(track: &TextTrack, start: f64, text: &str) -> Option<VttCue> {
+    let cues = track.cues()?;
+
+    (0..cues.length())
+        .filter_map(|index| cues.get(index)?.dyn_into::<VttCue>().ok())
+        .find(|cue| (cue.start_time() - start).abs() < 0.001 && cue.text() == text)
+}
+
+/// Kind of the text tracks that play `role`.
+fn role_kind(role: TrackRole) -> Option<TextTrackKind> {
+    match role {
+        TrackRole::Caption => Some(TextTrackKind::Captions),
+        TrackRole::Subtitle | TrackRole::ForcedSubtitle => Some(TextTrackKind::Subtitles),
+        TrackRole::Description => Some(TextTrackKind::Descriptions),
+        _ => None,
+    }
+}
+
+/// Shifts `cue` to be `offset` seconds later than authored, starting over from its authored
+/// times. A cue straddling 0 starts at 0 and keeps its end. A cue ending before 0 is parked at 0
+/// with no duration, so that it shows again once the offset moves it back.
+fn shift_cue(cue: &TextTrackCue, offset: f64) {
+    let applied = cue_property(cue, APPLIED_OFFSET).unwrap_or(0.);
+    if offset == applied {
+        return;
+    }
+
+    let authored_start =
+        cue_property(cue, AUTHORED_START).unwrap_or_else(|| cue.start_time() - applied);
+    let authored_end = cue_property(cue, AUTHORED_END).unwrap_or_else(|| cue.end_time() - applied);
+
+    let end = (authored_end + offset).max(0.);
+    cue.set_start_time((authored_start + offset).clamp(0., end));
+    cue.set_end_time(end);
+
+    set_cue_property(cue, APPLIED_OFFSET, offset);
+    set_cue_property(cue, AUTHORED_START, authored_start);
+    set_cue_property(cue, AUTHORED_END, authored_end);
+}
+
+/// Ends `cue` at `end`, in shifted time, keeping its authored end in step.
+fn set_cue_end(cue: &TextTrackCue, end: f64) {
+    let applied = cue_property(cue, APPLIED_OFFSET).unwrap_or(0.);
+
+    cue.set_end_time(end);
+    set_cue_property(cue, AUTHORED_END, end - applied);
+}
+
+fn cue_property(cue: &TextTrackCue, name: &str) -> Option<f64> {
+    js_sys::Reflect::get(cue, &JsValue::from_str(name))
+        .ok()
+        .and_then(|value| value.as_f64())
+}
+
+fn set_cue_property(cue: &TextTrackCue, name: &str, value: f64) {
+    let _ = js_sys::Reflect::set(cue, &JsValue::from_str(name), &JsValue::from_f64(value));
+}
@@ -0,0 +1,93 @@
+use crate::manifest::SegmentIndex;
+use crate::manifest::SegmentUrlTemplate;
+use crate::manifest::Track;
+
+use url::Url;
+
+/// A preview image for a point in time: a region of a sprite sheet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Thumbnail {
+    /// URL of the sprite sheet.
+    pub url: String,
+    /// Crop rectangle within the sprite sheet, in pixels.
+    pub x: u64,
+    pub y: u64,
+    pub width: u64,
+    pub height: u64,
+    /// Start of the time the thumbnail stands for, in seconds.
+    pub start: f64,
+    pub duration: f64,
+}
+
+/// Tiled thumbnails from an `image` adaptation set, as described by the DASH-IF guidelines:
+/// every segment is a sprite sheet of `columns` × `rows` thumbnails that split the segment's
+/// duration evenly, row by row.
+#[derive(Clone, Debug)]
+pub struct ThumbnailTrack {
+    template: SegmentUrlTemplate,
+    base_url: Url,
+    index: SegmentIndex,
+    presentation_time_offset: f64,
+    columns: u64,
+    rows: u64,
+    /// Size of a whole sprite sheet.
+    width: u64,
+    height: u64,
+}
+
+impl ThumbnailTrack {
+    /// `None` unless `track` is a tiled thumbnail representation addressed by `$Number$`.
+    /// Segment URLs are resolved against `root`, like media segments.
+    pub fn new(track: &Track, root: &Url) -> Option<Self> {
+        if !track.is_image() {
+            return None;
+        }
+
+        // `media_url_template` insists on a template with a media attribute.
+        track.segment_template()?.media.as_ref()?;
+        let (columns, rows) = track.thumbnail_tiles()?;
+
+        Some(Self {
+            template: track.media_url_template(),
+            base_url: track.base_url(root),
+            index: track.segment_index()?,
+            presentation_time_offset: track.presentation_time_offset(),
+            columns,
+            rows,
+            width: track.width()?,
+            height: track.height()?,
+        })
+    }
+
+    /// Number of thumbnails per sprite sheet.
+    pub fn tiles(&self) -> u64 {
+        self.columns * self.rows
+    }
+
+    /// The thumbnail for presentation time `time`, in seconds.
+    pub fn thumbnail_for(&self, time: f64) -> Option<Thumbnail> {
+        if time < 0. || self.tiles() == 0 {
+            return None;
+        }
+
+        let number = self.index.segment_at(time + self.presentation_time_offset);
+        let sheet_start = self.index.segment_start(number) - self.presentation_time_offset;
+
+        let duration = self.index.duration() / self.tiles() as f64;
+        let tile = (((time - sheet_start) / duration).floor().max(0.) as u64).min(self.tiles() - 1);
+
+        let width = self.width / self.columns;
+        let height = self.height / self.rows;
+        let path = self.template.number(number);
+
+        Some(Thumbnail {
+            url: self.base_url.join(&path).map_or(path, |url| url.into()),
+            x: tile % self.columns * width,
+            y: tile / self.columns * height,
+            width,
+            height,
+            start: sheet_start + tile as f64 * duration,
+            duration,
+        })
+    }
+}
@@ -0,0 +1,361 @@
+//! Optional Dioxus components. Only built with the `ui` feature.
+
+use crate::error::AshinaError;
+use crate::event::PlayerEvent;
+use crate::manifest::TrackInfo;
+use crate::MediaPlayer;
+use crate::QualitySelection;
+
+use dioxus::prelude::*;
+use futures::future::Either;
+use gloo_timers::future::TimeoutFuture;
+use wasm_bindgen::JsCast;
+use web_sys::HtmlVideoElement;
+use web_sys::TextTrackMode;
+
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+/// How often the overlay reads the video element.
+const POLL_INTERVAL_MS: u32 = 1000;
+/// Menu value standing for automatic quality or no subtitles.
+const AUTO: &str = "auto";
+
+/// Numbers the video elements rendered by [`VideoPlayer`], so ids on a page never collide.
+static NEXT_VIDEO_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct OverlayStats {
+    representation: Option<String>,
+    bitrate: Option<u64>,
+    bandwidth_estimate: Option<f64>,
+    width: u32,
+    height: u32,
+    /// Seconds buffered ahead of the playhead.
+    buffer_health: f64,
+    dropped_frames: u32,
+    total_frames: u32,
+}
+
+/// Debug panel laid over the video element with id `video_id`, showing the current rendition,
+/// bitrate, bandwidth estimate, buffer health and dropped frames. Place it in a positioned
+/// container together with the video.
+#[component]
+pub fn StatsOverlay(player: Signal<MediaPlayer>, video_id: String) -> Element {
+    let mut stats = use_signal(OverlayStats::default);
+
+    use_future(move || async move {
+        let events = player.write().subscribe();
+
+        while let Ok(event) = events.recv_async().await {
+            match event {
+                PlayerEvent::QualityChanged {
+                    representation,
+                    bitrate,
+                    ..
+                } => {
+                    let mut stats = stats.write();
+                    stats.representation = Some(representation.to_string());
+                    stats.bitrate = bitrate;
+                }
+                PlayerEvent::Heartbeat(heartbeat) => {
+                    stats.write().bandwidth_estimate = heartbeat.bandwidth_estimate;
+                }
+                _ => {}
+            }
+        }
+    });
+
+    use_future(move || {
+        let video_id = video_id.clone();
+
+        async move {
+            loop {
+                if let Some(video) = video_element(&video_id) {
+                    let mut stats = stats.write();
+                    stats.width = video.video_width();
+                    stats.height = video.video_height();
+                    stats.buffer_health = buffered_ahead(&video);
+
+                    let quality = video.get_video_playback_quality();
+                    stats.dropped_frames = quality.dropped_video_frames();
+                    stats.total_frames = quality.total_video_frames();
+                }
+
+                TimeoutFuture::new(POLL_INTERVAL_MS).await;
+            }
+        }
+    });
+
+    let stats = stats.read();
+    let kbps = |bps: f64| format!("{:.0} kbps", bps / 1000.);
+    let representation = stats.representation.clone().unwrap_or("-".into());
+    let bitrate = stats
+        .bitrate
+        .map_or("-".into(), |bitrate| kbps(bitrate as f64));
+    let bandwidth = stats.bandwidth_estimate.map_or("-".into(), kbps);
+
+    rsx! {
+        div {
+            position: "absolute",
+            top: "0.5rem",
+            left: "0.5rem",
+            padding: "0.5rem",
+            background: "rgba(0, 0, 0, 0.7)",
+            color: "white",
+            font_family: "monospace",
+            font_size: "0.75rem",
+            pointer_events: "none",
+            z_index: "1",
+
+            div { "Resolution: {stats.width}x{stats.height}" }
+            div { "Representation: {representation}" }
+            div { "Bitrate: {bitrate}" }
+            div { "Bandwidth estimate: {bandwidth}" }
+            div { "Buffer health: {stats.buffer_health:.1} s" }
+            div { "Dropped frames: {stats.dropped_frames} / {stats.total_frames}" }
+        }
+    }
+}
+
+/// A video element with its own [`MediaPlayer`], playing the manifest at `src`. Changing `src`
+/// loads the new manifest into the same element; unmounting destroys the player. Below the
+/// video sit menus for quality, audio track and subtitles, the latter two only when there is
+/// something to choose from.
+#[component]
+pub fn VideoPlayer(
+    src: String,
+    #[props(default)] autoplay: bool,
+    #[props(default = true)] controls: bool,
+    on_error: Option<EventHandler<AshinaError>>,
+    on_ended: Option<EventHandler<()>>,
+) -> Element {
+    let video_id = use_hook(|| {
+        format!(
+            "ashina-video-{}",
+            NEXT_VIDEO_ID.fetch_add(1, Ordering::Relaxed)
+        )
+    });
+    let mut player = use_signal(MediaPlayer::new);
+    let mut created = use_signal(|| false);
+    let mut tracks = use_signal(Vec::<TrackInfo>::new);
+    let mut subtitles = use_signal(Vec::<String>::new);
+
+    let id = video_id.clone();
+    use_effect(use_reactive!(|src| {
+        let id = id.clone();
+
+        spawn(async move {
+            tracks.set(vec![]);
+            subtitles.set(vec![]);
+
+            // `create` only once: it attaches the element, later sources reuse it. The write
+            // guard only lives while the command is sent, not while it loads.
+            let load = if created() {
+                Either::Left(player.write().load(src))
+            } else {
+                created.set(true);
+                Either::Right(player.write().create(id.clone(), src))
+            };
+            let result = load.await;
+
+            match result {
+                Ok(()) => {
+                    let list = player.read().tracks();
+                    tracks.set(list.await);
+                    subtitles
+                        .set(video_element(&id).map_or(vec![], |video| subtitle_labels(&video)));
+                }
+                Err(error) if error.is_cancelled() => {}
+                Err(error) => {
+                    if let Some(on_error) = on_error {
+                        on_error.call(error);
+                    }
+                }
+            }
+        });
+    }));
+
+    let qualities: Vec<_> = tracks
+        .read()
+        .iter()
+        .filter(|track| track.supported && track.height.is_some())
+        .cloned()
+        .collect();
+
+    let mut audio_tracks: Vec<_> = tracks
+        .read()
+        .iter()
+        .filter(|track| {
+            track
+                .content_type
+                .as_deref()
+                .is_some_and(|content_type| content_type.starts_with("audio"))
+        })
+        .map(|track| (track.track.clone(), audio_label(track)))
+        .collect();
+    audio_tracks.dedup_by(|a, b| a.0 == b.0);
+
+    let menu_id = video_id.clone();
+    let subtitle_id = video_id.clone();
+
+    rsx! {
+        div {
+            video {
+                id: "{video_id}",
+                controls,
+                autoplay,
+                width: "100%",
+                background_color: "black",
+                onended: move |_| {
+                    if let Some(on_ended) = on_ended {
+                        on_ended.call(());
+                    }
+                },
+                onloadedmetadata: move |_| {
+                    if let Some(video) = video_element(&menu_id) {
+                        subtitles.set(subtitle_labels(&video));
+                    }
+                },
+            }
+
+            div {
+                display: "flex",
+                gap: "0.5rem",
+
+                if !qualities.is_empty() {
+                    select {
+                        onchange: move |event| {
+                            let value = event.value();
+                            let selection = if value == AUTO {
+                                QualitySelection::Auto
+                            } else {
+                                QualitySelection::Pinned(value.into())
+                            };
+
+                            player.write().set_quality(selection);
+                        },
+                        option { value: AUTO, "Auto" }
+                        for track in qualities {
+                            option {
+                                value: "{track.representation}",
+                                {quality_label(&track)}
+                            }
+                        }
+                    }
+                }
+
+                if audio_tracks.len() > 1 {
+                    select {
+                        onchange: move |event| {
+                            let value = event.value();
+                            let track = tracks
+                                .read()
+                                .iter()
+                                .find(|track| track.track.as_str() == value)
+                                .map(|track| track.track.clone());
+
+                            if let Some(track) = track {
+                                player.write().set_audio_track(track);
+                            }
+                        },
+                        for (track, label) in audio_tracks {
+                            option { value: "{track}", "{label}" }
+                        }
+                    }
+                }
+
+                if !subtitles.read().is_empty() {
+                    select {
+                        onchange: move |event| {
+                            if let Some(video) = video_element(&subtitle_id) {
+                                show_subtitles(&video, event.value().parse().ok());
+                            }
+                        },
+                        option { value: AUTO, "Subtitles off" }
+                        for (index, label) in subtitles.read().iter().enumerate() {
+                            option { value: "{index}", "{label}" }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn quality_label(track: &TrackInfo) -> String {
+    let mut label = format!("{}p", track.height.unwrap_or_default());
+    if track.dynamic_range.is_hdr() {
+        label = format!("{label} {}", track.dynamic_range.label());
+    }
+
+    match track.bitrate {
+        Some(bitrate) => format!("{label} ({:.0} kbps)", bitrate as f64 / 1000.),
+        None => label,
+    }
+}
+
+/// The manifest's label for an audio track, falling back to its id, with its channel layout
+/// and marked when it is described audio.
+fn audio_label(track: &TrackInfo) -> String {
+    let mut label = track.label.clone().unwrap_or(track.track.to_string());
+    if let Some(layout) = track.channel_layout() {
+        label = format!("{label} ({layout})");
+    }
+
+    match track.audio_description {
+        true => format!("{label} (AD)"),
+        false => label,
+    }
+}
+
+fn subtitle_labels(video: &HtmlVideoElement) -> Vec<String> {
+    let Some(tracks) = video.text_tracks() else {
+        return vec![];
+    };
+
+    (0..tracks.length())
+        .filter_map(|index| tracks.get(index))
+        .enumerate()
+        .map(|(index, track)| match (track.label(), track.language()) {
+            (label, _) if !label.is_empty() => label,
+            (_, language) if !language.is_empty() => language,
+            _ => format!("Track {}", index + 1),
+        })
+        .collect()
+}
+
+/// Shows the text track at `index`, hiding every other one. `None` turns subtitles off.
+fn show_subtitles(video: &HtmlVideoElement, index: Option<u32>) {
+    let Some(tracks) = video.text_tracks() else {
+        return;
+    };
+
+    for current in 0..tracks.length() {
+        if let Some(track) = tracks.get(current) {
+            track.set_mode(if Some(current) == index {
+                TextTrackMode::Showing
+            } else {
+                TextTrackMode::Disabled
+            });
+        }
+    }
+}
+
+fn video_element(id: &str) -> Option<HtmlVideoElement> {
+    web_sys::window()?
+        .document()?
+        .get_element_by_id(id)?
+        .dyn_into()
+        .ok()
+}
+
+fn buffered_ahead(video: &HtmlVideoElement) -> f64 {
+    let position = video.current_time();
+    let ranges = video.buffered();
+
+    (0..ranges.length())
+        .filter_map(|index| Some((ranges.start(index).ok()?, ranges.end(index).ok()?)))
+        .find(|(start, end)| (*start..=*end).contains(&position))
+        .map_or(0., |(_, end)| end - position)
+}